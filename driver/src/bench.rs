@@ -0,0 +1,85 @@
+use std::time::{Duration, Instant};
+
+// What `bench` collects: for each report, the time from its read returning to the
+// dispatcher having written its events to uinput.
+#[derive(Default)]
+pub struct LatencyStats {
+    latencies: Vec<Duration>,
+    first_read: Option<Instant>,
+    last_read: Option<Instant>,
+}
+
+impl LatencyStats {
+    pub fn record(&mut self, read_at: Instant, written_at: Instant) {
+        self.latencies.push(written_at.saturating_duration_since(read_at));
+        self.first_read.get_or_insert(read_at);
+        self.last_read = Some(read_at);
+    }
+
+    pub fn count(&self) -> usize {
+        self.latencies.len()
+    }
+
+    // Nearest-rank percentile, `percent` from 0 to 100.
+    pub fn percentile(&self, percent: f32) -> Option<Duration> {
+        let mut sorted = self.latencies.clone();
+        sorted.sort_unstable();
+        let rank = (percent / 100.0 * sorted.len() as f32).ceil() as usize;
+        sorted.get(rank.clamp(1, sorted.len().max(1)) - 1).copied()
+    }
+
+    // Reports per second between the first and last read.
+    pub fn rate(&self) -> f32 {
+        match (self.first_read, self.last_read) {
+            (Some(first), Some(last)) if last > first => {
+                (self.count() - 1) as f32 / last.duration_since(first).as_secs_f32()
+            }
+            _ => 0.0,
+        }
+    }
+
+    pub fn summary(&self, with_rate: bool) -> String {
+        if self.latencies.is_empty() {
+            return "No reports received.".to_string();
+        }
+        let micros = |percent| self.percentile(percent).unwrap_or_default().as_micros();
+        let mut summary = format!(
+            "{} reports, read to uinput write: p50 {} µs, p95 {} µs, p99 {} µs, max {} µs",
+            self.count(),
+            micros(50.0),
+            micros(95.0),
+            micros(99.0),
+            micros(100.0)
+        );
+        if with_rate {
+            summary += &format!("\nEnd-to-end rate: {:.1} reports/s", self.rate());
+        }
+        summary
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percentiles_and_rate_come_from_the_recorded_reports() {
+        let mut stats = LatencyStats::default();
+        assert_eq!(stats.percentile(50.0), None);
+        assert_eq!(stats.summary(true), "No reports received.");
+
+        let start = Instant::now();
+        for i in 0..100u64 {
+            let read_at = start + Duration::from_millis(5 * i);
+            stats.record(read_at, read_at + Duration::from_micros(i + 1));
+        }
+
+        assert_eq!(stats.percentile(50.0), Some(Duration::from_micros(50)));
+        assert_eq!(stats.percentile(95.0), Some(Duration::from_micros(95)));
+        assert_eq!(stats.percentile(99.0), Some(Duration::from_micros(99)));
+        assert_eq!(stats.percentile(100.0), Some(Duration::from_micros(100)));
+        assert!((stats.rate() - 200.0).abs() < 0.01);
+        assert!(stats.summary(false).starts_with("100 reports"));
+        assert!(stats.summary(true).ends_with("200.0 reports/s"));
+    }
+}
@@ -0,0 +1,362 @@
+use crate::config::ProfileSwitch;
+use crate::keys::{ComboVisitor, deserialize_combo, deserialize_key};
+
+use evdev::Key;
+use serde::Deserialize;
+use serde::de::value::MapAccessDeserializer;
+use serde::de::{self, Deserializer, MapAccess, SeqAccess, Visitor};
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Error;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::Duration;
+
+// What a tablet or pen button does: hold a key combination for as long as the button
+// is held, play a macro or run a shell command once when it is pressed, switch the
+// pen to the eraser ("eraser"), slow the pen down for detail work while held
+// ("precision"), move the mapping to the next monitor ("next_monitor"), turn the
+// lazy-brush stabilizer on or off ("stabilizer"), shrink or grow the mouse area
+// ("shrink_area", "grow_area"), toggle between mouse and tablet mode ("toggle_mode"),
+// or switch to the next profile ("next_profile") or a named one (`{ profile = "..." }`).
+#[derive(Clone, Debug, PartialEq)]
+pub enum Binding {
+    Keys(Vec<Key>),
+    Macro { steps: Vec<MacroStep> },
+    Exec { exec: String },
+    Eraser,
+    Precision,
+    NextMonitor,
+    Stabilizer,
+    ShrinkArea,
+    GrowArea,
+    ToggleMode,
+    Profile(ProfileSwitch),
+}
+
+// The table forms of a binding, `{ macro = [...] }`, `{ exec = "..." }` or
+// `{ profile = "..." }`.
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum BindingTable {
+    Macro(Vec<MacroStep>),
+    Exec(String),
+    Profile(String),
+}
+
+// Written by hand rather than untagged so key name errors reach the user instead of
+// "did not match any variant".
+impl<'de> Deserialize<'de> for Binding {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(BindingVisitor)
+    }
+}
+
+struct BindingVisitor;
+
+impl<'de> Visitor<'de> for BindingVisitor {
+    type Value = Binding;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a key combination, a list of keys, or a macro or exec table")
+    }
+
+    fn visit_str<E: de::Error>(self, text: &str) -> Result<Binding, E> {
+        match text.trim().to_lowercase().as_str() {
+            "eraser" => return Ok(Binding::Eraser),
+            "precision" => return Ok(Binding::Precision),
+            "next_monitor" => return Ok(Binding::NextMonitor),
+            "stabilizer" => return Ok(Binding::Stabilizer),
+            "shrink_area" => return Ok(Binding::ShrinkArea),
+            "grow_area" => return Ok(Binding::GrowArea),
+            "toggle_mode" => return Ok(Binding::ToggleMode),
+            "next_profile" => return Ok(Binding::Profile(ProfileSwitch::Next)),
+            _ => {}
+        }
+        ComboVisitor
+            .visit_str(text)
+            .map(|combo| Binding::Keys(combo.0))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, seq: A) -> Result<Binding, A::Error> {
+        ComboVisitor
+            .visit_seq(seq)
+            .map(|combo| Binding::Keys(combo.0))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<Binding, A::Error> {
+        Ok(
+            match BindingTable::deserialize(MapAccessDeserializer::new(map))? {
+                BindingTable::Macro(steps) => Binding::Macro { steps },
+                BindingTable::Exec(exec) => Binding::Exec { exec },
+                BindingTable::Profile(name) => Binding::Profile(ProfileSwitch::select(&name)),
+            },
+        )
+    }
+}
+
+impl std::str::FromStr for Binding {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, String> {
+        BindingVisitor
+            .visit_str::<de::value::Error>(text)
+            .map_err(|err| err.to_string())
+    }
+}
+
+impl From<Vec<Key>> for Binding {
+    fn from(keys: Vec<Key>) -> Self {
+        Binding::Keys(keys)
+    }
+}
+
+impl Binding {
+    // Every key this binding can emit, for registering them on the virtual keyboard.
+    pub fn keys(&self) -> Vec<Key> {
+        match self {
+            Binding::Keys(keys) => keys.clone(),
+            Binding::Macro { steps } => steps.iter().flat_map(MacroStep::keys).collect(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+// A binding triggered by several tablet buttons held together, written in the config
+// as `"3+4" = "save"`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Chord {
+    pub buttons: Vec<u8>,
+    pub binding: Binding,
+}
+
+impl Chord {
+    // Bit for each button, matching the tablet's button flags.
+    pub fn mask(&self) -> u16 {
+        self.buttons.iter().fold(0, |mask, id| mask | 1 << id)
+    }
+}
+
+fn parse_chord_buttons(text: &str) -> Result<Vec<u8>, String> {
+    let buttons = text
+        .split('+')
+        .map(|id| match id.trim().parse::<u8>() {
+            Ok(id) if id < 14 => Ok(id),
+            _ => Err(format!(
+                "`{}` in chord `{}` is not a tablet button (0-13)",
+                id, text
+            )),
+        })
+        .collect::<Result<Vec<u8>, String>>()?;
+    if buttons.len() < 2 {
+        return Err(format!("chord `{}` needs at least two buttons", text));
+    }
+    Ok(buttons)
+}
+
+pub fn deserialize_chords<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<Chord>, D::Error> {
+    let chords = HashMap::<String, Binding>::deserialize(deserializer)?;
+    let mut chords = chords
+        .into_iter()
+        .map(|(buttons, binding)| {
+            let buttons = parse_chord_buttons(&buttons).map_err(de::Error::custom)?;
+            Ok(Chord { buttons, binding })
+        })
+        .collect::<Result<Vec<Chord>, D::Error>>()?;
+    // Larger chords first, so 3+4+5 wins over 3+4 when all three are held.
+    chords.sort_by_key(|chord| std::cmp::Reverse(chord.buttons.len()));
+    Ok(chords)
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub enum MacroStep {
+    #[serde(deserialize_with = "deserialize_key")]
+    Press(Key),
+    #[serde(deserialize_with = "deserialize_key")]
+    Release(Key),
+    // Presses the keys in order, then releases them in reverse, like a shortcut.
+    #[serde(deserialize_with = "deserialize_combo")]
+    Tap(Vec<Key>),
+    // Milliseconds to wait before the next step.
+    Delay(u64),
+}
+
+impl MacroStep {
+    // Long waits would stall the pen, since macros run on the dispatcher thread.
+    const MAX_DELAY_MS: u64 = 1000;
+
+    fn keys(&self) -> Vec<Key> {
+        match self {
+            MacroStep::Press(key) | MacroStep::Release(key) => vec![*key],
+            MacroStep::Tap(keys) => keys.clone(),
+            MacroStep::Delay(_) => Vec::new(),
+        }
+    }
+}
+
+// Plays the steps through `emit_keys`, which sends a frame of keys pressed (1) or
+// released (0), so the caller keeps track of what is still down.
+pub fn play_macro<E>(
+    steps: &[MacroStep],
+    mut emit_keys: impl FnMut(&[Key], i32) -> Result<(), E>,
+) -> Result<(), E> {
+    for step in steps {
+        match step {
+            MacroStep::Press(key) => emit_keys(&[*key], 1)?,
+            MacroStep::Release(key) => emit_keys(&[*key], 0)?,
+            MacroStep::Tap(keys) => {
+                emit_keys(keys, 1)?;
+                let reversed: Vec<Key> = keys.iter().rev().copied().collect();
+                emit_keys(&reversed, 0)?;
+            }
+            MacroStep::Delay(ms) => {
+                thread::sleep(Duration::from_millis((*ms).min(MacroStep::MAX_DELAY_MS)))
+            }
+        }
+    }
+    Ok(())
+}
+
+// Starts `command` through the shell without waiting for it. A thread reaps it so
+// finished commands don't linger as zombies.
+pub fn spawn_command(command: &str) -> Result<(), Error> {
+    spawn_command_with_env(command, &[])
+}
+
+// The same, with extra environment variables.
+pub fn spawn_command_with_env(command: &str, env: &[(&str, String)]) -> Result<(), Error> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .envs(env.iter().map(|(name, value)| (name, value)))
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .spawn()?;
+    thread::spawn(move || child.wait());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::virtual_output::{RecordingOutput, VirtualOutput};
+    use evdev::{EventType, InputEvent};
+
+    #[test]
+    fn macro_is_parsed_from_toml() {
+        let buttons: std::collections::HashMap<String, Binding> = toml::from_str(
+            r#"
+            0 = ["KEY_TAB"]
+            1 = { macro = [{ tap = ["KEY_LEFTCTRL", "KEY_E"] }, { delay = 50 }, { tap = ["KEY_ENTER"] }] }
+            2 = { exec = "flameshot gui" }
+            3 = "eraser"
+            4 = "Precision"
+            5 = "next_monitor"
+            6 = "next_profile"
+            7 = { profile = "krita" }
+            8 = "shrink_area"
+            9 = "grow_area"
+            10 = "toggle_mode"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(buttons["0"], Binding::Keys(vec![Key::KEY_TAB]));
+        assert_eq!(
+            buttons["1"],
+            Binding::Macro {
+                steps: vec![
+                    MacroStep::Tap(vec![Key::KEY_LEFTCTRL, Key::KEY_E]),
+                    MacroStep::Delay(50),
+                    MacroStep::Tap(vec![Key::KEY_ENTER]),
+                ]
+            }
+        );
+        assert_eq!(
+            buttons["2"],
+            Binding::Exec {
+                exec: "flameshot gui".to_string()
+            }
+        );
+        assert_eq!(buttons["3"], Binding::Eraser);
+        assert_eq!(buttons["4"], Binding::Precision);
+        assert_eq!(buttons["5"], Binding::NextMonitor);
+        assert_eq!(buttons["6"], Binding::Profile(ProfileSwitch::Next));
+        assert_eq!(
+            buttons["7"],
+            Binding::Profile(ProfileSwitch::Select(Some("krita".to_string())))
+        );
+        assert_eq!(buttons["8"], Binding::ShrinkArea);
+        assert_eq!(buttons["9"], Binding::GrowArea);
+        assert_eq!(buttons["10"], Binding::ToggleMode);
+    }
+
+    #[test]
+    fn bad_key_names_are_reported() {
+        let err = toml::from_str::<std::collections::HashMap<String, Binding>>(
+            r#"0 = { macro = [{ tap = "ctrl+shft+e" }] }"#,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("unknown key `shft`"), "{}", err);
+    }
+
+    #[test]
+    fn chords_are_parsed_from_toml() {
+        #[derive(Debug, Deserialize)]
+        struct Chords {
+            #[serde(deserialize_with = "deserialize_chords")]
+            chords: Vec<Chord>,
+        }
+        let parsed: Chords = toml::from_str("[chords]\n\"3+4\" = \"save\"\n").unwrap();
+        assert_eq!(
+            parsed.chords,
+            vec![Chord {
+                buttons: vec![3, 4],
+                binding: Binding::Keys(vec![Key::KEY_LEFTCTRL, Key::KEY_S]),
+            }]
+        );
+        assert_eq!(parsed.chords[0].mask(), 0b11000);
+
+        let err = toml::from_str::<Chords>("[chords]\n\"3\" = \"save\"\n").unwrap_err();
+        assert!(
+            err.to_string().contains("needs at least two buttons"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn tap_releases_keys_in_reverse_order() {
+        let output = RecordingOutput::new();
+        let steps = [MacroStep::Tap(vec![Key::KEY_LEFTCTRL, Key::KEY_E])];
+        let mut recording = output.clone();
+        play_macro(&steps, |keys, state| {
+            let events: Vec<InputEvent> = keys
+                .iter()
+                .map(|key| InputEvent::new(EventType::KEY, key.code(), state))
+                .collect();
+            recording.emit(&events)
+        })
+        .unwrap();
+
+        let keys: Vec<(u16, i32)> = output
+            .events()
+            .iter()
+            .filter(|event| event.event_type() == EventType::KEY)
+            .map(|event| (event.code(), event.value()))
+            .collect();
+        assert_eq!(
+            keys,
+            vec![
+                (Key::KEY_LEFTCTRL.code(), 1),
+                (Key::KEY_E.code(), 1),
+                (Key::KEY_E.code(), 0),
+                (Key::KEY_LEFTCTRL.code(), 0),
+            ]
+        );
+    }
+}
@@ -0,0 +1,375 @@
+use crate::config::AXIS_MAX;
+use crate::virtual_device::RawDataReader;
+
+use serde::Deserialize;
+
+use std::fs;
+use std::io::Error;
+use std::path::Path;
+
+// Nominal pressure the rest of the driver works with: 2000 at rest, decreasing towards
+// 0 with force. Coordinates are mapped onto 0..=AXIS_MAX.
+pub const NOMINAL_PRESSURE_REST: i32 = 2000;
+
+// Measured by `v1060p calibrate`; the defaults leave the raw values untouched.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct CalibrationConfig {
+    pub x_min: i32,
+    pub x_max: i32,
+    pub y_min: i32,
+    pub y_max: i32,
+    // Raw pressure with the pen hovering, and with the pen pressed as hard as possible.
+    pub pressure_rest: i32,
+    pub pressure_full: i32,
+}
+
+impl Default for CalibrationConfig {
+    fn default() -> Self {
+        CalibrationConfig {
+            x_min: 0,
+            x_max: AXIS_MAX,
+            y_min: 0,
+            y_max: AXIS_MAX,
+            pressure_rest: NOMINAL_PRESSURE_REST,
+            pressure_full: 0,
+        }
+    }
+}
+
+impl CalibrationConfig {
+    pub fn map_position(&self, x: i32, y: i32) -> (i32, i32) {
+        let scale = |value: i32, min: i32, max: i32| (value - min) * AXIS_MAX / (max - min).max(1);
+        (
+            scale(x, self.x_min, self.x_max),
+            scale(y, self.y_min, self.y_max),
+        )
+    }
+
+    // Without a calibration the whole raw range from [axes] is used.
+    pub fn or_raw_range(self, x_max: i32, y_max: i32) -> Self {
+        match (self.x_min, self.x_max, self.y_min, self.y_max) == (0, AXIS_MAX, 0, AXIS_MAX) {
+            true => CalibrationConfig { x_max, y_max, ..self },
+            false => self,
+        }
+    }
+
+    // Rescales a raw reading so the measured rest..full span covers the nominal one.
+    pub fn map_pressure(&self, raw_pressure: i32) -> i32 {
+        let span = (self.pressure_rest - self.pressure_full).max(1);
+        let depression = self.pressure_rest - raw_pressure;
+        NOMINAL_PRESSURE_REST - depression * NOMINAL_PRESSURE_REST / span
+    }
+
+    fn to_toml(self) -> String {
+        format!(
+            "[calibration]\n\
+             x_min = {}\nx_max = {}\ny_min = {}\ny_max = {}\n\
+             pressure_rest = {}\npressure_full = {}\n",
+            self.x_min, self.x_max, self.y_min, self.y_max, self.pressure_rest, self.pressure_full
+        )
+    }
+}
+
+// Learns the pen's pressure range while it is used, for `pressure.learn`: the
+// highest reading while hovering is its rest, the lowest while touching its full
+// pressure. Until it has been pressed MIN_LEARNED_SPAN below rest, full pressure is
+// taken to be that far down, so a first light stroke doesn't max out.
+#[derive(Debug, Default)]
+pub struct PressureLearner {
+    rest: Option<i32>,
+    hardest: Option<i32>,
+}
+
+impl PressureLearner {
+    const MIN_LEARNED_SPAN: i32 = NOMINAL_PRESSURE_REST / 2;
+
+    pub fn observe(&mut self, raw_pressure: i32, is_touching: bool) {
+        match is_touching {
+            true => self.hardest = Some(self.hardest.map_or(raw_pressure, |h| h.min(raw_pressure))),
+            false => self.rest = Some(self.rest.map_or(raw_pressure, |r| r.max(raw_pressure))),
+        }
+    }
+
+    // `calibration` with the pressure range learned so far.
+    pub fn apply(&self, calibration: &CalibrationConfig) -> CalibrationConfig {
+        let pressure_rest = self.rest.unwrap_or(calibration.pressure_rest);
+        let pressure_full = match self.hardest {
+            Some(hardest) => hardest.min(pressure_rest - Self::MIN_LEARNED_SPAN),
+            None => calibration.pressure_full,
+        };
+        CalibrationConfig {
+            pressure_rest,
+            pressure_full,
+            ..*calibration
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Step {
+    Hover,
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+    FullPress,
+}
+
+impl Step {
+    const ALL: [Step; 6] = [
+        Step::Hover,
+        Step::TopLeft,
+        Step::TopRight,
+        Step::BottomLeft,
+        Step::BottomRight,
+        Step::FullPress,
+    ];
+
+    pub fn prompt(self) -> &'static str {
+        match self {
+            Step::Hover => "Hold the pen just above the tablet without touching it.",
+            Step::TopLeft => "Touch the top-left corner of the active area, then lift the pen.",
+            Step::TopRight => "Touch the top-right corner of the active area, then lift the pen.",
+            Step::BottomLeft => {
+                "Touch the bottom-left corner of the active area, then lift the pen."
+            }
+            Step::BottomRight => {
+                "Touch the bottom-right corner of the active area, then lift the pen."
+            }
+            Step::FullPress => "Press the pen down as hard as you comfortably can, then lift it.",
+        }
+    }
+}
+
+// Walks through the calibration steps, fed one raw report at a time.
+pub struct Calibrator {
+    step: usize,
+    samples: Vec<(i32, i32, i32)>,
+    pressure_rest: i32,
+    corners: Vec<(i32, i32)>,
+    pressure_full: i32,
+}
+
+impl Default for Calibrator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Calibrator {
+    const HOVER_SAMPLES: usize = 50;
+    const MIN_TOUCH_SAMPLES: usize = 5;
+    // Depression below the rest reading that counts as touching the surface.
+    const TOUCH_MARGIN: i32 = 100;
+
+    pub fn new() -> Self {
+        Calibrator {
+            step: 0,
+            samples: Vec::new(),
+            pressure_rest: NOMINAL_PRESSURE_REST,
+            corners: Vec::new(),
+            pressure_full: NOMINAL_PRESSURE_REST,
+        }
+    }
+
+    // The step waiting for input, or None once everything is measured.
+    pub fn current_step(&self) -> Option<Step> {
+        Step::ALL.get(self.step).copied()
+    }
+
+    // Returns true when the report completed the current step.
+    pub fn feed(&mut self, raw_data: &RawDataReader) -> bool {
+        let Some(step) = self.current_step() else {
+            return false;
+        };
+        let is_in_range = raw_data.pen_buttons() != 0;
+        let (x, y, pressure) = (
+            raw_data.x_axis(),
+            raw_data.signed_y_axis(),
+            raw_data.pressure(),
+        );
+        let is_touching = is_in_range && self.pressure_rest - pressure > Self::TOUCH_MARGIN;
+
+        let is_done = match step {
+            Step::Hover => {
+                if is_in_range {
+                    self.samples.push((x, y, pressure));
+                }
+                if self.samples.len() < Self::HOVER_SAMPLES {
+                    return false;
+                }
+                self.pressure_rest = median(self.samples.iter().map(|s| s.2));
+                true
+            }
+            _ if is_touching => {
+                self.samples.push((x, y, pressure));
+                false
+            }
+            _ if self.samples.len() < Self::MIN_TOUCH_SAMPLES => {
+                // Too short to be a deliberate touch; wait for the next one.
+                self.samples.clear();
+                false
+            }
+            Step::FullPress => {
+                self.pressure_full = self.samples.iter().map(|s| s.2).min().unwrap_or(0);
+                true
+            }
+            _ => {
+                let corner = (
+                    median(self.samples.iter().map(|s| s.0)),
+                    median(self.samples.iter().map(|s| s.1)),
+                );
+                self.corners.push(corner);
+                true
+            }
+        };
+
+        if is_done {
+            self.samples.clear();
+            self.step += 1;
+        }
+        is_done
+    }
+
+    pub fn result(&self) -> Result<CalibrationConfig, String> {
+        let [top_left, top_right, bottom_left, bottom_right] = self.corners[..] else {
+            return Err("calibration is not finished".to_string());
+        };
+        let calibration = CalibrationConfig {
+            x_min: (top_left.0 + bottom_left.0) / 2,
+            x_max: (top_right.0 + bottom_right.0) / 2,
+            y_min: (top_left.1 + top_right.1) / 2,
+            y_max: (bottom_left.1 + bottom_right.1) / 2,
+            pressure_rest: self.pressure_rest,
+            pressure_full: self.pressure_full,
+        };
+
+        if calibration.x_max - calibration.x_min < AXIS_MAX / 4
+            || calibration.y_max - calibration.y_min < AXIS_MAX / 4
+        {
+            return Err("the corners are too close together; please try again".to_string());
+        }
+        if calibration.pressure_rest - calibration.pressure_full < Self::TOUCH_MARGIN * 2 {
+            return Err("the full press was too light; please try again".to_string());
+        }
+        Ok(calibration)
+    }
+}
+
+fn median(values: impl Iterator<Item = i32>) -> i32 {
+    let mut values: Vec<i32> = values.collect();
+    values.sort_unstable();
+    values.get(values.len() / 2).copied().unwrap_or(0)
+}
+
+// Replaces the [calibration] table of the config file, keeping everything else.
+pub fn write_calibration(path: &Path, calibration: CalibrationConfig) -> Result<(), Error> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(err) => return Err(err),
+    };
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, replace_calibration_table(&contents, calibration))
+}
+
+fn replace_calibration_table(contents: &str, calibration: CalibrationConfig) -> String {
+    let mut output = String::new();
+    let mut is_in_calibration = false;
+    for line in contents.lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with('[') {
+            is_in_calibration = trimmed == "[calibration]";
+        }
+        if !is_in_calibration {
+            output.push_str(line);
+            output.push('\n');
+        }
+    }
+
+    if !output.is_empty() && !output.ends_with("\n\n") {
+        output.push('\n');
+    }
+    output.push_str(&calibration.to_toml());
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(x: i32, y: i32, pressure: i32) -> RawDataReader {
+        RawDataReader::from_fields(x as u16, y as u16, pressure as u16, 2)
+    }
+
+    fn touch(calibrator: &mut Calibrator, x: i32, y: i32, pressure: i32) {
+        for _ in 0..10 {
+            calibrator.feed(&report(x, y, pressure));
+        }
+        assert!(calibrator.feed(&report(x, y, 1990)));
+    }
+
+    #[test]
+    fn corners_and_pressure_are_measured() {
+        let mut calibrator = Calibrator::new();
+        for _ in 0..Calibrator::HOVER_SAMPLES {
+            calibrator.feed(&report(2000, 2000, 1990));
+        }
+        assert_eq!(calibrator.current_step(), Some(Step::TopLeft));
+
+        touch(&mut calibrator, 100, 200, 1500);
+        touch(&mut calibrator, 3900, 220, 1500);
+        touch(&mut calibrator, 120, 3800, 1500);
+        touch(&mut calibrator, 3920, 3820, 1500);
+        touch(&mut calibrator, 2000, 2000, 300);
+        assert_eq!(calibrator.current_step(), None);
+
+        let calibration = calibrator.result().unwrap();
+        assert_eq!(
+            calibration,
+            CalibrationConfig {
+                x_min: 110,
+                x_max: 3910,
+                y_min: 210,
+                y_max: 3810,
+                pressure_rest: 1990,
+                pressure_full: 300,
+            }
+        );
+        assert_eq!(calibration.map_position(3910, 210), (AXIS_MAX, 0));
+        assert_eq!(calibration.map_pressure(1990), NOMINAL_PRESSURE_REST);
+        assert_eq!(calibration.map_pressure(300), 0);
+    }
+
+    #[test]
+    fn pressure_range_is_learned_from_use() {
+        let mut learner = PressureLearner::default();
+        let calibration = CalibrationConfig::default();
+        learner.observe(1950, false);
+        learner.observe(1940, false);
+        learner.observe(1400, true);
+        // A light stroke doesn't count as full pressure yet.
+        assert_eq!(learner.apply(&calibration).pressure_rest, 1950);
+        assert_eq!(learner.apply(&calibration).pressure_full, 950);
+
+        learner.observe(500, true);
+        learner.observe(700, true);
+        let learned = learner.apply(&calibration);
+        assert_eq!(learned.pressure_full, 500);
+        assert_eq!(learned.map_pressure(500), 0);
+        assert_eq!(learned.map_pressure(1950), NOMINAL_PRESSURE_REST);
+    }
+
+    #[test]
+    fn calibration_table_is_replaced() {
+        let contents = "output = \"HDMI-1\"\n\n[calibration]\nx_min = 1\n\n[eraser]\nbutton = 6\n";
+        let replaced = replace_calibration_table(contents, CalibrationConfig::default());
+
+        assert!(replaced.starts_with("output = \"HDMI-1\"\n\n[eraser]\nbutton = 6\n"));
+        assert!(replaced.ends_with("pressure_full = 0\n"));
+        assert_eq!(replaced.matches("[calibration]").count(), 1);
+    }
+}
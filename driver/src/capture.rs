@@ -0,0 +1,98 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Error, ErrorKind, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+// One raw report and when it arrived, relative to the start of the capture.
+pub struct CapturedReport {
+    pub timestamp: Duration,
+    pub data: Vec<u8>,
+}
+
+// Writes reports as text, one per line: "<seconds> <hex bytes>", so captures can be
+// attached to bug reports and read without special tools.
+pub struct Recorder {
+    writer: BufWriter<File>,
+    start: Instant,
+}
+
+impl Recorder {
+    pub fn create(path: &Path) -> Result<Self, Error> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(writer, "# v1060p raw report capture: <seconds> <report bytes in hex>")?;
+        Ok(Recorder {
+            writer,
+            start: Instant::now(),
+        })
+    }
+
+    pub fn record(&mut self, report: &[u8]) -> Result<(), Error> {
+        let hex: Vec<String> = report.iter().map(|byte| format!("{:02x}", byte)).collect();
+        writeln!(
+            self.writer,
+            "{:.6} {}",
+            self.start.elapsed().as_secs_f64(),
+            hex.join(" ")
+        )?;
+        // Flush every report so a crash or unplug doesn't lose the interesting part.
+        self.writer.flush()
+    }
+}
+
+pub fn read_capture(path: &Path) -> Result<Vec<CapturedReport>, Error> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut reports = Vec::new();
+
+    for (number, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let invalid = || {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("{}:{}: malformed capture line", path.display(), number + 1),
+            )
+        };
+        let mut fields = line.split_whitespace();
+        let seconds: f64 = fields
+            .next()
+            .and_then(|field| field.parse().ok())
+            .ok_or_else(invalid)?;
+        let data = fields
+            .map(|byte| u8::from_str_radix(byte, 16))
+            .collect::<Result<Vec<u8>, _>>()
+            .map_err(|_| invalid())?;
+
+        reports.push(CapturedReport {
+            timestamp: Duration::from_secs_f64(seconds.max(0.0)),
+            data,
+        });
+    }
+
+    Ok(reports)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recorded_reports_are_read_back() {
+        let path = std::env::temp_dir().join(format!("v1060p-capture-{}.txt", std::process::id()));
+        let mut recorder = Recorder::create(&path).unwrap();
+        recorder.record(&[0x08, 0x10, 0xff]).unwrap();
+        recorder.record(&[0x00; 64]).unwrap();
+        drop(recorder);
+
+        let reports = read_capture(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(reports.len(), 2);
+        assert_eq!(reports[0].data, vec![0x08, 0x10, 0xff]);
+        assert_eq!(reports[1].data, vec![0x00; 64]);
+        assert!(reports[0].timestamp <= reports[1].timestamp);
+    }
+}
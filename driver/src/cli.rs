@@ -0,0 +1,182 @@
+use clap::{Parser, Subcommand};
+use v1060p::config::Mode;
+use v1060p::physical_device::Backend;
+
+use std::path::PathBuf;
+
+#[derive(Parser)]
+#[command(version, about = "Linux driver for the VINSA 1060 Plus drawing tablet")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// USB device node (e.g. /dev/bus/usb/001/004) or hidraw node (e.g.
+    /// /dev/hidraw3) of the tablet. Defaults to the first known tablet found.
+    #[arg(short, long, value_name = "PATH")]
+    pub device: Option<PathBuf>,
+
+    /// How to read the tablet: `usb` claims it through libusb, detaching the
+    /// kernel's HID driver; `hidraw` reads its /dev/hidraw node and leaves the
+    /// kernel driver bound.
+    #[arg(short, long, value_enum, default_value_t = Backend::Usb)]
+    pub backend: Backend,
+
+    /// Configuration file. Defaults to ~/.config/vinsa-driver/config.toml.
+    #[arg(short, long, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+
+    /// Mode the driver starts in. Defaults to the mode it was last left in, or
+    /// the `mode` set in the config.
+    #[arg(short, long, value_enum)]
+    pub mode: Option<Mode>,
+
+    /// Fraction of the tablet used in mouse mode (0.1 to 1.0, capped by
+    /// `mouse_area_scale_max` in the config).
+    #[arg(short = 'a', long, value_name = "SCALE", value_parser = parse_mouse_area_scale)]
+    pub mouse_area_scale: Option<f32>,
+
+    /// Increase log verbosity: -v adds raw reports, -vv everything. RUST_LOG
+    /// overrides this.
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    pub verbose: u8,
+
+    /// Print every report as hex with its decoded position, pressure and
+    /// buttons, e.g. to compare tablet revisions.
+    #[arg(long)]
+    pub debug_raw: bool,
+
+    /// Log to the systemd journal instead of stderr.
+    #[cfg(feature = "journald")]
+    #[arg(long)]
+    pub journald: bool,
+
+    /// Save every raw report with a timestamp to FILE (for bug reports).
+    #[arg(long, value_name = "FILE", conflicts_with = "replay")]
+    pub record: Option<PathBuf>,
+
+    /// Play back a capture made with --record instead of reading the tablet.
+    #[arg(long, value_name = "FILE")]
+    pub replay: Option<PathBuf>,
+
+    /// Load the configuration and look for the tablet, then exit without
+    /// claiming it or creating virtual devices.
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Run in the background. Logs are lost unless sent to the journal.
+    #[arg(long)]
+    pub daemon: bool,
+
+    /// PID file written with --daemon. Defaults to /run/v1060p.pid as root,
+    /// else $XDG_RUNTIME_DIR/v1060p.pid.
+    #[arg(long, value_name = "FILE", requires = "daemon")]
+    pub pid_file: Option<PathBuf>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Measure the active area and pressure range of this tablet and save them
+    /// to the configuration file.
+    Calibrate,
+    /// Bind tablet buttons by pressing them: asks for a button, then the action
+    /// for it, and saves it to the configuration file.
+    Learn,
+    /// Show the raw and resulting pressure live while you press the pen, to help
+    /// pick thresholds. Uses the mode from --mode or the config.
+    TestPressure,
+    /// Measure how long each report takes from being read to its events being
+    /// written to uinput, and print the 50th, 95th and 99th percentiles. Reads the
+    /// tablet, or with --replay the capture as fast as it goes.
+    Bench {
+        /// How long to measure for. Ctrl+C stops earlier.
+        #[arg(long, value_name = "SECONDS", default_value_t = 10)]
+        seconds: u64,
+        /// Also print the end-to-end rate, in reports per second.
+        #[arg(long)]
+        rate: bool,
+    },
+    /// Check or create the configuration file.
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+    /// Check uinput, the tablet, its hidraw nodes, kernel drivers and the session,
+    /// and suggest fixes for anything that would stop the driver.
+    Doctor,
+    /// Install a udev rule giving GROUP access to the tablet and /dev/uinput, so
+    /// the driver can run without sudo.
+    InstallUdev {
+        /// Group that gets access.
+        #[arg(long, default_value = "input")]
+        group: String,
+        /// Print the rule instead of installing it.
+        #[arg(long)]
+        print: bool,
+    },
+    /// Show the mode, tablet, uptime and report rate of the running driver.
+    Status {
+        /// Print the mode, area, pressure, battery and profile as one JSON line,
+        /// for waybar or polybar custom modules.
+        #[arg(long)]
+        json: bool,
+        /// Keep running and print a new line whenever the status changes.
+        #[arg(long, requires = "json")]
+        follow: bool,
+    },
+    /// Change a setting of the running driver until its config is reloaded,
+    /// xsetwacom-style: `set Area 0 0 4095 4095`, `set Button 2 "key ctrl z"`,
+    /// `set Mode absolute`, `set Rotate half`, `set MapToOutput HDMI-1`,
+    /// `set PressureCurve 0 10 90 100`, `set Stabilizer toggle` or `set ResetArea`.
+    Set {
+        parameter: String,
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        values: Vec<String>,
+    },
+    /// Switch the running driver to a profile by name, back to the top-level
+    /// settings with "default", or to the next profile with "next".
+    Profile { name: String },
+    /// Install a systemd service that starts the driver when the tablet is
+    /// plugged in and restarts it if it fails.
+    InstallService {
+        /// Install it for the current user instead of system-wide. The tablet
+        /// then starts it through the rule from `install-udev`.
+        #[arg(long)]
+        user: bool,
+        /// Print the unit instead of installing it.
+        #[arg(long)]
+        print: bool,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ConfigAction {
+    /// Report syntax errors, unknown keys, invalid key names and out-of-range
+    /// values, with their line and column.
+    Check,
+    /// Write a default configuration file with every setting explained.
+    Init {
+        /// Replace the file if it already exists.
+        #[arg(long)]
+        force: bool,
+    },
+}
+
+fn parse_mouse_area_scale(value: &str) -> Result<f32, String> {
+    let scale: f32 = value
+        .parse()
+        .map_err(|_| format!("`{}` is not a number", value))?;
+    if (0.1..=1.0).contains(&scale) {
+        Ok(scale)
+    } else {
+        Err("must be between 0.1 and 1.0".to_string())
+    }
+}
+
+impl Cli {
+    pub fn journald(&self) -> bool {
+        #[cfg(feature = "journald")]
+        return self.journald;
+        #[cfg(not(feature = "journald"))]
+        false
+    }
+}
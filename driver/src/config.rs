@@ -0,0 +1,900 @@
+use crate::binding::{Binding, Chord, deserialize_chords};
+use crate::calibration::CalibrationConfig;
+use crate::double_tap::DoubleTapConfig;
+use crate::filter::SmoothingConfig;
+use crate::flick::FlickConfig;
+use crate::gesture::GestureConfig;
+use crate::hooks::HooksConfig;
+use crate::keys::{self, deserialize_combo_list, is_button};
+use crate::palm::PalmRejectionConfig;
+use crate::pressure::{MAX_PRESSURE, PressureCurve, PressureResponse};
+use crate::privileges::PrivilegesConfig;
+use crate::protocol::Protocol;
+use crate::radial_menu::RadialMenuConfig;
+use crate::relative::RelativeConfig;
+use crate::repeat::AutoRepeatConfig;
+use crate::screen::FollowMonitor;
+use crate::scroll::ScrollConfig;
+use crate::tilt::TiltConfig;
+use crate::zone::ZoneConfig;
+
+use evdev::Key;
+use serde::{Deserialize, Serialize};
+use tracing::{error, info, warn};
+
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct Config {
+    // Mode to start in when neither the command line nor the saved state says.
+    pub mode: Mode,
+    pub tablet_buttons: HashMap<u8, Binding>,
+    #[serde(deserialize_with = "deserialize_chords")]
+    pub chords: Vec<Chord>,
+    pub gestures: GestureConfig,
+    pub layer: LayerConfig,
+    pub auto_repeat: AutoRepeatConfig,
+    pub debounce: DebounceConfig,
+    pub pen_buttons: HashMap<u8, Binding>,
+    pub pressure: PressureConfig,
+    pub calibration: CalibrationConfig,
+    #[serde(deserialize_with = "deserialize_axes")]
+    pub axes: AxesConfig,
+    // Report layout, for clones that share a USB ID with a different model.
+    pub protocol: Option<Protocol>,
+    pub output: Option<String>,
+    // Keep the mapping on the monitor with the cursor or the focused window instead.
+    pub follow_monitor: Option<FollowMonitor>,
+    // Largest fraction of the tablet the mouse-mode area can grow to.
+    pub mouse_area_scale_max: f32,
+    pub mouse_area: MouseAreaConfig,
+    pub tablet_area: TabletAreaConfig,
+    pub rotation: Rotation,
+    // Mirror the raw coordinates, e.g. for a tablet mounted upside-down.
+    pub invert_x: bool,
+    pub invert_y: bool,
+    // Tablet button that turns the rotation by 180° (e.g. to switch hands).
+    pub rotation_button: Option<u8>,
+    // Tablet buttons whose keys stay down after a press until the next pen tap, so
+    // a modifier doesn't have to be held while drawing.
+    pub sticky_buttons: Vec<u8>,
+    pub eraser: EraserConfig,
+    pub hold_right_click: HoldClickConfig,
+    pub precision: PrecisionConfig,
+    pub settle: SettleConfig,
+    pub stabilizer: StabilizerConfig,
+    pub multimedia: MultimediaConfig,
+    pub relative: RelativeConfig,
+    pub scroll: ScrollConfig,
+    pub tilt: TiltConfig,
+    pub zones: Vec<ZoneConfig>,
+    pub radial_menu: RadialMenuConfig,
+    pub flicks: FlickConfig,
+    pub double_tap: DoubleTapConfig,
+    pub palm_rejection: PalmRejectionConfig,
+    pub smoothing: SmoothingConfig,
+    pub profiles: HashMap<String, Profile>,
+    // Start in the mode and mouse area scale the driver was last left in, unless
+    // given on the command line.
+    pub restore_state: bool,
+    // Show desktop notifications when the mode or mouse area changes.
+    pub notifications: bool,
+    // Grab the tablet's own kernel input nodes so applications don't get every event
+    // twice, once from them and once from the virtual devices.
+    pub grab_kernel_devices: bool,
+    pub virtual_devices: VirtualDevicesConfig,
+    pub privileges: PrivilegesConfig,
+    pub hooks: HooksConfig,
+    pub script: ScriptConfig,
+    // Settings for one tablet when several are connected, or for one model, keyed by
+    // its USB serial number, port path (e.g. "1-2.3") or USB ID (e.g. "08f2:6811")
+    // and merged over the rest of the file.
+    pub device: HashMap<String, toml::Table>,
+    // The file as loaded, to merge the device sections over.
+    #[serde(skip)]
+    pub(crate) table: toml::Table,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    #[default]
+    Mouse,
+    Tablet,
+}
+
+// Clockwise rotation of the tablet, e.g. 180 for left-handed use.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(try_from = "u16")]
+pub enum Rotation {
+    #[default]
+    Deg0,
+    Deg90,
+    Deg180,
+    Deg270,
+}
+
+impl TryFrom<u16> for Rotation {
+    type Error = String;
+
+    fn try_from(degrees: u16) -> Result<Self, Self::Error> {
+        match degrees {
+            0 => Ok(Rotation::Deg0),
+            90 => Ok(Rotation::Deg90),
+            180 => Ok(Rotation::Deg180),
+            270 => Ok(Rotation::Deg270),
+            _ => Err(format!("rotation must be 0, 90, 180 or 270, not {}", degrees)),
+        }
+    }
+}
+
+impl Rotation {
+    pub fn degrees(self) -> u16 {
+        match self {
+            Rotation::Deg0 => 0,
+            Rotation::Deg90 => 90,
+            Rotation::Deg180 => 180,
+            Rotation::Deg270 => 270,
+        }
+    }
+
+    pub fn flipped(self) -> Self {
+        match self {
+            Rotation::Deg0 => Rotation::Deg180,
+            Rotation::Deg90 => Rotation::Deg270,
+            Rotation::Deg180 => Rotation::Deg0,
+            Rotation::Deg270 => Rotation::Deg90,
+        }
+    }
+
+    // Turns tablet coordinates (0..=max on both axes) into screen-oriented ones.
+    pub fn apply(self, x: i32, y: i32, max: i32) -> (i32, i32) {
+        match self {
+            Rotation::Deg0 => (x, y),
+            Rotation::Deg90 => (max - y, x),
+            Rotation::Deg180 => (max - x, max - y),
+            Rotation::Deg270 => (y, max - x),
+        }
+    }
+}
+
+// Where the reduced mouse-mode area sits on the tablet.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MouseAreaAnchor {
+    #[default]
+    Center,
+    TopLeft,
+    // Centered on where the pen last touched down when this anchor was chosen.
+    LastTouch,
+}
+
+impl MouseAreaAnchor {
+    pub fn next(self) -> Self {
+        match self {
+            MouseAreaAnchor::Center => MouseAreaAnchor::TopLeft,
+            MouseAreaAnchor::TopLeft => MouseAreaAnchor::LastTouch,
+            MouseAreaAnchor::LastTouch => MouseAreaAnchor::Center,
+        }
+    }
+}
+
+// Range the pen position is worked on in, from 0 to this on both axes: areas, the
+// rotation and the output mapping all use it.
+pub const AXIS_MAX: i32 = 4096;
+
+// Largest raw X and Y the tablet reports, mapped onto 0..=AXIS_MAX unless it has been
+// calibrated, and the range of the virtual pen's X and Y.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct AxesConfig {
+    pub x_max: i32,
+    pub y_max: i32,
+    pub output_max: i32,
+}
+
+impl Default for AxesConfig {
+    fn default() -> Self {
+        AxesConfig {
+            x_max: AXIS_MAX,
+            y_max: AXIS_MAX,
+            output_max: AXIS_MAX,
+        }
+    }
+}
+
+impl AxesConfig {
+    // Scales a position from 0..=AXIS_MAX to the virtual pen's range, within it.
+    pub fn to_output(&self, x: i32, y: i32) -> (i32, i32) {
+        let scale = |axis: i32| {
+            (axis.clamp(0, AXIS_MAX) as i64 * self.output_max as i64 / AXIS_MAX as i64) as i32
+        };
+        (scale(x), scale(y))
+    }
+}
+
+fn deserialize_axes<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<AxesConfig, D::Error> {
+    let axes = AxesConfig::deserialize(deserializer)?;
+    let ranges = [("x_max", axes.x_max), ("y_max", axes.y_max), ("output_max", axes.output_max)];
+    match ranges.iter().find(|(_, max)| !(256..=65535).contains(max)) {
+        Some((name, max)) => Err(serde::de::Error::custom(format!(
+            "axes.{} must be between 256 and 65535, not {}",
+            name, max
+        ))),
+        None => Ok(axes),
+    }
+}
+
+// Tablet button that cycles through the anchors, and the fraction of the tablet the
+// mouse area starts at.
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct MouseAreaConfig {
+    pub anchor: MouseAreaAnchor,
+    pub cycle_button: Option<u8>,
+    pub scale: f32,
+}
+
+impl Default for MouseAreaConfig {
+    fn default() -> Self {
+        MouseAreaConfig {
+            anchor: MouseAreaAnchor::default(),
+            cycle_button: None,
+            scale: 0.3,
+        }
+    }
+}
+
+// Fraction of the tablet mapped to the output in tablet mode, as a centered square
+// like the mouse area. 1.0 uses the whole tablet. `rect` picks the area by its
+// corners instead, [left, top, right, bottom] in tablet units (0-4096), like
+// xsetwacom's Area.
+#[derive(Clone, Copy, Deserialize)]
+#[serde(default)]
+pub struct TabletAreaConfig {
+    pub scale: f32,
+    #[serde(deserialize_with = "deserialize_area_rect")]
+    pub rect: Option<[i32; 4]>,
+}
+
+impl Default for TabletAreaConfig {
+    fn default() -> Self {
+        TabletAreaConfig {
+            scale: 1.0,
+            rect: None,
+        }
+    }
+}
+
+// Checks that the corners of an area are in order and on the tablet.
+pub fn validate_area_rect([left, top, right, bottom]: [i32; 4]) -> Result<[i32; 4], String> {
+    let is_on_tablet = |value: i32| (0..=AXIS_MAX).contains(&value);
+    if [left, top, right, bottom].into_iter().all(is_on_tablet) && left < right && top < bottom {
+        Ok([left, top, right, bottom])
+    } else {
+        Err("area must be left top right bottom, within 0-4096".to_string())
+    }
+}
+
+fn deserialize_area_rect<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<[i32; 4]>, D::Error> {
+    let rect = <[i32; 4]>::deserialize(deserializer)?;
+    validate_area_rect(rect).map(Some).map_err(serde::de::Error::custom)
+}
+
+#[derive(Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum EraserMode {
+    // Each press switches between pen and eraser.
+    #[default]
+    Toggle,
+    // The eraser is active only while the button is held.
+    Hold,
+}
+
+// Tablet button that, while held, switches the other buttons to a second set of
+// bindings. Buttons missing from that set keep their usual binding.
+#[derive(Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct LayerConfig {
+    pub button: Option<u8>,
+    pub tablet_buttons: HashMap<u8, Binding>,
+}
+
+// Ignores a tablet button changing state again within `ms` of its last change, for
+// units whose contacts bounce. `buttons` overrides the window for single buttons.
+#[derive(Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct DebounceConfig {
+    pub ms: u64,
+    pub buttons: HashMap<u8, u64>,
+}
+
+impl DebounceConfig {
+    pub fn window(&self, id: u8) -> Duration {
+        Duration::from_millis(*self.buttons.get(&id).unwrap_or(&self.ms))
+    }
+}
+
+// Names the virtual devices show up with in libinput, xinput and evtest.
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct VirtualDevicesConfig {
+    pub pen_name: String,
+    pub pad_name: String,
+    pub mouse_name: String,
+    pub dial_name: String,
+    pub input_property: InputProperty,
+    pub pen_backend: PenBackend,
+}
+
+// How libinput treats the pen: as a tablet moving a cursor, or as a screen tablet
+// drawing right under the pen.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum InputProperty {
+    #[default]
+    Pointer,
+    Direct,
+}
+
+// What the virtual pen is made through: uinput, with the axes and keys the driver
+// sends, or uhid, as a HID digitizer the kernel maps like a native tablet.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PenBackend {
+    #[default]
+    Uinput,
+    Uhid,
+}
+
+impl Default for VirtualDevicesConfig {
+    fn default() -> Self {
+        VirtualDevicesConfig {
+            pen_name: "VINSA 1060+ Pen".to_string(),
+            pad_name: "VINSA 1060+ Pad".to_string(),
+            mouse_name: "VINSA 1060+ Mouse".to_string(),
+            dial_name: "VINSA 1060+ Dial".to_string(),
+            input_property: InputProperty::default(),
+            pen_backend: PenBackend::default(),
+        }
+    }
+}
+
+// Pen button that switches the reported tool to BTN_TOOL_RUBBER.
+#[derive(Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct EraserConfig {
+    pub button: Option<u8>,
+    pub mode: EraserMode,
+}
+
+// Pen button that, held for `delay_ms` while the pen touches, lifts the pen and
+// right-clicks instead. Pressed while hovering it keeps its usual binding.
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct HoldClickConfig {
+    pub button: Option<u8>,
+    pub delay_ms: u64,
+}
+
+impl Default for HoldClickConfig {
+    fn default() -> Self {
+        HoldClickConfig {
+            button: None,
+            delay_ms: 600,
+        }
+    }
+}
+
+// How much a "precision" button slows the pen down: the screen area the tablet
+// covers shrinks by `zoom` around where the pen was when it was pressed.
+#[derive(Clone, Copy, Deserialize)]
+#[serde(default)]
+pub struct PrecisionConfig {
+    pub zoom: f32,
+}
+
+impl Default for PrecisionConfig {
+    fn default() -> Self {
+        PrecisionConfig { zoom: 4.0 }
+    }
+}
+
+// Reports skipped after the pen comes into range, and touches held back after it
+// touches down, in each mode. The first reports often still carry coordinates from
+// where the pen last was, which puts a hook at the start of the stroke.
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(default)]
+pub struct SettleConfig {
+    pub mouse: u32,
+    pub tablet: u32,
+}
+
+// Lazy-brush stabilizer: while the pen touches, what is drawn trails it on a string
+// `radius` tablet units long and only moves once the string is pulled taut, which
+// evens out shaky lines. Also turned on and off by a "stabilizer" binding.
+#[derive(Clone, Copy, Deserialize)]
+#[serde(default)]
+pub struct StabilizerConfig {
+    pub enabled: bool,
+    pub radius: f32,
+}
+
+impl Default for StabilizerConfig {
+    fn default() -> Self {
+        StabilizerConfig {
+            enabled: false,
+            radius: 30.0,
+        }
+    }
+}
+
+// A Rhai script whose hooks react to tablet buttons and the pen, for mappings the
+// config can't express. Only used when built with the `scripting` feature.
+#[derive(Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ScriptConfig {
+    // Relative to the config file's directory.
+    pub path: Option<PathBuf>,
+}
+
+// Keys sent when the pen taps the icon strip above the active area. The strip is
+// split into as many equal-width icons as there are entries, left to right.
+// Reports with a raw Y at or above `boundary` are on the strip; when `enabled` is
+// false the strip is treated as more drawing surface.
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct MultimediaConfig {
+    pub enabled: bool,
+    pub boundary: i32,
+    #[serde(deserialize_with = "deserialize_combo_list")]
+    pub icons: Vec<Vec<Key>>,
+    // Slide along the strip to turn a dial on its own device instead of tapping icons.
+    pub dial: bool,
+    // Tablet units (4096 across) of sliding per REL_DIAL step.
+    pub dial_step: f32,
+}
+
+impl Default for MultimediaConfig {
+    fn default() -> Self {
+        MultimediaConfig {
+            enabled: true,
+            boundary: 61000,
+            icons: vec![
+                vec![Key::KEY_MUTE],
+                vec![Key::KEY_VOLUMEDOWN],
+                vec![Key::KEY_VOLUMEUP],
+                vec![Key::KEY_PREVIOUSSONG],
+                vec![Key::KEY_PLAYPAUSE],
+                vec![Key::KEY_NEXTSONG],
+                vec![Key::KEY_BRIGHTNESSDOWN],
+                vec![Key::KEY_BRIGHTNESSUP],
+            ],
+            dial: false,
+            dial_step: 100.0,
+        }
+    }
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct PressureConfig {
+    pub curve: PressureCurve,
+    // Keep adjusting the pressure range to the pen's actual rest and hardest readings.
+    pub learn: bool,
+    pub mouse: PressureResponse,
+    pub tablet: PressureResponse,
+}
+
+impl Default for PressureConfig {
+    fn default() -> Self {
+        PressureConfig {
+            curve: PressureCurve::default(),
+            learn: false,
+            mouse: PressureResponse {
+                touch_threshold: 800,
+                release_threshold: None,
+                click_threshold: Some(600),
+                pressure_gain: 2.0,
+                pressure_gamma: 1.0,
+                pressure_min_out: 0,
+                pressure_max_out: MAX_PRESSURE,
+            },
+            tablet: PressureResponse {
+                touch_threshold: 510,
+                release_threshold: None,
+                click_threshold: None,
+                pressure_gain: 3.0,
+                pressure_gamma: 1.0,
+                pressure_min_out: 0,
+                pressure_max_out: MAX_PRESSURE,
+            },
+        }
+    }
+}
+
+impl PressureConfig {
+    pub fn for_mode(&self, is_mouse_mode: bool) -> &PressureResponse {
+        if is_mouse_mode { &self.mouse } else { &self.tablet }
+    }
+}
+
+// Overrides applied while a matching application is focused. Anything left out
+// falls back to the top-level settings.
+#[derive(Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Profile {
+    pub window_class: Vec<String>,
+    pub tablet_buttons: Option<HashMap<u8, Binding>>,
+    pub pen_buttons: Option<HashMap<u8, Binding>>,
+    pub pressure: Option<PressureConfig>,
+    pub tablet_area: Option<TabletAreaConfig>,
+}
+
+// A change of profile asked for by a binding or over the control socket.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ProfileSwitch {
+    // The next profile by name, going back to the top-level settings after the last.
+    Next,
+    // None, or "default" in the config, is the top-level settings.
+    Select(Option<String>),
+}
+
+impl ProfileSwitch {
+    pub fn select(name: &str) -> Self {
+        ProfileSwitch::Select((name != "default").then(|| name.to_string()))
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        let tablet_buttons: HashMap<u8, Binding> = [
+            (0, vec![Key::KEY_TAB]),        // TAB
+            (1, vec![Key::KEY_SPACE]),      // SPACE
+            (2, vec![Key::KEY_LEFTALT]),    // ALT
+            (3, vec![Key::KEY_LEFTCTRL]),   // CTRL
+            (4, vec![Key::KEY_PAGEUP]),     // MOUSE UP
+            (5, vec![Key::KEY_PAGEDOWN]),   // MOUSE DOWN
+            (7, vec![Key::KEY_LEFTCTRL, Key::KEY_KPMINUS]), // CTRL- ZOOM
+            (8, vec![Key::KEY_LEFTCTRL, Key::KEY_KPPLUS]),  // CTRL+ ZOOM
+            (9, vec![Key::KEY_ESC]),        // ESC CANCEL
+        ]
+        .into_iter()
+        .map(|(id, keys)| (id, Binding::from(keys)))
+        .chain([
+            (6, Binding::ShrinkArea),  // MOUSE AREA -
+            (12, Binding::ToggleMode), // TOGGLE MOUSE/TABLET
+            (13, Binding::GrowArea),   // MOUSE AREA +
+        ])
+        .collect();
+
+        let pen_buttons: HashMap<u8, Binding> = [
+            (4, Binding::from(vec![Key::BTN_STYLUS])),
+            (6, Binding::from(vec![Key::BTN_STYLUS2])),
+        ]
+        .into_iter()
+        .collect();
+
+        Config {
+            mode: Mode::default(),
+            tablet_buttons,
+            chords: Vec::new(),
+            gestures: GestureConfig::default(),
+            layer: LayerConfig::default(),
+            auto_repeat: AutoRepeatConfig::default(),
+            debounce: DebounceConfig::default(),
+            pen_buttons,
+            pressure: PressureConfig::default(),
+            calibration: CalibrationConfig::default(),
+            axes: AxesConfig::default(),
+            protocol: None,
+            output: None,
+            follow_monitor: None,
+            mouse_area_scale_max: 1.0,
+            mouse_area: MouseAreaConfig::default(),
+            tablet_area: TabletAreaConfig::default(),
+            rotation: Rotation::default(),
+            invert_x: false,
+            invert_y: false,
+            rotation_button: None,
+            sticky_buttons: Vec::new(),
+            eraser: EraserConfig::default(),
+            hold_right_click: HoldClickConfig::default(),
+            precision: PrecisionConfig::default(),
+            settle: SettleConfig::default(),
+            stabilizer: StabilizerConfig::default(),
+            multimedia: MultimediaConfig::default(),
+            relative: RelativeConfig::default(),
+            scroll: ScrollConfig::default(),
+            tilt: TiltConfig::default(),
+            zones: Vec::new(),
+            radial_menu: RadialMenuConfig::default(),
+            flicks: FlickConfig::default(),
+            double_tap: DoubleTapConfig::default(),
+            palm_rejection: PalmRejectionConfig::default(),
+            smoothing: SmoothingConfig::default(),
+            profiles: HashMap::new(),
+            restore_state: true,
+            notifications: true,
+            grab_kernel_devices: false,
+            virtual_devices: VirtualDevicesConfig::default(),
+            privileges: PrivilegesConfig::default(),
+            hooks: HooksConfig::default(),
+            script: ScriptConfig::default(),
+            device: HashMap::new(),
+            table: toml::Table::new(),
+        }
+    }
+}
+
+impl Config {
+    // ~/.config/vinsa-driver/config.toml (or $XDG_CONFIG_HOME/vinsa-driver/config.toml)
+    pub fn default_path() -> Option<PathBuf> {
+        let config_dir = match env::var_os("XDG_CONFIG_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+        };
+        Some(config_dir.join("vinsa-driver").join("config.toml"))
+    }
+
+    // Every key the virtual keyboard may emit, across the base map and all profiles.
+    pub fn all_tablet_keys(&self) -> Vec<Key> {
+        self.profiles
+            .values()
+            .filter_map(|profile| profile.tablet_buttons.as_ref())
+            .chain([
+                &self.tablet_buttons,
+                &self.gestures.long_press,
+                &self.gestures.double_press,
+                &self.layer.tablet_buttons,
+            ])
+            .flat_map(|buttons| buttons.values().flat_map(Binding::keys))
+            .chain(self.chords.iter().flat_map(|chord| chord.binding.keys()))
+            .chain(
+                self.zones
+                    .iter()
+                    .flat_map(|zone| zone.increase.iter().chain(&zone.decrease))
+                    .flat_map(Binding::keys),
+            )
+            .chain(self.radial_menu.items.iter().flat_map(|item| item.action.keys()))
+            .chain(self.flicks.bindings().flat_map(Binding::keys))
+            .chain(self.multimedia.icons.iter().flatten().cloned())
+            // Keyboard keys bound to pen buttons go out through the keyboard too.
+            .chain(self.pen_binding_keys().filter(|key| !is_button(*key)))
+            // A script may send anything.
+            .chain(self.script.path.iter().flat_map(|_| keys::all_keys()))
+            .collect()
+    }
+
+    // Relative motion, scroll-drag and zones without bindings go through a virtual
+    // mouse.
+    pub fn needs_virtual_mouse(&self) -> bool {
+        self.relative.enabled
+            || self.scroll.button.is_some()
+            || self.zones.iter().any(ZoneConfig::scrolls)
+    }
+
+    pub fn all_pen_keys(&self) -> Vec<Key> {
+        self.pen_binding_keys().filter(|key| is_button(*key)).collect()
+    }
+
+    fn pen_binding_keys(&self) -> impl Iterator<Item = Key> + '_ {
+        self.profiles
+            .values()
+            .filter_map(|profile| profile.pen_buttons.as_ref())
+            .chain([&self.pen_buttons])
+            .flat_map(|buttons| buttons.values().flat_map(Binding::keys))
+    }
+
+    // First profile whose window_class entries match the focused window's class.
+    pub fn profile_for_window(&self, window_class: &str) -> Option<(&str, &Profile)> {
+        let window_class = window_class.to_lowercase();
+        let mut names: Vec<&String> = self.profiles.keys().collect();
+        names.sort();
+
+        names.into_iter().find_map(|name| {
+            let profile = &self.profiles[name];
+            profile
+                .window_class
+                .iter()
+                .any(|class| window_class.contains(&class.to_lowercase()))
+                .then_some((name.as_str(), profile))
+        })
+    }
+
+    // The profile `switch` leads to from `current`.
+    pub fn switch_profile(
+        &self,
+        current: Option<&str>,
+        switch: &ProfileSwitch,
+    ) -> Result<Option<String>, String> {
+        match switch {
+            ProfileSwitch::Select(Some(name)) if !self.profiles.contains_key(name) => {
+                Err(format!("unknown profile `{}`", name))
+            }
+            ProfileSwitch::Select(name) => Ok(name.clone()),
+            ProfileSwitch::Next => {
+                let mut names: Vec<&String> = self.profiles.keys().collect();
+                names.sort();
+                let next = match current {
+                    Some(current) => names.iter().position(|name| *name == current).map(|i| i + 1),
+                    None => Some(0),
+                };
+                Ok(next.and_then(|i| names.get(i)).map(|name| name.to_string()))
+            }
+        }
+    }
+
+    pub fn path(custom_path: Option<&Path>) -> Option<PathBuf> {
+        custom_path.map(Path::to_path_buf).or_else(Self::default_path)
+    }
+
+    // A missing default file means defaults; a broken file is reported and ignored.
+    // An explicitly requested file must exist.
+    pub fn load(custom_path: Option<&Path>) -> Self {
+        if let Some(path) = custom_path {
+            return Self::load_from(path).unwrap_or_else(|err| {
+                error!("{}", err);
+                std::process::exit(1);
+            });
+        }
+
+        let Some(path) = Self::default_path() else {
+            return Self::default();
+        };
+        if !path.exists() {
+            return Self::default();
+        }
+
+        Self::load_from(&path).unwrap_or_else(|err| {
+            warn!("{} Using default button mappings.", err);
+            Self::default()
+        })
+    }
+
+    pub fn load_from(path: &Path) -> Result<Self, String> {
+        let contents = fs::read_to_string(path)
+            .map_err(|err| format!("Error reading {}: {}", path.display(), err))?;
+        let mut config: Config = toml::from_str(&contents)
+            .map_err(|err| format!("Error parsing {}: {}", path.display(), err))?;
+        config.table = contents
+            .parse()
+            .map_err(|err| format!("Error parsing {}: {}", path.display(), err))?;
+        if let (Some(script), Some(dir)) = (&config.script.path, path.parent())
+            && script.is_relative()
+        {
+            // In the table too, so device sections don't undo it.
+            let script = dir.join(script);
+            if let Some(toml::Value::Table(table)) = config.table.get_mut("script") {
+                table.insert("path".into(), script.display().to_string().into());
+            }
+            config.script.path = Some(script);
+        }
+        info!("Loaded config from {}.", path.display());
+        Ok(config)
+    }
+
+    // The configuration for the tablet known by `identifiers`: the device section
+    // matching the first of them that has one, merged over the rest of the file.
+    // Names are matched ignoring case, so USB IDs can be written in either.
+    pub fn for_device(&self, identifiers: &[String]) -> Result<Config, String> {
+        let section = identifiers.iter().find_map(|id| {
+            self.device
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case(id))
+        });
+        let mut table = self.table.clone();
+        if let Some((_, section)) = section {
+            merge_tables(&mut table, section);
+        }
+        // Through text, since deserializing a Value can't turn keys like "3" into the
+        // numbers button tables are keyed by.
+        let mut config: Config = toml::to_string(&table)
+            .map_err(|err| err.to_string())
+            .and_then(|text| toml::from_str(&text).map_err(|err| err.message().to_string()))
+            .map_err(|err| match section {
+                Some((name, _)) => format!("Error in [device.\"{}\"]: {}", name, err),
+                None => format!("Error in the configuration: {}", err),
+            })?;
+        config.table = self.table.clone();
+        Ok(config)
+    }
+}
+
+// Values from `overlay` replace those in `base`, except tables, which are merged key
+// by key so a device section can change e.g. just `pressure.curve`.
+fn merge_tables(base: &mut toml::Table, overlay: &toml::Table) {
+    for (key, value) in overlay {
+        match (base.get_mut(key), value) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(overlay)) => {
+                merge_tables(base, overlay)
+            }
+            _ => {
+                base.insert(key.clone(), value.clone());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn device_sections_are_merged_over_the_file() {
+        let path = env::temp_dir().join(format!("vinsa-config-{}.toml", std::process::id()));
+        let contents = r#"
+            output = "HDMI-1"
+            rotation = 180
+
+            [pressure.tablet]
+            touch_threshold = 400
+            pressure_gain = 5.0
+
+            [tablet_buttons]
+            3 = "undo"
+
+            [device."1-2.3"]
+            output = "DP-2"
+            pressure.tablet.touch_threshold = 900
+
+            [device."08F2:6811"]
+            axes = { x_max = 32767, y_max = 32767 }
+        "#;
+        fs::write(&path, contents).unwrap();
+        let config = Config::load_from(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let ids = ["ABC123".to_string(), "1-2.3".to_string()];
+        let second = config.for_device(&ids).unwrap();
+        assert_eq!(second.output.as_deref(), Some("DP-2"));
+        assert_eq!(second.rotation, Rotation::Deg180);
+        assert_eq!(second.pressure.tablet.touch_threshold, 900);
+        assert_eq!(second.pressure.tablet.pressure_gain, 5.0);
+        assert_eq!(second.tablet_buttons[&3], "undo".parse().unwrap());
+
+        let first = config.for_device(&["1-1".to_string()]).unwrap();
+        assert_eq!(first.output.as_deref(), Some("HDMI-1"));
+        assert_eq!(first.pressure.tablet.touch_threshold, 400);
+
+        // The port path is more specific than the USB ID, so it wins.
+        let ids = ["1-2.3".to_string(), "08f2:6811".to_string()];
+        assert_eq!(config.for_device(&ids).unwrap().axes.x_max, AXIS_MAX);
+        let ids = ["1-4".to_string(), "08f2:6811".to_string()];
+        assert_eq!(config.for_device(&ids).unwrap().axes.x_max, 32767);
+    }
+
+    #[test]
+    fn profiles_are_cycled_by_name() {
+        let config: Config =
+            toml::from_str("[profiles.krita]\n[profiles.gimp]\n[profiles.inkscape]\n").unwrap();
+        let next = |current| config.switch_profile(current, &ProfileSwitch::Next).unwrap();
+        assert_eq!(next(None).as_deref(), Some("gimp"));
+        assert_eq!(next(Some("gimp")).as_deref(), Some("inkscape"));
+        assert_eq!(next(Some("krita")), None);
+
+        let select = |name| config.switch_profile(Some("gimp"), &ProfileSwitch::select(name));
+        assert_eq!(select("krita"), Ok(Some("krita".to_string())));
+        assert_eq!(select("default"), Ok(None));
+        assert!(select("blender").is_err());
+    }
+
+    #[test]
+    fn axis_ranges_are_checked_on_load() {
+        let config: Config = toml::from_str("[axes]\nx_max = 32767\n").unwrap();
+        assert_eq!(config.axes.x_max, 32767);
+        assert_eq!(config.axes.output_max, AXIS_MAX);
+
+        let err = toml::from_str::<Config>("[axes]\noutput_max = 0\n").err().unwrap();
+        assert!(err.to_string().contains("axes.output_max"));
+    }
+}
@@ -0,0 +1,220 @@
+use toml::Spanned;
+use toml::de::{DeTable, DeValue};
+
+use std::fmt;
+use std::ops::Range;
+
+use crate::config::Config;
+
+// Written by `config init`. Every setting appears in it, those that are off by
+// default as `#key = value`, so it is also the list of keys `config check` knows.
+pub const DEFAULT_CONFIG: &str = include_str!("default_config.toml");
+
+// Tables whose keys are button numbers, chords or names rather than settings.
+const FREE_FORM_TABLES: &[&str] = &[
+    "tablet_buttons",
+    "pen_buttons",
+    "chords",
+    "gestures.long_press",
+    "gestures.double_press",
+    "layer.tablet_buttons",
+    "debounce.buttons",
+    "profiles.*.tablet_buttons",
+    "profiles.*.pen_buttons",
+];
+
+// Fractions of the tablet, which the driver clamps to 0.1-1.0.
+const SCALES: &[&[&str]] = &[
+    &["mouse_area_scale_max"],
+    &["mouse_area", "scale"],
+    &["tablet_area", "scale"],
+];
+
+// A mistake in the config file, at a 1-based line and column.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Issue {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+// Everything wrong with a config file: syntax errors, values the driver would
+// refuse, keys it would ignore and scales it would clamp. Empty if the file is fine.
+pub fn check(contents: &str) -> Vec<Issue> {
+    let table = match DeTable::parse(contents) {
+        Ok(table) => table,
+        Err(err) => return vec![issue(contents, err.span().unwrap_or(0..0), err.message())],
+    };
+    let mut found: Vec<(Range<usize>, String)> = Vec::new();
+
+    let schema = schema();
+    find_unknown_keys(table.get_ref(), &schema, &schema, "", "", &mut found);
+
+    match toml::from_str::<Config>(contents) {
+        Ok(mut config) => {
+            for path in SCALES {
+                let scale = match path {
+                    ["mouse_area_scale_max"] => config.mouse_area_scale_max,
+                    ["mouse_area", _] => config.mouse_area.scale,
+                    _ => config.tablet_area.scale,
+                };
+                if let Some(span) = value_span(table.get_ref(), path)
+                    && !(0.1..=1.0).contains(&scale)
+                {
+                    let name = path.join(".");
+                    let message = format!("{} must be between 0.1 and 1.0, not {}", name, scale);
+                    found.push((span, message));
+                }
+            }
+
+            config.table = contents.parse().unwrap_or_default();
+            // Device sections are only deserialized once merged over the rest.
+            let devices = table.get_ref().get("device").map(Spanned::get_ref);
+            if let Some(DeValue::Table(devices)) = devices {
+                for (name, _) in devices.iter() {
+                    if let Err(err) = config.for_device(&[name.get_ref().to_string()]) {
+                        found.push((name.span(), err));
+                    }
+                }
+            }
+        }
+        Err(err) => found.push((err.span().unwrap_or(0..0), err.message().to_string())),
+    }
+
+    found.sort_by_key(|(span, _)| span.start);
+    found
+        .into_iter()
+        .map(|(span, message)| issue(contents, span, &message))
+        .collect()
+}
+
+// The default file with its commented-out settings turned on.
+fn schema() -> toml::Table {
+    let enabled: Vec<&str> = DEFAULT_CONFIG
+        .lines()
+        .map(|line| match line.strip_prefix('#') {
+            Some(setting) if !setting.is_empty() && !setting.starts_with([' ', '#']) => setting,
+            _ => line,
+        })
+        .collect();
+    enabled.join("\n").parse().expect("the default config is valid TOML")
+}
+
+// `shown` is the path as written, for messages; `path` has profile names replaced by
+// `*` and device sections start over at the top level, to look up in the schema.
+fn find_unknown_keys(
+    table: &DeTable,
+    schema: &toml::Table,
+    root: &toml::Table,
+    path: &str,
+    shown: &str,
+    found: &mut Vec<(Range<usize>, String)>,
+) {
+    if FREE_FORM_TABLES.contains(&path) {
+        return;
+    }
+    for (key, value) in table.iter() {
+        let name = key.get_ref().as_ref();
+        let child_shown = join(shown, name);
+        let (child_path, child_schema) = match path {
+            "profiles" => {
+                let example = schema.values().next().and_then(toml::Value::as_table);
+                ("profiles.*".to_string(), example)
+            }
+            // A profile's pressure takes everything the top-level one does.
+            "profiles.*" if name == "pressure" => {
+                ("pressure".to_string(), root.get(name).and_then(toml::Value::as_table))
+            }
+            "device" => (String::new(), Some(root)),
+            _ => match schema.get(name) {
+                Some(expected) => (join(path, name), expected.as_table()),
+                None => {
+                    let message = match shown {
+                        "" => format!("unknown key `{}`", name),
+                        _ => format!("unknown key `{}` in [{}]", name, shown),
+                    };
+                    found.push((key.span(), message));
+                    continue;
+                }
+            },
+        };
+        // Anything else of the wrong type is reported when the file is deserialized.
+        if let (DeValue::Table(table), Some(schema)) = (value.get_ref(), child_schema) {
+            find_unknown_keys(table, schema, root, &child_path, &child_shown, found);
+        }
+    }
+}
+
+fn join(path: &str, key: &str) -> String {
+    match path {
+        "" => key.to_string(),
+        _ => format!("{}.{}", path, key),
+    }
+}
+
+fn value_span(table: &DeTable, path: &[&str]) -> Option<Range<usize>> {
+    let (last, parents) = path.split_last()?;
+    let mut table = table;
+    for parent in parents {
+        let DeValue::Table(child) = table.get(*parent)?.get_ref() else {
+            return None;
+        };
+        table = child;
+    }
+    table.get(*last).map(Spanned::span)
+}
+
+fn issue(contents: &str, span: Range<usize>, message: &str) -> Issue {
+    let before = &contents[..span.start.min(contents.len())];
+    let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+    Issue {
+        line: before.matches('\n').count() + 1,
+        column: before[line_start..].chars().count() + 1,
+        message: message.trim().to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_config_is_clean_and_complete() {
+        assert_eq!(check(DEFAULT_CONFIG), vec![]);
+        let defaults: Config = toml::from_str(DEFAULT_CONFIG).unwrap();
+        assert_eq!(defaults.tablet_buttons, Config::default().tablet_buttons);
+        assert_eq!(defaults.pressure.tablet.touch_threshold, 510);
+
+        // The commented-out settings are valid too, once turned on.
+        let enabled = toml::to_string(&schema()).unwrap();
+        assert_eq!(check(&enabled), vec![]);
+    }
+
+    #[test]
+    fn mistakes_are_reported_with_their_position() {
+        let contents = "mode = \"tablet\"\nnotifcations = false\n\n\
+                        [mouse_area]\nscale = 1.5\nancor = \"center\"\n\n\
+                        [profiles.krita]\nwindow_class = [\"krita\"]\ncolour = 1\n";
+        let issues: Vec<String> = check(contents).iter().map(Issue::to_string).collect();
+        assert_eq!(
+            issues,
+            [
+                "2:1: unknown key `notifcations`",
+                "5:9: mouse_area.scale must be between 0.1 and 1.0, not 1.5",
+                "6:1: unknown key `ancor` in [mouse_area]",
+                "10:1: unknown key `colour` in [profiles.krita]",
+            ]
+        );
+
+        let bad_key = check("[tablet_buttons]\n3 = \"ctrl+zz\"\n");
+        assert_eq!((bad_key.len(), bad_key[0].line), (1, 2));
+        assert!(bad_key[0].message.contains("zz"));
+        assert_eq!(check("[axes\n")[0].line, 1);
+    }
+}
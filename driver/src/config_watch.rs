@@ -0,0 +1,159 @@
+use signal_hook::consts::signal::SIGHUP;
+use signal_hook::iterator::Signals;
+use tracing::{debug, warn};
+
+use std::collections::HashMap;
+use std::ffi::{CString, OsStr};
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::mem;
+use std::os::fd::{AsRawFd, FromRawFd};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+
+// Editors either write the file in place or save a new one over it.
+const WATCHED_CHANGES: u32 = libc::IN_CLOSE_WRITE | libc::IN_MOVED_TO | libc::IN_CREATE;
+
+// Signals a reload whenever the config file is saved or SIGHUP arrives.
+pub fn spawn_config_watcher(path: PathBuf) -> Receiver<()> {
+    let (sender, receiver) = mpsc::channel();
+    let mut signals = Signals::new([SIGHUP]).expect("Error registering SIGHUP.");
+
+    let hangups = sender.clone();
+    thread::spawn(move || {
+        for _ in signals.forever() {
+            if hangups.send(()).is_err() {
+                return;
+            }
+        }
+    });
+
+    match ConfigInotify::watch(&path) {
+        Ok(inotify) => {
+            thread::spawn(move || inotify.forward_changes(&sender));
+        }
+        // Without a config, e.g. when running on the defaults, there is nothing to miss.
+        Err(err) if !path.exists() => debug!("Not watching {}: {}", path.display(), err),
+        Err(err) => warn!(
+            "Not watching {} for changes, reload it with SIGHUP: {}",
+            path.display(),
+            err
+        ),
+    }
+
+    receiver
+}
+
+// An inotify instance watching the directories the config file is in, since the
+// file itself may be replaced. A symlinked config is watched at its target as well.
+struct ConfigInotify {
+    file: File,
+    // File names to look out for, by watch descriptor.
+    names: HashMap<i32, Vec<Vec<u8>>>,
+}
+
+impl ConfigInotify {
+    fn watch(path: &Path) -> io::Result<Self> {
+        // SAFETY: plain inotify_init1(2), whose result is checked.
+        let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC) };
+        if fd == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut inotify = ConfigInotify {
+            // SAFETY: `fd` was just opened and belongs to nothing else.
+            file: unsafe { File::from_raw_fd(fd) },
+            names: HashMap::new(),
+        };
+        let target = fs::canonicalize(path).ok().filter(|target| target != path);
+        for path in [Some(path), target.as_deref()].into_iter().flatten() {
+            inotify.add(path)?;
+        }
+        Ok(inotify)
+    }
+
+    fn add(&mut self, path: &Path) -> io::Result<()> {
+        let (Some(dir), Some(name)) = (path.parent(), path.file_name()) else {
+            return Err(io::ErrorKind::InvalidInput.into());
+        };
+        let dir = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+        let dir = CString::new(dir.as_os_str().to_owned().into_vec())?;
+        let fd = self.file.as_raw_fd();
+        // SAFETY: `dir` is a NUL terminated path.
+        let wd = unsafe { libc::inotify_add_watch(fd, dir.as_ptr(), WATCHED_CHANGES) };
+        if wd == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        self.names.entry(wd).or_default().push(name.as_bytes().to_vec());
+        Ok(())
+    }
+
+    // Blocks on the inotify events, sending one reload per batch that touches the
+    // config file.
+    fn forward_changes(mut self, sender: &Sender<()>) {
+        let mut buffer = vec![0u8; 4096];
+        loop {
+            let length = match self.file.read(&mut buffer) {
+                Ok(length) => length,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => {
+                    warn!("Stopped watching the config file: {}", err);
+                    return;
+                }
+            };
+            let is_changed = changed_files(&buffer[..length]).any(|(wd, name)| {
+                self.names
+                    .get(&wd)
+                    .is_some_and(|names| names.iter().any(|watched| watched == name.as_bytes()))
+            });
+            if is_changed && sender.send(()).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+// The watch descriptor and file name of each struct inotify_event in `events`.
+fn changed_files(mut events: &[u8]) -> impl Iterator<Item = (i32, &OsStr)> {
+    std::iter::from_fn(move || {
+        const HEADER: usize = mem::size_of::<libc::inotify_event>();
+        let header = events.get(..HEADER)?;
+        let field =
+            |offset: usize| u32::from_ne_bytes(header[offset..offset + 4].try_into().unwrap());
+        // wd, mask, cookie, len
+        let (wd, length) = (field(0) as i32, field(12) as usize);
+        let name = events.get(HEADER..HEADER + length)?;
+        events = &events[HEADER + length..];
+        // The name is padded with NULs.
+        let end = name.iter().position(|&byte| byte == 0).unwrap_or(name.len());
+        Some((wd, OsStr::from_bytes(&name[..end])))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(wd: i32, name: &[u8]) -> Vec<u8> {
+        // NUL terminated and padded, as the kernel sends them.
+        let padded = (name.len() + 1).div_ceil(16) * 16;
+        let mut event = Vec::new();
+        for value in [wd as u32, libc::IN_CLOSE_WRITE, 0, padded as u32] {
+            event.extend_from_slice(&value.to_ne_bytes());
+        }
+        event.extend_from_slice(name);
+        event.resize(event.len() + padded - name.len(), 0);
+        event
+    }
+
+    #[test]
+    fn inotify_events_are_split_into_their_file_names() {
+        let events = [event(1, b".config.toml.swp"), event(2, b"config.toml")].concat();
+        let files: Vec<_> = changed_files(&events).collect();
+        assert_eq!(files, [(1, OsStr::new(".config.toml.swp")), (2, OsStr::new("config.toml"))]);
+
+        // A cut off event is dropped rather than misread.
+        assert_eq!(changed_files(&events[..events.len() - 4]).count(), 1);
+    }
+}
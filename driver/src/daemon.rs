@@ -0,0 +1,66 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+
+use crate::privileges::is_root;
+
+// /run/v1060p.pid when running as root, else $XDG_RUNTIME_DIR/v1060p.pid.
+pub fn pid_file_path() -> PathBuf {
+    match env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) if !dir.is_empty() && !is_root() => PathBuf::from(dir).join("v1060p.pid"),
+        _ => PathBuf::from("/run/v1060p.pid"),
+    }
+}
+
+// Holds the PID file of the running daemon and removes it when dropped.
+pub struct PidFile {
+    path: PathBuf,
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+// Pid of the instance that wrote `path`, if it is still running.
+pub fn running_instance(path: &Path) -> Option<libc::pid_t> {
+    let pid: libc::pid_t = fs::read_to_string(path).ok()?.trim().parse().ok()?;
+    // Signal 0 only checks that the process exists.
+    (unsafe { libc::kill(pid, 0) } == 0).then_some(pid)
+}
+
+// Forks into the background: the parent exits, and the child leaves the terminal's
+// session, drops its standard streams and records its pid in `path`. Must run before
+// any thread is started, as only the calling thread survives the fork.
+pub fn daemonize(path: &Path) -> io::Result<PidFile> {
+    if let Some(pid) = running_instance(path) {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("already running with pid {} ({})", pid, path.display()),
+        ));
+    }
+    // SAFETY: no other threads exist yet, so the child is in a consistent state.
+    match unsafe { libc::fork() } {
+        -1 => return Err(io::Error::last_os_error()),
+        0 => {}
+        _ => std::process::exit(0),
+    }
+    if unsafe { libc::setsid() } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let null = fs::File::options().read(true).write(true).open("/dev/null")?;
+    for fd in [libc::STDIN_FILENO, libc::STDOUT_FILENO, libc::STDERR_FILENO] {
+        if unsafe { libc::dup2(null.as_raw_fd(), fd) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    fs::write(path, format!("{}\n", std::process::id()))?;
+    Ok(PidFile {
+        path: path.to_path_buf(),
+    })
+}
@@ -0,0 +1,118 @@
+use std::collections::HashMap;
+use std::sync::mpsc::Sender;
+
+use zbus::blocking::Connection;
+use zbus::blocking::connection::Builder;
+use zbus::{fdo, interface};
+
+use crate::config::{Mode, ProfileSwitch};
+use crate::ipc::{self, Message, Request, Response, Status};
+use crate::pressure::MAX_PRESSURE;
+
+pub const BUS_NAME: &str = "org.feveal.VinsaDriver";
+pub const OBJECT_PATH: &str = "/org/feveal/VinsaDriver";
+
+// Forwards every call to the main loop, exactly like the control socket does.
+struct VinsaDriver {
+    requests: Sender<Message>,
+}
+
+impl VinsaDriver {
+    fn call(&self, request: Request) -> fdo::Result<()> {
+        match ipc::send_request(&self.requests, request) {
+            Response::Error(err) => Err(fdo::Error::Failed(err)),
+            _ => Ok(()),
+        }
+    }
+
+    fn status(&self) -> fdo::Result<Status> {
+        match ipc::send_request(&self.requests, Request::GetStatus { is_json: false }) {
+            Response::Status(status) => Ok(status),
+            Response::Error(err) => Err(fdo::Error::Failed(err)),
+            _ => Err(fdo::Error::Failed("unexpected reply".to_string())),
+        }
+    }
+}
+
+#[interface(name = "org.feveal.VinsaDriver")]
+impl VinsaDriver {
+    /// "mouse" or "tablet".
+    #[zbus(property)]
+    fn mode(&self) -> fdo::Result<String> {
+        let status = self.status()?;
+        Ok(if status.is_mouse_mode { "mouse" } else { "tablet" }.to_string())
+    }
+
+    #[zbus(property)]
+    fn mouse_area_scale(&self) -> fdo::Result<f64> {
+        Ok(self.status()?.mouse_area_scale as f64)
+    }
+
+    /// Range of the pressure values reported by the virtual pen.
+    #[zbus(property)]
+    fn pressure_range(&self) -> (i32, i32) {
+        (0, MAX_PRESSURE)
+    }
+
+    /// Active profile, or "default".
+    #[zbus(property)]
+    fn profile(&self) -> fdo::Result<String> {
+        Ok(self.status()?.profile.unwrap_or_else(|| "default".to_string()))
+    }
+
+    /// Totals since the driver started: "reports", "malformed" (dropped),
+    /// "emit_errors" and "reconnects".
+    #[zbus(property)]
+    fn statistics(&self) -> fdo::Result<HashMap<String, u64>> {
+        let counters = self.status()?.counters;
+        Ok(HashMap::from([
+            ("reports".to_string(), counters.reports),
+            ("malformed".to_string(), counters.malformed),
+            ("emit_errors".to_string(), counters.emit_errors),
+            ("reconnects".to_string(), counters.reconnects),
+        ]))
+    }
+
+    fn set_mode(&self, mode: &str) -> fdo::Result<()> {
+        let mode = match mode {
+            "mouse" => Mode::Mouse,
+            "tablet" => Mode::Tablet,
+            _ => return Err(fdo::Error::InvalidArgs("expected mouse or tablet".to_string())),
+        };
+        self.call(Request::SetMode(mode))
+    }
+
+    fn toggle_mode(&self) -> fdo::Result<()> {
+        self.call(Request::ToggleMode)
+    }
+
+    fn set_mouse_area_scale(&self, scale: f64) -> fdo::Result<()> {
+        if !(0.1..=1.0).contains(&scale) {
+            return Err(fdo::Error::InvalidArgs("expected 0.1 to 1.0".to_string()));
+        }
+        self.call(Request::SetArea(scale as f32))
+    }
+
+    /// Selects a profile by name; "default" returns to the top-level settings.
+    fn set_profile(&self, name: &str) -> fdo::Result<()> {
+        self.call(Request::SwitchProfile(ProfileSwitch::select(name)))
+    }
+
+    /// Switches to the next profile by name, after the last back to the default.
+    fn next_profile(&self) -> fdo::Result<()> {
+        self.call(Request::SwitchProfile(ProfileSwitch::Next))
+    }
+}
+
+// Uses the session bus when there is one, else the system bus (which needs a
+// D-Bus policy allowing the driver to own the name).
+pub fn spawn_dbus_service(requests: Sender<Message>) -> zbus::Result<Connection> {
+    let serve = |builder: Builder<'static>, requests: Sender<Message>| {
+        builder
+            .name(BUS_NAME)?
+            .serve_at(OBJECT_PATH, VinsaDriver { requests })?
+            .build()
+    };
+
+    serve(Builder::session()?, requests.clone()).or_else(|_| serve(Builder::system()?, requests))
+}
@@ -0,0 +1,351 @@
+// Minimal HID report descriptor parser: finds where the pen position, pressure and
+// buttons sit in the input report, so firmware revisions that move them still work.
+
+use std::collections::HashMap;
+
+const GENERIC_DESKTOP: u16 = 0x01;
+const BUTTON: u16 = 0x09;
+const DIGITIZER: u16 = 0x0d;
+
+const USAGE_X: u16 = 0x30;
+const USAGE_Y: u16 = 0x31;
+const USAGE_TIP_PRESSURE: u16 = 0x30;
+const USAGE_IN_RANGE: u16 = 0x32;
+const USAGE_BARREL_SWITCH: u16 = 0x44;
+const USAGE_SECONDARY_BARREL_SWITCH: u16 = 0x5a;
+const USAGE_X_TILT: u16 = 0x3d;
+const USAGE_Y_TILT: u16 = 0x3e;
+
+// A field of `size` bits starting `offset` bits into the report, report ID included.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Field {
+    pub offset: usize,
+    pub size: usize,
+}
+
+impl Field {
+    // HID fields are little-endian and need not be byte-aligned. Bits past the end of
+    // a short report read as 0.
+    pub fn read(self, data: &[u8]) -> u32 {
+        (0..self.size.min(32)).fold(0, |value, bit| {
+            let position = self.offset + bit;
+            let is_set = data
+                .get(position / 8)
+                .is_some_and(|byte| byte & (1 << (position % 8)) != 0);
+            value | (is_set as u32) << bit
+        })
+    }
+
+    // Tilt and other signed fields are two's complement.
+    pub fn read_signed(self, data: &[u8]) -> i32 {
+        let size = self.size.clamp(1, 32) as u32;
+        let shift = 32 - size;
+        ((self.read(data) << shift) as i32) >> shift
+    }
+}
+
+// The Logical Minimum and Maximum of a field: the values it reports, end to end.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LogicalRange {
+    pub min: i32,
+    pub max: i32,
+}
+
+impl LogicalRange {
+    // Where `value` sits in the range, as 0..=`onto`.
+    pub fn scale(self, value: i32, onto: i32) -> i32 {
+        let span = (self.max as i64 - self.min as i64).max(1);
+        ((value as i64 - self.min as i64).clamp(0, span) * onto as i64 / span) as i32
+    }
+
+    // Reads `field`, signed if the range goes below 0.
+    pub fn read(self, field: Field, data: &[u8]) -> i32 {
+        match self.min < 0 {
+            true => field.read_signed(data),
+            false => field.read(data) as i32,
+        }
+    }
+}
+
+// Location of the fields the driver uses, in the input report that carries X and Y.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ReportLayout {
+    pub report_id: Option<u8>,
+    pub x: Option<Field>,
+    pub y: Option<Field>,
+    pub pressure: Option<Field>,
+    // Ranges of the fields above; None where the descriptor gives none.
+    pub x_range: Option<LogicalRange>,
+    pub y_range: Option<LogicalRange>,
+    pub pressure_range: Option<LogicalRange>,
+    pub in_range: Option<Field>,
+    pub barrel: Option<Field>,
+    pub secondary_barrel: Option<Field>,
+    pub x_tilt: Option<Field>,
+    pub y_tilt: Option<Field>,
+    // Tablet buttons as consecutive bits, button 1 first.
+    pub buttons: Option<Field>,
+    // Bytes in the report, its ID included.
+    pub length: usize,
+    // Every input report the descriptor declares; empty if it doesn't number them.
+    pub report_ids: ReportIds,
+}
+
+// A set of report IDs, kept Copy like the layout holding it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ReportIds([u64; 4]);
+
+impl ReportIds {
+    pub fn insert(&mut self, id: u8) {
+        self.0[id as usize / 64] |= 1 << (id % 64);
+    }
+
+    pub fn contains(&self, id: u8) -> bool {
+        self.0[id as usize / 64] & (1 << (id % 64)) != 0
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct GlobalState {
+    usage_page: u16,
+    report_size: usize,
+    report_count: usize,
+    report_id: Option<u8>,
+    logical_minimum: i32,
+    // Unsigned unless the minimum is negative, so kept with its size in bytes.
+    logical_maximum: (u32, usize),
+}
+
+impl GlobalState {
+    fn logical_range(&self) -> Option<LogicalRange> {
+        let (value, size) = self.logical_maximum;
+        let max = match self.logical_minimum < 0 {
+            true => sign_extend(value, size),
+            false => value.min(i32::MAX as u32) as i32,
+        };
+        (max > self.logical_minimum).then_some(LogicalRange {
+            min: self.logical_minimum,
+            max,
+        })
+    }
+}
+
+fn sign_extend(value: u32, size: usize) -> i32 {
+    let shift = 32 - 8 * size.clamp(1, 4) as u32;
+    ((value << shift) as i32) >> shift
+}
+
+struct InputField {
+    report_id: Option<u8>,
+    usage_page: u16,
+    usage: u16,
+    field: Field,
+    range: Option<LogicalRange>,
+}
+
+// None if the descriptor has no input report with both X and Y, e.g. on the vendor
+// interface of the VINSA; callers then fall back to the model's fixed offsets.
+pub fn parse(descriptor: &[u8]) -> Option<ReportLayout> {
+    let (fields, lengths) = input_fields(descriptor);
+    let is_position = |usage| {
+        move |field: &&InputField| field.usage_page == GENERIC_DESKTOP && field.usage == usage
+    };
+    let x = fields.iter().find(is_position(USAGE_X))?;
+    let report_id = x.report_id;
+    let in_report: Vec<&InputField> = fields
+        .iter()
+        .filter(|field| field.report_id == report_id)
+        .collect();
+    let find_field = |usage_page: u16, usage: u16| {
+        in_report
+            .iter()
+            .find(|field| field.usage_page == usage_page && field.usage == usage)
+    };
+    let find = |usage_page: u16, usage: u16| find_field(usage_page, usage).map(|field| field.field);
+    let y = find_field(GENERIC_DESKTOP, USAGE_Y)?;
+    let pressure = find_field(DIGITIZER, USAGE_TIP_PRESSURE);
+
+    let buttons: Vec<Field> = in_report
+        .iter()
+        .filter(|field| field.usage_page == BUTTON)
+        .map(|field| field.field)
+        .collect();
+    let buttons = buttons.first().map(|first| Field {
+        offset: first.offset,
+        size: buttons.len().min(16),
+    });
+
+    let mut report_ids = ReportIds::default();
+    lengths.keys().flatten().for_each(|&id| report_ids.insert(id));
+    Some(ReportLayout {
+        report_id,
+        x: Some(x.field),
+        y: Some(y.field),
+        pressure: pressure.map(|field| field.field),
+        x_range: x.range,
+        y_range: y.range,
+        pressure_range: pressure.and_then(|field| field.range),
+        in_range: find(DIGITIZER, USAGE_IN_RANGE),
+        barrel: find(DIGITIZER, USAGE_BARREL_SWITCH),
+        secondary_barrel: find(DIGITIZER, USAGE_SECONDARY_BARREL_SWITCH),
+        x_tilt: find(DIGITIZER, USAGE_X_TILT),
+        y_tilt: find(DIGITIZER, USAGE_Y_TILT),
+        buttons,
+        length: lengths.get(&report_id).map_or(0, |bits| bits.div_ceil(8)),
+        report_ids,
+    })
+}
+
+// Every variable input field with its usage and position in its report, and the
+// length in bits of each input report.
+fn input_fields(descriptor: &[u8]) -> (Vec<InputField>, HashMap<Option<u8>, usize>) {
+    let mut fields = Vec::new();
+    let mut global = GlobalState::default();
+    let mut global_stack = Vec::new();
+    let mut usages: Vec<(u16, u16)> = Vec::new();
+    let mut usage_minimum = None;
+    let mut usage_maximum = None;
+    // Bits used so far in each report.
+    let mut offsets: HashMap<Option<u8>, usize> = HashMap::new();
+
+    let mut position = 0;
+    while let Some(&prefix) = descriptor.get(position) {
+        // Long items carry their size in the next byte; nothing standard uses them.
+        if prefix == 0xfe {
+            let size = descriptor.get(position + 1).copied().unwrap_or(0) as usize;
+            position += 3 + size;
+            continue;
+        }
+        let size = match prefix & 0x03 {
+            3 => 4,
+            size => size as usize,
+        };
+        let Some(bytes) = descriptor.get(position + 1..position + 1 + size) else {
+            break;
+        };
+        position += 1 + size;
+        let value = bytes
+            .iter()
+            .rev()
+            .fold(0u32, |value, &byte| value << 8 | byte as u32);
+
+        match (prefix >> 2) & 0x03 {
+            // Main items
+            0 => {
+                if prefix >> 4 == 0x8 {
+                    // The report ID takes up the first byte of its report.
+                    let first_bit = if global.report_id.is_some() { 8 } else { 0 };
+                    let offset = offsets.entry(global.report_id).or_insert(first_bit);
+                    let is_constant = value & 0x01 != 0;
+                    let is_variable = value & 0x02 != 0;
+                    if !is_constant && is_variable {
+                        if let (Some(min), Some(max)) = (usage_minimum, usage_maximum) {
+                            usages.extend((min..=max).map(|usage| (global.usage_page, usage)));
+                        }
+                        for index in 0..global.report_count {
+                            let Some(&(usage_page, usage)) = usages.get(index).or(usages.last())
+                            else {
+                                break;
+                            };
+                            fields.push(InputField {
+                                report_id: global.report_id,
+                                usage_page,
+                                usage,
+                                field: Field {
+                                    offset: *offset + index * global.report_size,
+                                    size: global.report_size,
+                                },
+                                range: global.logical_range(),
+                            });
+                        }
+                    }
+                    *offset += global.report_count * global.report_size;
+                }
+                usages.clear();
+                usage_minimum = None;
+                usage_maximum = None;
+            }
+            // Global items
+            1 => match prefix >> 4 {
+                0x0 => global.usage_page = value as u16,
+                0x1 => global.logical_minimum = sign_extend(value, size),
+                0x2 => global.logical_maximum = (value, size),
+                0x7 => global.report_size = value as usize,
+                0x8 => global.report_id = Some(value as u8),
+                0x9 => global.report_count = value as usize,
+                0xa => global_stack.push(global),
+                0xb => global = global_stack.pop().unwrap_or(global),
+                _ => {}
+            },
+            // Local items; a 4-byte usage names its own page in the high half.
+            2 => {
+                let page = match size {
+                    4 => (value >> 16) as u16,
+                    _ => global.usage_page,
+                };
+                match prefix >> 4 {
+                    0x0 => usages.push((page, value as u16)),
+                    0x1 => usage_minimum = Some(value as u16),
+                    0x2 => usage_maximum = Some(value as u16),
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+    (fields, offsets)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    // A pen report (ID 10): tip, barrel, secondary barrel and in-range bits, 4 bits of
+    // padding, 16-bit X and Y, 16-bit pressure, then 8 tablet buttons.
+    pub(crate) const PEN_DESCRIPTOR: &[u8] = &[
+        0x05, 0x0d, 0x09, 0x02, 0xa1, 0x01, 0x85, 0x0a, 0x09, 0x20, 0xa1, 0x00, 0x09, 0x42, 0x09,
+        0x44, 0x09, 0x5a, 0x09, 0x32, 0x15, 0x00, 0x25, 0x01, 0x75, 0x01, 0x95, 0x04, 0x81, 0x02,
+        0x95, 0x04, 0x81, 0x03, 0x05, 0x01, 0x09, 0x30, 0x09, 0x31, 0x26, 0xff, 0x7f, 0x75, 0x10,
+        0x95, 0x02, 0x81, 0x02, 0x05, 0x0d, 0x09, 0x30, 0x26, 0xff, 0x1f, 0x75, 0x10, 0x95, 0x01,
+        0x81, 0x02, 0x05, 0x09, 0x19, 0x01, 0x29, 0x08, 0x25, 0x01, 0x75, 0x01, 0x95, 0x08, 0x81,
+        0x02, 0xc0, 0xc0,
+    ];
+
+    #[test]
+    fn pen_fields_are_located() {
+        let layout = parse(PEN_DESCRIPTOR).unwrap();
+        let field = |offset, size| Some(Field { offset, size });
+        assert_eq!(layout.report_id, Some(10));
+        assert_eq!(layout.barrel, field(9, 1));
+        assert_eq!(layout.in_range, field(11, 1));
+        assert_eq!(layout.x, field(16, 16));
+        assert_eq!(layout.y, field(32, 16));
+        assert_eq!(layout.pressure, field(48, 16));
+        assert_eq!(layout.x_range, Some(LogicalRange { min: 0, max: 0x7fff }));
+        assert_eq!(layout.pressure_range, Some(LogicalRange { min: 0, max: 0x1fff }));
+        assert_eq!(layout.buttons, field(64, 8));
+        assert_eq!(layout.length, 9);
+        assert!(layout.report_ids.contains(10) && !layout.report_ids.contains(11));
+
+        let report = [0x0a, 0x0a, 0x34, 0x12, 0x78, 0x56, 0x00, 0x01, 0x05];
+        assert_eq!(layout.x.unwrap().read(&report), 0x1234);
+        assert_eq!(layout.pressure.unwrap().read(&report), 0x0100);
+        assert_eq!(layout.in_range.unwrap().read(&report), 1);
+        assert_eq!(layout.buttons.unwrap().read(&report), 0x05);
+        assert_eq!(layout.x_range.unwrap().scale(0x7fff, 4096), 4096);
+
+        // A 1-byte maximum of 0xff is 255 after a minimum of 0, and -1 after -127.
+        let mut global = GlobalState {
+            logical_maximum: (0xff, 1),
+            ..GlobalState::default()
+        };
+        assert_eq!(global.logical_range(), Some(LogicalRange { min: 0, max: 255 }));
+        global.logical_minimum = -127;
+        assert_eq!(global.logical_range(), Some(LogicalRange { min: -127, max: -1 }));
+        // A vendor-defined report has no position to find.
+        assert_eq!(
+            parse(&[0x06, 0x00, 0xff, 0x09, 0x01, 0xa1, 0x01, 0xc0]),
+            None
+        );
+    }
+}
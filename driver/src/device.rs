@@ -0,0 +1,131 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::protocol::Protocol;
+use crate::vendor_init::{self, InitStep};
+use crate::{PID, VID};
+
+// Tablets this driver knows how to talk to. `interface` is the USB interface that
+// sends the vendor reports (and receives the full-mode report).
+pub struct KnownDevice {
+    pub vid: u16,
+    pub pid: u16,
+    pub interface: u8,
+    pub name: &'static str,
+    pub protocol: Protocol,
+    // Handshake to send when the tablet is opened.
+    pub init: &'static [InitStep],
+}
+
+pub const KNOWN_DEVICES: &[KnownDevice] = &[KnownDevice {
+    vid: VID,
+    pid: PID,
+    interface: 2,
+    name: "VINSA 1060 Plus",
+    protocol: Protocol::Vinsa,
+    init: vendor_init::VINSA_1060_PLUS,
+}];
+
+pub fn known_device(vid: u16, pid: u16) -> Option<&'static KnownDevice> {
+    KNOWN_DEVICES
+        .iter()
+        .find(|device| device.vid == vid && device.pid == pid)
+}
+
+// A /dev/hidraw* node belonging to a known tablet, with the USB device behind it.
+#[derive(Debug, PartialEq, Eq)]
+pub struct HidrawNode {
+    pub path: PathBuf,
+    pub vid: u16,
+    pub pid: u16,
+    pub interface: u8,
+    pub bus: u8,
+    pub address: u8,
+}
+
+const HIDRAW_CLASS: &str = "/sys/class/hidraw";
+
+// Every hidraw node of a known tablet, on the interface that carries its reports.
+pub fn scan_hidraw() -> Vec<HidrawNode> {
+    let Ok(entries) = fs::read_dir(HIDRAW_CLASS) else {
+        return Vec::new();
+    };
+    let mut nodes: Vec<HidrawNode> = entries
+        .flatten()
+        .filter_map(|entry| hidraw_node(Path::new(&entry.file_name())))
+        .filter(|node| {
+            known_device(node.vid, node.pid).is_some_and(|known| known.interface == node.interface)
+        })
+        .collect();
+    nodes.sort_by(|a, b| a.path.cmp(&b.path));
+    nodes
+}
+
+pub fn is_hidraw_path(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| name.starts_with("hidraw"))
+}
+
+// Looks up a hidraw node ("hidraw3" or "/dev/hidraw3") in sysfs.
+pub fn hidraw_node(path: &Path) -> Option<HidrawNode> {
+    let name = path.file_name()?.to_str()?;
+    let hid_device = fs::canonicalize(Path::new(HIDRAW_CLASS).join(name).join("device")).ok()?;
+    let (vid, pid) = parse_hid_id(&fs::read_to_string(hid_device.join("uevent")).ok()?)?;
+
+    // .../1-2/1-2:1.2/0003:08F2:6811.0003: the HID device sits under the USB
+    // interface, which sits under the USB device.
+    let usb_interface = hid_device.parent()?;
+    let interface = parse_interface_number(usb_interface.file_name()?.to_str()?)?;
+    let usb_device = usb_interface.parent()?;
+    let read_number = |file: &str| -> Option<u8> {
+        fs::read_to_string(usb_device.join(file))
+            .ok()?
+            .trim()
+            .parse()
+            .ok()
+    };
+
+    Some(HidrawNode {
+        path: Path::new("/dev").join(name),
+        vid,
+        pid,
+        interface,
+        bus: read_number("busnum")?,
+        address: read_number("devnum")?,
+    })
+}
+
+// The `HID_ID=0003:000008F2:00006811` line of a HID device's uevent file.
+fn parse_hid_id(uevent: &str) -> Option<(u16, u16)> {
+    let id = uevent
+        .lines()
+        .find_map(|line| line.strip_prefix("HID_ID="))?;
+    let mut fields = id.split(':').skip(1);
+    let vid = u32::from_str_radix(fields.next()?, 16).ok()?;
+    let pid = u32::from_str_radix(fields.next()?, 16).ok()?;
+    Some((u16::try_from(vid).ok()?, u16::try_from(pid).ok()?))
+}
+
+// USB interface directories are named BUS-PORT:CONFIG.INTERFACE, e.g. "1-2:1.2".
+fn parse_interface_number(name: &str) -> Option<u8> {
+    let (_, config_and_interface) = name.split_once(':')?;
+    config_and_interface.split_once('.')?.1.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sysfs_names_are_parsed() {
+        let uevent = "DRIVER=hid-generic\nHID_ID=0003:000008F2:00006811\nHID_NAME=UGTABLET\n";
+        assert_eq!(parse_hid_id(uevent), Some((0x08f2, 0x6811)));
+        assert_eq!(parse_hid_id("HID_NAME=x\n"), None);
+        assert_eq!(parse_interface_number("1-2:1.2"), Some(2));
+        assert_eq!(parse_interface_number("usb1"), None);
+        assert!(known_device(0x08f2, 0x6811).is_some());
+        assert!(is_hidraw_path(Path::new("/dev/hidraw3")));
+        assert!(!is_hidraw_path(Path::new("/dev/bus/usb/001/004")));
+    }
+}
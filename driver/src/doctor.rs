@@ -0,0 +1,276 @@
+use rusb::Error as RusbError;
+
+use std::env;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::ErrorKind;
+use std::path::Path;
+
+use crate::config::Config;
+use crate::device::{self, HidrawNode};
+use crate::grab;
+use crate::ipc;
+use crate::physical_device::PhysicalDevice;
+
+const UINPUT_PATH: &str = "/dev/uinput";
+const UDEV_FIX: &str = "run `sudo v1060p install-udev`, add yourself to the `input` group \
+                        and log in again";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Ok,
+    Warning,
+    Problem,
+}
+
+// The result of one check, with what to do about it when it isn't fine.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Check {
+    pub name: &'static str,
+    pub outcome: Outcome,
+    pub detail: String,
+    pub fix: Option<String>,
+}
+
+impl Check {
+    fn ok(name: &'static str, detail: impl Into<String>) -> Self {
+        Check {
+            name,
+            outcome: Outcome::Ok,
+            detail: detail.into(),
+            fix: None,
+        }
+    }
+
+    fn warning(name: &'static str, detail: impl Into<String>, fix: impl Into<String>) -> Self {
+        Check {
+            name,
+            outcome: Outcome::Warning,
+            detail: detail.into(),
+            fix: Some(fix.into()),
+        }
+    }
+
+    fn problem(name: &'static str, detail: impl Into<String>, fix: impl Into<String>) -> Self {
+        Check {
+            name,
+            outcome: Outcome::Problem,
+            detail: detail.into(),
+            fix: Some(fix.into()),
+        }
+    }
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let label = match self.outcome {
+            Outcome::Ok => "ok",
+            Outcome::Warning => "warn",
+            Outcome::Problem => "FAIL",
+        };
+        write!(f, "[{:>4}] {}: {}", label, self.name, self.detail)?;
+        if let Some(fix) = &self.fix {
+            write!(f, "\n       fix: {}", fix)?;
+        }
+        Ok(())
+    }
+}
+
+// Everything the driver needs from the system, in the order it needs it.
+pub fn run(config: &Config, device_path: Option<&Path>) -> Vec<Check> {
+    let nodes = device::scan_hidraw();
+    let mut checks = vec![check_uinput(), check_tablet(device_path)];
+    checks.push(check_hidraw(&nodes));
+    checks.extend(check_kernel_drivers(&nodes));
+    checks.push(check_kernel_input(config, device_path));
+    checks.push(check_running_driver());
+    checks.push(check_session(
+        env::var("XDG_SESSION_TYPE").ok().as_deref(),
+        env::var_os("WAYLAND_DISPLAY").is_some(),
+        env::var_os("DISPLAY").is_some(),
+        env::var_os("SWAYSOCK").is_some() || env::var_os("HYPRLAND_INSTANCE_SIGNATURE").is_some(),
+    ));
+    checks
+}
+
+fn check_uinput() -> Check {
+    const NAME: &str = "uinput";
+    match OpenOptions::new().write(true).open(UINPUT_PATH) {
+        Ok(_) => Check::ok(NAME, format!("{} is writable", UINPUT_PATH)),
+        Err(err) if err.kind() == ErrorKind::NotFound => Check::problem(
+            NAME,
+            format!("{} does not exist", UINPUT_PATH),
+            "run `sudo modprobe uinput`, and add `uinput` to /etc/modules-load.d/ to keep it",
+        ),
+        Err(err) if err.kind() == ErrorKind::PermissionDenied => Check::problem(
+            NAME,
+            format!("{} is not writable by this user", UINPUT_PATH),
+            UDEV_FIX,
+        ),
+        Err(err) => Check::problem(NAME, format!("cannot open {}: {}", UINPUT_PATH, err), UDEV_FIX),
+    }
+}
+
+fn check_tablet(device_path: Option<&Path>) -> Check {
+    const NAME: &str = "tablet";
+    // rusb panics on first use when libusb cannot start, so try it on its own first.
+    if let Err(err) = rusb::Context::new() {
+        return Check::problem(
+            NAME,
+            format!("libusb cannot start: {}", err),
+            "check that /dev/bus/usb exists; containers and VMs need the USB bus passed in",
+        );
+    }
+    let device = match PhysicalDevice::locate(device_path) {
+        Ok(device) => device,
+        Err(RusbError::NoDevice) => {
+            return Check::problem(
+                NAME,
+                "no known tablet is connected",
+                "plug it in and check that `lsusb` lists 08f2:6811; try another cable or port",
+            );
+        }
+        Err(err) => {
+            return Check::problem(NAME, format!("cannot list USB devices: {}", err), UDEV_FIX);
+        }
+    };
+    let location = format!("bus {:03} address {:03}", device.bus_number(), device.address());
+    match device.open() {
+        Ok(_) => Check::ok(NAME, format!("found on {} and can be opened", location)),
+        Err(RusbError::Access) => Check::problem(
+            NAME,
+            format!("found on {} but this user cannot open it", location),
+            UDEV_FIX,
+        ),
+        Err(err) => Check::problem(
+            NAME,
+            format!("found on {} but cannot be opened: {}", location, err),
+            "unplug and replug it",
+        ),
+    }
+}
+
+fn check_hidraw(nodes: &[HidrawNode]) -> Check {
+    const NAME: &str = "hidraw";
+    if nodes.is_empty() {
+        // The driver detaches the kernel's HID driver, which takes the nodes with it.
+        return Check::ok(NAME, "no hidraw node (normal while the driver runs)");
+    }
+    let unreadable: Vec<String> = nodes
+        .iter()
+        .filter(|node| File::open(&node.path).is_err())
+        .map(|node| node.path.display().to_string())
+        .collect();
+    if unreadable.is_empty() {
+        let paths: Vec<String> = nodes.iter().map(|node| node.path.display().to_string()).collect();
+        Check::ok(NAME, format!("{} readable", paths.join(", ")))
+    } else {
+        Check::warning(NAME, format!("{} not readable", unreadable.join(", ")), UDEV_FIX)
+    }
+}
+
+// The kernel driver bound to each hidraw node. hid-generic is expected and
+// detached on start; a tablet driver such as hid-uclogic remaps the reports first.
+fn check_kernel_drivers(nodes: &[HidrawNode]) -> Vec<Check> {
+    nodes
+        .iter()
+        .filter_map(|node| {
+            let name = node.path.file_name()?;
+            let sysfs = Path::new("/sys/class/hidraw").join(name);
+            let link = fs::read_link(sysfs.join("device/driver")).ok()?;
+            let driver = link.file_name()?.to_string_lossy().into_owned();
+            Some(kernel_driver_check(&node.path.display().to_string(), &driver))
+        })
+        .collect()
+}
+
+fn kernel_driver_check(node: &str, driver: &str) -> Check {
+    const NAME: &str = "kernel driver";
+    if driver == "hid-generic" {
+        return Check::ok(NAME, format!("{} uses hid-generic", node));
+    }
+    Check::warning(
+        NAME,
+        format!("{} is bound to {}, which may change the tablet's reports", node, driver),
+        format!(
+            "blacklist it with `echo blacklist {} | sudo tee /etc/modprobe.d/v1060p.conf`",
+            driver.replace('-', "_")
+        ),
+    )
+}
+
+// Input nodes the kernel made for the tablet's other interfaces, which send a
+// second cursor unless grabbed.
+fn check_kernel_input(config: &Config, device_path: Option<&Path>) -> Check {
+    const NAME: &str = "kernel input";
+    let device = rusb::Context::new().and_then(|_| PhysicalDevice::locate(device_path));
+    let Ok(device) = device else {
+        return Check::ok(NAME, "no tablet to check");
+    };
+    let nodes = grab::kernel_input_nodes((device.bus_number(), device.address()));
+    if nodes.is_empty() || config.grab_kernel_devices {
+        return Check::ok(NAME, format!("{} kernel input node(s), none in the way", nodes.len()));
+    }
+    let paths: Vec<String> = nodes.iter().map(|path| path.display().to_string()).collect();
+    Check::warning(
+        NAME,
+        format!("{} also send events, which can move the cursor twice", paths.join(", ")),
+        "set `grab_kernel_devices = true` in the config",
+    )
+}
+
+fn check_running_driver() -> Check {
+    const NAME: &str = "driver";
+    let path = ipc::socket_path();
+    match ipc::query(&path, "get-status") {
+        Ok(reply) => Check::ok(NAME, format!("running: {}", reply)),
+        Err(_) => Check::ok(NAME, format!("not running (no answer on {})", path.display())),
+    }
+}
+
+// Output mapping, focus following and per-app profiles ask sway or Hyprland on
+// Wayland, and xrandr on X11.
+fn check_session(
+    session_type: Option<&str>,
+    has_wayland_display: bool,
+    has_x_display: bool,
+    has_known_compositor: bool,
+) -> Check {
+    const NAME: &str = "session";
+    let is_wayland = session_type == Some("wayland") || has_wayland_display;
+    if is_wayland && has_known_compositor {
+        Check::ok(NAME, "Wayland (sway or Hyprland)")
+    } else if is_wayland {
+        Check::warning(
+            NAME,
+            "Wayland with a compositor the driver cannot ask for monitors or windows",
+            "map the tablet to a monitor in the compositor's settings instead of `output`",
+        )
+    } else if session_type == Some("x11") || has_x_display {
+        Check::ok(NAME, "X11")
+    } else {
+        Check::ok(NAME, "no graphical session (fine for a service)")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn session_and_kernel_driver_checks() {
+        let outcome = |check: Check| check.outcome;
+        assert_eq!(outcome(check_session(Some("wayland"), true, true, true)), Outcome::Ok);
+        assert_eq!(outcome(check_session(Some("wayland"), true, false, false)), Outcome::Warning);
+        assert_eq!(outcome(check_session(None, false, true, false)), Outcome::Ok);
+        let tty = check_session(Some("tty"), false, false, false);
+        assert_eq!(tty.detail, "no graphical session (fine for a service)");
+
+        assert_eq!(outcome(kernel_driver_check("/dev/hidraw3", "hid-generic")), Outcome::Ok);
+        let uclogic = kernel_driver_check("/dev/hidraw3", "hid-uclogic");
+        assert_eq!(uclogic.outcome, Outcome::Warning);
+        assert!(uclogic.fix.unwrap().contains("blacklist hid_uclogic"));
+        let line = check_session(Some("wayland"), true, false, false).to_string();
+        assert!(line.starts_with("[warn] session: ") && line.contains("\n       fix: "));
+    }
+}
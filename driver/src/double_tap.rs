@@ -0,0 +1,113 @@
+use serde::Deserialize;
+
+use std::time::{Duration, Instant};
+
+// Helps double-clicking with the pen in mouse mode: a tap soon after another one and
+// close to it lands exactly where the first did, so the desktop sees a double-click
+// rather than two clicks a few pixels apart.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct DoubleTapConfig {
+    pub enabled: bool,
+    // The longest a tap may last, and the longest wait for the next tap after it.
+    pub time_ms: u64,
+    // How far apart, in tablet units (4096 across), the taps may be.
+    pub distance: f32,
+}
+
+impl Default for DoubleTapConfig {
+    fn default() -> Self {
+        DoubleTapConfig {
+            enabled: false,
+            time_ms: 300,
+            distance: 60.0,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct DoubleTap {
+    // Where and when the current touch started.
+    down: Option<((i32, i32), Instant)>,
+    // Where the last tap clicked and when it was lifted.
+    last_tap: Option<((i32, i32), Instant)>,
+    // Where the current touch is pinned to, while it stays close to it.
+    pinned: Option<(i32, i32)>,
+}
+
+impl DoubleTap {
+    // The position to send for the pen at `position`.
+    pub fn update(
+        &mut self,
+        config: &DoubleTapConfig,
+        position: (i32, i32),
+        is_touching: bool,
+        now: Instant,
+    ) -> (i32, i32) {
+        if !config.enabled {
+            return position;
+        }
+        let time = Duration::from_millis(config.time_ms);
+        let is_close = |to: (i32, i32)| {
+            ((position.0 - to.0) as f32).hypot((position.1 - to.1) as f32) <= config.distance
+        };
+
+        if !is_touching {
+            let pinned = self.pinned.take();
+            if let Some((start, at)) = self.down.take() {
+                let clicked_at = pinned.unwrap_or(start);
+                let is_tap = now.duration_since(at) <= time && is_close(clicked_at);
+                self.last_tap = is_tap.then_some((clicked_at, now));
+            }
+            return pinned.unwrap_or(position);
+        }
+
+        if self.down.is_none() {
+            self.down = Some((position, now));
+            self.pinned = self
+                .last_tap
+                .take()
+                .filter(|&(tap, at)| now.duration_since(at) <= time && is_close(tap))
+                .map(|(tap, _)| tap);
+        }
+        // Moving away makes it a drag, which goes where the pen is.
+        self.pinned = self.pinned.filter(|&pinned| is_close(pinned));
+        self.pinned.unwrap_or(position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn second_tap_lands_on_the_first() {
+        let config = DoubleTapConfig {
+            enabled: true,
+            ..DoubleTapConfig::default()
+        };
+        let mut double_tap = DoubleTap::default();
+        let start = Instant::now();
+        let mut at = |ms, position, is_touching| {
+            double_tap.update(&config, position, is_touching, start + Duration::from_millis(ms))
+        };
+
+        assert_eq!(at(0, (2000, 2000), true), (2000, 2000));
+        assert_eq!(at(80, (2005, 2000), false), (2005, 2000));
+        assert_eq!(at(200, (2030, 2020), true), (2000, 2000));
+        assert_eq!(at(260, (2035, 2020), false), (2000, 2000));
+
+        // A third tap makes a triple-click; one too late or too far is left alone.
+        assert_eq!(at(400, (1980, 2010), true), (2000, 2000));
+        assert_eq!(at(450, (1980, 2010), false), (2000, 2000));
+        assert_eq!(at(900, (2010, 2000), true), (2010, 2000));
+        assert_eq!(at(950, (2010, 2000), false), (2010, 2000));
+        assert_eq!(at(1000, (2200, 2000), true), (2200, 2000));
+        assert_eq!(at(1050, (2200, 2000), false), (2200, 2000));
+
+        // Dragging away from the pinned spot follows the pen.
+        assert_eq!(at(1100, (2210, 2000), true), (2200, 2000));
+        assert_eq!(at(1150, (2400, 2000), true), (2400, 2000));
+        assert_eq!(at(1160, (2210, 2000), true), (2210, 2000));
+    }
+}
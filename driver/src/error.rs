@@ -0,0 +1,12 @@
+use thiserror::Error;
+
+// Everything that can go wrong while talking to the tablet or the virtual devices.
+#[derive(Debug, Error)]
+pub enum DriverError {
+    #[error("USB error: {0}")]
+    Usb(#[from] rusb::Error),
+    #[error("uinput error: {0}")]
+    Uinput(#[from] std::io::Error),
+}
+
+pub type Result<T> = std::result::Result<T, DriverError>;
@@ -0,0 +1,115 @@
+use async_io::Async;
+use futures_lite::future;
+use signal_hook::low_level::pipe;
+
+use std::io;
+use std::os::fd::{AsRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::time::Duration;
+
+use crate::usb::PendingReads;
+
+// Becomes readable when an exit signal arrives and stays so, since nothing reads it.
+// Threads that block on a file descriptor wait on it too, so they notice the driver
+// exiting without waking up to check.
+pub struct ExitNotice {
+    receiver: UnixStream,
+    // The signal handlers write to copies of it.
+    sender: UnixStream,
+    // libusb reads can't wait on another descriptor, so they are cancelled instead.
+    usb_reads: PendingReads,
+}
+
+// What ended a wait.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Wakeup {
+    // The poll(2) revents of the descriptors waited on, together.
+    Ready(libc::c_short),
+    TimedOut,
+    Exit,
+}
+
+impl ExitNotice {
+    pub fn on_signals(signals: &[libc::c_int]) -> io::Result<Self> {
+        let notice = Self::new()?;
+        for &signal in signals {
+            pipe::register(signal, notice.sender.try_clone()?)?;
+        }
+        Ok(notice)
+    }
+
+    fn new() -> io::Result<Self> {
+        let (receiver, sender) = UnixStream::pair()?;
+        Ok(ExitNotice {
+            receiver,
+            sender,
+            usb_reads: PendingReads::default(),
+        })
+    }
+
+    pub fn usb_reads(&self) -> &PendingReads {
+        &self.usb_reads
+    }
+
+    // Resolves once the driver exits, for tasks on the reactor.
+    pub async fn exited(&self) {
+        match self.receiver.try_clone().and_then(Async::new) {
+            Ok(receiver) => {
+                let _ = receiver.readable().await;
+            }
+            Err(_) => future::pending().await,
+        }
+    }
+
+    // Blocks until one of `fds` is readable, `timeout` passes or the driver exits,
+    // whichever comes first. Without `fds` it only waits for the exit.
+    pub fn wait_readable(&self, fds: &[RawFd], timeout: Option<Duration>) -> io::Result<Wakeup> {
+        let mut fds: Vec<libc::pollfd> = [self.receiver.as_raw_fd()]
+            .iter()
+            .chain(fds)
+            .map(|&fd| libc::pollfd {
+                fd,
+                events: libc::POLLIN,
+                revents: 0,
+            })
+            .collect();
+        let millis = timeout.map_or(-1, |timeout| {
+            timeout.as_millis().min(i32::MAX as u128) as libc::c_int
+        });
+        loop {
+            // SAFETY: `fds` holds valid pollfds, as many as given.
+            match unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, millis) } {
+                // Signals interrupt the wait, the exit signal included, before its
+                // handler's write is seen.
+                -1 if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted => {}
+                -1 => return Err(io::Error::last_os_error()),
+                0 => return Ok(Wakeup::TimedOut),
+                _ if fds[0].revents != 0 => return Ok(Wakeup::Exit),
+                _ => return Ok(Wakeup::Ready(fds[1..].iter().fold(0, |all, fd| all | fd.revents))),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Write;
+
+    #[test]
+    fn waits_end_for_good_once_the_driver_exits() {
+        let notice = ExitNotice::new().unwrap();
+        let (mut tablet, reader) = UnixStream::pair().unwrap();
+        let fd = [reader.as_raw_fd()];
+        let short = Some(Duration::from_millis(1));
+        assert_eq!(notice.wait_readable(&fd, short).unwrap(), Wakeup::TimedOut);
+
+        tablet.write_all(b"report").unwrap();
+        assert_eq!(notice.wait_readable(&fd, None).unwrap(), Wakeup::Ready(libc::POLLIN));
+
+        (&notice.sender).write_all(&[0]).unwrap();
+        assert_eq!(notice.wait_readable(&fd, None).unwrap(), Wakeup::Exit);
+        assert_eq!(notice.wait_readable(&[], None).unwrap(), Wakeup::Exit);
+    }
+}
@@ -0,0 +1,319 @@
+use serde::Deserialize;
+
+use std::collections::VecDeque;
+use std::f32::consts::PI;
+use std::time::Instant;
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum FilterConfig {
+    None,
+    // Exponential moving average; lower alpha means smoother and laggier.
+    Ema { alpha: f32 },
+    // Median of the last `window` samples; removes single-sample spikes.
+    Median { window: usize },
+    // Drops a sample that lands more than `max_jump` tablet units from the last one,
+    // unless the next sample agrees with it. Belongs first, before any smoothing.
+    Spike { max_jump: i32 },
+    // Moving average whose smoothing (0 none, towards 1 heavy) falls from
+    // `max_smoothing` at or below the low end of `velocity_range` to `min_smoothing`
+    // at or above its high end, in tablet units per report.
+    Adaptive {
+        #[serde(default)]
+        min_smoothing: f32,
+        #[serde(default = "default_max_smoothing")]
+        max_smoothing: f32,
+        #[serde(default = "default_velocity_range")]
+        velocity_range: [f32; 2],
+    },
+    // 1€ filter: strong smoothing when slow, little lag when fast. `min_cutoff` (Hz)
+    // sets the jitter reduction at rest, `beta` how quickly it opens up with speed.
+    OneEuro {
+        min_cutoff: f32,
+        beta: f32,
+        #[serde(default = "default_derivative_cutoff")]
+        d_cutoff: f32,
+    },
+}
+
+fn default_derivative_cutoff() -> f32 {
+    1.0
+}
+
+fn default_max_smoothing() -> f32 {
+    0.8
+}
+
+fn default_velocity_range() -> [f32; 2] {
+    [2.0, 40.0]
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct SmoothingConfig {
+    pub mouse: Vec<FilterConfig>,
+    pub tablet: Vec<FilterConfig>,
+}
+
+impl Default for SmoothingConfig {
+    fn default() -> Self {
+        SmoothingConfig {
+            mouse: vec![FilterConfig::Ema { alpha: 0.5 }],
+            tablet: vec![FilterConfig::Ema { alpha: 0.25 }],
+        }
+    }
+}
+
+enum Filter {
+    None,
+    Ema { alpha: f32, last: Option<(f32, f32)> },
+    Median { window: usize, samples: VecDeque<(i32, i32)> },
+    // The last sample let through, and one held back as a possible spike.
+    Spike { max_jump: f32, last: Option<(i32, i32)>, held: Option<(i32, i32)> },
+    // The last input, to measure the speed, and the last output.
+    Adaptive {
+        smoothing: (f32, f32),
+        velocity_range: (f32, f32),
+        last: Option<((i32, i32), (f32, f32))>,
+    },
+    OneEuro { x: OneEuroAxis, y: OneEuroAxis, last_time: Option<Instant> },
+}
+
+#[derive(Clone, Copy)]
+struct OneEuroAxis {
+    min_cutoff: f32,
+    beta: f32,
+    d_cutoff: f32,
+    last: Option<(f32, f32)>, // (filtered value, filtered derivative)
+}
+
+impl OneEuroAxis {
+    fn smoothing_factor(cutoff: f32, dt: f32) -> f32 {
+        let tau = 1.0 / (2.0 * PI * cutoff);
+        1.0 / (1.0 + tau / dt)
+    }
+
+    fn apply(&mut self, value: f32, dt: f32) -> f32 {
+        let Some((last_value, last_derivative)) = self.last else {
+            self.last = Some((value, 0.0));
+            return value;
+        };
+
+        let derivative = (value - last_value) / dt;
+        let derivative_alpha = Self::smoothing_factor(self.d_cutoff, dt);
+        let derivative = last_derivative + derivative_alpha * (derivative - last_derivative);
+
+        let cutoff = self.min_cutoff + self.beta * derivative.abs();
+        let alpha = Self::smoothing_factor(cutoff, dt);
+        let filtered = last_value + alpha * (value - last_value);
+
+        self.last = Some((filtered, derivative));
+        filtered
+    }
+}
+
+impl Filter {
+    fn new(config: &FilterConfig) -> Self {
+        match *config {
+            FilterConfig::None => Filter::None,
+            FilterConfig::Ema { alpha } => Filter::Ema {
+                alpha: alpha.clamp(0.01, 1.0),
+                last: None,
+            },
+            FilterConfig::Median { window } => Filter::Median {
+                window: window.max(1),
+                samples: VecDeque::new(),
+            },
+            FilterConfig::Spike { max_jump } => Filter::Spike {
+                max_jump: max_jump.max(1) as f32,
+                last: None,
+                held: None,
+            },
+            FilterConfig::Adaptive {
+                min_smoothing,
+                max_smoothing,
+                velocity_range: [low, high],
+            } => Filter::Adaptive {
+                smoothing: (min_smoothing.clamp(0.0, 0.99), max_smoothing.clamp(0.0, 0.99)),
+                velocity_range: (low.max(0.0), high.max(low + 1.0)),
+                last: None,
+            },
+            FilterConfig::OneEuro {
+                min_cutoff,
+                beta,
+                d_cutoff,
+            } => {
+                let axis = OneEuroAxis {
+                    min_cutoff: min_cutoff.max(0.001),
+                    beta: beta.max(0.0),
+                    d_cutoff: d_cutoff.max(0.001),
+                    last: None,
+                };
+                Filter::OneEuro {
+                    x: axis,
+                    y: axis,
+                    last_time: None,
+                }
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        match self {
+            Filter::None => {}
+            Filter::Ema { last, .. } => *last = None,
+            Filter::Median { samples, .. } => samples.clear(),
+            Filter::Spike { last, held, .. } => {
+                *last = None;
+                *held = None;
+            }
+            Filter::Adaptive { last, .. } => *last = None,
+            Filter::OneEuro { x, y, last_time } => {
+                x.last = None;
+                y.last = None;
+                *last_time = None;
+            }
+        }
+    }
+
+    fn apply(&mut self, x: i32, y: i32) -> (i32, i32) {
+        match self {
+            Filter::None => (x, y),
+            Filter::Ema { alpha, last } => {
+                let (x, y) = (x as f32, y as f32);
+                let (smoothed_x, smoothed_y) = match *last {
+                    Some((last_x, last_y)) => {
+                        (last_x + *alpha * (x - last_x), last_y + *alpha * (y - last_y))
+                    }
+                    None => (x, y),
+                };
+                *last = Some((smoothed_x, smoothed_y));
+                (smoothed_x.round() as i32, smoothed_y.round() as i32)
+            }
+            Filter::Median { window, samples } => {
+                samples.push_back((x, y));
+                while samples.len() > *window {
+                    samples.pop_front();
+                }
+                let median = |axis: fn(&(i32, i32)) -> i32| {
+                    let mut values: Vec<i32> = samples.iter().map(axis).collect();
+                    values.sort_unstable();
+                    values[values.len() / 2]
+                };
+                (median(|s| s.0), median(|s| s.1))
+            }
+            Filter::Spike {
+                max_jump,
+                last,
+                held,
+            } => {
+                let is_near = |(from_x, from_y): (i32, i32)| {
+                    ((x - from_x) as f32).hypot((y - from_y) as f32) <= *max_jump
+                };
+                // A single far-off sample is garbage, two close together are the pen
+                // really being somewhere else.
+                let is_plausible = last.is_none_or(is_near) || held.is_some_and(is_near);
+                if is_plausible {
+                    *last = Some((x, y));
+                    *held = None;
+                    (x, y)
+                } else {
+                    *held = Some((x, y));
+                    last.unwrap_or((x, y))
+                }
+            }
+            Filter::Adaptive {
+                smoothing: (min_smoothing, max_smoothing),
+                velocity_range: (low, high),
+                last,
+            } => {
+                let Some(((last_x, last_y), (smoothed_x, smoothed_y))) = *last else {
+                    *last = Some(((x, y), (x as f32, y as f32)));
+                    return (x, y);
+                };
+                let speed = ((x - last_x) as f32).hypot((y - last_y) as f32);
+                let fast = ((speed - *low) / (*high - *low)).clamp(0.0, 1.0);
+                let alpha = 1.0 - (*max_smoothing + (*min_smoothing - *max_smoothing) * fast);
+                let smoothed = (
+                    smoothed_x + alpha * (x as f32 - smoothed_x),
+                    smoothed_y + alpha * (y as f32 - smoothed_y),
+                );
+                *last = Some(((x, y), smoothed));
+                (smoothed.0.round() as i32, smoothed.1.round() as i32)
+            }
+            Filter::OneEuro {
+                x: x_axis,
+                y: y_axis,
+                last_time,
+            } => {
+                let now = Instant::now();
+                // Reports arrive every few ms; never divide by a zero interval.
+                let dt = last_time
+                    .map(|last| now.duration_since(last).as_secs_f32())
+                    .unwrap_or(0.0)
+                    .max(0.001);
+                *last_time = Some(now);
+                (
+                    x_axis.apply(x as f32, dt).round() as i32,
+                    y_axis.apply(y as f32, dt).round() as i32,
+                )
+            }
+        }
+    }
+}
+
+// Filters applied one after the other to every coordinate pair.
+pub struct FilterPipeline {
+    filters: Vec<Filter>,
+}
+
+impl FilterPipeline {
+    pub fn new(configs: &[FilterConfig]) -> Self {
+        FilterPipeline {
+            filters: configs.iter().map(Filter::new).collect(),
+        }
+    }
+
+    // Drops the filter history, e.g. when the pen comes back into range elsewhere.
+    pub fn reset(&mut self) {
+        self.filters.iter_mut().for_each(Filter::reset);
+    }
+
+    pub fn apply(&mut self, x: i32, y: i32) -> (i32, i32) {
+        self.filters
+            .iter_mut()
+            .fold((x, y), |(x, y), filter| filter.apply(x, y))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn single_spikes_are_dropped_but_real_jumps_are_followed() {
+        let mut filter = FilterPipeline::new(&[FilterConfig::Spike { max_jump: 200 }]);
+        assert_eq!(filter.apply(1000, 1000), (1000, 1000));
+        assert_eq!(filter.apply(1050, 1000), (1050, 1000));
+        assert_eq!(filter.apply(4000, 20), (1050, 1000));
+        assert_eq!(filter.apply(1100, 1000), (1100, 1000));
+
+        // A real jump lands in the same place twice in a row.
+        assert_eq!(filter.apply(3000, 3000), (1100, 1000));
+        assert_eq!(filter.apply(3010, 3000), (3010, 3000));
+    }
+
+    #[test]
+    fn adaptive_smoothing_lets_fast_strokes_through() {
+        let mut filter = FilterPipeline::new(&[FilterConfig::Adaptive {
+            min_smoothing: 0.0,
+            max_smoothing: 0.9,
+            velocity_range: [2.0, 50.0],
+        }]);
+        filter.apply(1000, 1000);
+        // Slow: only a tenth of the way.
+        assert_eq!(filter.apply(1002, 1000), (1000, 1000));
+        // Fast: all the way at once.
+        assert_eq!(filter.apply(1100, 1000), (1100, 1000));
+    }
+}
@@ -0,0 +1,195 @@
+use serde::Deserialize;
+
+use std::time::{Duration, Instant};
+
+use crate::binding::Binding;
+use crate::config::AXIS_MAX;
+
+// Quick strokes from the edges of the tablet that run a binding instead of drawing,
+// e.g. to switch workspaces. A touch landing within `edge` of a bound edge is held
+// back until it either travels `distance` inwards within `time_ms`, or doesn't and
+// goes on as usual.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct FlickConfig {
+    // In tablet units, 4096 across.
+    pub edge: i32,
+    pub distance: i32,
+    pub time_ms: u64,
+    // Named by the edge the flick starts from, as the screen is oriented.
+    pub from_left: Option<Binding>,
+    pub from_right: Option<Binding>,
+    pub from_top: Option<Binding>,
+    pub from_bottom: Option<Binding>,
+}
+
+impl Default for FlickConfig {
+    fn default() -> Self {
+        FlickConfig {
+            edge: 150,
+            distance: 600,
+            time_ms: 250,
+            from_left: None,
+            from_right: None,
+            from_top: None,
+            from_bottom: None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Edge {
+    Left,
+    Right,
+    Top,
+    Bottom,
+}
+
+impl FlickConfig {
+    pub fn binding(&self, edge: Edge) -> Option<&Binding> {
+        match edge {
+            Edge::Left => self.from_left.as_ref(),
+            Edge::Right => self.from_right.as_ref(),
+            Edge::Top => self.from_top.as_ref(),
+            Edge::Bottom => self.from_bottom.as_ref(),
+        }
+    }
+
+    pub fn bindings(&self) -> impl Iterator<Item = &Binding> {
+        [&self.from_left, &self.from_right, &self.from_top, &self.from_bottom]
+            .into_iter()
+            .flatten()
+    }
+
+    // The bound edge (x, y) is on, if any.
+    fn edge_at(&self, x: i32, y: i32) -> Option<Edge> {
+        let edges = [
+            (Edge::Left, x < self.edge),
+            (Edge::Right, x > AXIS_MAX - self.edge),
+            (Edge::Top, y < self.edge),
+            (Edge::Bottom, y > AXIS_MAX - self.edge),
+        ];
+        edges
+            .into_iter()
+            .find(|&(edge, is_on)| is_on && self.binding(edge).is_some())
+            .map(|(edge, _)| edge)
+    }
+}
+
+// What to do with the touch of a report.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Touch {
+    // As usual: touching or not, as the pen is.
+    Draw,
+    // Keep the pen up.
+    Hold,
+    // Keep the pen up and run the edge's binding.
+    Flick(Edge),
+    // A held-back touch was lifted without flicking: send it as a tap.
+    Tap,
+}
+
+#[derive(Default)]
+enum State {
+    #[default]
+    Idle,
+    Pending {
+        edge: Edge,
+        start: (i32, i32),
+        at: Instant,
+    },
+    Flicked,
+    Drawing,
+}
+
+#[derive(Default)]
+pub struct FlickTracker {
+    state: State,
+}
+
+impl FlickTracker {
+    // `x` and `y` are in tablet units, oriented as the screen.
+    pub fn update(
+        &mut self,
+        config: &FlickConfig,
+        x: i32,
+        y: i32,
+        is_touching: bool,
+        now: Instant,
+    ) -> Touch {
+        if !is_touching {
+            let was_pending = matches!(self.state, State::Pending { .. });
+            self.state = State::Idle;
+            return if was_pending { Touch::Tap } else { Touch::Draw };
+        }
+        match self.state {
+            State::Idle => match config.edge_at(x, y) {
+                Some(edge) => {
+                    self.state = State::Pending {
+                        edge,
+                        start: (x, y),
+                        at: now,
+                    };
+                    Touch::Hold
+                }
+                None => {
+                    self.state = State::Drawing;
+                    Touch::Draw
+                }
+            },
+            State::Pending { edge, start, at } => {
+                let inwards = match edge {
+                    Edge::Left => x - start.0,
+                    Edge::Right => start.0 - x,
+                    Edge::Top => y - start.1,
+                    Edge::Bottom => start.1 - y,
+                };
+                if inwards >= config.distance {
+                    self.state = State::Flicked;
+                    Touch::Flick(edge)
+                } else if now.duration_since(at) > Duration::from_millis(config.time_ms) {
+                    self.state = State::Drawing;
+                    Touch::Draw
+                } else {
+                    Touch::Hold
+                }
+            }
+            State::Flicked => Touch::Hold,
+            State::Drawing => Touch::Draw,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quick_strokes_from_a_bound_edge_flick() {
+        let config: FlickConfig = toml::from_str("from_left = \"super+right\"").unwrap();
+        let mut tracker = FlickTracker::default();
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+
+        // A touch landing away from the bound edges draws, even once it slides onto one.
+        assert_eq!(tracker.update(&config, 2000, 2000, true, at(0)), Touch::Draw);
+        assert_eq!(tracker.update(&config, 100, 2000, true, at(10)), Touch::Draw);
+        assert_eq!(tracker.update(&config, 100, 2000, false, at(20)), Touch::Draw);
+        assert_eq!(tracker.update(&config, 4050, 2000, true, at(30)), Touch::Draw);
+        tracker.update(&config, 4050, 2000, false, at(40));
+
+        assert_eq!(tracker.update(&config, 50, 2000, true, at(100)), Touch::Hold);
+        assert_eq!(tracker.update(&config, 400, 2050, true, at(150)), Touch::Hold);
+        assert_eq!(tracker.update(&config, 700, 2100, true, at(200)), Touch::Flick(Edge::Left));
+        assert_eq!(tracker.update(&config, 1500, 2100, true, at(250)), Touch::Hold);
+        assert_eq!(tracker.update(&config, 1500, 2100, false, at(300)), Touch::Draw);
+
+        // Too slow to be a flick, so it draws; a tap at the edge stays a tap.
+        assert_eq!(tracker.update(&config, 50, 2000, true, at(400)), Touch::Hold);
+        assert_eq!(tracker.update(&config, 200, 2000, true, at(700)), Touch::Draw);
+        assert_eq!(tracker.update(&config, 900, 2000, true, at(750)), Touch::Draw);
+        tracker.update(&config, 900, 2000, false, at(800));
+        assert_eq!(tracker.update(&config, 50, 2000, true, at(900)), Touch::Hold);
+        assert_eq!(tracker.update(&config, 50, 2000, false, at(950)), Touch::Tap);
+    }
+}
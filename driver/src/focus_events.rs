@@ -0,0 +1,301 @@
+// Focus changes as the desktop announces them, so the window and monitor watchers
+// sleep until something changes instead of asking every few hundred milliseconds.
+
+use serde_json::Value;
+use x11rb::connection::{Connection, RequestConnection as _};
+use x11rb::errors::ConnectionError;
+use x11rb::properties::WmClass;
+use x11rb::protocol::Event;
+use x11rb::protocol::randr::{self, ConnectionExt as _, NotifyMask};
+use x11rb::protocol::xinput::{self, ConnectionExt as _, XIEventMask};
+use x11rb::protocol::xproto::{
+    Atom, AtomEnum, ChangeWindowAttributesAux, ConnectionExt as _, EventMask, Window,
+};
+use x11rb::rust_connection::RustConnection;
+
+use std::env;
+use std::io::{self, BufRead, BufReader, Read};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+// Runs `on_event` for each event of `types` (a JSON list, e.g. `["window"]`) that
+// sway sends through `swaymsg -t subscribe`, until it returns false or sway exits.
+pub fn sway_events(types: &str, on_event: impl FnMut(&Value) -> bool) -> io::Result<()> {
+    let mut child = Command::new("swaymsg")
+        .args(["-t", "subscribe", "-m", "-r", types])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()?;
+    let stdout = child.stdout.take().ok_or(io::ErrorKind::BrokenPipe)?;
+    let result = read_sway_events(stdout, on_event);
+    let _ = child.kill();
+    let _ = child.wait();
+    result
+}
+
+// swaymsg may pretty-print each event over several lines, so they are read as a
+// stream of JSON values rather than line by line.
+fn read_sway_events(reader: impl Read, mut on_event: impl FnMut(&Value) -> bool) -> io::Result<()> {
+    for event in serde_json::Deserializer::from_reader(reader).into_iter::<Value>() {
+        if !on_event(&event?) {
+            break;
+        }
+    }
+    Ok(())
+}
+
+// Runs `on_event` with the name and data of each event on Hyprland's event socket,
+// e.g. "activewindow" and "kitty,~", until it returns false or Hyprland exits.
+pub fn hyprland_events(on_event: impl FnMut(&str, &str) -> bool) -> io::Result<()> {
+    let signature = env::var_os("HYPRLAND_INSTANCE_SIGNATURE").ok_or(io::ErrorKind::NotFound)?;
+    // Hyprland 0.40 moved its sockets from /tmp/hypr to the runtime directory.
+    let dirs = [env::var_os("XDG_RUNTIME_DIR").map(PathBuf::from), Some("/tmp".into())];
+    let stream = dirs
+        .into_iter()
+        .flatten()
+        .map(|dir| dir.join("hypr").join(&signature).join(".socket2.sock"))
+        .find_map(|path| UnixStream::connect(path).ok())
+        .ok_or(io::ErrorKind::NotFound)?;
+    read_hyprland_events(BufReader::new(stream), on_event)
+}
+
+// Events are lines of the form `EVENT>>DATA`.
+fn read_hyprland_events(
+    reader: impl BufRead,
+    mut on_event: impl FnMut(&str, &str) -> bool,
+) -> io::Result<()> {
+    for line in reader.lines() {
+        if let Some((event, data)) = line?.split_once(">>")
+            && !on_event(event, data)
+        {
+            break;
+        }
+    }
+    Ok(())
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum X11Change {
+    // _NET_ACTIVE_WINDOW changed.
+    ActiveWindow,
+    // The focused window moved or was resized, with `follow_window_moves`.
+    WindowMoved,
+    // The pointer moved, with `follow_pointer`.
+    Pointer,
+    // Monitors were added, removed or rearranged.
+    Screen,
+}
+
+// A connection to the X server listening on the root window for focus changes, and
+// for moves of the focused window or the pointer when asked to.
+pub struct X11Events {
+    connection: RustConnection,
+    root: Window,
+    net_active_window: Atom,
+    active: Option<Window>,
+    follows_window: bool,
+    // A change read while skipping queued pointer motion, returned next.
+    pending: Option<X11Change>,
+}
+
+impl X11Events {
+    pub fn connect() -> Result<Self, String> {
+        let (connection, screen) =
+            x11rb::connect(None).map_err(|err| format!("Cannot connect to the X server: {}", err))?;
+        let root = connection.setup().roots[screen].root;
+        let net_active_window = connection
+            .intern_atom(false, b"_NET_ACTIVE_WINDOW")
+            .map_err(x11_error)?
+            .reply()
+            .map_err(x11_error)?
+            .atom;
+        let attributes = ChangeWindowAttributesAux::new().event_mask(EventMask::PROPERTY_CHANGE);
+        connection
+            .change_window_attributes(root, &attributes)
+            .map_err(x11_error)?;
+        let has_randr = connection
+            .extension_information(randr::X11_EXTENSION_NAME)
+            .map_err(x11_error)?
+            .is_some();
+        if has_randr {
+            connection
+                .randr_select_input(root, NotifyMask::SCREEN_CHANGE)
+                .map_err(x11_error)?;
+        }
+        connection.flush().map_err(x11_error)?;
+
+        let mut events = X11Events {
+            connection,
+            root,
+            net_active_window,
+            active: None,
+            follows_window: false,
+            pending: None,
+        };
+        events.active = events.query_active_window();
+        Ok(events)
+    }
+
+    // Also reports moves of the focused window, as WindowMoved.
+    pub fn follow_window_moves(&mut self) -> Result<(), ConnectionError> {
+        self.follows_window = true;
+        self.watch_moves(self.active, EventMask::STRUCTURE_NOTIFY)?;
+        self.connection.flush()
+    }
+
+    // Also reports pointer motion, as Pointer, through XInput 2 raw events, which
+    // reach the root window whichever window is under the pointer.
+    pub fn follow_pointer(&self) -> Result<(), String> {
+        let version = self
+            .connection
+            .xinput_xi_query_version(2, 0)
+            .map_err(x11_error)?
+            .reply()
+            .map_err(|err| format!("XInput 2 is not available: {}", err))?;
+        if version.major_version < 2 {
+            return Err("XInput 2 is not available".to_string());
+        }
+        let mask = xinput::EventMask {
+            deviceid: xinput::Device::ALL_MASTER.into(),
+            mask: vec![XIEventMask::RAW_MOTION],
+        };
+        self.connection
+            .xinput_xi_select_events(self.root, &[mask])
+            .map_err(x11_error)?;
+        self.connection.flush().map_err(x11_error)
+    }
+
+    pub fn active_window(&self) -> Option<Window> {
+        self.active
+    }
+
+    // The instance and class names in WM_CLASS, as `xprop` shows them: "gimp, Gimp".
+    pub fn window_class(&self, window: Window) -> Option<String> {
+        let class = WmClass::get(&self.connection, window).ok()?.reply().ok()??;
+        let [instance, class] = [class.instance(), class.class()].map(String::from_utf8_lossy);
+        Some(format!("{}, {}", instance, class))
+    }
+
+    // The middle of `window`, on the root window.
+    pub fn window_center(&self, window: Window) -> Option<(i32, i32)> {
+        let geometry = self.connection.get_geometry(window).ok()?.reply().ok()?;
+        let origin = self
+            .connection
+            .translate_coordinates(window, self.root, 0, 0)
+            .ok()?
+            .reply()
+            .ok()?;
+        Some((
+            origin.dst_x as i32 + geometry.width as i32 / 2,
+            origin.dst_y as i32 + geometry.height as i32 / 2,
+        ))
+    }
+
+    pub fn pointer(&self) -> Option<(i32, i32)> {
+        let pointer = self.connection.query_pointer(self.root).ok()?.reply().ok()?;
+        Some((pointer.root_x as i32, pointer.root_y as i32))
+    }
+
+    // Blocks until the next change. Pointer motion already queued behind one is
+    // skipped, as only where the pointer ended up matters.
+    pub fn wait(&mut self) -> Result<X11Change, ConnectionError> {
+        if let Some(change) = self.pending.take() {
+            return Ok(change);
+        }
+        let change = loop {
+            let event = self.connection.wait_for_event()?;
+            if let Some(change) = self.change(event)? {
+                break change;
+            }
+        };
+        if change == X11Change::Pointer {
+            while let Some(event) = self.connection.poll_for_event()? {
+                match self.change(event)? {
+                    Some(X11Change::Pointer) | None => {}
+                    Some(other) => {
+                        self.pending = Some(other);
+                        break;
+                    }
+                }
+            }
+        }
+        Ok(change)
+    }
+
+    fn change(&mut self, event: Event) -> Result<Option<X11Change>, ConnectionError> {
+        Ok(Some(match event {
+            Event::PropertyNotify(event)
+                if event.window == self.root && event.atom == self.net_active_window =>
+            {
+                let active = self.query_active_window();
+                if self.follows_window && active != self.active {
+                    self.watch_moves(self.active, EventMask::NO_EVENT)?;
+                    self.watch_moves(active, EventMask::STRUCTURE_NOTIFY)?;
+                    self.connection.flush()?;
+                }
+                self.active = active;
+                X11Change::ActiveWindow
+            }
+            Event::ConfigureNotify(event) if Some(event.window) == self.active => {
+                X11Change::WindowMoved
+            }
+            Event::XinputRawMotion(_) => X11Change::Pointer,
+            Event::RandrScreenChangeNotify(_) => X11Change::Screen,
+            _ => return Ok(None),
+        }))
+    }
+
+    fn query_active_window(&self) -> Option<Window> {
+        let reply = self
+            .connection
+            .get_property(false, self.root, self.net_active_window, AtomEnum::WINDOW, 0, 1)
+            .ok()?
+            .reply()
+            .ok()?;
+        reply.value32()?.next().filter(|&window| window != x11rb::NONE)
+    }
+
+    // Selects `mask` on `window`, which belongs to another client; the selection is
+    // this connection's own, so the window is otherwise unaffected.
+    fn watch_moves(&self, window: Option<Window>, mask: EventMask) -> Result<(), ConnectionError> {
+        if let Some(window) = window {
+            let attributes = ChangeWindowAttributesAux::new().event_mask(mask);
+            self.connection.change_window_attributes(window, &attributes)?;
+        }
+        Ok(())
+    }
+}
+
+fn x11_error(err: impl std::fmt::Display) -> String {
+    format!("X11 error: {}", err)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compositor_events_are_split_out_of_their_streams() {
+        // Pretty-printed, as some swaymsg versions send them.
+        let sway = "{\n  \"change\": \"focus\",\n  \"container\": { \"app_id\": \"krita\" }\n}\n\
+                    {\"change\": \"title\"}\n{\"change\": \"close\"}";
+        let mut changes = Vec::new();
+        read_sway_events(sway.as_bytes(), |event| {
+            changes.push(event["change"].as_str().unwrap().to_string());
+            changes.len() < 2
+        })
+        .unwrap();
+        assert_eq!(changes, ["focus", "title"]);
+
+        let hyprland = "workspace>>2\nactivewindow>>kitty,~/src, a title\nnot an event\n";
+        let mut events = Vec::new();
+        read_hyprland_events(hyprland.as_bytes(), |event, data| {
+            events.push((event.to_string(), data.to_string()));
+            true
+        })
+        .unwrap();
+        assert_eq!(events[1], ("activewindow".to_string(), "kitty,~/src, a title".to_string()));
+        assert_eq!(events.len(), 2);
+    }
+}
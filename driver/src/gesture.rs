@@ -0,0 +1,201 @@
+use crate::binding::Binding;
+
+use serde::Deserialize;
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+// Alternate actions for tablet buttons: held for at least `long_press_ms`, or pressed
+// twice within `double_press_ms`. A button listed here taps its normal binding once
+// the gesture is decided instead of holding it.
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct GestureConfig {
+    pub long_press_ms: u64,
+    pub double_press_ms: u64,
+    pub long_press: HashMap<u8, Binding>,
+    pub double_press: HashMap<u8, Binding>,
+}
+
+impl Default for GestureConfig {
+    fn default() -> Self {
+        GestureConfig {
+            long_press_ms: 500,
+            double_press_ms: 300,
+            long_press: HashMap::new(),
+            double_press: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Gesture {
+    Short,
+    Long,
+    Double,
+}
+
+#[derive(Clone, Copy)]
+enum ButtonState {
+    Down(Instant),
+    // Released after a short press, waiting to see whether a second press follows.
+    Up(Instant),
+    // The gesture already fired; ignore the button until it is released.
+    Done,
+}
+
+#[derive(Default)]
+pub struct GestureTracker {
+    config: GestureConfig,
+    buttons: HashMap<u8, ButtonState>,
+}
+
+impl GestureTracker {
+    pub fn new(config: &GestureConfig) -> Self {
+        GestureTracker {
+            config: config.clone(),
+            buttons: HashMap::new(),
+        }
+    }
+
+    pub fn handles(&self, id: u8) -> bool {
+        self.config.long_press.contains_key(&id) || self.config.double_press.contains_key(&id)
+    }
+
+    pub fn binding(&self, id: u8, gesture: Gesture) -> Option<&Binding> {
+        match gesture {
+            Gesture::Short => None,
+            Gesture::Long => self.config.long_press.get(&id),
+            Gesture::Double => self.config.double_press.get(&id),
+        }
+    }
+
+    pub fn press(&mut self, id: u8, now: Instant) -> Option<Gesture> {
+        match self.buttons.insert(id, ButtonState::Down(now)) {
+            Some(ButtonState::Up(released))
+                if now.duration_since(released) < double_press_delay(&self.config) =>
+            {
+                self.buttons.insert(id, ButtonState::Done);
+                Some(Gesture::Double)
+            }
+            // A pending short press that poll() has not resolved yet.
+            Some(ButtonState::Up(_)) => Some(Gesture::Short),
+            _ => None,
+        }
+    }
+
+    pub fn release(&mut self, id: u8, now: Instant) -> Option<Gesture> {
+        match self.buttons.remove(&id)? {
+            ButtonState::Down(pressed) if self.is_long(id, pressed, now) => Some(Gesture::Long),
+            // Wait for a possible second press before settling on a short one.
+            ButtonState::Down(_) if self.config.double_press.contains_key(&id) => {
+                self.buttons.insert(id, ButtonState::Up(now));
+                None
+            }
+            ButtonState::Down(_) => Some(Gesture::Short),
+            ButtonState::Up(_) | ButtonState::Done => None,
+        }
+    }
+
+    // Forgets a press that something else, like a chord, has taken over.
+    pub fn cancel(&mut self, id: u8) {
+        self.buttons.remove(&id);
+    }
+
+    // Gestures decided by time passing alone: long presses still held and short
+    // presses whose double-press window ran out.
+    pub fn poll(&mut self, now: Instant) -> Vec<(u8, Gesture)> {
+        let mut gestures = Vec::new();
+        for (&id, state) in self.buttons.iter_mut() {
+            match *state {
+                ButtonState::Down(pressed)
+                    if self.config.long_press.contains_key(&id)
+                        && now.duration_since(pressed) >= long_press_delay(&self.config) =>
+                {
+                    *state = ButtonState::Done;
+                    gestures.push((id, Gesture::Long));
+                }
+                ButtonState::Up(released)
+                    if now.duration_since(released) >= double_press_delay(&self.config) =>
+                {
+                    gestures.push((id, Gesture::Short));
+                }
+                _ => {}
+            }
+        }
+        self.buttons
+            .retain(|id, _| !gestures.contains(&(*id, Gesture::Short)));
+        gestures.sort_by_key(|(id, _)| *id);
+        gestures
+    }
+
+    // When `poll` will next have something to decide, if any press is pending.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.buttons
+            .iter()
+            .filter_map(|(id, state)| match *state {
+                ButtonState::Down(pressed) if self.config.long_press.contains_key(id) => {
+                    Some(pressed + long_press_delay(&self.config))
+                }
+                ButtonState::Up(released) => Some(released + double_press_delay(&self.config)),
+                _ => None,
+            })
+            .min()
+    }
+
+    fn is_long(&self, id: u8, pressed: Instant, now: Instant) -> bool {
+        self.config.long_press.contains_key(&id)
+            && now.duration_since(pressed) >= long_press_delay(&self.config)
+    }
+}
+
+fn long_press_delay(config: &GestureConfig) -> Duration {
+    Duration::from_millis(config.long_press_ms)
+}
+
+fn double_press_delay(config: &GestureConfig) -> Duration {
+    Duration::from_millis(config.double_press_ms)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tracker() -> GestureTracker {
+        let mut config = GestureConfig::default();
+        config.long_press.insert(3, Binding::Keys(vec![]));
+        config.double_press.insert(3, Binding::Keys(vec![]));
+        GestureTracker::new(&config)
+    }
+
+    fn ms(start: Instant, millis: u64) -> Instant {
+        start + Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn short_press_waits_for_the_double_press_window() {
+        let mut tracker = tracker();
+        let start = Instant::now();
+        assert_eq!(tracker.press(3, start), None);
+        assert_eq!(tracker.release(3, ms(start, 100)), None);
+        assert_eq!(tracker.next_deadline(), Some(ms(start, 400)));
+        assert_eq!(tracker.poll(ms(start, 200)), vec![]);
+        assert_eq!(tracker.poll(ms(start, 400)), vec![(3, Gesture::Short)]);
+        assert_eq!(tracker.poll(ms(start, 800)), vec![]);
+    }
+
+    #[test]
+    fn long_and_double_presses_are_recognised() {
+        let mut tracker = tracker();
+        let start = Instant::now();
+        tracker.press(3, start);
+        assert_eq!(tracker.poll(ms(start, 600)), vec![(3, Gesture::Long)]);
+        assert_eq!(tracker.release(3, ms(start, 700)), None);
+
+        tracker.press(3, ms(start, 1000));
+        tracker.release(3, ms(start, 1050));
+        assert_eq!(tracker.press(3, ms(start, 1200)), Some(Gesture::Double));
+        assert_eq!(tracker.release(3, ms(start, 1250)), None);
+        assert_eq!(tracker.poll(ms(start, 2000)), vec![]);
+    }
+}
@@ -0,0 +1,72 @@
+use evdev::Device;
+use tracing::{info, warn};
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::hotplug::TabletId;
+
+const INPUT_CLASS: &str = "/sys/class/input";
+
+// The kernel's own input nodes for a tablet (made by hid-generic for interfaces the
+// driver doesn't claim), grabbed so applications only see the virtual devices.
+// Dropping it releases them.
+pub struct KernelGrab {
+    devices: Vec<Device>,
+}
+
+impl KernelGrab {
+    pub fn new(tablet: TabletId) -> Self {
+        let devices = kernel_input_nodes(tablet)
+            .into_iter()
+            .filter_map(|path| {
+                let device = Device::open(&path)
+                    .and_then(|mut device| device.grab().map(|()| device))
+                    .inspect_err(|err| warn!("Cannot grab {}: {}", path.display(), err))
+                    .ok()?;
+                info!(
+                    "Grabbed {} ({}).",
+                    path.display(),
+                    device.name().unwrap_or("?")
+                );
+                Some(device)
+            })
+            .collect();
+        KernelGrab { devices }
+    }
+}
+
+impl Drop for KernelGrab {
+    fn drop(&mut self) {
+        for device in &mut self.devices {
+            let _ = device.ungrab();
+        }
+    }
+}
+
+// The kernel's /dev/input/event* nodes for a tablet.
+pub fn kernel_input_nodes(tablet: TabletId) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(INPUT_CLASS) else {
+        return Vec::new();
+    };
+    let mut names: Vec<String> = entries
+        .flatten()
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .filter(|name| name.starts_with("event"))
+        .filter(|name| usb_device_of(name) == Some(tablet))
+        .collect();
+    names.sort();
+    names.iter().map(|name| Path::new("/dev/input").join(name)).collect()
+}
+
+// Bus number and address of the USB device an input node belongs to, found by walking
+// up its sysfs path to the directory with `busnum` and `devnum`.
+fn usb_device_of(event_name: &str) -> Option<TabletId> {
+    let device = fs::canonicalize(Path::new(INPUT_CLASS).join(event_name).join("device")).ok()?;
+    device.ancestors().find_map(|dir| {
+        let read_number = |file: &str| -> Option<u8> {
+            fs::read_to_string(dir.join(file)).ok()?.trim().parse().ok()
+        };
+        Some((read_number("busnum")?, read_number("devnum")?))
+    })
+}
@@ -0,0 +1,138 @@
+use std::fs::File;
+use std::io::{self, Read};
+use std::mem;
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd};
+use std::path::Path;
+use std::time::Duration;
+
+use crate::exit_notice::{ExitNotice, Wakeup};
+use crate::vendor_init::InitTransport;
+
+// Largest report descriptor hidraw hands out (HID_MAX_DESCRIPTOR_SIZE).
+const MAX_DESCRIPTOR_SIZE: usize = 4096;
+
+// struct hidraw_report_descriptor
+#[repr(C)]
+struct ReportDescriptor {
+    size: u32,
+    value: [u8; MAX_DESCRIPTOR_SIZE],
+}
+
+// A /dev/hidraw* node of the tablet. The kernel's HID driver stays bound, so nothing
+// is detached; string descriptors can't be read through it, so the handshake goes
+// without the digitizer parameters.
+pub struct Hidraw {
+    file: File,
+}
+
+impl Hidraw {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = File::options().read(true).write(true).open(path)?;
+        Ok(Hidraw { file })
+    }
+
+    // Blocks until a report arrives, or fails with `TimedOut` once `timeout` passes.
+    pub fn read_with_timeout(&self, buffer: &mut [u8], timeout: Duration) -> io::Result<usize> {
+        let mut poll = libc::pollfd {
+            fd: self.file.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let millis = timeout.as_millis().min(i32::MAX as u128) as libc::c_int;
+        // SAFETY: `poll` is a single valid pollfd.
+        match unsafe { libc::poll(&mut poll, 1, millis) } {
+            -1 => return Err(io::Error::last_os_error()),
+            0 => return Err(io::Error::from(io::ErrorKind::TimedOut)),
+            _ => {}
+        }
+        self.read_ready(buffer, poll.revents)
+    }
+
+    // Like `read_with_timeout`, without a timeout when `timeout` is None, and failing
+    // with `Interrupted` once the driver exits.
+    pub fn read_unless_exiting(
+        &self,
+        buffer: &mut [u8],
+        timeout: Option<Duration>,
+        exit: &ExitNotice,
+    ) -> io::Result<usize> {
+        match exit.wait_readable(&[self.file.as_raw_fd()], timeout)? {
+            Wakeup::Ready(revents) => self.read_ready(buffer, revents),
+            Wakeup::TimedOut => Err(io::Error::from(io::ErrorKind::TimedOut)),
+            Wakeup::Exit => Err(io::Error::from(io::ErrorKind::Interrupted)),
+        }
+    }
+
+    fn read_ready(&self, buffer: &mut [u8], revents: libc::c_short) -> io::Result<usize> {
+        // The node hangs up once the tablet is unplugged.
+        if revents & (libc::POLLERR | libc::POLLHUP | libc::POLLNVAL) != 0 {
+            return Err(io::Error::from_raw_os_error(libc::ENODEV));
+        }
+        (&self.file).read(buffer)
+    }
+
+    pub fn as_fd(&self) -> BorrowedFd<'_> {
+        self.file.as_fd()
+    }
+
+    pub fn report_descriptor(&self) -> io::Result<Vec<u8>> {
+        let mut size: libc::c_int = 0;
+        // HIDIOCGRDESCSIZE: _IOR('H', 0x01, int)
+        let request = ioc(IOC_READ, 0x01, mem::size_of::<libc::c_int>());
+        // SAFETY: `size` is an int, as encoded in the request.
+        unsafe { self.ioctl(request, &mut size as *mut libc::c_int as *mut libc::c_void)? };
+
+        let mut descriptor = ReportDescriptor {
+            size: (size.max(0) as usize).min(MAX_DESCRIPTOR_SIZE) as u32,
+            value: [0; MAX_DESCRIPTOR_SIZE],
+        };
+        // HIDIOCGRDESC: _IOR('H', 0x02, struct hidraw_report_descriptor)
+        let request = ioc(IOC_READ, 0x02, mem::size_of::<ReportDescriptor>());
+        let argument = &mut descriptor as *mut ReportDescriptor as *mut libc::c_void;
+        // SAFETY: `descriptor` is a hidraw_report_descriptor, as encoded in the request.
+        unsafe { self.ioctl(request, argument)? };
+        Ok(descriptor.value[..descriptor.size as usize].to_vec())
+    }
+
+    // `argument` must point to what `request` encodes.
+    unsafe fn ioctl(&self, request: libc::c_ulong, argument: *mut libc::c_void) -> io::Result<()> {
+        match unsafe { libc::ioctl(self.file.as_raw_fd(), request, argument) } {
+            -1 => Err(io::Error::last_os_error()),
+            _ => Ok(()),
+        }
+    }
+}
+
+const IOC_READ: libc::c_ulong = 2;
+const IOC_WRITE: libc::c_ulong = 1;
+
+// _IOC(direction, 'H', number, size), as the hidraw ioctls are defined.
+fn ioc(direction: libc::c_ulong, number: libc::c_ulong, size: usize) -> libc::c_ulong {
+    direction << 30 | (size as libc::c_ulong) << 16 | (b'H' as libc::c_ulong) << 8 | number
+}
+
+impl InitTransport for Hidraw {
+    fn feature_report(&mut self, report: &[u8]) -> io::Result<()> {
+        // HIDIOCSFEATURE(len): _IOC(_IOC_READ | _IOC_WRITE, 'H', 0x06, len)
+        let request = ioc(IOC_READ | IOC_WRITE, 0x06, report.len());
+        let mut buffer = report.to_vec();
+        // SAFETY: the buffer is valid for `report.len()` bytes, as encoded in the request.
+        unsafe { self.ioctl(request, buffer.as_mut_ptr() as *mut libc::c_void) }
+    }
+
+    fn string_descriptor(&mut self, _index: u8) -> io::Result<Vec<u8>> {
+        Err(io::Error::from(io::ErrorKind::Unsupported))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ioctl_requests_match_the_kernel_headers() {
+        assert_eq!(ioc(IOC_READ, 0x01, 4), 0x8004_4801);
+        assert_eq!(ioc(IOC_READ, 0x02, 4100), 0x9004_4802);
+        assert_eq!(ioc(IOC_READ | IOC_WRITE, 0x06, 8), 0xc008_4806);
+    }
+}
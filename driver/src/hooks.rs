@@ -0,0 +1,105 @@
+use serde::Deserialize;
+use tracing::{debug, error};
+
+use crate::binding;
+
+// Shell commands run when the driver's state changes, e.g. to show an OSD or tell a
+// window-manager script. They get the new state in V1060P_* environment variables.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct HooksConfig {
+    pub on_mode_change: Option<String>,
+    pub on_profile_change: Option<String>,
+    pub on_connect: Option<String>,
+    pub on_disconnect: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Hook {
+    ModeChange,
+    ProfileChange,
+    Connect,
+    Disconnect,
+}
+
+impl Hook {
+    fn name(self) -> &'static str {
+        match self {
+            Hook::ModeChange => "mode_change",
+            Hook::ProfileChange => "profile_change",
+            Hook::Connect => "connect",
+            Hook::Disconnect => "disconnect",
+        }
+    }
+}
+
+impl HooksConfig {
+    fn command(&self, hook: Hook) -> Option<&str> {
+        match hook {
+            Hook::ModeChange => self.on_mode_change.as_deref(),
+            Hook::ProfileChange => self.on_profile_change.as_deref(),
+            Hook::Connect => self.on_connect.as_deref(),
+            Hook::Disconnect => self.on_disconnect.as_deref(),
+        }
+    }
+}
+
+// The state a hook is told about: that of the tablet it ran for.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HookState<'a> {
+    pub is_mouse_mode: bool,
+    pub mouse_area_scale: f32,
+    pub profile: Option<&'a str>,
+    pub device: &'a str,
+}
+
+// Starts the command configured for `hook`, if any, without waiting for it.
+pub fn run(config: &HooksConfig, hook: Hook, state: &HookState) {
+    let Some(command) = config.command(hook) else {
+        return;
+    };
+    debug!("Running the {} hook.", hook.name());
+    if let Err(err) = binding::spawn_command_with_env(command, &environment(hook, state)) {
+        error!("Error running the {} hook `{}`: {}", hook.name(), command, err);
+    }
+}
+
+fn environment(hook: Hook, state: &HookState) -> Vec<(&'static str, String)> {
+    let mode = if state.is_mouse_mode { "mouse" } else { "tablet" };
+    vec![
+        ("V1060P_EVENT", hook.name().to_string()),
+        ("V1060P_MODE", mode.to_string()),
+        ("V1060P_MOUSE_AREA", format!("{:.0}", state.mouse_area_scale * 100.0)),
+        ("V1060P_PROFILE", state.profile.unwrap_or("default").to_string()),
+        ("V1060P_DEVICE", state.device.to_string()),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hooks_are_told_the_new_state() {
+        let config: HooksConfig = toml::from_str("on_mode_change = \"osd $V1060P_MODE\"").unwrap();
+        assert_eq!(config.command(Hook::ModeChange), Some("osd $V1060P_MODE"));
+        assert_eq!(config.command(Hook::Connect), None);
+
+        let state = HookState {
+            is_mouse_mode: false,
+            mouse_area_scale: 0.3,
+            profile: None,
+            device: "1-2, 08f2:6811",
+        };
+        assert_eq!(
+            environment(Hook::ModeChange, &state),
+            [
+                ("V1060P_EVENT", "mode_change".to_string()),
+                ("V1060P_MODE", "tablet".to_string()),
+                ("V1060P_MOUSE_AREA", "30".to_string()),
+                ("V1060P_PROFILE", "default".to_string()),
+                ("V1060P_DEVICE", "1-2, 08f2:6811".to_string()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,393 @@
+use async_channel::Sender;
+use async_io::{Async, Timer};
+use futures_lite::{FutureExt as _, pin};
+use rusb::Error as RusbError;
+use tracing::{error, info, warn};
+
+use std::collections::HashSet;
+use std::io::{self, Read, Write};
+use std::mem;
+use std::ops::ControlFlow;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, mpsc};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::exit_notice::{ExitNotice, Wakeup};
+use crate::physical_device::{Backend, PhysicalDevice, ReportFormat};
+use crate::virtual_device::MAX_REPORT_LENGTH;
+
+// How long the tablet may stay quiet before it counts as idle.
+const IDLE_TIMEOUT: Duration = Duration::from_millis(250);
+
+// Bus number and address of a connected tablet.
+pub type TabletId = (u8, u8);
+
+// What a tablet's reader sends on. The channel closes once the tablet is let go of.
+pub enum TabletEvent {
+    Report {
+        data: Vec<u8>,
+        // When the read returned, as close to the hardware as the driver gets.
+        at: Instant,
+    },
+    // Reports stopped, e.g. the pen left the tablet. Sent once until they resume.
+    Idle,
+}
+
+// Errors after which the device handle is useless and must be reopened.
+pub fn is_disconnect(err: &RusbError) -> bool {
+    matches!(
+        err,
+        RusbError::NoDevice | RusbError::Io | RusbError::Pipe | RusbError::NotFound
+    )
+}
+
+// A tablet the watcher opened, ready to be read. The watcher looks for it again once
+// it is dropped, in case it is still plugged in.
+pub struct ConnectedTablet {
+    pub id: TabletId,
+    // The names the config can select the tablet by.
+    pub identifiers: Vec<String>,
+    pub format: ReportFormat,
+    // Before `_active`, so the tablet is let go of by the time the watcher looks.
+    device: PhysicalDevice,
+    _active: Active,
+}
+
+impl ConnectedTablet {
+    // Reads reports into `reports` until the tablet is unplugged or the driver exits,
+    // then lets go of the tablet and closes `reports`. hidraw nodes are waited on by
+    // the reactor; libusb reads block, so they get a thread of their own.
+    pub async fn read(self, reports: Sender<TabletEvent>, exit: Arc<ExitNotice>) {
+        let node = self.device.report_fd().map(|fd| fd.try_clone_to_owned().and_then(Async::new));
+        match node {
+            Some(Ok(node)) => {
+                self.read_on_reactor(&node, &reports, &exit).await;
+                drop(self);
+                drop(reports);
+            }
+            _ => blocking::unblock(move || self.read_blocking(reports, &exit)).await,
+        }
+    }
+
+    async fn read_on_reactor(
+        &self,
+        node: &Async<OwnedFd>,
+        reports: &Sender<TabletEvent>,
+        exit: &ExitNotice,
+    ) {
+        let mut buffer = vec![0u8; MAX_REPORT_LENGTH];
+        let mut is_idle = false;
+        let exited = exit.exited();
+        pin!(exited);
+        loop {
+            let read = async {
+                loop {
+                    if let Err(err) = node.readable().await {
+                        return Err(io_error(err));
+                    }
+                    // Readiness may be reported without a report to read.
+                    match self.device.read_with_timeout(&mut buffer, Duration::ZERO) {
+                        Err(RusbError::Timeout) => continue,
+                        result => return result,
+                    }
+                }
+            };
+            // Once idle, nothing is due until the next report.
+            let idle = async {
+                match is_idle {
+                    true => Timer::never().await,
+                    false => Timer::after(IDLE_TIMEOUT).await,
+                };
+                Err(RusbError::Timeout)
+            };
+            let exiting = async {
+                exited.as_mut().await;
+                Err(RusbError::Interrupted)
+            };
+            let result = exiting.or(read.or(idle)).await;
+            let event = match next_event(result, &buffer, &mut is_idle) {
+                ControlFlow::Continue(Some(event)) => event,
+                ControlFlow::Continue(None) => continue,
+                ControlFlow::Break(()) => return,
+            };
+            if reports.send(event).await.is_err() {
+                return;
+            }
+        }
+    }
+
+    fn read_blocking(self, reports: Sender<TabletEvent>, exit: &ExitNotice) {
+        let mut buffer = vec![0u8; MAX_REPORT_LENGTH];
+        let mut is_idle = false;
+        loop {
+            // Once idle, nothing is due until the next report.
+            let timeout = (!is_idle).then_some(IDLE_TIMEOUT);
+            let result = self.device.read_unless_exiting(&mut buffer, timeout, exit);
+            let event = match next_event(result, &buffer, &mut is_idle) {
+                ControlFlow::Continue(Some(event)) => event,
+                ControlFlow::Continue(None) => continue,
+                ControlFlow::Break(()) => break,
+            };
+            if reports.send_blocking(event).is_err() {
+                break;
+            }
+        }
+        drop(self);
+        drop(reports);
+    }
+}
+
+// What a read gives the reader to send on, if anything, or whether it is done.
+fn next_event(
+    result: Result<usize, RusbError>,
+    buffer: &[u8],
+    is_idle: &mut bool,
+) -> ControlFlow<(), Option<TabletEvent>> {
+    match result {
+        Ok(length) => {
+            *is_idle = false;
+            ControlFlow::Continue(Some(TabletEvent::Report {
+                data: buffer[..length].to_vec(),
+                at: Instant::now(),
+            }))
+        }
+        Err(RusbError::Timeout) => {
+            *is_idle = true;
+            ControlFlow::Continue(Some(TabletEvent::Idle))
+        }
+        Err(RusbError::Interrupted) => ControlFlow::Break(()),
+        Err(err) if is_disconnect(&err) => ControlFlow::Break(()),
+        Err(_) => ControlFlow::Continue(None),
+    }
+}
+
+fn io_error(err: io::Error) -> RusbError {
+    match err.raw_os_error() {
+        Some(libc::ENODEV) => RusbError::NoDevice,
+        _ => RusbError::Io,
+    }
+}
+
+// Keeps a tablet counted as connected. Dropping it wakes the watcher.
+struct Active {
+    id: TabletId,
+    active: Arc<Mutex<HashSet<TabletId>>>,
+    ended: Arc<UnixStream>,
+}
+
+impl Drop for Active {
+    fn drop(&mut self) {
+        self.active.lock().unwrap().remove(&self.id);
+        let _ = (&*self.ended).write(&[0]);
+    }
+}
+
+// Scans the bus for tablets whenever a USB device or hidraw node is added, until the
+// driver exits, and hands each one it opens over to `sender`.
+pub fn spawn_tablet_watcher(
+    device_path: Option<PathBuf>,
+    backend: Backend,
+    exit: Arc<ExitNotice>,
+    sender: mpsc::Sender<ConnectedTablet>,
+) {
+    thread::spawn(move || {
+        let monitor = UeventMonitor::open()
+            .inspect_err(|err| warn!("Tablets plugged in later won't be noticed: {}", err))
+            .ok();
+        // Tablets that are let go of write to it, so one still plugged in is opened
+        // again.
+        let (ended, ended_tablets) = UnixStream::pair().expect("Error creating a socket pair.");
+        ended_tablets.set_nonblocking(true).expect("Error creating a socket pair.");
+        let ended = Arc::new(ended);
+        let active = Arc::new(Mutex::new(HashSet::new()));
+        let mut announced = false;
+
+        loop {
+            let devices = PhysicalDevice::locate_all(device_path.as_deref()).unwrap_or_default();
+            for device in devices {
+                let id = (device.bus_number(), device.address());
+                if !active.lock().unwrap().insert(id) {
+                    continue;
+                }
+                match PhysicalDevice::open(device, backend) {
+                    Ok(device) => {
+                        let tablet = ConnectedTablet {
+                            id,
+                            identifiers: device.identifiers(),
+                            format: device.report_format(),
+                            device,
+                            _active: Active {
+                                id,
+                                active: active.clone(),
+                                ended: ended.clone(),
+                            },
+                        };
+                        if sender.send(tablet).is_err() {
+                            return;
+                        }
+                    }
+                    // Retried on the tablet's next uevent, once udev got to its nodes.
+                    Err(err) => {
+                        error!("Error setting up the tablet: {}", err);
+                        active.lock().unwrap().remove(&id);
+                    }
+                }
+            }
+
+            match active.lock().unwrap().is_empty() {
+                true if !announced => {
+                    info!("Waiting for the tablet to be connected...");
+                    announced = true;
+                }
+                true => {}
+                false => announced = false,
+            }
+
+            match wait_for_change(monitor.as_ref(), &ended_tablets, &exit) {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(err) => {
+                    error!("Error waiting for tablets: {}", err);
+                    return;
+                }
+            }
+        }
+        exit.usb_reads().cancel_all();
+    });
+}
+
+// Sleeps until a USB device or hidraw node is added or a tablet is let go of, or,
+// with false, until the driver exits.
+fn wait_for_change(
+    monitor: Option<&UeventMonitor>,
+    ended_tablets: &UnixStream,
+    exit: &ExitNotice,
+) -> io::Result<bool> {
+    let mut fds = vec![ended_tablets.as_raw_fd()];
+    fds.extend(monitor.map(|monitor| monitor.fd.as_raw_fd()));
+    loop {
+        if exit.wait_readable(&fds, None)? == Wakeup::Exit {
+            return Ok(false);
+        }
+        let has_ended = (&*ended_tablets).read(&mut [0; 64]).is_ok_and(|length| length > 0);
+        if monitor.is_some_and(UeventMonitor::has_added) || has_ended {
+            return Ok(true);
+        }
+    }
+}
+
+// Kernel uevents, sent as devices come and go, and the same uevents again once udev
+// has applied its rules to them, permissions included.
+const UEVENT_GROUPS: u32 = 0b11;
+
+// A netlink socket receiving uevents.
+struct UeventMonitor {
+    fd: OwnedFd,
+}
+
+impl UeventMonitor {
+    fn open() -> io::Result<Self> {
+        // SAFETY: plain socket(2), whose result is checked.
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_DGRAM | libc::SOCK_CLOEXEC | libc::SOCK_NONBLOCK,
+                libc::NETLINK_KOBJECT_UEVENT,
+            )
+        };
+        if fd == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: `fd` was just opened and belongs to nothing else.
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        // SAFETY: all zeroes is a valid sockaddr_nl.
+        let mut address: libc::sockaddr_nl = unsafe { mem::zeroed() };
+        address.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        address.nl_groups = UEVENT_GROUPS;
+        // SAFETY: `address` is a sockaddr_nl of the length given.
+        let result = unsafe {
+            libc::bind(
+                fd.as_raw_fd(),
+                &address as *const libc::sockaddr_nl as *const libc::sockaddr,
+                mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            )
+        };
+        match result {
+            -1 => Err(io::Error::last_os_error()),
+            _ => Ok(UeventMonitor { fd }),
+        }
+    }
+
+    // Reads the uevents queued up, and whether any of them added a USB device or a
+    // hidraw node.
+    fn has_added(&self) -> bool {
+        let mut buffer = vec![0u8; 8192];
+        let mut has_added = false;
+        loop {
+            // SAFETY: `buffer` is writable for its length.
+            let length = unsafe {
+                libc::recv(self.fd.as_raw_fd(), buffer.as_mut_ptr().cast(), buffer.len(), 0)
+            };
+            if length < 0 {
+                return has_added;
+            }
+            has_added |= is_added_device(&buffer[..length as usize]);
+        }
+    }
+}
+
+// Whether the uevent message adds a device the driver may open. The kernel sends
+// `action@devpath` and udev a binary header first, followed by NUL separated
+// KEY=value properties in both.
+fn is_added_device(message: &[u8]) -> bool {
+    let property = |key: &[u8]| {
+        message
+            .split(|&byte| byte == 0)
+            .find_map(|field| field.strip_prefix(key)?.strip_prefix(b"="))
+    };
+    matches!(property(b"ACTION"), Some(b"add" | b"bind"))
+        && matches!(property(b"SUBSYSTEM"), Some(b"usb" | b"hidraw"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_turn_into_reports_idles_or_the_end() {
+        let buffer = [0x08, 0x80, 0x12];
+        let mut is_idle = true;
+        let event = next_event(Ok(2), &buffer, &mut is_idle);
+        assert!(matches!(event, ControlFlow::Continue(Some(TabletEvent::Report { data, .. }))
+            if data == [0x08, 0x80]));
+        assert!(!is_idle);
+
+        let event = next_event(Err(RusbError::Timeout), &buffer, &mut is_idle);
+        assert!(matches!(event, ControlFlow::Continue(Some(TabletEvent::Idle))));
+        assert!(is_idle);
+
+        let event = next_event(Err(RusbError::Overflow), &buffer, &mut is_idle);
+        assert!(matches!(event, ControlFlow::Continue(None)));
+        for err in [RusbError::Interrupted, RusbError::NoDevice] {
+            assert!(next_event(Err(err), &buffer, &mut is_idle).is_break());
+        }
+    }
+
+    #[test]
+    fn uevents_adding_usb_devices_or_hidraw_nodes_are_picked_out() {
+        let kernel = b"add@/devices/pci0000:00/0000:00:14.0/usb1/1-2\0ACTION=add\0\
+                       DEVPATH=/devices/pci0000:00/0000:00:14.0/usb1/1-2\0SUBSYSTEM=usb\0\
+                       DEVTYPE=usb_device\0SEQNUM=4711\0";
+        assert!(is_added_device(kernel));
+
+        let udev = b"libudev\0\xfe\xed\xca\xfe(\0\0\0\0ACTION=bind\0SUBSYSTEM=hidraw\0";
+        assert!(is_added_device(udev));
+
+        assert!(!is_added_device(b"remove@/x\0ACTION=remove\0SUBSYSTEM=usb\0"));
+        assert!(!is_added_device(b"add@/x\0ACTION=add\0SUBSYSTEM=input\0"));
+        assert!(!is_added_device(b"add@/x\0ACTION=add\0SUBSYSTEM=usbmisc\0"));
+    }
+}
@@ -0,0 +1,399 @@
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+use std::time::Duration;
+
+use crate::binding::Binding;
+use crate::config::{self, Mode, ProfileSwitch, Rotation};
+use crate::keys;
+use crate::pressure::{MAX_PRESSURE, PressureCurve};
+use crate::privileges::is_root;
+
+use evdev::Key;
+
+const REPLY_TIMEOUT: Duration = Duration::from_secs(2);
+
+pub enum Request {
+    SetMode(Mode),
+    ToggleMode,
+    SetArea(f32),
+    SwitchProfile(ProfileSwitch),
+    Set(Setting),
+    // `is_json` answers with a JSON object for status bars.
+    GetStatus { is_json: bool },
+}
+
+// A runtime change from `set`, named loosely after xsetwacom's parameters so its
+// scripts and guides carry over. Lasts until the config is reloaded.
+#[derive(Debug)]
+pub enum Setting {
+    // Tablet-mode area as left, top, right, bottom; None goes back to the config's.
+    Area(Option<[i32; 4]>),
+    Button(u8, Binding),
+    Mode(Mode),
+    Rotate(Rotation),
+    MapToOutput(String),
+    PressureCurve(PressureCurve),
+    // On, off, or None to toggle.
+    Stabilizer(Option<bool>),
+}
+
+pub struct Status {
+    pub is_mouse_mode: bool,
+    pub mouse_area_scale: f32,
+    pub profile: Option<String>,
+    // Pressure the pen sends right now, 0 to MAX_PRESSURE.
+    pub pressure: i32,
+    // Identifiers of the first connected tablet, if any.
+    pub device: Option<String>,
+    pub uptime: Duration,
+    // Reports per second the tablet sent over the last second.
+    pub report_rate: f32,
+    pub counters: Counters,
+}
+
+// Running totals for a tablet since the driver started, carried over when it is
+// plugged back in, so a flaky cable or hub shows up in them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Counters {
+    pub reports: u64,
+    // Reports dropped instead of being dispatched.
+    pub malformed: u64,
+    // Times events failed to reach the virtual devices.
+    pub emit_errors: u64,
+    pub reconnects: u64,
+}
+
+impl Status {
+    // One line, with waybar's `text` and `class` so it can be used as is, and the
+    // values on their own for other bars.
+    pub fn to_json(&self) -> String {
+        let mode = if self.is_mouse_mode { "mouse" } else { "tablet" };
+        let area = (self.mouse_area_scale * 100.0).round() as u32;
+        let text = match self.is_mouse_mode {
+            true => format!("MOUSE {}%", area),
+            false => "TABLET".to_string(),
+        };
+        serde_json::json!({
+            "text": text,
+            "class": mode,
+            "connected": true,
+            "mode": mode,
+            "area": area,
+            "pressure": self.pressure,
+            "pressure_max": MAX_PRESSURE,
+            // The tablet is powered over USB and its pen needs no battery.
+            "battery": null,
+            "profile": self.profile.as_deref().unwrap_or("default"),
+            "device": self.device,
+            "reports": self.counters.reports,
+            "malformed": self.counters.malformed,
+            "emit_errors": self.counters.emit_errors,
+            "reconnects": self.counters.reconnects,
+        })
+        .to_string()
+    }
+
+    // What `status --json` prints while the driver or the tablet is away.
+    pub fn disconnected_json() -> String {
+        serde_json::json!({
+            "text": "",
+            "class": "disconnected",
+            "connected": false,
+        })
+        .to_string()
+    }
+}
+
+pub enum Response {
+    Ok,
+    Status(Status),
+    StatusJson(Status),
+    Error(String),
+}
+
+impl fmt::Display for Response {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Response::Ok => write!(f, "ok"),
+            Response::Status(status) => write!(
+                f,
+                "mode={} area={:.0} profile={} device={} uptime={}s rate={:.0}/s reports={} \
+                 malformed={} emit_errors={} reconnects={}",
+                if status.is_mouse_mode { "mouse" } else { "tablet" },
+                status.mouse_area_scale * 100.0,
+                status.profile.as_deref().unwrap_or("default"),
+                status.device.as_deref().unwrap_or("none"),
+                status.uptime.as_secs(),
+                status.report_rate,
+                status.counters.reports,
+                status.counters.malformed,
+                status.counters.emit_errors,
+                status.counters.reconnects
+            ),
+            Response::StatusJson(status) => write!(f, "{}", status.to_json()),
+            Response::Error(err) => write!(f, "error: {}", err),
+        }
+    }
+}
+
+// A control request (from the socket or D-Bus), answered by the main loop through `reply`.
+pub struct Message {
+    pub request: Request,
+    reply: Sender<Response>,
+}
+
+impl Message {
+    pub fn reply(self, response: Response) {
+        let _ = self.reply.send(response);
+    }
+}
+
+// Hands `request` to the main loop and waits for its answer.
+pub fn send_request(sender: &Sender<Message>, request: Request) -> Response {
+    let (reply, response) = mpsc::channel();
+    if sender.send(Message { request, reply }).is_err() {
+        return Response::Error("the driver is shutting down".to_string());
+    }
+    response
+        .recv_timeout(REPLY_TIMEOUT)
+        .unwrap_or_else(|_| Response::Error("the driver did not answer in time".to_string()))
+}
+
+// /run/vinsa-driver/vinsa-driver.sock when running as root, in a directory of its own
+// so it can still be removed after the driver switches users; else
+// $XDG_RUNTIME_DIR/vinsa-driver.sock.
+pub fn socket_path() -> PathBuf {
+    match env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) if !dir.is_empty() && !is_root() => PathBuf::from(dir).join("vinsa-driver.sock"),
+        _ => PathBuf::from("/run/vinsa-driver/vinsa-driver.sock"),
+    }
+}
+
+// Sends one command to the running driver and returns its one-line answer.
+pub fn query(path: &Path, command: &str) -> std::io::Result<String> {
+    let mut stream = UnixStream::connect(path)?;
+    stream.set_read_timeout(Some(REPLY_TIMEOUT * 2))?;
+    writeln!(stream, "{}", command)?;
+    let mut reply = String::new();
+    BufReader::new(stream).read_line(&mut reply)?;
+    Ok(reply.trim_end().to_string())
+}
+
+pub fn parse_request(line: &str) -> Result<Request, String> {
+    let mut words = line.split_whitespace();
+    let command = words.next().ok_or("empty command")?;
+    let argument = words.next();
+
+    match (command, argument) {
+        ("set-mode", Some("mouse")) => Ok(Request::SetMode(Mode::Mouse)),
+        ("set-mode", Some("tablet")) => Ok(Request::SetMode(Mode::Tablet)),
+        ("set-mode", Some("toggle")) => Ok(Request::ToggleMode),
+        ("set-mode", _) => Err("usage: set-mode mouse|tablet|toggle".to_string()),
+        ("set-area", Some(percent)) => percent
+            .trim_end_matches('%')
+            .parse::<f32>()
+            .ok()
+            .filter(|percent| (10.0..=100.0).contains(percent))
+            .map(|percent| Request::SetArea(percent / 100.0))
+            .ok_or_else(|| "usage: set-area <10-100>".to_string()),
+        ("set-area", None) => Err("usage: set-area <10-100>".to_string()),
+        ("set-profile", Some(name)) => Ok(Request::SwitchProfile(ProfileSwitch::select(name))),
+        ("set-profile", None) => Err("usage: set-profile <name>|default".to_string()),
+        ("next-profile", _) => Ok(Request::SwitchProfile(ProfileSwitch::Next)),
+        ("set", Some(parameter)) => parse_setting(parameter, words.collect()).map(Request::Set),
+        ("set", None) => Err(SET_USAGE.to_string()),
+        ("get-status", Some("json")) => Ok(Request::GetStatus { is_json: true }),
+        ("get-status", _) => Ok(Request::GetStatus { is_json: false }),
+        (command, _) => Err(format!("unknown command `{}`", command)),
+    }
+}
+
+const SET_USAGE: &str =
+    "usage: set Area|ResetArea|Button|Mode|Rotate|MapToOutput|PressureCurve|Stabilizer <values>";
+
+fn parse_setting(parameter: &str, values: Vec<&str>) -> Result<Setting, String> {
+    let numbers = |count: usize| -> Option<Vec<i32>> {
+        let numbers: Option<Vec<i32>> = values.iter().map(|value| value.parse().ok()).collect();
+        let numbers = numbers?;
+        (numbers.len() == count).then_some(numbers)
+    };
+
+    match parameter.to_lowercase().as_str() {
+        "area" => {
+            let corners = numbers(4).ok_or("usage: set Area <left> <top> <right> <bottom>")?;
+            let rect = [corners[0], corners[1], corners[2], corners[3]];
+            Ok(Setting::Area(Some(config::validate_area_rect(rect)?)))
+        }
+        "resetarea" => Ok(Setting::Area(None)),
+        "button" => {
+            let usage = "usage: set Button <0-13> \"key ctrl z\"|\"button 3\"|<binding>";
+            let (id, action) = values.split_first().ok_or(usage)?;
+            let id = id.parse::<u8>().ok().filter(|id| *id < 14).ok_or(usage)?;
+            Ok(Setting::Button(id, parse_button_action(action)?))
+        }
+        "mode" => match values.first().map(|mode| mode.to_lowercase()).as_deref() {
+            Some("absolute") => Ok(Setting::Mode(Mode::Tablet)),
+            Some("relative") => Ok(Setting::Mode(Mode::Mouse)),
+            _ => Err("usage: set Mode absolute|relative".to_string()),
+        },
+        "rotate" => match values.first().map(|rotate| rotate.to_lowercase()).as_deref() {
+            Some("none") => Ok(Setting::Rotate(Rotation::Deg0)),
+            Some("cw") => Ok(Setting::Rotate(Rotation::Deg90)),
+            Some("half") => Ok(Setting::Rotate(Rotation::Deg180)),
+            Some("ccw") => Ok(Setting::Rotate(Rotation::Deg270)),
+            _ => Err("usage: set Rotate none|cw|half|ccw".to_string()),
+        },
+        "maptooutput" => match values.as_slice() {
+            [output] => Ok(Setting::MapToOutput(output.to_string())),
+            _ => Err("usage: set MapToOutput <output>|<WxH+X+Y>".to_string()),
+        },
+        // Two control points from 0 to 100, as xsetwacom takes them.
+        "pressurecurve" => {
+            let points = numbers(4).ok_or("usage: set PressureCurve <x1> <y1> <x2> <y2>")?;
+            let point = |i: usize| [points[i] as f32 / 100.0, points[i + 1] as f32 / 100.0];
+            PressureCurve::try_from(vec![point(0), point(2)]).map(Setting::PressureCurve)
+        }
+        "stabilizer" => match values.first().map(|state| state.to_lowercase()).as_deref() {
+            Some("on") => Ok(Setting::Stabilizer(Some(true))),
+            Some("off") => Ok(Setting::Stabilizer(Some(false))),
+            Some("toggle") => Ok(Setting::Stabilizer(None)),
+            _ => Err("usage: set Stabilizer on|off|toggle".to_string()),
+        },
+        _ => Err(SET_USAGE.to_string()),
+    }
+}
+
+// xsetwacom's "key +ctrl z -ctrl" and "button 3", or a binding as written in the
+// config ("ctrl+z", "undo", "precision").
+fn parse_button_action(words: &[&str]) -> Result<Binding, String> {
+    match words {
+        ["key", keys @ ..] if !keys.is_empty() => keys
+            .iter()
+            // Releases are implied, since the keys are let go with the button.
+            .filter(|key| !key.starts_with('-'))
+            .map(|key| keys::parse_key(key.trim_start_matches('+')))
+            .collect::<Result<Vec<Key>, String>>()
+            .map(Binding::Keys),
+        ["button", "1"] => Ok(Binding::Keys(vec![Key::BTN_LEFT])),
+        ["button", "2"] => Ok(Binding::Keys(vec![Key::BTN_MIDDLE])),
+        ["button", "3"] => Ok(Binding::Keys(vec![Key::BTN_RIGHT])),
+        ["button", ..] => Err("only buttons 1-3 can be bound".to_string()),
+        words => words.join(" ").parse(),
+    }
+}
+
+// Accepts line-based commands on `path`; each line gets exactly one line back.
+pub fn spawn_control_socket(path: &Path, sender: Sender<Message>) -> std::io::Result<()> {
+    if path.exists() {
+        fs::remove_file(path)?;
+    }
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let listener = UnixListener::bind(path)?;
+
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let sender = sender.clone();
+            thread::spawn(move || handle_client(stream, sender));
+        }
+    });
+
+    Ok(())
+}
+
+fn handle_client(stream: UnixStream, sender: Sender<Message>) {
+    let Ok(mut writer) = stream.try_clone() else {
+        return;
+    };
+
+    for line in BufReader::new(stream).lines() {
+        let Ok(line) = line else {
+            return;
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let response = match parse_request(&line) {
+            Ok(request) => send_request(&sender, request),
+            Err(err) => Response::Error(err),
+        };
+
+        if writeln!(writer, "{}", response).is_err() {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn setting(line: &str) -> Setting {
+        match parse_request(line) {
+            Ok(Request::Set(setting)) => setting,
+            _ => panic!("`{}` is not a setting", line),
+        }
+    }
+
+    #[test]
+    fn xsetwacom_style_settings_are_parsed() {
+        assert!(matches!(
+            setting("set Area 0 0 4095 2048"),
+            Setting::Area(Some([0, 0, 4095, 2048]))
+        ));
+        assert!(matches!(
+            setting("set Button 2 key +ctrl z -ctrl"),
+            Setting::Button(2, Binding::Keys(keys)) if keys == [Key::KEY_LEFTCTRL, Key::KEY_Z]
+        ));
+        assert!(matches!(
+            setting("set button 9 button 3"),
+            Setting::Button(9, Binding::Keys(keys)) if keys == [Key::BTN_RIGHT]
+        ));
+        assert!(matches!(
+            setting("set Button 1 precision"),
+            Setting::Button(1, Binding::Precision)
+        ));
+        assert!(matches!(setting("set Rotate half"), Setting::Rotate(Rotation::Deg180)));
+        assert!(matches!(setting("set Stabilizer toggle"), Setting::Stabilizer(None)));
+        assert!(parse_request("set Area 100 0 50 50").is_err());
+        assert!(parse_request("set Button 14 key a").is_err());
+    }
+
+    #[test]
+    fn json_status_has_the_values_status_bars_show() {
+        let status = Status {
+            is_mouse_mode: true,
+            mouse_area_scale: 0.4,
+            profile: None,
+            pressure: 1200,
+            device: Some("1-2".to_string()),
+            uptime: Duration::from_secs(5),
+            report_rate: 200.0,
+            counters: Counters {
+                reports: 1000,
+                reconnects: 2,
+                ..Counters::default()
+            },
+        };
+        let json: serde_json::Value = serde_json::from_str(&status.to_json()).unwrap();
+        assert_eq!(json["text"], "MOUSE 40%");
+        assert_eq!(json["mode"], "mouse");
+        assert_eq!(json["area"], 40);
+        assert_eq!(json["pressure"], 1200);
+        assert_eq!(json["profile"], "default");
+        assert!(json["battery"].is_null());
+        assert_eq!((json["reports"].as_u64(), json["reconnects"].as_u64()), (Some(1000), Some(2)));
+        let line = Response::Status(status).to_string();
+        assert!(line.ends_with("reports=1000 malformed=0 emit_errors=0 reconnects=2"), "{}", line);
+        let request = parse_request("get-status json");
+        assert!(matches!(request, Ok(Request::GetStatus { is_json: true })));
+    }
+}
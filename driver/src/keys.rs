@@ -0,0 +1,171 @@
+use evdev::Key;
+use serde::Deserialize;
+use serde::de::{self, Deserializer, SeqAccess, Visitor};
+
+use std::fmt;
+use std::str::FromStr;
+
+// Common shortcuts that can be written by name instead of as a key combination.
+const ACTIONS: &[(&str, &[Key])] = &[
+    ("undo", &[Key::KEY_LEFTCTRL, Key::KEY_Z]),
+    ("redo", &[Key::KEY_LEFTCTRL, Key::KEY_LEFTSHIFT, Key::KEY_Z]),
+    ("copy", &[Key::KEY_LEFTCTRL, Key::KEY_C]),
+    ("cut", &[Key::KEY_LEFTCTRL, Key::KEY_X]),
+    ("paste", &[Key::KEY_LEFTCTRL, Key::KEY_V]),
+    ("save", &[Key::KEY_LEFTCTRL, Key::KEY_S]),
+    ("select_all", &[Key::KEY_LEFTCTRL, Key::KEY_A]),
+    ("zoom_in", &[Key::KEY_LEFTCTRL, Key::KEY_KPPLUS]),
+    ("zoom_out", &[Key::KEY_LEFTCTRL, Key::KEY_KPMINUS]),
+    ("left_click", &[Key::BTN_LEFT]),
+    ("right_click", &[Key::BTN_RIGHT]),
+    ("middle_click", &[Key::BTN_MIDDLE]),
+];
+
+// Short names for keys whose evdev name is awkward to guess.
+const ALIASES: &[(&str, &str)] = &[
+    ("CTRL", "LEFTCTRL"),
+    ("CONTROL", "LEFTCTRL"),
+    ("SHIFT", "LEFTSHIFT"),
+    ("ALT", "LEFTALT"),
+    ("ALTGR", "RIGHTALT"),
+    ("SUPER", "LEFTMETA"),
+    ("META", "LEFTMETA"),
+    ("WIN", "LEFTMETA"),
+    ("RETURN", "ENTER"),
+    ("ESCAPE", "ESC"),
+    ("DEL", "DELETE"),
+    ("INS", "INSERT"),
+    ("PGUP", "PAGEUP"),
+    ("PGDN", "PAGEDOWN"),
+    ("PLUS", "KPPLUS"),
+    ("-", "MINUS"),
+    ("=", "EQUAL"),
+    ("[", "LEFTBRACE"),
+    ("]", "RIGHTBRACE"),
+];
+
+// Mouse, stylus and other BTN_* buttons, as opposed to keyboard keys.
+pub fn is_button(key: Key) -> bool {
+    (Key::BTN_0.code()..Key::KEY_OK.code()).contains(&key.code())
+}
+
+// Every keyboard key and the usual mouse buttons, for when what will be sent isn't
+// known in advance.
+pub fn all_keys() -> impl Iterator<Item = Key> {
+    (Key::KEY_ESC.code()..=Key::KEY_MICMUTE.code())
+        .map(Key::new)
+        .chain([Key::BTN_LEFT, Key::BTN_RIGHT, Key::BTN_MIDDLE])
+}
+
+// One key, by evdev name ("KEY_Z", "BTN_STYLUS") or short name ("z", "ctrl", "pgup").
+pub fn parse_key(name: &str) -> Result<Key, String> {
+    let name = name.trim();
+    if let Ok(key) = Key::from_str(name) {
+        return Ok(key);
+    }
+
+    let upper = name.to_uppercase();
+    let short_name = ALIASES
+        .iter()
+        .find(|(alias, _)| *alias == upper)
+        .map_or(upper.as_str(), |(_, evdev_name)| evdev_name);
+    Key::from_str(&format!("KEY_{}", short_name)).map_err(|_| {
+        format!(
+            "unknown key `{}` (use an evdev name like `KEY_Z` or a short one like `ctrl` or `z`)",
+            name
+        )
+    })
+}
+
+// A shortcut name ("undo") or keys joined with '+' ("KEY_LEFTCTRL+KEY_Z", "ctrl+shift+e").
+pub fn parse_combo(text: &str) -> Result<Vec<Key>, String> {
+    let text = text.trim();
+    if let Some((_, keys)) = ACTIONS
+        .iter()
+        .find(|(action, _)| text.eq_ignore_ascii_case(action))
+    {
+        return Ok(keys.to_vec());
+    }
+    if text.is_empty() {
+        return Err("empty key combination".to_string());
+    }
+
+    text.split('+')
+        .map(|name| parse_key(name).map_err(|err| format!("{} in `{}`", err, text)))
+        .collect()
+}
+
+// Keys written as one combination string or as a list of them, e.g. "ctrl+z" or
+// ["KEY_LEFTCTRL", "KEY_Z"].
+pub struct Combo(pub Vec<Key>);
+
+impl<'de> Deserialize<'de> for Combo {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(ComboVisitor)
+    }
+}
+
+pub(crate) struct ComboVisitor;
+
+impl<'de> Visitor<'de> for ComboVisitor {
+    type Value = Combo;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a key combination like \"ctrl+z\" or a list of key names")
+    }
+
+    fn visit_str<E: de::Error>(self, text: &str) -> Result<Combo, E> {
+        parse_combo(text).map(Combo).map_err(E::custom)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Combo, A::Error> {
+        let mut keys = Vec::new();
+        while let Some(text) = seq.next_element::<String>()? {
+            keys.extend(parse_combo(&text).map_err(de::Error::custom)?);
+        }
+        Ok(Combo(keys))
+    }
+}
+
+pub fn deserialize_key<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Key, D::Error> {
+    let name = String::deserialize(deserializer)?;
+    parse_key(&name).map_err(de::Error::custom)
+}
+
+pub fn deserialize_combo<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Key>, D::Error> {
+    Ok(Combo::deserialize(deserializer)?.0)
+}
+
+pub fn deserialize_combo_list<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<Vec<Key>>, D::Error> {
+    let combos = Vec::<Combo>::deserialize(deserializer)?;
+    Ok(combos.into_iter().map(|combo| combo.0).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn evdev_and_short_names_are_accepted() {
+        assert_eq!(
+            parse_combo("KEY_LEFTCTRL+KEY_Z"),
+            Ok(vec![Key::KEY_LEFTCTRL, Key::KEY_Z])
+        );
+        assert_eq!(
+            parse_combo("Ctrl + Shift + e"),
+            Ok(vec![Key::KEY_LEFTCTRL, Key::KEY_LEFTSHIFT, Key::KEY_E])
+        );
+        assert_eq!(parse_combo("BTN_STYLUS"), Ok(vec![Key::BTN_STYLUS]));
+        assert_eq!(parse_combo("pgup"), Ok(vec![Key::KEY_PAGEUP]));
+        assert_eq!(parse_combo("undo"), Ok(vec![Key::KEY_LEFTCTRL, Key::KEY_Z]));
+    }
+
+    #[test]
+    fn unknown_keys_are_named_in_the_error() {
+        let err = parse_combo("ctrl+KEY_FOO").unwrap_err();
+        assert!(err.starts_with("unknown key `KEY_FOO`"), "{}", err);
+        assert!(err.ends_with("in `ctrl+KEY_FOO`"), "{}", err);
+    }
+}
@@ -0,0 +1,136 @@
+use crate::binding::Binding;
+use crate::virtual_device::RawDataReader;
+
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+
+// Finds the tablet button pressed first. Buttons already held when it starts are
+// ignored until released, so a button still down from the last prompt doesn't count.
+#[derive(Default)]
+pub struct ButtonLearner {
+    // Active-low flags of the last report.
+    last_flags: Option<u16>,
+}
+
+impl ButtonLearner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn feed(&mut self, raw_data: &RawDataReader) -> Option<u8> {
+        let flags = raw_data.tablet_buttons_as_binary_flags();
+        let last_flags = self.last_flags.replace(flags)?;
+        let pressed = last_flags & !flags;
+        (pressed != 0).then(|| pressed.trailing_zeros() as u8)
+    }
+}
+
+// Checks an action typed at the prompt, as it would be written in the config.
+pub fn parse_action(action: &str) -> Result<Binding, String> {
+    action.parse()
+}
+
+// Sets `id = "action"` in the [tablet_buttons] table of the config file, keeping
+// everything else.
+pub fn write_tablet_button(path: &Path, id: u8, action: &str) -> Result<(), Error> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == ErrorKind::NotFound => String::new(),
+        Err(err) => return Err(err),
+    };
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, set_tablet_button(&contents, id, action))
+}
+
+fn set_tablet_button(contents: &str, id: u8, action: &str) -> String {
+    let entry = format!("{} = {}", id, toml::Value::String(action.to_string()));
+    let is_entry = |line: &str| {
+        let key = line.split('=').next().unwrap_or("").trim().trim_matches('"');
+        line.contains('=') && key == id.to_string()
+    };
+
+    let mut lines: Vec<String> = Vec::new();
+    let mut table_end = None;
+    let mut is_in_table = false;
+    let mut is_written = false;
+    for line in contents.lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with('[') {
+            if is_in_table {
+                table_end = Some(lines.len());
+            }
+            is_in_table = trimmed == "[tablet_buttons]";
+        }
+        if is_in_table && !is_written && is_entry(trimmed) {
+            lines.push(entry.clone());
+            is_written = true;
+            continue;
+        }
+        lines.push(line.to_string());
+    }
+    if is_in_table {
+        table_end = Some(lines.len());
+    }
+
+    if !is_written {
+        match table_end {
+            Some(end) => {
+                // After the table's last entry rather than the blank lines that follow it.
+                let end = (0..end)
+                    .rev()
+                    .find(|&i| !lines[i].trim().is_empty())
+                    .map_or(end, |i| i + 1);
+                lines.insert(end, entry);
+            }
+            None => {
+                if lines.last().is_some_and(|line| !line.trim().is_empty()) {
+                    lines.push(String::new());
+                }
+                lines.push("[tablet_buttons]".to_string());
+                lines.push(entry);
+            }
+        }
+    }
+    lines.join("\n") + "\n"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(flags: u16) -> RawDataReader {
+        let mut reader = RawDataReader::new();
+        reader.data[11] = flags as u8;
+        reader.data[12] = (flags >> 8) as u8;
+        reader
+    }
+
+    #[test]
+    fn first_newly_pressed_button_is_found() {
+        let mut learner = ButtonLearner::new();
+        // Button 1 is still held from before.
+        assert_eq!(learner.feed(&report(0xfffd)), None);
+        assert_eq!(learner.feed(&report(0xfffd)), None);
+        assert_eq!(learner.feed(&report(0xffff)), None);
+        assert_eq!(learner.feed(&report(0xefff)), Some(12));
+    }
+
+    #[test]
+    fn binding_is_written_into_the_tablet_buttons_table() {
+        let contents = "output = \"HDMI-1\"\n\n\
+                        [tablet_buttons]\n0 = \"ctrl+z\"\n3 = \"e\"\n\n\
+                        [eraser]\nbutton = 6\n";
+        let replaced = set_tablet_button(contents, 3, "ctrl+shift+z");
+        assert!(replaced.contains("0 = \"ctrl+z\"\n3 = \"ctrl+shift+z\"\n\n[eraser]"));
+
+        let added = set_tablet_button(contents, 7, "precision");
+        assert!(added.contains("3 = \"e\"\n7 = \"precision\"\n\n[eraser]"));
+
+        let created = set_tablet_button("output = \"HDMI-1\"\n", 2, "undo");
+        assert_eq!(created, "output = \"HDMI-1\"\n\n[tablet_buttons]\n2 = \"undo\"\n");
+        assert!(parse_action("not a key").is_err());
+    }
+}
@@ -0,0 +1,66 @@
+//! Userspace driver for the VINSA 1060 Plus drawing tablet (08f2:6811).
+//!
+//! [`physical_device::PhysicalDevice`] reads raw reports through libusb or hidraw,
+//! which are parsed by [`virtual_device::RawDataReader`] with the model's
+//! [`protocol::ReportParser`] and turned into uinput events (or [`uhid`] reports for
+//! the pen) by [`virtual_device::DeviceDispatcher`] according to a [`config::Config`].
+//! While the driver runs, [`hotplug`] hands over each tablet it opens, which is read
+//! in a task of its own feeding the tablet's dispatcher task.
+
+pub mod bench;
+pub mod binding;
+pub mod calibration;
+pub mod capture;
+pub mod config;
+pub mod config_check;
+pub mod config_watch;
+pub mod daemon;
+pub mod dbus;
+pub mod descriptor;
+pub mod device;
+pub mod doctor;
+pub mod double_tap;
+pub mod error;
+pub mod exit_notice;
+pub mod filter;
+pub mod flick;
+pub mod focus_events;
+pub mod gesture;
+pub mod grab;
+pub mod hidraw;
+pub mod hooks;
+pub mod hotplug;
+pub mod ipc;
+pub mod keys;
+pub mod learn;
+pub mod logging;
+pub mod notify;
+#[cfg(feature = "overlay")]
+pub mod overlay;
+pub mod palm;
+pub mod physical_device;
+pub mod pressure;
+pub mod pressure_meter;
+pub mod privileges;
+pub mod profiles;
+pub mod protocol;
+pub mod radial_menu;
+pub mod relative;
+pub mod repeat;
+pub mod screen;
+pub mod service;
+pub mod state;
+pub mod scroll;
+#[cfg(feature = "scripting")]
+pub mod script;
+pub mod tilt;
+pub mod udev;
+pub mod uhid;
+pub mod usb;
+pub mod vendor_init;
+pub mod virtual_device;
+pub mod virtual_output;
+pub mod zone;
+
+pub const VID: u16 = 0x08f2;
+pub const PID: u16 = 0x6811;
@@ -0,0 +1,31 @@
+use tracing_subscriber::EnvFilter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+
+// Mode changes and connection events by default, raw reports with -v, everything with
+// -vv. RUST_LOG (e.g. `RUST_LOG=v1060p::virtual_device=debug`) overrides the flags.
+pub fn init(verbose: u8, journald: bool) {
+    let level = match verbose {
+        0 => "info",
+        1 => "debug",
+        _ => "trace",
+    };
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(level));
+    let registry = tracing_subscriber::registry().with(filter);
+
+    if journald {
+        #[cfg(feature = "journald")]
+        match tracing_journald::layer() {
+            Ok(layer) => return registry.with(layer).init(),
+            Err(err) => eprintln!("Cannot log to the journal, using stderr: {}", err),
+        }
+    }
+
+    registry
+        .with(
+            tracing_subscriber::fmt::layer()
+                .with_target(false)
+                .with_writer(std::io::stderr),
+        )
+        .init();
+}
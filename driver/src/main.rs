@@ -1,53 +1,1561 @@
-mod virtual_device;
-mod physical_device;
+mod cli;
 
+use async_executor::LocalExecutor;
+use async_io::Timer;
+use clap::Parser;
+use futures_lite::FutureExt as _;
+use indexmap::IndexMap;
+use rusb::Error as RusbError;
 use signal_hook::consts::signal::*;
-use signal_hook::flag::register;
-use std::sync::atomic::{AtomicBool, Ordering};
+use signal_hook::flag::{register, register_conditional_shutdown};
+use signal_hook::iterator::Signals;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::Write;
 use std::sync::Arc;
-use std::u16;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::{Duration, Instant};
 
-use physical_device::PhysicalDevice;
-use virtual_device::{DeviceDispatcher, RawDataReader};
+use cli::{Cli, Command, ConfigAction};
+use v1060p::bench::LatencyStats;
+use v1060p::calibration::{self, Calibrator};
+use v1060p::capture::{self, Recorder};
+use v1060p::config::{Config, Mode, PenBackend, ProfileSwitch};
+use v1060p::error::Result;
+use v1060p::exit_notice::ExitNotice;
+use v1060p::grab::KernelGrab;
+use v1060p::hooks::{self, Hook, HookState};
+use v1060p::hotplug::{ConnectedTablet, TabletEvent, TabletId};
+use v1060p::ipc::{self, Counters, Message, Request, Response, Setting, Status};
+use v1060p::learn::{self, ButtonLearner};
+#[cfg(feature = "overlay")]
+use v1060p::overlay::{self, Area, Menu, OverlayCommand};
+use v1060p::physical_device::{Backend, PhysicalDevice, ReportFormat};
+use v1060p::pressure_meter::PressureMeter;
+use v1060p::state::RuntimeState;
+use v1060p::virtual_device::{DeviceDispatcher, MAX_REPORT_LENGTH, RawDataReader};
+use v1060p::{
+    config_check, config_watch, daemon, dbus, doctor, hotplug, logging, notify, privileges,
+    profiles, screen, service, udev,
+};
 
-const VID: u16 = 0x08f2;
-const PID: u16 = 0x6811;
+use tracing::{debug, error, info, warn};
+
+// Everything the coordinator waits on, gathered on one channel so it can sleep until
+// any of them has work instead of polling each in turn.
+enum LoopEvent {
+    Connected(Box<ConnectedTablet>),
+    // A tablet's dispatcher task handled a report or let go of the tablet, which may
+    // have changed the state that is saved and shown.
+    TabletChanged,
+    Control(Message),
+    ConfigChanged,
+    WindowClass(String),
+    // Monitor with the cursor or focused window, when the mapping follows it.
+    FocusedMonitor(String),
+    // Wakes the coordinator to start shutting down; the exit notice tells the readers.
+    Exit,
+}
 
 fn main() {
+    let cli = Cli::parse();
+    logging::init(cli.verbose, cli.journald());
+    // Before loading it, which gives up on a broken file.
+    if let Some(Command::Config { action }) = &cli.command {
+        config_command(&cli, action);
+        return;
+    }
+    let config = Config::load(cli.config.as_deref());
+
+    match &cli.command {
+        Some(Command::Calibrate) => {
+            calibrate(&cli, &config);
+            return;
+        }
+        Some(Command::Learn) => {
+            learn(&cli, &config);
+            return;
+        }
+        Some(Command::TestPressure) => {
+            test_pressure(&cli, &config);
+            return;
+        }
+        Some(Command::Bench { seconds, rate }) => {
+            bench(&cli, &config, Duration::from_secs(*seconds), *rate);
+            return;
+        }
+        Some(Command::Config { .. }) => unreachable!("handled before loading the config"),
+        Some(Command::Doctor) => {
+            doctor(&cli, &config);
+            return;
+        }
+        Some(Command::InstallUdev { group, print }) => {
+            install_udev(group, *print);
+            return;
+        }
+        Some(Command::InstallService { user, print }) => {
+            install_service(*user, *print);
+            return;
+        }
+        Some(Command::Status { json, follow }) => {
+            match (*json, *follow) {
+                (true, true) => follow_status(),
+                (true, false) => println!("{}", json_status()),
+                _ => status(),
+            }
+            return;
+        }
+        Some(Command::Set { parameter, values }) => {
+            set(parameter, values);
+            return;
+        }
+        Some(Command::Profile { name }) => {
+            match name.as_str() {
+                "next" => send_command("next-profile"),
+                name => send_command(&format!("set-profile {}", name)),
+            }
+            return;
+        }
+        None => {}
+    }
+
+    if let Some(path) = &cli.replay {
+        replay(path, &config, &cli);
+        return;
+    }
+
+    if cli.dry_run {
+        let device = PhysicalDevice::locate(cli.device.as_deref()).unwrap_or_else(|err| {
+            error!("Error finding the tablet: {}", err);
+            std::process::exit(1);
+        });
+        println!(
+            "Found tablet on bus {:03} address {:03}.",
+            device.bus_number(),
+            device.address()
+        );
+        println!("Dry run: configuration is valid, exiting.");
+        return;
+    }
+
+    // Before anything starts a thread, since only this one survives the fork.
+    let _pid_file = cli.daemon.then(|| {
+        let path = cli.pid_file.clone().unwrap_or_else(daemon::pid_file_path);
+        daemon::daemonize(&path).unwrap_or_else(|err| {
+            error!("Error starting the daemon: {}", err);
+            std::process::exit(1);
+        })
+    });
+    let started = Instant::now();
+    let exit = register_exit_signals();
+    let (event_sender, events) = async_channel::unbounded();
+    wake_on_exit_signals(event_sender.clone());
+    let state_path = RuntimeState::default_path().filter(|_| config.restore_state);
+    let saved_state = state_path.as_deref().and_then(RuntimeState::load);
+    let mode = cli.mode.or(saved_state.map(|state| state.mode));
+    let is_mouse_mode = mode.unwrap_or(config.mode) == Mode::Mouse;
+    let mouse_area_scale = cli
+        .mouse_area_scale
+        .or(saved_state.map(|state| state.mouse_area_scale));
+    let recorder = cli.record.as_deref().map(|path| {
+        Recorder::create(path).unwrap_or_else(|err| {
+            error!("Error creating {}: {}", path.display(), err);
+            std::process::exit(1);
+        })
+    });
+
+    let config_path = Config::path(cli.config.as_deref());
+    if let Some(path) = config_path.clone() {
+        let changes = config_watch::spawn_config_watcher(path);
+        forward(changes, &event_sender, |()| LoopEvent::ConfigChanged);
+    }
+
+    let socket_path = ipc::socket_path();
+    let (control_sender, control_messages) = mpsc::channel();
+    forward(control_messages, &event_sender, LoopEvent::Control);
+    let has_socket = ipc::spawn_control_socket(&socket_path, control_sender.clone())
+        .inspect_err(|err| warn!("Error creating {}: {}", socket_path.display(), err))
+        .is_ok();
+    let _dbus_connection = dbus::spawn_dbus_service(control_sender)
+        .inspect_err(|err| warn!("D-Bus interface not available: {}", err))
+        .ok();
+    let notifier = config
+        .notifications
+        .then(|| {
+            notify::spawn_notifier()
+                .inspect_err(|err| warn!("Desktop notifications not available: {}", err))
+                .ok()
+        })
+        .flatten();
+    let (tablet_sender, connected_tablets) = mpsc::channel();
+    let device = cli.device.clone();
+    let exit_notice =
+        ExitNotice::on_signals(&EXIT_SIGNALS).expect("Error registering interrupt signals.");
+    let exit_notice = Arc::new(exit_notice);
+    hotplug::spawn_tablet_watcher(device, cli.backend, Arc::clone(&exit_notice), tablet_sender);
+    forward(connected_tablets, &event_sender, |tablet| LoopEvent::Connected(Box::new(tablet)));
+
+    let driver = Rc::new(RefCell::new(Driver {
+        config,
+        config_path,
+        backend: cli.backend,
+        debug_raw: cli.debug_raw,
+        started,
+        tablets: IndexMap::new(),
+        is_mouse_mode,
+        mouse_area_scale,
+        active_profile: None,
+        focused_monitor: None,
+        state_path,
+        saved_state,
+        notifier,
+        notified_state: None,
+        hooked_mode: None,
+        recorder,
+        socket_path: has_socket.then_some(socket_path),
+        has_dropped_privileges: false,
+        past_counters: HashMap::new(),
+        #[cfg(feature = "overlay")]
+        overlay: None,
+        #[cfg(feature = "overlay")]
+        shown_menu: None,
+        #[cfg(feature = "overlay")]
+        shown_area_scale: None,
+    }));
+    let executor = LocalExecutor::new();
+    let coordinator = Coordinator {
+        executor: &executor,
+        driver: Rc::clone(&driver),
+        events,
+        event_sender,
+        exit,
+        exit_notice,
+        is_watching_windows: false,
+        is_following_monitors: false,
+    };
+    async_io::block_on(executor.run(coordinator.run()));
+
+    if let Some(socket_path) = &driver.borrow().socket_path {
+        let _ = std::fs::remove_file(socket_path);
+    }
+    info!("The driver has exited.");
+}
+
+// Handles everything but the tablets' reports. Each connected tablet gets a reader
+// task, sending on what it reads, and a dispatcher task driving the tablet's virtual
+// devices from that, which tells the coordinator when it is done with a report.
+// All tasks run on this thread and share the `Driver`, never across an await.
+struct Coordinator<'a> {
+    executor: &'a LocalExecutor<'static>,
+    driver: Rc<RefCell<Driver>>,
+    events: async_channel::Receiver<LoopEvent>,
+    event_sender: async_channel::Sender<LoopEvent>,
+    exit: Arc<AtomicBool>,
+    exit_notice: Arc<ExitNotice>,
+    is_watching_windows: bool,
+    is_following_monitors: bool,
+}
+
+impl Coordinator<'_> {
+    async fn run(mut self) {
+        while self.follow_up() {
+            let Ok(event) = self.events.recv().await else {
+                break;
+            };
+            match event {
+                LoopEvent::Connected(tablet) => self.connect(*tablet),
+                LoopEvent::TabletChanged => {}
+                event => self.driver.borrow_mut().handle(event),
+            }
+        }
+    }
+
+    // Starts the watchers the config asks for and follows up on the last event, or
+    // tells that the driver is done.
+    fn follow_up(&mut self) -> bool {
+        let mut driver = self.driver.borrow_mut();
+        // Keep going after a signal until the readers have released their tablets.
+        if self.exit.load(Ordering::Relaxed) && driver.tablets.is_empty() {
+            return false;
+        }
+
+        let config = &driver.config;
+        if !self.is_watching_windows
+            && config.profiles.values().any(|p| !p.window_class.is_empty())
+        {
+            let windows = profiles::spawn_window_watcher();
+            forward(windows, &self.event_sender, LoopEvent::WindowClass);
+            self.is_watching_windows = true;
+        }
+        if !self.is_following_monitors && let Some(follow) = config.follow_monitor {
+            let monitors = screen::spawn_monitor_watcher(follow);
+            forward(monitors, &self.event_sender, LoopEvent::FocusedMonitor);
+            self.is_following_monitors = true;
+        }
+        driver.show_changes();
+        true
+    }
+
+    // Sets up the tablet's virtual devices and starts its reader and dispatcher tasks.
+    // A tablet that can't be set up is still read, and its reports dropped, until it
+    // is unplugged.
+    fn connect(&self, tablet: ConnectedTablet) {
+        let id = tablet.id;
+        self.driver.borrow_mut().connect(&tablet);
+        let (report_sender, reports) = async_channel::unbounded();
+        let exit_notice = Arc::clone(&self.exit_notice);
+        self.executor.spawn(tablet.read(report_sender, exit_notice)).detach();
+        let driver = Rc::clone(&self.driver);
+        let changes = self.event_sender.clone();
+        self.executor.spawn(drive_tablet(id, reports, driver, changes)).detach();
+    }
+}
 
-    let mut physical_device = PhysicalDevice::new(VID, PID);
-    physical_device.init().set_full_mode();
+// Drives the tablet's virtual devices from the reports its reader sends, and runs the
+// button gestures and repeats falling due in between. Lets go of the tablet once the
+// reader is done with it.
+async fn drive_tablet(
+    id: TabletId,
+    reports: async_channel::Receiver<TabletEvent>,
+    driver: Rc<RefCell<Driver>>,
+    changes: async_channel::Sender<LoopEvent>,
+) {
+    loop {
+        let deadline = driver.borrow().next_deadline(id);
+        let report = async { Some(reports.recv().await) };
+        let due = async {
+            match deadline {
+                Some(deadline) => Timer::at(deadline).await,
+                None => Timer::never().await,
+            };
+            None
+        };
+        match report.or(due).await {
+            Some(Ok(event)) => driver.borrow_mut().dispatch(id, event),
+            Some(Err(_)) => break,
+            None => driver.borrow_mut().tick(id),
+        }
+        if changes.send(LoopEvent::TabletChanged).await.is_err() {
+            return;
+        }
+    }
+    driver.borrow_mut().disconnect(id);
+    let _ = changes.send(LoopEvent::TabletChanged).await;
+}
+
+// What the driver keeps track of while it runs.
+struct Driver {
+    config: Config,
+    config_path: Option<PathBuf>,
+    backend: Backend,
+    debug_raw: bool,
+    started: Instant,
+    // In connection order, so the first connected tablet is the one whose state is
+    // saved and shown.
+    tablets: IndexMap<TabletId, Tablet>,
+    // The state the next tablet starts in, while none is connected.
+    is_mouse_mode: bool,
+    mouse_area_scale: Option<f32>,
+    active_profile: Option<String>,
+    focused_monitor: Option<String>,
+    state_path: Option<PathBuf>,
+    saved_state: Option<RuntimeState>,
+    notifier: Option<mpsc::Sender<String>>,
+    notified_state: Option<(bool, f32, u32)>,
+    // Mode the mode-change hook was last run for.
+    hooked_mode: Option<bool>,
+    recorder: Option<Recorder>,
+    // The control socket, if it could be created.
+    socket_path: Option<PathBuf>,
+    has_dropped_privileges: bool,
+    // Counters of disconnected tablets, by identifiers, for when they come back.
+    past_counters: HashMap<String, Counters>,
+    // Started when something is first shown on screen.
+    #[cfg(feature = "overlay")]
+    overlay: Option<Option<mpsc::Sender<OverlayCommand>>>,
+    #[cfg(feature = "overlay")]
+    shown_menu: Option<Menu>,
+    #[cfg(feature = "overlay")]
+    shown_area_scale: Option<f32>,
+}
+
+impl Driver {
+    // Follows up on whatever the last event changed: profile switches bound to
+    // buttons, and the state file, notifications, hooks and overlay.
+    fn show_changes(&mut self) {
+        let profile_switches: Vec<ProfileSwitch> = self
+            .tablets
+            .values_mut()
+            .filter_map(|tablet| tablet.dispatcher.take_profile_switch())
+            .collect();
+        for switch in profile_switches {
+            let (tablets, config) = (&mut self.tablets, &self.config);
+            let profile = &mut self.active_profile;
+            let result = switch_profile(&switch, tablets, config, profile, self.notifier.as_ref());
+            if let Err(err) = result {
+                warn!("{}", err);
+            }
+        }
+        if let Some(tablet) = self.tablets.values().next() {
+            if let Some(path) = &self.state_path {
+                save_state(&tablet.dispatcher, &mut self.saved_state, path);
+            }
+            if let Some(notifier) = &self.notifier {
+                notify_changes(&tablet.dispatcher, &mut self.notified_state, notifier);
+            }
+            let is_mouse_mode = tablet.dispatcher.is_mouse_mode();
+            if self.hooked_mode.replace(is_mouse_mode).is_some_and(|was| was != is_mouse_mode) {
+                run_hook(Hook::ModeChange, tablet, self.active_profile.as_deref());
+            }
+        }
+        #[cfg(feature = "overlay")]
+        if let Some(tablet) = self.tablets.values().next() {
+            show_mouse_area(&tablet.dispatcher, &mut self.shown_area_scale, &mut self.overlay);
+        }
+        #[cfg(feature = "overlay")]
+        show_radial_menu(&self.tablets, &mut self.shown_menu, &mut self.overlay);
+    }
 
-    let mut data_reader = RawDataReader::new();
-    let mut device_dispatcher = DeviceDispatcher::new();
+    fn handle(&mut self, event: LoopEvent) {
+        match event {
+            LoopEvent::Exit => info!("Shutting down..."),
+            LoopEvent::Control(message) if self.tablets.is_empty() => {
+                let scale = self.mouse_area_scale.unwrap_or(self.config.mouse_area.scale);
+                let status = disconnected_status(
+                    self.is_mouse_mode,
+                    scale,
+                    &self.active_profile,
+                    self.started,
+                );
+                answer_without_tablet(message, status);
+            }
+            LoopEvent::Control(message) => {
+                let profile = &mut self.active_profile;
+                let notifier = self.notifier.as_ref();
+                let (tablets, config, started) = (&mut self.tablets, &self.config, self.started);
+                handle_control_message(message, tablets, config, profile, notifier, started);
+            }
+            LoopEvent::ConfigChanged => self.reload_config(),
+            LoopEvent::WindowClass(window_class) => {
+                let config = &self.config;
+                let profile = config.profile_for_window(&window_class);
+                let profile_name = profile.map(|(name, _)| name.to_string());
+                // A profile picked by hand stays until a window with its own is focused.
+                let is_picked_by_hand = self
+                    .active_profile
+                    .as_ref()
+                    .and_then(|name| config.profiles.get(name))
+                    .is_some_and(|profile| profile.window_class.is_empty());
+                let keeps_picked = profile_name.is_none() && is_picked_by_hand;
+                if profile_name != self.active_profile && !keeps_picked {
+                    info!("Profile: {}", profile_name.as_deref().unwrap_or("default"));
+                    for tablet in self.tablets.values_mut() {
+                        tablet.apply_profile(profile_name.as_deref());
+                    }
+                    if let Some(tablet) = self.tablets.values().next() {
+                        run_hook(Hook::ProfileChange, tablet, profile_name.as_deref());
+                    }
+                    self.active_profile = profile_name;
+                }
+            }
+            LoopEvent::FocusedMonitor(monitor) => {
+                // The watcher keeps running if a reload turned following off.
+                if self.config.follow_monitor.is_some() {
+                    debug!("Following monitor {}.", monitor);
+                    for tablet in self.tablets.values_mut() {
+                        tablet.dispatcher.set_output(&monitor);
+                    }
+                }
+                self.focused_monitor = Some(monitor);
+            }
+            LoopEvent::Connected(_) | LoopEvent::TabletChanged => {}
+        }
+    }
 
-    println!("Driver is running.");
-    main_loop({
-        || {
-            if physical_device
-                .read_device_responses(&mut data_reader.data)
-                .is_ok()
+    fn reload_config(&mut self) {
+        let Some(path) = &self.config_path else {
+            return;
+        };
+        match Config::load_from(path) {
+            Ok(new_config) => {
+                self.config = new_config;
+                if !self
+                    .active_profile
+                    .as_ref()
+                    .is_some_and(|n| self.config.profiles.contains_key(n))
+                {
+                    self.active_profile = None;
+                }
+                for tablet in self.tablets.values_mut() {
+                    let tablet_config =
+                        device_config(&self.config, &tablet.identifiers, &tablet.format);
+                    let profile = self.active_profile.as_deref();
+                    if let Err(err) = tablet.reload(tablet_config, profile) {
+                        error!("Error applying the configuration: {}", err);
+                    }
+                    if self.config.follow_monitor.is_some()
+                        && let Some(monitor) = &self.focused_monitor
+                    {
+                        tablet.dispatcher.set_output(monitor);
+                    }
+                }
+            }
+            Err(err) => warn!("{} Keeping the previous configuration.", err),
+        }
+    }
+
+    fn connect(&mut self, connected: &ConnectedTablet) {
+        let id = connected.id;
+        debug!("Found tablet on bus {:03} address {:03}.", id.0, id.1);
+        // A new tablet starts in the state of one that is already running.
+        if let Some(tablet) = self.tablets.values().next() {
+            self.is_mouse_mode = tablet.dispatcher.is_mouse_mode();
+            self.mouse_area_scale = Some(tablet.dispatcher.mouse_area_scale());
+        }
+        let tablet_config = device_config(&self.config, &connected.identifiers, &connected.format);
+        let mut tablet = match connect(
+            id,
+            tablet_config,
+            connected.identifiers.clone(),
+            connected.format,
+            self.is_mouse_mode,
+            self.mouse_area_scale,
+            self.active_profile.as_deref(),
+        ) {
+            Ok(tablet) => tablet,
+            Err(err) => {
+                error!("Error setting up the tablet: {}", err);
+                return;
+            }
+        };
+        let identifiers = tablet.identifiers.join(", ");
+        info!("Driver is running ({}).", identifiers);
+        if let Some(counters) = self.past_counters.remove(&identifiers) {
+            tablet.counters = Counters {
+                reconnects: counters.reconnects + 1,
+                ..counters
+            };
+        }
+        if self.config.follow_monitor.is_some()
+            && let Some(monitor) = &self.focused_monitor
+        {
+            tablet.dispatcher.set_output(monitor);
+        }
+        run_hook(Hook::Connect, &tablet, self.active_profile.as_deref());
+        self.tablets.insert(id, tablet);
+        if !self.has_dropped_privileges {
+            drop_privileges(&self.config, self.backend, id, self.socket_path.as_ref());
+            self.has_dropped_privileges = true;
+        }
+    }
+
+    fn next_deadline(&self, id: TabletId) -> Option<Instant> {
+        self.tablets.get(&id)?.dispatcher.next_deadline()
+    }
+
+    // Reports of a tablet that couldn't be set up are dropped.
+    fn dispatch(&mut self, id: TabletId, event: TabletEvent) {
+        let Some(tablet) = self.tablets.get_mut(&id) else {
+            return;
+        };
+        let result = match event {
+            TabletEvent::Report { data, at } => {
+                if let Some(recorder) = &mut self.recorder
+                    && let Err(err) = recorder.record(&data)
+                {
+                    warn!("Error recording report: {}", err);
+                }
+                tablet.report_rate.record(at);
+                tablet.counters.reports += 1;
+                let data_reader = &mut tablet.data_reader;
+                if let Err(err) = data_reader.load(&data) {
+                    debug!("Dropped a malformed report: {}", err);
+                    tablet.counters.malformed += 1;
+                    return;
+                }
+                data_reader.read_at = Some(at);
+                debug!("{:02x?}", data_reader.data);
+                if self.debug_raw {
+                    println!("{}\n", data_reader.annotated_hex());
+                }
+                tablet.dispatcher.dispatch(data_reader)
+            }
+            TabletEvent::Idle => tablet
+                .dispatcher
+                .leave_proximity()
+                .and_then(|()| tablet.dispatcher.tick()),
+        };
+        if let Err(err) = result {
+            error!("Error emitting events: {}", err);
+            tablet.counters.emit_errors += 1;
+        }
+    }
+
+    fn tick(&mut self, id: TabletId) {
+        if let Some(tablet) = self.tablets.get_mut(&id)
+            && let Err(err) = tablet.dispatcher.tick()
+        {
+            error!("Error emitting events: {}", err);
+            tablet.counters.emit_errors += 1;
+        }
+    }
+
+    // Also how each tablet is let go of when exiting. Dropping it releases the kernel
+    // grab.
+    fn disconnect(&mut self, id: TabletId) {
+        let Some(mut tablet) = self.tablets.shift_remove(&id) else {
+            return;
+        };
+        info!("Tablet disconnected ({}).", tablet.identifiers.join(", "));
+        self.past_counters.insert(tablet.identifiers.join(", "), tablet.counters);
+        if let Err(err) = tablet.dispatcher.shutdown() {
+            error!("Error removing the virtual devices: {}", err);
+        }
+        run_hook(Hook::Disconnect, &tablet, self.active_profile.as_deref());
+        // Carry the runtime state over to the next connection.
+        self.is_mouse_mode = tablet.dispatcher.is_mouse_mode();
+        self.mouse_area_scale = Some(tablet.dispatcher.mouse_area_scale());
+        if let Some(path) = &self.state_path {
+            save_state(&tablet.dispatcher, &mut self.saved_state, path);
+        }
+    }
+}
+
+fn status() {
+    let path = ipc::socket_path();
+    match ipc::query(&path, "get-status") {
+        Ok(reply) => println!("{}", reply),
+        Err(err) => {
+            error!("The driver is not running ({}: {}).", path.display(), err);
+            std::process::exit(1);
+        }
+    }
+}
+
+// The status as JSON, or a placeholder when the driver or the tablet is away, so a
+// status bar always gets a line it can show.
+fn json_status() -> String {
+    match ipc::query(&ipc::socket_path(), "get-status json") {
+        Ok(reply) if reply.starts_with('{') => reply,
+        _ => Status::disconnected_json(),
+    }
+}
+
+const STATUS_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+// Polls the driver and prints each status that differs from the last, until the
+// bar that started it goes away.
+fn follow_status() {
+    let mut last = String::new();
+    loop {
+        let status = json_status();
+        if status != last {
+            let mut stdout = std::io::stdout().lock();
+            if writeln!(stdout, "{}", status).and_then(|_| stdout.flush()).is_err() {
+                return;
+            }
+            last = status;
+        }
+        thread::sleep(STATUS_POLL_INTERVAL);
+    }
+}
+
+// Passes a setting to the running driver. The values are joined with spaces, so
+// `"key ctrl z"` works quoted or not.
+fn set(parameter: &str, values: &[String]) {
+    let command = format!("set {} {}", parameter, values.join(" "));
+    send_command(command.trim_end());
+}
+
+// Exits with 1 if the driver isn't running or refuses the command.
+fn send_command(command: &str) {
+    let path = ipc::socket_path();
+    match ipc::query(&path, command) {
+        Ok(reply) if reply.starts_with("error") => {
+            eprintln!("{}", reply);
+            std::process::exit(1);
+        }
+        Ok(_) => {}
+        Err(err) => {
+            error!("The driver is not running ({}: {}).", path.display(), err);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn config_command(cli: &Cli, action: &ConfigAction) {
+    let Some(path) = Config::path(cli.config.as_deref()) else {
+        error!("Cannot find the configuration directory; set HOME or use --config.");
+        std::process::exit(1);
+    };
+    match action {
+        ConfigAction::Check => {
+            let contents = std::fs::read_to_string(&path).unwrap_or_else(|err| {
+                error!("Error reading {}: {}", path.display(), err);
+                if err.kind() == std::io::ErrorKind::NotFound {
+                    error!("`v1060p config init` writes a default one.");
+                }
+                std::process::exit(1);
+            });
+            let issues = config_check::check(&contents);
+            for issue in &issues {
+                println!("{}:{}", path.display(), issue);
+            }
+            if !issues.is_empty() {
+                std::process::exit(1);
+            }
+            println!("{} is valid.", path.display());
+        }
+        ConfigAction::Init { force } => {
+            if path.exists() && !force {
+                error!("{} already exists; use --force to replace it.", path.display());
+                std::process::exit(1);
+            }
+            let written = path
+                .parent()
+                .map_or(Ok(()), std::fs::create_dir_all)
+                .and_then(|()| std::fs::write(&path, config_check::DEFAULT_CONFIG));
+            if let Err(err) = written {
+                error!("Error writing {}: {}", path.display(), err);
+                std::process::exit(1);
+            }
+            println!("Wrote {}.", path.display());
+        }
+    }
+}
+
+// Exits with 1 if anything would stop the driver from working.
+fn doctor(cli: &Cli, config: &Config) {
+    let checks = doctor::run(config, cli.device.as_deref());
+    for check in &checks {
+        println!("{}", check);
+    }
+    if checks.iter().any(|check| check.outcome == doctor::Outcome::Problem) {
+        std::process::exit(1);
+    }
+}
+
+fn install_udev(group: &str, print: bool) {
+    let rules = udev::rules(group);
+    if print {
+        print!("{}", rules);
+        return;
+    }
+    if let Err(err) = udev::install(Path::new(udev::RULES_PATH), &rules) {
+        error!("Error installing {}: {}", udev::RULES_PATH, err);
+        std::process::exit(1);
+    }
+    println!("Installed {}.", udev::RULES_PATH);
+    println!("Add yourself to the `{}` group and log in again to use the tablet.", group);
+}
+
+fn install_service(is_user: bool, print: bool) {
+    let executable = std::env::current_exe().unwrap_or_else(|err| {
+        error!("Cannot find the driver's own path: {}", err);
+        std::process::exit(1);
+    });
+    let unit = service::unit(&executable, is_user);
+    if print {
+        print!("{}", unit);
+        return;
+    }
+    let Some(unit_dir) = service::unit_dir(is_user) else {
+        error!("Cannot find the systemd unit directory; set HOME.");
+        std::process::exit(1);
+    };
+    match service::install(&unit_dir, &unit, is_user) {
+        Ok(path) => println!("Installed {}.", path.display()),
+        Err(err) => {
+            error!("Error installing the service: {}", err);
+            std::process::exit(1);
+        }
+    }
+    if is_user {
+        println!("Run `sudo v1060p install-udev` too, if you haven't, so the tablet starts it.");
+    }
+}
+
+// Switches to the configured user once the first tablet is set up. Exits rather than
+// carrying on as root when that fails, and warns first about device nodes the user
+// can't open, without which the tablet can't be set up again when it reconnects.
+fn drop_privileges(
+    config: &Config,
+    backend: Backend,
+    (bus, address): TabletId,
+    socket_path: Option<&PathBuf>,
+) {
+    let Some(user) = &config.privileges.user else {
+        return;
+    };
+    if !privileges::is_root() {
+        warn!("Not running as root, so not switching to {}.", user);
+        return;
+    }
+    let account = match config.privileges.account() {
+        Ok(Some(account)) if account.uid != 0 => account,
+        Ok(_) => return,
+        Err(err) => {
+            error!("Cannot switch to {}: {}", user, err);
+            std::process::exit(1);
+        }
+    };
+    let mut nodes = vec![PathBuf::from("/dev/uinput")];
+    if config.virtual_devices.pen_backend == PenBackend::Uhid {
+        nodes.push(PathBuf::from("/dev/uhid"));
+    }
+    nodes.extend(backend.node_path(bus, address));
+    let groups = privileges::groups(user, account.gid).unwrap_or_else(|_| vec![account.gid]);
+    let closed: Vec<String> = nodes
+        .iter()
+        .filter(|node| !privileges::can_read_write(account, &groups, node))
+        .map(|node| node.display().to_string())
+        .collect();
+    if !closed.is_empty() {
+        warn!(
+            "{} cannot open {}, so the tablet won't work again after it reconnects. \
+             Install the udev rules with `v1060p install-udev`.",
+            user,
+            closed.join(" or ")
+        );
+    }
+    // Lets the user's own clients connect to the control socket, and the driver remove
+    // it on exit.
+    if let Some(socket_path) = socket_path {
+        for path in [socket_path.as_path()].into_iter().chain(socket_path.parent()) {
+            if let Err(err) = std::os::unix::fs::chown(path, Some(account.uid), Some(account.gid))
             {
-                device_dispatcher.dispatch(&data_reader);
-                if device_dispatcher.syn().is_err() {
-                    println!("Error emitting SYN.");
+                warn!("Error handing {} over to {}: {}", path.display(), user, err);
+            }
+        }
+    }
+    if let Err(err) = privileges::switch_to(user, account) {
+        error!("Cannot switch to {}: {}", user, err);
+        std::process::exit(1);
+    }
+    info!("Running as {}.", user);
+}
+
+// Writes the mode and area scale to the state file when they differ from what was
+// last saved.
+fn save_state(
+    device_dispatcher: &DeviceDispatcher,
+    saved_state: &mut Option<RuntimeState>,
+    path: &Path,
+) {
+    let state = RuntimeState {
+        mode: match device_dispatcher.is_mouse_mode() {
+            true => Mode::Mouse,
+            false => Mode::Tablet,
+        },
+        mouse_area_scale: device_dispatcher.mouse_area_scale(),
+    };
+    if *saved_state == Some(state) {
+        return;
+    }
+    if let Err(err) = state.save(path) {
+        warn!("Error saving {}: {}", path.display(), err);
+    }
+    // Remembered even on failure, so a broken path isn't retried on every report.
+    *saved_state = Some(state);
+}
+
+// Announces mode and mouse area changes, whether they came from the tablet buttons
+// or the control interfaces, and hold-clicks. The state at startup is taken as
+// already known.
+fn notify_changes(
+    device_dispatcher: &DeviceDispatcher,
+    notified_state: &mut Option<(bool, f32, u32)>,
+    notifier: &mpsc::Sender<String>,
+) {
+    let state = (
+        device_dispatcher.is_mouse_mode(),
+        device_dispatcher.mouse_area_scale(),
+        device_dispatcher.hold_click_count(),
+    );
+    let Some((was_mouse_mode, last_scale, last_hold_clicks)) = notified_state.replace(state)
+    else {
+        return;
+    };
+    if state.0 != was_mouse_mode {
+        let mode = if state.0 { "MOUSE" } else { "TABLET" };
+        let _ = notifier.send(format!("Mode: {}", mode));
+    } else if state.1 != last_scale {
+        let _ = notifier.send(format!("Mouse area: {:.0}%", state.1 * 100.0));
+    } else if state.2 != last_hold_clicks {
+        let _ = notifier.send("Right click".to_string());
+    }
+}
+
+// Shows the radial menu of whichever tablet has one open, redrawing it only when the
+// picked slice changes.
+#[cfg(feature = "overlay")]
+fn show_radial_menu(
+    tablets: &IndexMap<TabletId, Tablet>,
+    shown_menu: &mut Option<Menu>,
+    overlay: &mut Option<Option<mpsc::Sender<OverlayCommand>>>,
+) {
+    let menu = tablets.values().find_map(|tablet| {
+        let menu = tablet.dispatcher.radial_menu();
+        let config = &tablet.config.radial_menu;
+        menu.is_open().then(|| Menu {
+            labels: config.items.iter().map(|item| item.label.clone()).collect(),
+            selected: menu.selected(),
+            radius: config.radius,
+        })
+    });
+    if menu == *shown_menu {
+        return;
+    }
+    *shown_menu = menu.clone();
+    show_on_screen(overlay, menu.map_or(OverlayCommand::Hide, OverlayCommand::Menu));
+}
+
+// Briefly shows the mouse area when its size changes in mouse mode, as the notifications
+// do, so adjusting it isn't blind.
+#[cfg(feature = "overlay")]
+fn show_mouse_area(
+    device_dispatcher: &DeviceDispatcher,
+    shown_area_scale: &mut Option<f32>,
+    overlay: &mut Option<Option<mpsc::Sender<OverlayCommand>>>,
+) {
+    let scale = device_dispatcher.mouse_area_scale();
+    let last_scale = shown_area_scale.replace(scale);
+    if last_scale.is_none_or(|last| last == scale) || !device_dispatcher.is_mouse_mode() {
+        return;
+    }
+    let area = Area {
+        rect: device_dispatcher.mouse_area_on_screen(),
+        label: format!("Mouse area: {:.0}%", scale * 100.0),
+    };
+    show_on_screen(overlay, OverlayCommand::Area(area));
+}
+
+#[cfg(feature = "overlay")]
+fn show_on_screen(
+    overlay: &mut Option<Option<mpsc::Sender<OverlayCommand>>>,
+    command: OverlayCommand,
+) {
+    let overlay = overlay.get_or_insert_with(|| {
+        overlay::spawn_overlay()
+            .inspect_err(|err| warn!("Nothing can be shown on screen: {}", err))
+            .ok()
+    });
+    if let Some(overlay) = overlay {
+        let _ = overlay.send(command);
+    }
+}
+
+// A connected tablet with the virtual devices it drives.
+struct Tablet {
+    id: TabletId,
+    identifiers: Vec<String>,
+    // Report layout detected when the tablet was opened, used unless the config names
+    // a protocol.
+    format: ReportFormat,
+    config: Config,
+    dispatcher: DeviceDispatcher,
+    data_reader: RawDataReader,
+    kernel_grab: Option<KernelGrab>,
+    report_rate: ReportRate,
+    counters: Counters,
+}
+
+// Reports per second, counted over windows of about a second.
+#[derive(Default)]
+struct ReportRate {
+    window_start: Option<Instant>,
+    count: u32,
+    rate: f32,
+}
+
+impl ReportRate {
+    const WINDOW: Duration = Duration::from_secs(1);
+
+    fn record(&mut self, now: Instant) {
+        let start = *self.window_start.get_or_insert(now);
+        let elapsed = now.duration_since(start);
+        if elapsed >= Self::WINDOW {
+            self.rate = self.count as f32 / elapsed.as_secs_f32();
+            self.window_start = Some(now);
+            self.count = 0;
+        }
+        self.count += 1;
+    }
+
+    // Zero once the tablet has gone quiet.
+    fn rate(&self, now: Instant) -> f32 {
+        match self.window_start {
+            Some(start) if now.duration_since(start) < 2 * Self::WINDOW => self.rate,
+            _ => 0.0,
+        }
+    }
+}
+
+impl Tablet {
+    fn apply_profile(&mut self, name: Option<&str>) {
+        let profile = name.and_then(|name| self.config.profiles.get(name));
+        self.dispatcher.apply_profile(&self.config, profile);
+    }
+
+    // Grabs or releases the kernel's input nodes for the tablet as configured.
+    fn update_kernel_grab(&mut self) {
+        match (self.config.grab_kernel_devices, &self.kernel_grab) {
+            (true, None) => self.kernel_grab = Some(KernelGrab::new(self.id)),
+            (false, Some(_)) => self.kernel_grab = None,
+            _ => {}
+        }
+    }
+
+    fn reload(&mut self, config: Config, profile: Option<&str>) -> Result<()> {
+        self.config = config;
+        self.data_reader = self.format.data_reader(self.config.protocol);
+        self.update_kernel_grab();
+        let profile = profile.and_then(|name| self.config.profiles.get(name));
+        self.dispatcher.reload(&self.config, profile)
+    }
+}
+
+// The tablet's device section merged over the configuration, with the raw ranges
+// the tablet gave where it sets none. A broken section is reported and left out.
+fn device_config(config: &Config, identifiers: &[String], format: &ReportFormat) -> Config {
+    let mut config = config.for_device(identifiers).unwrap_or_else(|err| {
+        warn!("{} Ignoring it.", err);
+        config.for_device(&[]).unwrap_or_default()
+    });
+    if let Some(params) = format.params {
+        config.axes = params.raw_axes(config.axes);
+    }
+    config
+}
+
+// Creates the virtual devices for a tablet, restoring the runtime state.
+fn connect(
+    id: TabletId,
+    config: Config,
+    identifiers: Vec<String>,
+    format: ReportFormat,
+    is_mouse_mode: bool,
+    mouse_area_scale: Option<f32>,
+    profile: Option<&str>,
+) -> Result<Tablet> {
+    let mut dispatcher = DeviceDispatcher::new(&config)?;
+    dispatcher.set_mouse_mode(is_mouse_mode)?;
+    if let Some(scale) = mouse_area_scale {
+        dispatcher.set_mouse_area_scale(scale);
+    }
+    let mut tablet = Tablet {
+        id,
+        identifiers,
+        format,
+        data_reader: format.data_reader(config.protocol),
+        config,
+        dispatcher,
+        kernel_grab: None,
+        report_rate: ReportRate::default(),
+        counters: Counters::default(),
+    };
+    tablet.update_kernel_grab();
+    if profile.is_some() {
+        tablet.apply_profile(profile);
+    }
+    Ok(tablet)
+}
+
+// Reads the next report into `data_reader`. A malformed one is dropped and reported
+// as a timeout, which every caller already waits through.
+fn read_report(
+    physical_device: &PhysicalDevice,
+    data_reader: &mut RawDataReader,
+) -> std::result::Result<(), RusbError> {
+    let mut buffer = vec![0u8; MAX_REPORT_LENGTH];
+    let length = physical_device.read_device_responses(&mut buffer)?;
+    data_reader.load(&buffer[..length]).map_err(|err| {
+        debug!("Dropped a malformed report: {}", err);
+        RusbError::Timeout
+    })
+}
+
+// Walks the user through the calibration steps and saves the result to the config.
+fn calibrate(cli: &Cli, config: &Config) {
+    let Some(config_path) = Config::path(cli.config.as_deref()) else {
+        error!("Cannot find the configuration directory; pass --config.");
+        std::process::exit(1);
+    };
+    let exit = register_exit_signals();
+    let physical_device = PhysicalDevice::locate(cli.device.as_deref())
+        .and_then(|device| PhysicalDevice::open(device, cli.backend))
+        .unwrap_or_else(|err| {
+            error!("Error setting up the tablet: {}", err);
+            std::process::exit(1);
+        });
+
+    let mut data_reader = physical_device.report_format().data_reader(config.protocol);
+    let mut calibrator = Calibrator::new();
+    println!("Calibrating. Press Ctrl+C to cancel.");
+    while let Some(step) = calibrator.current_step() {
+        println!("{}", step.prompt());
+        loop {
+            if exit.load(Ordering::Relaxed) {
+                println!("Calibration cancelled.");
+                return;
+            }
+            match read_report(&physical_device, &mut data_reader) {
+                Ok(()) if calibrator.feed(&data_reader) => break,
+                Ok(()) | Err(RusbError::Timeout) => {}
+                Err(err) => {
+                    error!("Error reading the tablet: {}", err);
+                    std::process::exit(1);
                 }
             }
         }
+    }
+
+    let calibration = calibrator.result().unwrap_or_else(|err| {
+        error!("Calibration failed: {}", err);
+        std::process::exit(1);
     });
+    if let Err(err) = calibration::write_calibration(&config_path, calibration) {
+        error!("Error writing {}: {}", config_path.display(), err);
+        std::process::exit(1);
+    }
+    println!("Calibration saved to {}.", config_path.display());
 }
 
-fn main_loop(mut f: impl FnMut()) {
-    let signals: Vec<i32> = vec![SIGINT, SIGTERM, SIGQUIT];
-    let flag = Arc::new(AtomicBool::new(false));
+// Asks for a button and then its action, over and over, saving each to the config
+// file, until Ctrl+C or Ctrl+D.
+fn learn(cli: &Cli, config: &Config) {
+    let Some(config_path) = Config::path(cli.config.as_deref()) else {
+        error!("Cannot find the configuration directory; pass --config.");
+        std::process::exit(1);
+    };
+    let exit = register_exit_signals();
+    let physical_device = PhysicalDevice::locate(cli.device.as_deref())
+        .and_then(|device| PhysicalDevice::open(device, cli.backend))
+        .unwrap_or_else(|err| {
+            error!("Error setting up the tablet: {}", err);
+            std::process::exit(1);
+        });
 
-    for signal in signals {
-        register(signal, Arc::clone(&flag)).expect("Error registering interrupt signals.");
+    let mut data_reader = physical_device.report_format().data_reader(config.protocol);
+    let stdin = std::io::stdin();
+    println!("Learning button bindings. Press Ctrl+C or Ctrl+D to stop.");
+    loop {
+        println!("Press the tablet button you want to map.");
+        let mut learner = ButtonLearner::new();
+        let id = loop {
+            if exit.load(Ordering::Relaxed) {
+                return;
+            }
+            match read_report(&physical_device, &mut data_reader) {
+                Ok(()) => {
+                    if let Some(id) = learner.feed(&data_reader) {
+                        break id;
+                    }
+                }
+                Err(RusbError::Timeout) => {}
+                Err(err) => {
+                    error!("Error reading the tablet: {}", err);
+                    std::process::exit(1);
+                }
+            }
+        };
+        if let Some(binding) = config.tablet_buttons.get(&id) {
+            println!("Button {} is bound to {:?}.", id, binding);
+        }
+
+        loop {
+            print!("Action for button {} (e.g. ctrl+z, undo, precision; empty to skip): ", id);
+            let _ = std::io::stdout().flush();
+            let mut line = String::new();
+            let is_closed = matches!(stdin.read_line(&mut line), Ok(0) | Err(_));
+            if is_closed || exit.load(Ordering::Relaxed) {
+                println!();
+                return;
+            }
+            let action = line.trim();
+            if action.is_empty() {
+                break;
+            }
+            if let Err(err) = learn::parse_action(action) {
+                println!("{}", err);
+                continue;
+            }
+            match learn::write_tablet_button(&config_path, id, action) {
+                Ok(()) => println!("Saved {} = \"{}\" to {}.", id, action, config_path.display()),
+                Err(err) => error!("Error writing {}: {}", config_path.display(), err),
+            }
+            break;
+        }
     }
+}
 
-    while !flag.load(Ordering::Relaxed) {
-        f();
+// Shows each report's pressure on one line that is redrawn in place, until Ctrl+C.
+fn test_pressure(cli: &Cli, config: &Config) {
+    let exit = register_exit_signals();
+    let physical_device = PhysicalDevice::locate(cli.device.as_deref())
+        .and_then(|device| PhysicalDevice::open(device, cli.backend))
+        .unwrap_or_else(|err| {
+            error!("Error setting up the tablet: {}", err);
+            std::process::exit(1);
+        });
+
+    let mut data_reader = physical_device.report_format().data_reader(config.protocol);
+    let is_mouse_mode = cli.mode.unwrap_or(config.mode) == Mode::Mouse;
+    let mut meter = PressureMeter::new(config, is_mouse_mode).unwrap_or_else(|err| {
+        error!("Error setting up the pressure test: {}", err);
+        std::process::exit(1);
+    });
+    println!(
+        "Testing pressure in {} mode. Press the pen; Ctrl+C to stop.",
+        if is_mouse_mode { "mouse" } else { "tablet" }
+    );
+
+    let mut stdout = std::io::stdout().lock();
+    while !exit.load(Ordering::Relaxed) {
+        let line = match read_report(&physical_device, &mut data_reader) {
+            Ok(()) => match meter.feed(&data_reader) {
+                Ok(Some(reading)) => reading.render(),
+                Ok(None) => "Bring the pen near the tablet.".to_string(),
+                Err(err) => format!("Error: {}", err),
+            },
+            Err(RusbError::Timeout) => continue,
+            Err(err) => {
+                error!("Error reading the tablet: {}", err);
+                std::process::exit(1);
+            }
+        };
+        // Carriage return and clear the line, so the reading stays in place.
+        let _ = write!(stdout, "\r\x1b[2K{}", line).and_then(|_| stdout.flush());
     }
     println!();
-    println!("The driver has exited.")
+}
+
+// Times reports through real virtual devices, from the read to the uinput write, so
+// changes to smoothing and the like can be compared by numbers.
+fn bench(cli: &Cli, config: &Config, duration: Duration, with_rate: bool) {
+    let exit = register_exit_signals();
+    let mut dispatcher = DeviceDispatcher::new(config).unwrap_or_else(|err| {
+        error!("Error creating the virtual devices: {}", err);
+        std::process::exit(1);
+    });
+    let mode = cli.mode.unwrap_or(config.mode);
+    if let Err(err) = dispatcher.set_mouse_mode(mode == Mode::Mouse) {
+        error!("Error emitting events: {}", err);
+    }
+    let mut stats = LatencyStats::default();
+    let deadline = Instant::now() + duration;
+    let mut measure = |data_reader: &mut RawDataReader, read_at: Instant| {
+        data_reader.read_at = Some(read_at);
+        if let Err(err) = dispatcher.dispatch(data_reader) {
+            error!("Error emitting events: {}", err);
+        }
+        stats.record(read_at, Instant::now());
+    };
+    let is_running = || !exit.load(Ordering::Relaxed) && Instant::now() < deadline;
+
+    if let Some(path) = &cli.replay {
+        let reports = capture::read_capture(path).unwrap_or_else(|err| {
+            error!("Error reading {}: {}", path.display(), err);
+            std::process::exit(1);
+        });
+        let mut data_reader = RawDataReader::with_protocol(config.protocol.unwrap_or_default());
+        // Back to back rather than at the recorded pace, so the rate is the most the
+        // driver keeps up with.
+        for report in reports.iter().take_while(|_| is_running()) {
+            let read_at = Instant::now();
+            match data_reader.load(&report.data) {
+                Ok(()) => measure(&mut data_reader, read_at),
+                Err(err) => debug!("Dropped a malformed report: {}", err),
+            }
+        }
+    } else {
+        let physical_device = PhysicalDevice::locate(cli.device.as_deref())
+            .and_then(|device| PhysicalDevice::open(device, cli.backend))
+            .unwrap_or_else(|err| {
+                error!("Error setting up the tablet: {}", err);
+                std::process::exit(1);
+            });
+        let mut data_reader = physical_device.report_format().data_reader(config.protocol);
+        println!(
+            "Measuring for {} s. Move the pen over the tablet; Ctrl+C to stop.",
+            duration.as_secs()
+        );
+        while is_running() {
+            match read_report(&physical_device, &mut data_reader) {
+                Ok(()) => measure(&mut data_reader, Instant::now()),
+                Err(RusbError::Timeout) => continue,
+                Err(err) => {
+                    error!("Error reading the tablet: {}", err);
+                    break;
+                }
+            }
+        }
+    }
+
+    if let Err(err) = dispatcher.shutdown() {
+        error!("Error removing the virtual devices: {}", err);
+    }
+    println!("{}", stats.summary(with_rate));
+}
+
+// Feeds a capture made with --record through the dispatcher, keeping the original timing.
+fn replay(path: &Path, config: &Config, cli: &Cli) {
+    let reports = capture::read_capture(path).unwrap_or_else(|err| {
+        error!("Error reading {}: {}", path.display(), err);
+        std::process::exit(1);
+    });
+
+    let exit = register_exit_signals();
+    let mut data_reader = RawDataReader::with_protocol(config.protocol.unwrap_or_default());
+    let mut device_dispatcher = DeviceDispatcher::new(config).unwrap_or_else(|err| {
+        error!("Error creating the virtual devices: {}", err);
+        std::process::exit(1);
+    });
+    let mode = cli.mode.unwrap_or(config.mode);
+    if let Err(err) = device_dispatcher.set_mouse_mode(mode == Mode::Mouse) {
+        error!("Error emitting events: {}", err);
+    }
+    if let Some(scale) = cli.mouse_area_scale {
+        device_dispatcher.set_mouse_area_scale(scale);
+    }
+
+    info!("Replaying {} reports from {}.", reports.len(), path.display());
+    let start = Instant::now();
+    for report in &reports {
+        if exit.load(Ordering::Relaxed) {
+            break;
+        }
+        if let Some(delay) = report.timestamp.checked_sub(start.elapsed()) {
+            thread::sleep(delay);
+        }
+
+        if let Err(err) = data_reader.load(&report.data) {
+            debug!("Dropped a malformed report: {}", err);
+            continue;
+        }
+        debug!("{:02x?}", data_reader.data);
+        if cli.debug_raw {
+            println!("{}\n", data_reader.annotated_hex());
+        }
+        if let Err(err) = device_dispatcher.dispatch(&data_reader) {
+            error!("Error emitting events: {}", err);
+        }
+    }
+    if let Err(err) = device_dispatcher.shutdown() {
+        error!("Error removing the virtual devices: {}", err);
+    }
+    info!("Replay finished.");
+}
+
+// Mode, area and profile requests apply to every connected tablet; the status is the
+// first one's.
+fn handle_control_message(
+    message: Message,
+    tablets: &mut IndexMap<TabletId, Tablet>,
+    config: &Config,
+    active_profile: &mut Option<String>,
+    notifier: Option<&mpsc::Sender<String>>,
+    started: Instant,
+) {
+    let Some(first) = tablets.values().next() else {
+        return;
+    };
+    match &message.request {
+        Request::SetMode(mode) => {
+            let result = tablets
+                .values_mut()
+                .try_for_each(|tablet| tablet.dispatcher.set_mouse_mode(*mode == Mode::Mouse));
+            message.reply(result_to_response(result));
+        }
+        Request::ToggleMode => {
+            let is_mouse_mode = !first.dispatcher.is_mouse_mode();
+            let result = tablets
+                .values_mut()
+                .try_for_each(|tablet| tablet.dispatcher.set_mouse_mode(is_mouse_mode));
+            message.reply(result_to_response(result));
+        }
+        Request::SetArea(scale) => {
+            for tablet in tablets.values_mut() {
+                tablet.dispatcher.set_mouse_area_scale(*scale);
+            }
+            message.reply(Response::Ok);
+        }
+        Request::SwitchProfile(switch) => {
+            let result = switch_profile(switch, tablets, config, active_profile, notifier);
+            message.reply(match result {
+                Ok(()) => Response::Ok,
+                Err(err) => Response::Error(err),
+            });
+        }
+        Request::Set(setting) => {
+            let result = tablets
+                .values_mut()
+                .try_for_each(|tablet| apply_setting(&mut tablet.dispatcher, setting));
+            message.reply(match result {
+                Ok(()) => Response::Ok,
+                Err(err) => Response::Error(err),
+            });
+        }
+        Request::GetStatus { is_json } => {
+            let status = Status {
+                is_mouse_mode: first.dispatcher.is_mouse_mode(),
+                mouse_area_scale: first.dispatcher.mouse_area_scale(),
+                profile: active_profile.clone(),
+                pressure: first.dispatcher.pressure(),
+                device: Some(first.identifiers.join(", ")),
+                uptime: started.elapsed(),
+                report_rate: first.report_rate.rate(Instant::now()),
+                counters: first.counters,
+            };
+            let response = match is_json {
+                true => Response::StatusJson(status),
+                false => Response::Status(status),
+            };
+            message.reply(response);
+        }
+    }
+}
+
+// The status while no tablet is connected, with the state the next one starts in.
+fn disconnected_status(
+    is_mouse_mode: bool,
+    mouse_area_scale: f32,
+    profile: &Option<String>,
+    started: Instant,
+) -> Status {
+    Status {
+        is_mouse_mode,
+        mouse_area_scale,
+        profile: profile.clone(),
+        pressure: 0,
+        device: None,
+        uptime: started.elapsed(),
+        report_rate: 0.0,
+        counters: Counters::default(),
+    }
+}
+
+// Answers right away when no tablet is connected: the status tells so, and changes
+// fail rather than being applied to a tablet plugged in later.
+fn answer_without_tablet(message: Message, status: Status) {
+    let response = match message.request {
+        Request::GetStatus { is_json: true } => Response::StatusJson(status),
+        Request::GetStatus { is_json: false } => Response::Status(status),
+        _ => Response::Error("the tablet is not connected".to_string()),
+    };
+    message.reply(response);
+}
+
+// Applies a profile chosen by a binding or over the socket to every tablet, and shows
+// its name.
+fn switch_profile(
+    switch: &ProfileSwitch,
+    tablets: &mut IndexMap<TabletId, Tablet>,
+    config: &Config,
+    active_profile: &mut Option<String>,
+    notifier: Option<&mpsc::Sender<String>>,
+) -> std::result::Result<(), String> {
+    let name = config.switch_profile(active_profile.as_deref(), switch)?;
+    let shown = name.as_deref().unwrap_or("default");
+    info!("Profile: {}", shown);
+    if let Some(notifier) = notifier {
+        let _ = notifier.send(format!("Profile: {}", shown));
+    }
+    for tablet in tablets.values_mut() {
+        tablet.apply_profile(name.as_deref());
+    }
+    if let Some(tablet) = tablets.values().next()
+        && name != *active_profile
+    {
+        run_hook(Hook::ProfileChange, tablet, name.as_deref());
+    }
+    *active_profile = name;
+    Ok(())
+}
+
+// Runs the tablet's hook for `hook` with its current state.
+fn run_hook(hook: Hook, tablet: &Tablet, profile: Option<&str>) {
+    let device = tablet.identifiers.join(", ");
+    let state = HookState {
+        is_mouse_mode: tablet.dispatcher.is_mouse_mode(),
+        mouse_area_scale: tablet.dispatcher.mouse_area_scale(),
+        profile,
+        device: &device,
+    };
+    hooks::run(&tablet.config.hooks, hook, &state);
+}
+
+fn apply_setting(
+    dispatcher: &mut DeviceDispatcher,
+    setting: &Setting,
+) -> std::result::Result<(), String> {
+    match setting {
+        Setting::Area(rect) => dispatcher.set_tablet_area_rect(*rect),
+        Setting::Button(id, binding) => dispatcher.set_tablet_button(*id, binding.clone())?,
+        Setting::Mode(mode) => dispatcher
+            .set_mouse_mode(*mode == Mode::Mouse)
+            .map_err(|err| err.to_string())?,
+        Setting::Rotate(rotation) => dispatcher.set_rotation(*rotation),
+        Setting::MapToOutput(output) => dispatcher.set_output(output),
+        Setting::PressureCurve(curve) => dispatcher.set_pressure_curve(curve.clone()),
+        Setting::Stabilizer(is_active) => {
+            let is_active = is_active.unwrap_or(!dispatcher.is_stabilizer_active());
+            dispatcher.set_stabilizer_active(is_active);
+        }
+    }
+    Ok(())
+}
+
+fn result_to_response(result: Result<()>) -> Response {
+    match result {
+        Ok(()) => Response::Ok,
+        Err(err) => Response::Error(err.to_string()),
+    }
+}
+
+const EXIT_SIGNALS: [i32; 3] = [SIGINT, SIGTERM, SIGQUIT];
+
+fn register_exit_signals() -> Arc<AtomicBool> {
+    let flag = Arc::new(AtomicBool::new(false));
+
+    for signal in EXIT_SIGNALS {
+        // A second signal exits right away, in case shutting down hangs.
+        register_conditional_shutdown(signal, 1, Arc::clone(&flag))
+            .and_then(|_| register(signal, Arc::clone(&flag)))
+            .expect("Error registering interrupt signals.");
+    }
+    flag
+}
+
+// Wakes the coordinator when an exit signal arrives. Registered after the exit flag,
+// so the flag is already set when the coordinator looks.
+fn wake_on_exit_signals(events: async_channel::Sender<LoopEvent>) {
+    let mut signals = Signals::new(EXIT_SIGNALS).expect("Error registering interrupt signals.");
+    thread::spawn(move || {
+        for _ in signals.forever() {
+            if events.send_blocking(LoopEvent::Exit).is_err() {
+                return;
+            }
+        }
+    });
+}
+
+// Moves everything arriving on `receiver` over to the coordinator's channel.
+fn forward<T: Send + 'static>(
+    receiver: Receiver<T>,
+    events: &async_channel::Sender<LoopEvent>,
+    wrap: fn(T) -> LoopEvent,
+) {
+    let events = events.clone();
+    thread::spawn(move || {
+        for item in receiver {
+            if events.send_blocking(wrap(item)).is_err() {
+                return;
+            }
+        }
+    });
 }
@@ -0,0 +1,52 @@
+use std::collections::HashMap;
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+
+use tracing::warn;
+use zbus::blocking::Connection;
+use zbus::zvariant::Value;
+
+const APP_NAME: &str = "VINSA 1060 Plus";
+// Short enough not to pile up while the area buttons are pressed repeatedly.
+const EXPIRE_TIMEOUT_MS: i32 = 2000;
+
+// Shows desktop notifications through org.freedesktop.Notifications. Calls are made
+// on a separate thread so a slow notification daemon can't stall the pen.
+pub fn spawn_notifier() -> zbus::Result<Sender<String>> {
+    let connection = Connection::session()?;
+    let (sender, receiver) = mpsc::channel::<String>();
+
+    thread::spawn(move || {
+        // Each notification replaces the previous one instead of stacking up.
+        let mut last_id = 0u32;
+        for body in receiver {
+            match notify(&connection, last_id, &body) {
+                Ok(id) => last_id = id,
+                Err(err) => warn!("Error showing notification: {}", err),
+            }
+        }
+    });
+
+    Ok(sender)
+}
+
+fn notify(connection: &Connection, replaces_id: u32, body: &str) -> zbus::Result<u32> {
+    let hints: HashMap<&str, Value> = HashMap::new();
+    let reply = connection.call_method(
+        Some("org.freedesktop.Notifications"),
+        "/org/freedesktop/Notifications",
+        Some("org.freedesktop.Notifications"),
+        "Notify",
+        &(
+            APP_NAME,
+            replaces_id,
+            "input-tablet",
+            body,
+            "",
+            Vec::<&str>::new(),
+            hints,
+            EXPIRE_TIMEOUT_MS,
+        ),
+    )?;
+    reply.body().deserialize()
+}
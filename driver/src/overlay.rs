@@ -0,0 +1,315 @@
+use tracing::warn;
+use x11rb::connection::Connection;
+use x11rb::errors::ReplyOrIdError;
+use x11rb::protocol::Event;
+use x11rb::protocol::xproto::{
+    Arc, ChangeGCAux, ColormapAlloc, ConnectionExt, CreateGCAux, CreateWindowAux, EventMask,
+    Gcontext, Rectangle, Screen, VisualClass, Visualid, Window, WindowClass,
+};
+use x11rb::rust_connection::RustConnection;
+
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// How often Expose events are looked for while something is shown.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+// How long the mouse area stays on screen after a change.
+const AREA_DURATION: Duration = Duration::from_millis(1500);
+// Colors as premultiplied ARGB; without a compositor the alpha is ignored.
+const TRANSPARENT: u32 = 0x0000_0000;
+const BACKGROUND: u32 = 0xd820_2020;
+const SELECTED: u32 = 0xe02a_5a9a;
+const AREA: u32 = 0x6014_3050;
+const AREA_BORDER: u32 = 0xff4a_9aff;
+const TEXT: u32 = 0xffff_ffff;
+// Cell size of the X core "fixed" font.
+const FONT_WIDTH: i16 = 6;
+const FONT_HEIGHT: i16 = 13;
+
+pub enum OverlayCommand {
+    // Shows the radial menu around the pointer, or redraws it with another slice
+    // picked.
+    Menu(Menu),
+    // Briefly shows where the mouse area is, unless a menu is open.
+    Area(Area),
+    Hide,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Menu {
+    pub labels: Vec<String>,
+    pub selected: Option<usize>,
+    pub radius: u16,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Area {
+    // [left, top, right, bottom] as fractions of the screen.
+    pub rect: [f32; 4],
+    pub label: String,
+}
+
+enum Shown {
+    Menu(Menu),
+    // Hidden again at the instant given.
+    Area(Area, Instant),
+}
+
+// Draws on top of every window through X11 (XWayland under Wayland). Drawing is
+// done on a separate thread so a slow X server can't stall the pen.
+pub fn spawn_overlay() -> Result<Sender<OverlayCommand>, String> {
+    let (connection, screen) =
+        x11rb::connect(None).map_err(|err| format!("Cannot connect to the X server: {}", err))?;
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut overlay = Overlay {
+            connection,
+            screen,
+            window: None,
+            shown: None,
+        };
+        if let Err(err) = overlay.run(receiver) {
+            warn!("The on-screen overlay stopped: {}", err);
+        }
+    });
+    Ok(sender)
+}
+
+struct Overlay {
+    connection: RustConnection,
+    screen: usize,
+    // The shown window and its graphics context.
+    window: Option<(Window, Gcontext)>,
+    shown: Option<Shown>,
+}
+
+impl Overlay {
+    fn run(&mut self, receiver: Receiver<OverlayCommand>) -> Result<(), ReplyOrIdError> {
+        loop {
+            let command = match self.window {
+                Some(_) => match receiver.recv_timeout(POLL_INTERVAL) {
+                    Ok(command) => Some(command),
+                    Err(RecvTimeoutError::Timeout) => None,
+                    Err(RecvTimeoutError::Disconnected) => return Ok(()),
+                },
+                None => match receiver.recv() {
+                    Ok(command) => Some(command),
+                    Err(_) => return Ok(()),
+                },
+            };
+
+            // Anything drawn before the window was mapped is lost, and is redrawn
+            // on the Expose that follows.
+            let mut needs_drawing = false;
+            while let Some(event) = self.connection.poll_for_event()? {
+                needs_drawing |= matches!(event, Event::Expose(_));
+            }
+            match command {
+                Some(OverlayCommand::Menu(menu)) => {
+                    if !matches!(self.shown, Some(Shown::Menu(_))) {
+                        self.hide()?;
+                        self.window = Some(self.open(self.around_pointer(menu.radius)?)?);
+                    }
+                    self.shown = Some(Shown::Menu(menu));
+                    needs_drawing = true;
+                }
+                Some(OverlayCommand::Area(area))
+                    if !matches!(self.shown, Some(Shown::Menu(_))) =>
+                {
+                    self.hide()?;
+                    self.window = Some(self.open(self.on_screen(area.rect))?);
+                    self.shown = Some(Shown::Area(area, Instant::now() + AREA_DURATION));
+                    needs_drawing = true;
+                }
+                Some(OverlayCommand::Hide) => self.hide()?,
+                Some(OverlayCommand::Area(_)) | None => {}
+            }
+            if let Some(Shown::Area(_, until)) = &self.shown
+                && Instant::now() >= *until
+            {
+                self.hide()?;
+            }
+            if let (true, Some((window, gc))) = (needs_drawing, self.window) {
+                match &self.shown {
+                    Some(Shown::Menu(menu)) => self.draw_menu(window, gc, menu)?,
+                    Some(Shown::Area(area, _)) => self.draw_area(window, gc, area)?,
+                    None => {}
+                }
+            }
+        }
+    }
+
+    // The square a menu of `radius` pixels takes, centered on the pointer.
+    fn around_pointer(&self, radius: u16) -> Result<Rectangle, ReplyOrIdError> {
+        let root = self.connection.setup().roots[self.screen].root;
+        let pointer = self.connection.query_pointer(root)?.reply()?;
+        Ok(Rectangle {
+            x: pointer.root_x - radius as i16,
+            y: pointer.root_y - radius as i16,
+            width: radius * 2,
+            height: radius * 2,
+        })
+    }
+
+    fn on_screen(&self, [left, top, right, bottom]: [f32; 4]) -> Rectangle {
+        let screen = &self.connection.setup().roots[self.screen];
+        let (width, height) = (screen.width_in_pixels as f32, screen.height_in_pixels as f32);
+        Rectangle {
+            x: (left * width) as i16,
+            y: (top * height) as i16,
+            width: (((right - left) * width) as u16).max(1),
+            height: (((bottom - top) * height) as u16).max(1),
+        }
+    }
+
+    // A borderless window covering `rect`, with an alpha channel if the X server has
+    // one.
+    fn open(&self, rect: Rectangle) -> Result<(Window, Gcontext), ReplyOrIdError> {
+        let connection = &self.connection;
+        let screen = &connection.setup().roots[self.screen];
+        let (depth, visual, colormap) = match argb_visual(screen) {
+            Some(visual) => {
+                let colormap = connection.generate_id()?;
+                connection.create_colormap(ColormapAlloc::NONE, colormap, screen.root, visual)?;
+                (32, visual, colormap)
+            }
+            None => (screen.root_depth, screen.root_visual, screen.default_colormap),
+        };
+
+        let window = connection.generate_id()?;
+        let attributes = CreateWindowAux::new()
+            .override_redirect(1)
+            .background_pixel(TRANSPARENT)
+            .border_pixel(0)
+            .colormap(colormap)
+            .event_mask(EventMask::EXPOSURE);
+        connection.create_window(
+            depth,
+            window,
+            screen.root,
+            rect.x,
+            rect.y,
+            rect.width,
+            rect.height,
+            0,
+            WindowClass::INPUT_OUTPUT,
+            visual,
+            &attributes,
+        )?;
+        let font = connection.generate_id()?;
+        connection.open_font(font, b"fixed")?;
+        let gc = connection.generate_id()?;
+        connection.create_gc(gc, window, &CreateGCAux::new().font(font))?;
+        connection.close_font(font)?;
+        connection.map_window(window)?;
+        connection.flush()?;
+        Ok((window, gc))
+    }
+
+    fn hide(&mut self) -> Result<(), ReplyOrIdError> {
+        if let Some((window, gc)) = self.window.take() {
+            self.connection.free_gc(gc)?;
+            self.connection.destroy_window(window)?;
+            self.connection.flush()?;
+        }
+        self.shown = None;
+        Ok(())
+    }
+
+    fn draw_menu(&self, window: Window, gc: Gcontext, menu: &Menu) -> Result<(), ReplyOrIdError> {
+        let connection = &self.connection;
+        let size = menu.radius * 2;
+        let foreground = |color: u32| ChangeGCAux::new().foreground(color);
+        connection.change_gc(gc, &foreground(TRANSPARENT))?;
+        let square = Rectangle {
+            x: 0,
+            y: 0,
+            width: size,
+            height: size,
+        };
+        connection.poly_fill_rectangle(window, gc, &[square])?;
+        let circle = Arc {
+            x: 0,
+            y: 0,
+            width: size,
+            height: size,
+            angle1: 0,
+            angle2: 360 * 64,
+        };
+        connection.change_gc(gc, &foreground(BACKGROUND))?;
+        connection.poly_fill_arc(window, gc, &[circle])?;
+
+        let slice = 360.0 / menu.labels.len().max(1) as f32;
+        if let Some(selected) = menu.selected {
+            // X11 angles are in 1/64° counterclockwise from 3 o'clock, and slices go
+            // clockwise from 12.
+            let start = 90.0 - selected as f32 * slice - slice / 2.0;
+            let wedge = Arc {
+                angle1: (start * 64.0) as i16,
+                angle2: (slice * 64.0) as i16,
+                ..circle
+            };
+            connection.change_gc(gc, &foreground(SELECTED))?;
+            connection.poly_fill_arc(window, gc, &[wedge])?;
+        }
+
+        let radius = menu.radius as f32;
+        for (i, label) in menu.labels.iter().enumerate() {
+            let angle = (i as f32 * slice).to_radians();
+            let x = radius + radius * 0.6 * angle.sin();
+            let y = radius - radius * 0.6 * angle.cos();
+            let text = &label.as_bytes()[..label.len().min(40)];
+            let background = match menu.selected == Some(i) {
+                true => SELECTED,
+                false => BACKGROUND,
+            };
+            connection.change_gc(gc, &foreground(TEXT).background(background))?;
+            let left = x as i16 - text.len() as i16 * FONT_WIDTH / 2;
+            connection.image_text8(window, gc, left, y as i16 + FONT_HEIGHT / 3, text)?;
+        }
+        connection.flush()?;
+        Ok(())
+    }
+
+    // The area filled and outlined, with its label in the middle.
+    fn draw_area(&self, window: Window, gc: Gcontext, area: &Area) -> Result<(), ReplyOrIdError> {
+        let connection = &self.connection;
+        let geometry = connection.get_geometry(window)?.reply()?;
+        let (width, height) = (geometry.width, geometry.height);
+        let fill = Rectangle {
+            x: 0,
+            y: 0,
+            width,
+            height,
+        };
+        connection.change_gc(gc, &ChangeGCAux::new().foreground(AREA))?;
+        connection.poly_fill_rectangle(window, gc, &[fill])?;
+        let border = ChangeGCAux::new().foreground(AREA_BORDER).line_width(4);
+        connection.change_gc(gc, &border)?;
+        let outline = Rectangle {
+            x: 2,
+            y: 2,
+            width: width.saturating_sub(4),
+            height: height.saturating_sub(4),
+        };
+        connection.poly_rectangle(window, gc, &[outline])?;
+
+        let text = &area.label.as_bytes()[..area.label.len().min(40)];
+        connection.change_gc(gc, &ChangeGCAux::new().foreground(TEXT).background(AREA))?;
+        let left = (width / 2) as i16 - text.len() as i16 * FONT_WIDTH / 2;
+        connection.image_text8(window, gc, left, (height / 2) as i16 + FONT_HEIGHT / 3, text)?;
+        connection.flush()?;
+        Ok(())
+    }
+}
+
+fn argb_visual(screen: &Screen) -> Option<Visualid> {
+    screen
+        .allowed_depths
+        .iter()
+        .filter(|depth| depth.depth == 32)
+        .flat_map(|depth| &depth.visuals)
+        .find(|visual| visual.class == VisualClass::TRUE_COLOR)
+        .map(|visual| visual.visual_id)
+}
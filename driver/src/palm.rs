@@ -0,0 +1,86 @@
+use serde::Deserialize;
+
+use crate::config::AXIS_MAX;
+use crate::pressure::MAX_PRESSURE;
+
+// The tablet only senses the pen, so a resting hand shows up as the tip brushing
+// the surface: light touches landing near the edges, where the hand rests. Such a
+// touch is ignored until it gets firmer than `min_pressure` or moves inwards.
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(default)]
+pub struct PalmRejectionConfig {
+    pub enabled: bool,
+    // Width of the band along the edges, in tablet units (4096 across).
+    pub edge: i32,
+    // 0.0 to 1.0 of full pressure, as sent.
+    pub min_pressure: f32,
+}
+
+impl Default for PalmRejectionConfig {
+    fn default() -> Self {
+        PalmRejectionConfig {
+            enabled: false,
+            edge: 200,
+            min_pressure: 0.3,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct PalmRejection {
+    // Whether the current touch was taken as one, or is still being ignored.
+    accepted: Option<bool>,
+}
+
+impl PalmRejection {
+    // Whether the touch at (x, y) with `pressure` (on the output scale) is ignored.
+    pub fn rejects(
+        &mut self,
+        config: &PalmRejectionConfig,
+        (x, y): (i32, i32),
+        pressure: i32,
+        is_touching: bool,
+    ) -> bool {
+        if !is_touching || !config.enabled {
+            self.accepted = None;
+            return false;
+        }
+        if self.accepted == Some(true) {
+            return false;
+        }
+        let is_on_edge = [x, y]
+            .iter()
+            .any(|&axis| axis < config.edge || axis > AXIS_MAX - config.edge);
+        let is_light = (pressure as f32) < config.min_pressure * MAX_PRESSURE as f32;
+        let is_palm = is_on_edge && is_light;
+        self.accepted = Some(!is_palm);
+        is_palm
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn light_touches_on_the_edges_are_ignored_until_firmer_or_inwards() {
+        let config = PalmRejectionConfig {
+            enabled: true,
+            ..PalmRejectionConfig::default()
+        };
+        let mut palm = PalmRejection::default();
+        let (light, firm) = (500, 3000);
+
+        assert!(palm.rejects(&config, (3950, 2000), light, true));
+        assert!(palm.rejects(&config, (3950, 2100), light, true));
+        assert!(!palm.rejects(&config, (3800, 2100), light, true));
+        // Once taken, the stroke goes on to the edge.
+        assert!(!palm.rejects(&config, (3950, 2100), light, true));
+        assert!(!palm.rejects(&config, (3950, 2100), light, false));
+
+        assert!(palm.rejects(&config, (2000, 100), light, true));
+        assert!(!palm.rejects(&config, (2000, 100), firm, true));
+        palm.rejects(&config, (2000, 100), 0, false);
+        assert!(!palm.rejects(&config, (2000, 2000), light, true));
+    }
+}
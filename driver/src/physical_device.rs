@@ -1,114 +1,291 @@
-use rusb::{
-    devices, ConfigDescriptor, Device, DeviceHandle, Error as RusbError, GlobalContext,
-    InterfaceDescriptor, TransferType,
-};
+use rusb::{devices, Device, Error as RusbError, GlobalContext};
 
+use tracing::debug;
+
+use std::fs;
+use std::io;
+use std::os::fd::BorrowedFd;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 
+use crate::descriptor::{self, ReportLayout};
+use crate::device::{self, KnownDevice};
+use crate::exit_notice::ExitNotice;
+use crate::hidraw::Hidraw;
+use crate::protocol::{self, Protocol};
+use crate::usb::UsbTablet;
+use crate::vendor_init::{self, DigitizerParams, InitStep};
+use crate::virtual_device::RawDataReader;
+
+// How the tablet's reports are read, from --backend.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Backend {
+    // Claims the tablet through libusb, detaching the kernel's HID driver.
+    #[default]
+    Usb,
+    // Reads the kernel's hidraw node of the tablet.
+    Hidraw,
+}
+
+impl Backend {
+    // The node this backend opens for the tablet at `bus` and `address`.
+    pub fn node_path(self, bus: u8, address: u8) -> Option<PathBuf> {
+        match self {
+            Backend::Usb => Some(format!("/dev/bus/usb/{:03}/{:03}", bus, address).into()),
+            Backend::Hidraw => device::scan_hidraw()
+                .into_iter()
+                .find(|node| (node.bus, node.address) == (bus, address))
+                .map(|node| node.path),
+        }
+    }
+}
+
+enum Transport {
+    Usb(UsbTablet),
+    Hidraw(Hidraw),
+}
+
 pub struct PhysicalDevice {
     device: Device<GlobalContext>,
-    device_handle: DeviceHandle<GlobalContext>,
-    endpoint_address: u8,
+    transport: Transport,
+    // Ranges the tablet gave in the vendor handshake.
+    params: Option<DigitizerParams>,
 }
 
-impl PhysicalDevice {
-    pub fn new(vid: u16, pid: u16) -> Self {
-        let device = Self::get_target_device(vid, pid).expect("Error finding device.");
+// What opening the tablet found out about its reports.
+#[derive(Clone, Copy, Debug)]
+pub struct ReportFormat {
+    // The model's layout, by its USB ID.
+    pub protocol: Protocol,
+    // Fields from the report descriptor, if it describes a pen report.
+    pub layout: Option<ReportLayout>,
+    pub params: Option<DigitizerParams>,
+}
 
-        PhysicalDevice {
-            endpoint_address: 0,
-            device_handle: device.open().expect("Error opening device."),
+impl ReportFormat {
+    // Reads the reports in this format, or in the protocol the config names.
+    pub fn data_reader(&self, configured: Option<Protocol>) -> RawDataReader {
+        let parser = protocol::select_parser(configured, self.protocol, self.layout);
+        RawDataReader::with_parser(parser).with_digitizer_params(self.params)
+    }
+}
+
+impl PhysicalDevice {
+    // Opens the tablet through `backend` and sends the vendor handshake, ready to
+    // read reports.
+    pub fn open(device: Device<GlobalContext>, backend: Backend) -> Result<Self, RusbError> {
+        let transport = match backend {
+            Backend::Usb => Transport::Usb(UsbTablet::open(&device)?),
+            Backend::Hidraw => {
+                let path = backend
+                    .node_path(device.bus_number(), device.address())
+                    .ok_or(RusbError::NotFound)?;
+                debug!("Reading {}.", path.display());
+                Transport::Hidraw(Hidraw::open(&path).map_err(io_to_rusb_error)?)
+            }
+        };
+        let mut physical_device = PhysicalDevice {
             device,
+            transport,
+            params: None,
+        };
+        let steps = physical_device.init_steps();
+        let result = match &mut physical_device.transport {
+            Transport::Usb(usb) => vendor_init::run(usb, steps),
+            Transport::Hidraw(hidraw) => vendor_init::run(hidraw, steps),
+        };
+        physical_device.params =
+            result.map_err(|err| err.downcast::<RusbError>().unwrap_or_else(io_to_rusb_error))?;
+        Ok(physical_device)
+    }
+
+    fn init_steps(&self) -> &'static [InitStep] {
+        self.known_device()
+            .map_or(vendor_init::VINSA_1060_PLUS, |known| known.init)
+    }
+
+    fn known_device(&self) -> Option<&'static KnownDevice> {
+        let descriptor = self.device.device_descriptor().ok()?;
+        device::known_device(descriptor.vendor_id(), descriptor.product_id())
+    }
+
+    pub fn bus_number(&self) -> u8 {
+        self.device.bus_number()
+    }
+
+    pub fn address(&self) -> u8 {
+        self.device.address()
+    }
+
+    pub fn report_format(&self) -> ReportFormat {
+        ReportFormat {
+            protocol: self.protocol(),
+            layout: self.report_layout(),
+            params: self.params,
         }
     }
 
-    pub fn init(&mut self) -> &mut Self {
-        self.device_handle
-            .set_auto_detach_kernel_driver(true)
-            .expect("Error detaching old driver.");
-
-        let configurations = Self::get_configurations(&self.device);
-        let interface_descriptors = Self::get_hid_interface_descriptors(&configurations);
-
-        for interface_descriptor in interface_descriptors {
-            self.device_handle
-                .claim_interface(interface_descriptor.interface_number())
-                .expect("Error claiming interface.");
-            for endpoint_descriptor in interface_descriptor.endpoint_descriptors() {
-                if endpoint_descriptor.transfer_type() == TransferType::Interrupt
-                    && endpoint_descriptor.max_packet_size() == 64
-                {
-                    self.endpoint_address = endpoint_descriptor.address();
-                }
+    // Field offsets from the HID report descriptor of the interface the reports come
+    // from, if it describes a pen report.
+    fn report_layout(&self) -> Option<ReportLayout> {
+        let descriptor = match &self.transport {
+            Transport::Usb(usb) => usb.report_descriptor(),
+            Transport::Hidraw(hidraw) => hidraw.report_descriptor().map_err(io_to_rusb_error),
+        };
+        let descriptor = descriptor
+            .inspect_err(|err| debug!("Cannot read the report descriptor: {}", err))
+            .ok()?;
+        let layout = descriptor::parse(&descriptor);
+        debug!("Report layout from the descriptor: {:?}", layout);
+        layout
+    }
+
+    // Report layout of the model, by its USB ID.
+    fn protocol(&self) -> Protocol {
+        self.known_device()
+            .map(|known| known.protocol)
+            .unwrap_or_default()
+    }
+
+    // Names the config can use to pick this tablet, most specific first: its USB
+    // serial number if it has one, its port path as in sysfs (e.g. "1-2.3") and its
+    // USB ID (e.g. "08f2:6811"), which covers every tablet of a model or clone.
+    pub fn identifiers(&self) -> Vec<String> {
+        let ports: Vec<String> = match self.device.port_numbers() {
+            Ok(ports) => ports.iter().map(u8::to_string).collect(),
+            Err(_) => Vec::new(),
+        };
+        let port_path = format!("{}-{}", self.device.bus_number(), ports.join("."));
+        // Through hidraw the device isn't opened, but sysfs has the serial number.
+        let serial = match &self.transport {
+            Transport::Usb(usb) => usb.serial_number(&self.device),
+            Transport::Hidraw(_) => {
+                let path = Path::new("/sys/bus/usb/devices").join(&port_path).join("serial");
+                fs::read_to_string(path).ok().map(|serial| serial.trim().to_string())
             }
+        };
+        let usb_id = self.device.device_descriptor().ok().map(|descriptor| {
+            format!("{:04x}:{:04x}", descriptor.vendor_id(), descriptor.product_id())
+        });
+        serial
+            .into_iter()
+            .filter(|serial| !serial.trim().is_empty())
+            .chain([port_path])
+            .chain(usb_id)
+            .collect()
+    }
+
+    pub fn read_device_responses(&self, buffer: &mut [u8]) -> Result<usize, RusbError> {
+        self.read_with_timeout(buffer, Duration::from_millis(250))
+    }
+
+    // Blocks until a report arrives or `timeout` passes.
+    pub fn read_with_timeout(
+        &self,
+        buffer: &mut [u8],
+        timeout: Duration,
+    ) -> Result<usize, RusbError> {
+        match &self.transport {
+            Transport::Usb(usb) => usb.read_with_timeout(buffer, timeout),
+            Transport::Hidraw(hidraw) => hidraw
+                .read_with_timeout(buffer, timeout)
+                .map_err(io_to_rusb_error),
         }
-        self.reset();
-        self
     }
 
-    pub fn reset(&mut self) {
-        self.device_handle.reset().expect("Error reseting device.");
+    // The node reports are read from, for waiting on it, if reads don't go through
+    // libusb.
+    pub fn report_fd(&self) -> Option<BorrowedFd<'_>> {
+        match &self.transport {
+            Transport::Usb(_) => None,
+            Transport::Hidraw(hidraw) => Some(hidraw.as_fd()),
+        }
     }
 
-    pub fn read_device_responses(&self, buffer: &mut [u8]) -> Result<usize, RusbError> {
-        self.device_handle
-            .read_interrupt(self.endpoint_address, buffer, Duration::from_secs(3))
-    }
-
-    pub fn set_full_mode(&mut self) -> &mut Self {
-        const REPORTS: [[u8; 8]; 1] = [[0x08, 0x03, 0x00, 0xff, 0xf0, 0x00, 0xff, 0xf0]];
-        let reports_as_slices: Vec<&[u8]> = REPORTS.iter().map(|r| &r[..]).collect();
-        self.set_report(&reports_as_slices)
-            .expect("Error sending report.");
-        self
-    }
-
-    pub fn set_report(&mut self, reports: &[&[u8]]) -> Result<(), RusbError> {
-        for report in reports.iter() {
-            self.device_handle.write_control(
-                0x21,
-                0x9,
-                0x0308,
-                2,
-                report,
-                Duration::from_millis(250),
-            )?;
+    // Blocks until a report arrives, `timeout` passes if there is one, or the driver
+    // exits, which fails with `Interrupted`.
+    pub fn read_unless_exiting(
+        &self,
+        buffer: &mut [u8],
+        timeout: Option<Duration>,
+        exit: &ExitNotice,
+    ) -> Result<usize, RusbError> {
+        match &self.transport {
+            Transport::Usb(usb) => usb.read_unless_cancelled(buffer, timeout, exit.usb_reads()),
+            Transport::Hidraw(hidraw) => hidraw
+                .read_unless_exiting(buffer, timeout, exit)
+                .map_err(io_to_rusb_error),
+        }
+    }
+
+    // Finds the tablet by the node given with --device, either its usbfs node
+    // (/dev/bus/usb/BBB/DDD) or one of its hidraw nodes, else by the known VID:PIDs.
+    pub fn locate(device_path: Option<&Path>) -> Result<Device<GlobalContext>, RusbError> {
+        match device_path {
+            Some(path) if device::is_hidraw_path(path) => {
+                let node = device::hidraw_node(path).ok_or(RusbError::NoDevice)?;
+                Self::get_device_by_address(node.bus, node.address)
+            }
+            Some(path) => Self::get_device_by_path(path),
+            None => Self::get_known_device(),
         }
+    }
 
-        Ok(())
+    // Every tablet to drive: the one given with --device, or all known ones.
+    pub fn locate_all(device_path: Option<&Path>) -> Result<Vec<Device<GlobalContext>>, RusbError> {
+        match device_path {
+            Some(_) => Ok(vec![Self::locate(device_path)?]),
+            None => Ok(devices()?.iter().filter(Self::is_known_device).collect()),
+        }
     }
 
-    fn is_target_device(vid: u16, pid: u16, device: &Device<GlobalContext>) -> bool {
-        let device_descriptor = device.device_descriptor().unwrap();
-        device_descriptor.vendor_id() == vid && device_descriptor.product_id() == pid
+    fn get_device_by_path(path: &Path) -> Result<Device<GlobalContext>, RusbError> {
+        let mut components = path.iter().rev();
+        let address = components.next().and_then(|a| a.to_str()?.parse::<u8>().ok());
+        let bus = components.next().and_then(|b| b.to_str()?.parse::<u8>().ok());
+        let (Some(bus), Some(address)) = (bus, address) else {
+            return Err(RusbError::InvalidParam);
+        };
+        Self::get_device_by_address(bus, address)
     }
 
-    fn get_target_device(vid: u16, pid: u16) -> Result<Device<GlobalContext>, RusbError> {
-        match devices()?
+    fn get_device_by_address(bus: u8, address: u8) -> Result<Device<GlobalContext>, RusbError> {
+        devices()?
             .iter()
-            .find(|device| Self::is_target_device(vid, pid, device))
+            .find(|device| device.bus_number() == bus && device.address() == address)
+            .ok_or(RusbError::NoDevice)
+    }
+
+    fn is_known_device(device: &Device<GlobalContext>) -> bool {
+        device.device_descriptor().is_ok_and(|descriptor| {
+            device::known_device(descriptor.vendor_id(), descriptor.product_id()).is_some()
+        })
+    }
+
+    // Prefers a tablet that shows up as hidraw; once the driver has detached the
+    // kernel's HID driver those nodes are gone, so fall back to a USB scan.
+    fn get_known_device() -> Result<Device<GlobalContext>, RusbError> {
+        if let Some(node) = device::scan_hidraw().first()
+            && let Ok(device) = Self::get_device_by_address(node.bus, node.address)
         {
-            Some(device) => Ok(device),
-            None => Err(RusbError::NoDevice),
+            return Ok(device);
         }
-    }
-    fn get_hid_interface_descriptors(
-        config_descriptors: &[ConfigDescriptor],
-    ) -> Vec<InterfaceDescriptor> {
-        config_descriptors
+        devices()?
             .iter()
-            .flat_map(|config_descriptor| config_descriptor.interfaces())
-            .flat_map(|interface| interface.descriptors())
-            .filter(|interface_descriptor| {
-                interface_descriptor.class_code() == rusb::constants::LIBUSB_CLASS_HID
-            })
-            .collect()
+            .find(Self::is_known_device)
+            .ok_or(RusbError::NoDevice)
     }
+}
 
-    fn get_configurations(device: &Device<GlobalContext>) -> Vec<ConfigDescriptor> {
-        let device_descriptor = device.device_descriptor().unwrap();
-        (0..device_descriptor.num_configurations())
-            .filter_map(|n| device.config_descriptor(n).ok())
-            .collect()
+// Errors from hidraw in the terms the rest of the driver handles, e.g. an unplugged
+// tablet as `NoDevice`.
+fn io_to_rusb_error(err: io::Error) -> RusbError {
+    match err.kind() {
+        io::ErrorKind::TimedOut => RusbError::Timeout,
+        io::ErrorKind::Interrupted => RusbError::Interrupted,
+        io::ErrorKind::NotFound => RusbError::NoDevice,
+        io::ErrorKind::PermissionDenied => RusbError::Access,
+        _ if err.raw_os_error() == Some(libc::ENODEV) => RusbError::NoDevice,
+        _ => RusbError::Io,
     }
 }
@@ -0,0 +1,233 @@
+use serde::Deserialize;
+
+pub const MAX_PRESSURE: i32 = 8191;
+// ABS_DISTANCE range: 0 is about to touch, the maximum is hovering freely.
+pub const MAX_DISTANCE: i32 = 63;
+
+// Piecewise-linear curve through (input, output) control points, both in 0.0..=1.0.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(try_from = "Vec<[f32; 2]>")]
+pub struct PressureCurve {
+    points: Vec<(f32, f32)>,
+}
+
+// How raw pen pressure becomes a touch: readings up to `touch_threshold` count as
+// hovering, anything above is multiplied by `pressure_gain`, raised to
+// `pressure_gamma` as a fraction of full pressure (below 1 is softer, above 1 is
+// firmer) and spread between `pressure_min_out` and `pressure_max_out`. Once down,
+// the pen stays down until the reading falls to `release_threshold` (by default 90%
+// of the touch threshold), so pressure right at the threshold doesn't make it flicker.
+// In mouse mode the pen clicks at `click_threshold` instead, released at 90% of it.
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PressureResponse {
+    pub touch_threshold: i32,
+    #[serde(default)]
+    pub release_threshold: Option<i32>,
+    #[serde(default)]
+    pub click_threshold: Option<i32>,
+    pub pressure_gain: f32,
+    #[serde(default = "default_gamma")]
+    pub pressure_gamma: f32,
+    #[serde(default)]
+    pub pressure_min_out: i32,
+    #[serde(default = "default_max_out")]
+    pub pressure_max_out: i32,
+}
+
+fn default_gamma() -> f32 {
+    1.0
+}
+
+fn default_max_out() -> i32 {
+    MAX_PRESSURE
+}
+
+impl PressureResponse {
+    pub fn is_touching(&self, raw_pressure: i32, was_touching: bool) -> bool {
+        crosses(raw_pressure, self.touch_threshold, self.release(), was_touching)
+    }
+
+    fn release(&self) -> i32 {
+        self.release_threshold.unwrap_or(self.touch_threshold * 9 / 10)
+    }
+
+    pub fn is_clicking(&self, raw_pressure: i32, was_clicking: bool) -> bool {
+        match self.click_threshold {
+            Some(threshold) => crosses(raw_pressure, threshold, threshold * 9 / 10, was_clicking),
+            None => self.is_touching(raw_pressure, was_clicking),
+        }
+    }
+
+    // While the pen is down, readings between the release and touch thresholds still
+    // give pressure, so a stroke doesn't go through gaps of none.
+    pub fn normalize(&self, raw_pressure: i32, is_touching: bool) -> i32 {
+        let x = 2000 - raw_pressure;
+        let threshold = match is_touching {
+            true => self.release().min(self.touch_threshold),
+            false => self.touch_threshold,
+        };
+        if x <= threshold {
+            return 0;
+        }
+        let fraction = (x as f32 * self.pressure_gain / MAX_PRESSURE as f32).clamp(0.0, 1.0);
+        let fraction = fraction.powf(self.pressure_gamma.max(0.01));
+        let max_out = self.pressure_max_out.clamp(0, MAX_PRESSURE);
+        let min_out = self.pressure_min_out.clamp(0, max_out);
+        (min_out as f32 + fraction * (max_out - min_out) as f32).round() as i32
+    }
+
+    // How far the reading is from counting as a touch, scaled to 0..=MAX_DISTANCE.
+    // The nib registers a little pressure before it touches, which gives a rough
+    // hover height just above the surface.
+    pub fn hover_distance(&self, raw_pressure: i32) -> i32 {
+        let threshold = self.touch_threshold.max(1);
+        let headroom = (threshold - (2000 - raw_pressure)).clamp(0, threshold);
+        headroom * MAX_DISTANCE / threshold
+    }
+}
+
+// Whether the reading is past `threshold`, or still past `release` if it already was.
+fn crosses(raw_pressure: i32, threshold: i32, release: i32, was_past: bool) -> bool {
+    let threshold = if was_past { release.min(threshold) } else { threshold };
+    2000 - raw_pressure > threshold
+}
+
+impl Default for PressureCurve {
+    fn default() -> Self {
+        PressureCurve {
+            points: vec![(0.0, 0.0), (1.0, 1.0)],
+        }
+    }
+}
+
+impl TryFrom<Vec<[f32; 2]>> for PressureCurve {
+    type Error = String;
+
+    fn try_from(raw_points: Vec<[f32; 2]>) -> Result<Self, Self::Error> {
+        let mut points: Vec<(f32, f32)> = raw_points.iter().map(|p| (p[0], p[1])).collect();
+
+        if points
+            .iter()
+            .any(|&(x, y)| !(0.0..=1.0).contains(&x) || !(0.0..=1.0).contains(&y))
+        {
+            return Err("pressure curve points must be within 0.0..=1.0".to_string());
+        }
+
+        points.sort_by(|a, b| a.0.total_cmp(&b.0));
+        if points.first().is_none_or(|p| p.0 > 0.0) {
+            points.insert(0, (0.0, 0.0));
+        }
+        if points.last().is_some_and(|p| p.0 < 1.0) {
+            points.push((1.0, 1.0));
+        }
+
+        Ok(PressureCurve { points })
+    }
+}
+
+impl PressureCurve {
+    pub fn apply(&self, pressure: i32) -> i32 {
+        if pressure <= 0 {
+            return 0;
+        }
+
+        let input = (pressure as f32 / MAX_PRESSURE as f32).min(1.0);
+        let output = self
+            .points
+            .windows(2)
+            .find(|segment| input <= segment[1].0)
+            .map(|segment| {
+                let ((x0, y0), (x1, y1)) = (segment[0], segment[1]);
+                if x1 - x0 <= f32::EPSILON {
+                    y1
+                } else {
+                    y0 + (input - x0) * (y1 - y0) / (x1 - x0)
+                }
+            })
+            .unwrap_or(1.0);
+
+        (output * MAX_PRESSURE as f32).round() as i32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_curve_is_linear() {
+        let curve = PressureCurve::default();
+        assert_eq!(curve.apply(0), 0);
+        assert_eq!(curve.apply(4096), 4096);
+        assert_eq!(curve.apply(MAX_PRESSURE), MAX_PRESSURE);
+    }
+
+    #[test]
+    fn control_points_are_interpolated() {
+        let curve = PressureCurve::try_from(vec![[0.5, 0.25]]).unwrap();
+        assert_eq!(curve.apply(MAX_PRESSURE / 2), (MAX_PRESSURE as f32 * 0.25).round() as i32);
+        assert_eq!(curve.apply(MAX_PRESSURE), MAX_PRESSURE);
+    }
+
+    #[test]
+    fn touch_is_released_below_a_lower_threshold() {
+        let response = PressureResponse {
+            touch_threshold: 600,
+            release_threshold: Some(500),
+            click_threshold: None,
+            pressure_gain: 1.0,
+            pressure_gamma: 1.0,
+            pressure_min_out: 0,
+            pressure_max_out: MAX_PRESSURE,
+        };
+        assert!(!response.is_touching(2000 - 550, false));
+        assert!(response.is_touching(2000 - 650, false));
+        assert!(response.is_touching(2000 - 550, true));
+        assert!(!response.is_touching(2000 - 500, true));
+    }
+
+    #[test]
+    fn hover_distance_falls_towards_the_touch_threshold() {
+        let response = PressureResponse {
+            touch_threshold: 600,
+            release_threshold: None,
+            click_threshold: None,
+            pressure_gain: 1.0,
+            pressure_gamma: 1.0,
+            pressure_min_out: 0,
+            pressure_max_out: MAX_PRESSURE,
+        };
+        assert_eq!(response.hover_distance(2000), MAX_DISTANCE);
+        assert_eq!(response.hover_distance(1700), MAX_DISTANCE / 2);
+        assert_eq!(response.hover_distance(1400), 0);
+        assert_eq!(response.hover_distance(1000), 0);
+    }
+
+    #[test]
+    fn output_is_shaped_between_the_floor_and_the_cap() {
+        let response = PressureResponse {
+            touch_threshold: 100,
+            release_threshold: None,
+            click_threshold: None,
+            pressure_gain: 4.0,
+            pressure_gamma: 2.0,
+            pressure_min_out: 1000,
+            pressure_max_out: 7000,
+        };
+        assert_eq!(response.normalize(2000 - 100, false), 0);
+        // Just past the threshold the output starts at the floor.
+        assert_eq!(response.normalize(2000 - 101, false), 1015);
+        // Half of full pressure comes out at a quarter of the span.
+        assert_eq!(response.normalize(2000 - 1024, false), 2500);
+        assert_eq!(response.normalize(2000 - 2048, false), 7000);
+        // Once down, the pen keeps some pressure down to the release threshold.
+        assert_eq!(response.normalize(2000 - 95, true), 1013);
+        assert_eq!(response.normalize(2000 - 90, true), 0);
+    }
+
+    #[test]
+    fn out_of_range_points_are_rejected() {
+        assert!(PressureCurve::try_from(vec![[0.5, 1.5]]).is_err());
+    }
+}
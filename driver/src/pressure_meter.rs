@@ -0,0 +1,127 @@
+use crate::config::Config;
+use crate::error::Result;
+use crate::pressure::MAX_PRESSURE;
+use crate::virtual_device::{DeviceDispatcher, RawDataReader};
+use crate::virtual_output::RecordingOutput;
+
+const BAR_WIDTH: usize = 30;
+// Raw readings are 2000 at rest and fall towards 0 with force.
+const LEVEL_MAX: i32 = 2000;
+
+// What `test-pressure` shows for one report.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Reading {
+    pub raw: i32,
+    // How far the calibrated reading is below rest, which the thresholds compare to.
+    pub level: i32,
+    pub threshold: i32,
+    // Pressure the virtual pen would send.
+    pub output: i32,
+    pub is_touching: bool,
+    pub peak: i32,
+}
+
+// Runs reports through a dispatcher whose devices only record, so the pressure
+// shown is exactly what the driver would send with this config.
+pub struct PressureMeter {
+    dispatcher: DeviceDispatcher,
+    pen: RecordingOutput,
+    peak: i32,
+}
+
+impl PressureMeter {
+    pub fn new(config: &Config, is_mouse_mode: bool) -> Result<Self> {
+        let pen = RecordingOutput::new();
+        let mut dispatcher = DeviceDispatcher::with_outputs(
+            config,
+            Box::new(pen.clone()),
+            Box::new(RecordingOutput::new()),
+            None,
+            None,
+        );
+        dispatcher.set_mouse_mode(is_mouse_mode)?;
+        Ok(PressureMeter {
+            dispatcher,
+            pen,
+            peak: 0,
+        })
+    }
+
+    // None while the pen is out of range.
+    pub fn feed(&mut self, raw_data: &RawDataReader) -> Result<Option<Reading>> {
+        self.dispatcher.dispatch(raw_data)?;
+        self.pen.clear();
+        if raw_data.pen_buttons() == 0 {
+            return Ok(None);
+        }
+
+        let output = self.dispatcher.pressure();
+        self.peak = self.peak.max(output);
+        Ok(Some(Reading {
+            raw: raw_data.pressure(),
+            level: LEVEL_MAX - self.dispatcher.mapped_pressure(raw_data),
+            threshold: self.dispatcher.touch_threshold(),
+            output,
+            is_touching: self.dispatcher.is_pen_down(),
+            peak: self.peak,
+        }))
+    }
+}
+
+impl Reading {
+    // One terminal line: the level against the touch threshold (`|`), then the output.
+    pub fn render(&self) -> String {
+        let mut level_bar = bar(self.level, LEVEL_MAX);
+        let marker = (self.threshold.clamp(0, LEVEL_MAX) as usize * BAR_WIDTH
+            / LEVEL_MAX as usize)
+            .min(BAR_WIDTH - 1);
+        level_bar.replace_range(marker..marker + 1, "|");
+        format!(
+            "raw {:4}  level {:4} [{}] threshold {:4}  out {:4} [{}] peak {:4}  {}",
+            self.raw,
+            self.level,
+            level_bar,
+            self.threshold,
+            self.output,
+            bar(self.output, MAX_PRESSURE),
+            self.peak,
+            if self.is_touching { "DOWN" } else { "up  " }
+        )
+    }
+}
+
+fn bar(value: i32, max: i32) -> String {
+    let filled = (value.clamp(0, max) as usize * BAR_WIDTH).div_ceil(max as usize);
+    "#".repeat(filled) + &*".".repeat(BAR_WIDTH - filled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Mode;
+
+    fn report(pressure: u16) -> RawDataReader {
+        RawDataReader::from_fields(0x0800, 0x0800, pressure, 2)
+    }
+
+    #[test]
+    fn readings_follow_the_driver_pressure() {
+        let config = Config {
+            mode: Mode::Tablet,
+            ..Config::default()
+        };
+        let mut meter = PressureMeter::new(&config, false).unwrap();
+
+        let hover = meter.feed(&report(2000)).unwrap().unwrap();
+        assert_eq!((hover.level, hover.output, hover.is_touching), (0, 0, false));
+        assert_eq!(hover.threshold, config.pressure.tablet.touch_threshold);
+
+        let press = meter.feed(&report(1000)).unwrap().unwrap();
+        assert_eq!((press.level, press.output, press.is_touching), (1000, 3000, true));
+        let line = press.render();
+        assert!(line.contains("out 3000") && line.contains("DOWN"));
+
+        let lifted = meter.feed(&report(2000)).unwrap().unwrap();
+        assert_eq!(lifted.peak, 3000);
+    }
+}
@@ -0,0 +1,206 @@
+use serde::Deserialize;
+
+use std::ffi::CString;
+use std::fs;
+use std::io;
+use std::mem::MaybeUninit;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::ptr;
+
+// Unprivileged account to switch to once the tablet and its virtual devices are
+// open, so the long-running event loop doesn't keep root.
+#[derive(Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct PrivilegesConfig {
+    pub user: Option<String>,
+    // Defaults to the user's primary group.
+    pub group: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Account {
+    pub uid: libc::uid_t,
+    pub gid: libc::gid_t,
+}
+
+impl PrivilegesConfig {
+    // The configured account, or None if no user is set.
+    pub fn account(&self) -> io::Result<Option<Account>> {
+        let Some(user) = &self.user else {
+            return Ok(None);
+        };
+        let (uid, primary_gid) = lookup_user(user)?;
+        let gid = match &self.group {
+            Some(group) => lookup_group(group)?,
+            None => primary_gid,
+        };
+        Ok(Some(Account { uid, gid }))
+    }
+}
+
+pub fn is_root() -> bool {
+    unsafe { libc::geteuid() == 0 }
+}
+
+// Switches the whole process to `account`, keeping the user's supplementary groups
+// (e.g. `input` for tablets plugged in later). Root can't be regained afterwards.
+pub fn switch_to(user: &str, account: Account) -> io::Result<()> {
+    let name = c_string(user)?;
+    let check = |result: libc::c_int| match result {
+        -1 => Err(io::Error::last_os_error()),
+        _ => Ok(()),
+    };
+    // Groups first: changing them needs the privileges that setuid gives up.
+    // SAFETY: plain syscalls on a valid, NUL-terminated user name.
+    unsafe {
+        check(libc::initgroups(name.as_ptr(), account.gid))?;
+        check(libc::setgid(account.gid))?;
+        check(libc::setuid(account.uid))?;
+        if libc::setuid(0) != -1 {
+            return Err(io::Error::other("root privileges could be regained"));
+        }
+    }
+    Ok(())
+}
+
+// Primary and supplementary groups of `user`, as `switch_to` leaves them.
+pub fn groups(user: &str, gid: libc::gid_t) -> io::Result<Vec<libc::gid_t>> {
+    let name = c_string(user)?;
+    let mut count: libc::c_int = 32;
+    loop {
+        let mut groups = vec![0; count as usize];
+        let capacity = count;
+        // SAFETY: `groups` has room for `count` entries; when that is too few,
+        // getgrouplist returns -1 and sets `count` to the number needed.
+        let result =
+            unsafe { libc::getgrouplist(name.as_ptr(), gid, groups.as_mut_ptr(), &mut count) };
+        if result != -1 {
+            groups.truncate(count as usize);
+            return Ok(groups);
+        }
+        count = count.max(capacity * 2);
+    }
+}
+
+// Whether `account`, in `groups`, may open `path` for reading and writing, going by
+// its mode bits as the kernel does. Access given through an ACL isn't seen.
+pub fn can_read_write(account: Account, groups: &[libc::gid_t], path: &Path) -> bool {
+    let Ok(metadata) = fs::metadata(path) else {
+        return false;
+    };
+    let mode = match metadata.mode() {
+        _ if account.uid == 0 => return true,
+        mode if metadata.uid() == account.uid => mode >> 6,
+        mode if groups.contains(&metadata.gid()) => mode >> 3,
+        mode => mode,
+    };
+    mode & 0o6 == 0o6
+}
+
+fn c_string(name: &str) -> io::Result<CString> {
+    CString::new(name).map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))
+}
+
+fn not_found(kind: &str, name: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, format!("no {} named `{}`", kind, name))
+}
+
+// Uid and primary gid of `user`.
+fn lookup_user(user: &str) -> io::Result<(libc::uid_t, libc::gid_t)> {
+    let name = c_string(user)?;
+    let mut entry = MaybeUninit::<libc::passwd>::uninit();
+    let mut buffer = vec![0 as libc::c_char; 4096];
+    let mut result = ptr::null_mut();
+    // SAFETY: every pointer is valid for the sizes passed; `entry` is only read when
+    // `result` says it was filled in.
+    let error = unsafe {
+        libc::getpwnam_r(
+            name.as_ptr(),
+            entry.as_mut_ptr(),
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut result,
+        )
+    };
+    match (error, result.is_null()) {
+        (0, true) => Err(not_found("user", user)),
+        (0, false) => {
+            let entry = unsafe { entry.assume_init() };
+            Ok((entry.pw_uid, entry.pw_gid))
+        }
+        (error, _) => Err(io::Error::from_raw_os_error(error)),
+    }
+}
+
+fn lookup_group(group: &str) -> io::Result<libc::gid_t> {
+    let name = c_string(group)?;
+    let mut entry = MaybeUninit::<libc::group>::uninit();
+    let mut buffer = vec![0 as libc::c_char; 4096];
+    let mut result = ptr::null_mut();
+    // SAFETY: as in lookup_user.
+    let error = unsafe {
+        libc::getgrnam_r(
+            name.as_ptr(),
+            entry.as_mut_ptr(),
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut result,
+        )
+    };
+    match (error, result.is_null()) {
+        (0, true) => Err(not_found("group", group)),
+        (0, false) => Ok(unsafe { entry.assume_init() }.gr_gid),
+        (error, _) => Err(io::Error::from_raw_os_error(error)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accounts_are_looked_up_by_name() {
+        let config = PrivilegesConfig {
+            user: Some("root".to_string()),
+            group: None,
+        };
+        assert_eq!(config.account().unwrap(), Some(Account { uid: 0, gid: 0 }));
+        assert_eq!(PrivilegesConfig::default().account().unwrap(), None);
+
+        let missing = PrivilegesConfig {
+            user: Some("no-such-user-v1060p".to_string()),
+            group: None,
+        };
+        assert_eq!(missing.account().unwrap_err().kind(), io::ErrorKind::NotFound);
+        assert!(groups("root", 0).unwrap().contains(&0));
+    }
+
+    #[test]
+    fn access_follows_the_owner_group_and_other_bits() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = std::env::temp_dir().join(format!("v1060p-access-{}", std::process::id()));
+        fs::write(&path, "").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o660)).unwrap();
+        let metadata = fs::metadata(&path).unwrap();
+        let other = Account {
+            uid: metadata.uid() + 1,
+            gid: metadata.gid() + 1,
+        };
+        let owner = Account {
+            uid: metadata.uid(),
+            ..other
+        };
+
+        let (in_group, not_in_group) = (&[metadata.gid()][..], &[other.gid][..]);
+        assert!(can_read_write(owner, not_in_group, &path));
+        assert!(can_read_write(other, in_group, &path));
+        assert!(!can_read_write(other, not_in_group, &path));
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o606)).unwrap();
+        assert!(!can_read_write(other, in_group, &path));
+        assert!(can_read_write(other, not_in_group, &path));
+        fs::remove_file(&path).unwrap();
+        assert!(!can_read_write(owner, in_group, &path));
+    }
+}
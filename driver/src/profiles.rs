@@ -0,0 +1,110 @@
+use serde_json::Value;
+use tracing::warn;
+
+use crate::focus_events::{self, X11Change, X11Events};
+use crate::screen::command_output;
+
+use std::env;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+// Reports the focused window's class in the background whenever it changes, woken by
+// the desktop's focus events.
+pub fn spawn_window_watcher() -> Receiver<String> {
+    let (sender, receiver) = mpsc::channel();
+
+    thread::spawn(move || {
+        let mut last_class = String::new();
+        let report = |class: Option<String>| match class {
+            Some(class) if class != last_class => {
+                let is_open = sender.send(class.clone()).is_ok();
+                last_class = class;
+                is_open
+            }
+            _ => true,
+        };
+        if let Err(err) = watch_windows(report) {
+            warn!("Stopped following the focused window: {}", err);
+        }
+    });
+
+    receiver
+}
+
+// Calls `report` with the focused window's class, then again on every focus change,
+// until it returns false.
+fn watch_windows(mut report: impl FnMut(Option<String>) -> bool) -> Result<(), String> {
+    if env::var_os("SWAYSOCK").is_some() {
+        if !report(sway_active_window_class()) {
+            return Ok(());
+        }
+        let result = focus_events::sway_events(r#"["window"]"#, |event| {
+            match event["change"].as_str() {
+                Some("focus") => report(sway_window_class(&event["container"])),
+                _ => true,
+            }
+        });
+        return result.map_err(|err| format!("swaymsg: {}", err));
+    }
+    if env::var_os("HYPRLAND_INSTANCE_SIGNATURE").is_some() {
+        if !report(hyprland_active_window_class()) {
+            return Ok(());
+        }
+        // activewindow>>CLASS,TITLE
+        let result = focus_events::hyprland_events(|event, data| match event {
+            "activewindow" => {
+                let class = data.split_once(',').map_or(data, |(class, _)| class);
+                report(Some(class.to_string()).filter(|class| !class.is_empty()))
+            }
+            _ => true,
+        });
+        return result.map_err(|err| format!("Hyprland event socket: {}", err));
+    }
+
+    let mut events = X11Events::connect()?;
+    let class = |events: &X11Events| {
+        events
+            .active_window()
+            .and_then(|window| events.window_class(window))
+    };
+    if !report(class(&events)) {
+        return Ok(());
+    }
+    loop {
+        let change = events.wait().map_err(|err| err.to_string())?;
+        if change == X11Change::ActiveWindow && !report(class(&events)) {
+            return Ok(());
+        }
+    }
+}
+
+fn sway_active_window_class() -> Option<String> {
+    fn find_focused(node: &Value) -> Option<&Value> {
+        if node["focused"].as_bool() == Some(true) {
+            return Some(node);
+        }
+        ["nodes", "floating_nodes"]
+            .iter()
+            .filter_map(|key| node[key].as_array())
+            .flatten()
+            .find_map(find_focused)
+    }
+
+    let tree: Value =
+        serde_json::from_str(&command_output("swaymsg", &["-t", "get_tree", "-r"])?).ok()?;
+    sway_window_class(find_focused(&tree)?)
+}
+
+// Wayland app ID, or the X11 class under XWayland.
+fn sway_window_class(container: &Value) -> Option<String> {
+    container["app_id"]
+        .as_str()
+        .or_else(|| container["window_properties"]["class"].as_str())
+        .map(str::to_string)
+}
+
+fn hyprland_active_window_class() -> Option<String> {
+    let window: Value =
+        serde_json::from_str(&command_output("hyprctl", &["activewindow", "-j"])?).ok()?;
+    window["class"].as_str().map(str::to_string)
+}
@@ -0,0 +1,411 @@
+use serde::Deserialize;
+use thiserror::Error;
+
+use std::sync::Arc;
+
+use crate::calibration::NOMINAL_PRESSURE_REST;
+use crate::config::AXIS_MAX;
+use crate::descriptor::{Field, LogicalRange, ReportLayout};
+
+// Decodes the fields of a raw report. Tablets built on the same controller send the
+// same fields, but not always at the same offsets or in the same byte order.
+pub trait ReportParser: Send + Sync {
+    fn x_axis(&self, data: &[u8]) -> u16;
+    fn y_axis(&self, data: &[u8]) -> u16;
+    fn pressure(&self, data: &[u8]) -> u16;
+    // Bit per pen button.
+    fn pen_buttons(&self, data: &[u8]) -> u8;
+    // Bit per tablet button, cleared while it is pressed. Unused bits are set.
+    fn tablet_buttons(&self, data: &[u8]) -> u16;
+    // Pen tilt in degrees, for pens that sense it.
+    fn tilt(&self, _data: &[u8]) -> Option<(i32, i32)> {
+        None
+    }
+    // Whether `data` is a whole report the fields can be read from.
+    fn check(&self, data: &[u8]) -> Result<(), MalformedReport> {
+        needs(data, FIXED_REPORT_LENGTH)
+    }
+}
+
+// Why a report was dropped instead of parsed.
+#[derive(Clone, Copy, Debug, Error, PartialEq, Eq)]
+pub enum MalformedReport {
+    #[error("{length} bytes where the report needs {needed}")]
+    TooShort { length: usize, needed: usize },
+    #[error("{0} bytes, a length no report of the tablet has")]
+    WrongLength(usize),
+    #[error("unknown report ID {0}")]
+    UnknownId(u8),
+    #[error("status byte {0:#04x} without its top bit set")]
+    BadStatus(u8),
+    // The rest of a report whose first part came as the previous read.
+    #[error("the other {0} bytes of a report cut short")]
+    Remainder(usize),
+}
+
+// The fixed layouts read up to the tablet buttons in bytes 11 and 12.
+const FIXED_REPORT_LENGTH: usize = 13;
+
+fn needs(data: &[u8], needed: usize) -> Result<(), MalformedReport> {
+    match data.len() < needed {
+        true => Err(MalformedReport::TooShort {
+            length: data.len(),
+            needed,
+        }),
+        false => Ok(()),
+    }
+}
+
+// Report layout to decode, from the `protocol` config key or the tablet's USB ID.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+pub enum Protocol {
+    #[default]
+    #[serde(rename = "vinsa")]
+    Vinsa,
+    // 10moons T503 and the T906 and Bosto boards built like it.
+    #[serde(rename = "10moons")]
+    TenMoons,
+}
+
+impl Protocol {
+    pub fn parser(self) -> Arc<dyn ReportParser> {
+        match self {
+            Protocol::Vinsa => Arc::new(Vinsa),
+            Protocol::TenMoons => Arc::new(TenMoons),
+        }
+    }
+}
+
+// The fields found in the tablet's report descriptor, when it has them, and the
+// model's fixed offsets otherwise. A protocol set in the config is taken as is.
+pub fn select_parser(
+    configured: Option<Protocol>,
+    detected: Protocol,
+    layout: Option<ReportLayout>,
+) -> Arc<dyn ReportParser> {
+    match (configured, layout) {
+        (Some(protocol), _) => protocol.parser(),
+        (None, Some(layout)) => Arc::new(DescriptorParser {
+            layout,
+            fallback: detected.parser(),
+        }),
+        (None, None) => detected.parser(),
+    }
+}
+
+fn u16_at(data: &[u8], high: usize, low: usize) -> u16 {
+    (data[high] as u16) << 8 | data[low] as u16
+}
+
+// Tablet buttons are active-low in bytes 11 and 12; the top byte only uses bits 0, 1,
+// 4 and 5.
+fn tablet_buttons_at_11(data: &[u8]) -> u16 {
+    u16_at(data, 12, 11) | (0xcc << 8)
+}
+
+// VINSA 1060 Plus: big-endian X, Y and pressure from byte 1, pen buttons in byte 9.
+pub struct Vinsa;
+
+impl ReportParser for Vinsa {
+    fn x_axis(&self, data: &[u8]) -> u16 {
+        u16_at(data, 1, 2)
+    }
+
+    fn y_axis(&self, data: &[u8]) -> u16 {
+        u16_at(data, 3, 4)
+    }
+
+    fn pressure(&self, data: &[u8]) -> u16 {
+        u16_at(data, 5, 6)
+    }
+
+    fn pen_buttons(&self, data: &[u8]) -> u8 {
+        data[9]
+    }
+
+    fn tablet_buttons(&self, data: &[u8]) -> u16 {
+        tablet_buttons_at_11(data)
+    }
+}
+
+// 10moons: little-endian fields with Y before X (bytes 2, 4 and 6).
+pub struct TenMoons;
+
+impl ReportParser for TenMoons {
+    fn x_axis(&self, data: &[u8]) -> u16 {
+        u16_at(data, 5, 4)
+    }
+
+    fn y_axis(&self, data: &[u8]) -> u16 {
+        u16_at(data, 3, 2)
+    }
+
+    fn pressure(&self, data: &[u8]) -> u16 {
+        u16_at(data, 7, 6)
+    }
+
+    fn pen_buttons(&self, data: &[u8]) -> u8 {
+        data[9]
+    }
+
+    fn tablet_buttons(&self, data: &[u8]) -> u16 {
+        tablet_buttons_at_11(data)
+    }
+}
+
+// Some firmware revisions send 8- or 10-byte reports in the layout of other UC-Logic
+// tablets instead: a status byte, then little-endian X, Y and pressure. Status 0xe0
+// marks a report of the tablet buttons alone, active-high from byte 4; otherwise bit
+// 6 is set while the pen is out of range and bits 1 and 2 are its barrel buttons.
+// The last two bytes of the 10-byte ones are not used.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ShortReport {
+    pressure_max: i32,
+}
+
+impl Default for ShortReport {
+    fn default() -> Self {
+        ShortReport { pressure_max: 8191 }
+    }
+}
+
+impl ShortReport {
+    // For a digitizer whose pressure goes up to `pressure_max`.
+    pub fn new(pressure_max: u16) -> Self {
+        ShortReport {
+            pressure_max: (pressure_max as i32).max(1),
+        }
+    }
+
+    pub fn is_buttons_report(data: &[u8]) -> bool {
+        data.get(1) == Some(&0xe0)
+    }
+}
+
+impl ReportParser for ShortReport {
+    fn x_axis(&self, data: &[u8]) -> u16 {
+        u16_at(data, 3, 2)
+    }
+
+    fn y_axis(&self, data: &[u8]) -> u16 {
+        u16_at(data, 5, 4)
+    }
+
+    // Grows with force, so it is turned around onto the VINSA's scale.
+    fn pressure(&self, data: &[u8]) -> u16 {
+        let pressure = (u16_at(data, 7, 6) as i32).min(self.pressure_max);
+        (NOMINAL_PRESSURE_REST - pressure * NOMINAL_PRESSURE_REST / self.pressure_max) as u16
+    }
+
+    // As the status byte the VINSA sends.
+    fn pen_buttons(&self, data: &[u8]) -> u8 {
+        match data[1] {
+            _ if Self::is_buttons_report(data) => 0,
+            status if status & 0x80 == 0 || status & 0x40 != 0 => 0,
+            status if status & 0x02 != 0 => 6,
+            status if status & 0x04 != 0 => 4,
+            _ => 2,
+        }
+    }
+
+    fn tablet_buttons(&self, data: &[u8]) -> u16 {
+        match Self::is_buttons_report(data) {
+            true => !u16_at(data, 5, 4),
+            false => 0xffff,
+        }
+    }
+
+    // The status byte always has its top bit set.
+    fn check(&self, data: &[u8]) -> Result<(), MalformedReport> {
+        match data.len() {
+            8 | 10 if data[1] & 0x80 != 0 => Ok(()),
+            8 | 10 => Err(MalformedReport::BadStatus(data[1])),
+            length if length < 8 => Err(MalformedReport::TooShort { length, needed: 8 }),
+            length => Err(MalformedReport::WrongLength(length)),
+        }
+    }
+}
+
+// Reads fields at the offsets from the HID report descriptor. Reports with another
+// report ID, and fields the descriptor doesn't have, go to `fallback`. X and Y are
+// scaled from their logical range onto 0..=AXIS_MAX, and Tip Pressure, which grows
+// with force, is turned around onto the VINSA's scale like ShortReport's.
+pub struct DescriptorParser {
+    layout: ReportLayout,
+    fallback: Arc<dyn ReportParser>,
+}
+
+impl DescriptorParser {
+    fn is_this_report(&self, data: &[u8]) -> bool {
+        self.layout
+            .report_id
+            .is_none_or(|id| data.first() == Some(&id))
+    }
+
+    fn read(&self, data: &[u8], field: Option<Field>) -> Option<u32> {
+        field
+            .filter(|_| self.is_this_report(data))
+            .map(|field| field.read(data))
+    }
+
+    fn read_scaled(
+        &self,
+        data: &[u8],
+        field: Option<Field>,
+        range: Option<LogicalRange>,
+        onto: i32,
+    ) -> Option<i32> {
+        let field = field.filter(|_| self.is_this_report(data))?;
+        Some(match range {
+            Some(range) => range.scale(range.read(field, data), onto),
+            None => field.read(data) as i32,
+        })
+    }
+}
+
+impl ReportParser for DescriptorParser {
+    fn x_axis(&self, data: &[u8]) -> u16 {
+        self.read_scaled(data, self.layout.x, self.layout.x_range, AXIS_MAX)
+            .map_or_else(|| self.fallback.x_axis(data), |x| x as u16)
+    }
+
+    fn y_axis(&self, data: &[u8]) -> u16 {
+        self.read_scaled(data, self.layout.y, self.layout.y_range, AXIS_MAX)
+            .map_or_else(|| self.fallback.y_axis(data), |y| y as u16)
+    }
+
+    // Without a logical range, the field's every value counts.
+    fn pressure(&self, data: &[u8]) -> u16 {
+        let range = self.layout.pressure.map(|field| {
+            self.layout.pressure_range.unwrap_or(LogicalRange {
+                min: 0,
+                max: ((1u64 << field.size.min(31)) - 1) as i32,
+            })
+        });
+        let pressure = self.read_scaled(data, self.layout.pressure, range, NOMINAL_PRESSURE_REST);
+        pressure.map_or_else(
+            || self.fallback.pressure(data),
+            |pressure| (NOMINAL_PRESSURE_REST - pressure) as u16,
+        )
+    }
+
+    // Turned into the status byte the VINSA sends: 0 out of range, 2 hovering, 6 and 4
+    // with the barrel buttons pressed.
+    fn pen_buttons(&self, data: &[u8]) -> u8 {
+        let Some(in_range) = self.read(data, self.layout.in_range) else {
+            return self.fallback.pen_buttons(data);
+        };
+        let is_pressed = |field| self.read(data, field).is_some_and(|bit| bit != 0);
+        match in_range {
+            0 => 0,
+            _ if is_pressed(self.layout.barrel) => 6,
+            _ if is_pressed(self.layout.secondary_barrel) => 4,
+            _ => 2,
+        }
+    }
+
+    fn tilt(&self, data: &[u8]) -> Option<(i32, i32)> {
+        let x = self.layout.x_tilt.filter(|_| self.is_this_report(data))?;
+        let y = self.layout.y_tilt?;
+        Some((x.read_signed(data), y.read_signed(data)))
+    }
+
+    // Reports with an ID the descriptor doesn't declare can't be from the tablet.
+    fn check(&self, data: &[u8]) -> Result<(), MalformedReport> {
+        match (self.layout.report_id, data.first()) {
+            (Some(_), Some(&id)) if !self.layout.report_ids.contains(id) => {
+                Err(MalformedReport::UnknownId(id))
+            }
+            _ if self.is_this_report(data) => needs(data, self.layout.length),
+            _ => self.fallback.check(data),
+        }
+    }
+
+    // Buttons are active-high in HID reports; bit N is button N+1.
+    fn tablet_buttons(&self, data: &[u8]) -> u16 {
+        self.read(data, self.layout.buttons)
+            .map_or_else(|| self.fallback.tablet_buttons(data), |bits| !(bits as u16))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layouts_decode_the_same_point() {
+        let mut vinsa = [0u8; 64];
+        vinsa[1..=6].copy_from_slice(&[0x12, 0x34, 0x05, 0x06, 0x01, 0x02]);
+        let mut ten_moons = [0u8; 64];
+        ten_moons[2..=7].copy_from_slice(&[0x06, 0x05, 0x34, 0x12, 0x02, 0x01]);
+
+        for (protocol, data) in [(Protocol::Vinsa, vinsa), (Protocol::TenMoons, ten_moons)] {
+            let parser = protocol.parser();
+            assert_eq!(parser.x_axis(&data), 0x1234);
+            assert_eq!(parser.y_axis(&data), 0x0506);
+            assert_eq!(parser.pressure(&data), 0x0102);
+        }
+        let protocol: Protocol = toml::Value::String("10moons".into()).try_into().unwrap();
+        assert_eq!(protocol, Protocol::TenMoons);
+    }
+
+    #[test]
+    fn short_reports_decode_pen_and_buttons() {
+        let short = ShortReport::default();
+        let pen = [0x08, 0x82, 0x34, 0x12, 0x06, 0x05, 0xff, 0x1f];
+        assert_eq!(short.check(&pen), Ok(()));
+        assert_eq!((short.x_axis(&pen), short.y_axis(&pen)), (0x1234, 0x0506));
+        assert_eq!(short.pressure(&pen), 0);
+        // Half of what a digitizer with 2048 levels reports is half way down.
+        assert_eq!(ShortReport::new(2047).pressure(&[0x08, 0x80, 0, 0, 0, 0, 0x00, 0x04]), 1000);
+        assert_eq!(short.pen_buttons(&pen), 6);
+        assert_eq!(short.tablet_buttons(&pen), 0xffff);
+        assert_eq!(short.pen_buttons(&[0x08, 0xc0, 0, 0, 0, 0, 0, 0]), 0);
+
+        let buttons = [0x08, 0xe0, 0x01, 0x01, 0x05, 0x00, 0x00, 0x00, 0x00, 0x00];
+        assert_eq!(short.check(&buttons), Ok(()));
+        assert_eq!(short.tablet_buttons(&buttons), !0x0005);
+        assert_eq!(short.pen_buttons(&buttons), 0);
+        assert_eq!(
+            short.check(&[0x08, 0x02, 0, 0, 0, 0, 0, 0]),
+            Err(MalformedReport::BadStatus(0x02))
+        );
+        assert_eq!(
+            short.check(&[0x08, 0x82, 0, 0, 0, 0, 0, 0, 0]),
+            Err(MalformedReport::WrongLength(9))
+        );
+        assert_eq!(
+            short.check(&[0x08, 0x82]),
+            Err(MalformedReport::TooShort { length: 2, needed: 8 })
+        );
+    }
+
+    #[test]
+    fn reports_too_short_or_with_unknown_ids_are_malformed() {
+        let parser = Protocol::Vinsa.parser();
+        assert_eq!(parser.check(&[0; 64]), Ok(()));
+        let too_short = MalformedReport::TooShort {
+            length: 8,
+            needed: 13,
+        };
+        assert_eq!(parser.check(&[0; 8]), Err(too_short));
+
+        let mut report_ids = crate::descriptor::ReportIds::default();
+        report_ids.insert(10);
+        report_ids.insert(3);
+        let layout = ReportLayout {
+            report_id: Some(10),
+            x: Some(Field { offset: 8, size: 16 }),
+            length: 9,
+            report_ids,
+            ..ReportLayout::default()
+        };
+        let parser = select_parser(None, Protocol::Vinsa, Some(layout));
+        assert_eq!(parser.check(&[10; 9]), Ok(()));
+        assert!(parser.check(&[10; 6]).is_err());
+        assert_eq!(parser.check(&[7; 64]), Err(MalformedReport::UnknownId(7)));
+        // Other declared reports are read at the fixed offsets.
+        assert!(parser.check(&[3; 9]).is_err());
+        assert_eq!(parser.check(&[3; 64]), Ok(()));
+    }
+}
@@ -0,0 +1,123 @@
+use serde::Deserialize;
+
+use std::f32::consts::TAU;
+
+use crate::binding::Binding;
+
+// A pie menu held open by a tablet button: moving the pen away from where it was
+// picks a slice, and letting go of the button runs it. Slices go clockwise from the
+// top, in the order of `items`.
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct RadialMenuConfig {
+    pub button: Option<u8>,
+    pub items: Vec<RadialMenuItem>,
+    // Tablet units the pen must move before a slice is picked, so a small wobble
+    // picks nothing.
+    pub dead_zone: i32,
+    // Size of the menu on screen, in pixels.
+    pub radius: u16,
+}
+
+impl Default for RadialMenuConfig {
+    fn default() -> Self {
+        RadialMenuConfig {
+            button: None,
+            items: Vec::new(),
+            dead_zone: 150,
+            radius: 120,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct RadialMenuItem {
+    pub label: String,
+    pub action: Binding,
+}
+
+#[derive(Default)]
+pub struct RadialMenu {
+    is_open: bool,
+    // Where the pen was when the menu opened, taken from the first report after.
+    origin: Option<(i32, i32)>,
+    selected: Option<usize>,
+}
+
+impl RadialMenu {
+    pub fn open(&mut self) {
+        *self = RadialMenu {
+            is_open: true,
+            ..RadialMenu::default()
+        };
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.is_open
+    }
+
+    pub fn selected(&self) -> Option<usize> {
+        self.selected
+    }
+
+    pub fn track(&mut self, config: &RadialMenuConfig, x: i32, y: i32) {
+        let (origin_x, origin_y) = *self.origin.get_or_insert((x, y));
+        let (dx, dy) = ((x - origin_x) as f32, (y - origin_y) as f32);
+        self.selected = match dx.hypot(dy) < config.dead_zone as f32 {
+            true => None,
+            false => slice_at(dx, dy, config.items.len()),
+        };
+    }
+
+    // Closes the menu, returning the slice picked.
+    pub fn close(&mut self) -> Option<usize> {
+        let selected = self.selected;
+        *self = RadialMenu::default();
+        selected
+    }
+}
+
+// The slice in direction (dx, dy), with y growing downwards, among `count` slices
+// going clockwise from the top. The first is centered on straight up.
+pub fn slice_at(dx: f32, dy: f32, count: usize) -> Option<usize> {
+    if count == 0 {
+        return None;
+    }
+    let slice = TAU / count as f32;
+    let clockwise_from_top = dx.atan2(-dy).rem_euclid(TAU);
+    Some(((clockwise_from_top + slice / 2.0) / slice) as usize % count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn moving_the_pen_picks_the_slice_in_its_direction() {
+        assert_eq!(slice_at(0.0, -10.0, 4), Some(0));
+        assert_eq!(slice_at(10.0, 0.0, 4), Some(1));
+        assert_eq!(slice_at(0.0, 10.0, 4), Some(2));
+        assert_eq!(slice_at(-10.0, -9.0, 4), Some(3));
+        assert_eq!(slice_at(-10.0, -11.0, 4), Some(0));
+        assert_eq!(slice_at(1.0, 1.0, 0), None);
+
+        let config = RadialMenuConfig {
+            items: ["undo", "redo", "save"]
+                .map(|action| RadialMenuItem {
+                    label: action.to_string(),
+                    action: action.parse().unwrap(),
+                })
+                .to_vec(),
+            ..RadialMenuConfig::default()
+        };
+        let mut menu = RadialMenu::default();
+        menu.open();
+        menu.track(&config, 2000, 2000);
+        menu.track(&config, 2050, 2000);
+        assert_eq!(menu.selected(), None);
+        menu.track(&config, 2300, 2100);
+        assert_eq!(menu.selected(), Some(1));
+        assert_eq!(menu.close(), Some(1));
+        assert!(!menu.is_open());
+    }
+}
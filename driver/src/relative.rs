@@ -0,0 +1,54 @@
+use serde::Deserialize;
+
+// Trackpad-like behaviour for mouse mode: pen movement becomes REL_X/REL_Y deltas.
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct RelativeConfig {
+    pub enabled: bool,
+    // Pixels per tablet unit, before acceleration.
+    pub sensitivity: f32,
+    // Extra gain per tablet unit of movement between two reports.
+    pub acceleration: f32,
+}
+
+impl Default for RelativeConfig {
+    fn default() -> Self {
+        RelativeConfig {
+            enabled: false,
+            sensitivity: 0.5,
+            acceleration: 0.0,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct RelativeMotion {
+    last_position: Option<(i32, i32)>,
+    remainder: (f32, f32),
+}
+
+impl RelativeMotion {
+    // Forget the last position so the next report doesn't jump (e.g. after the pen was lifted).
+    pub fn reset(&mut self) {
+        self.last_position = None;
+        self.remainder = (0.0, 0.0);
+    }
+
+    pub fn delta(&mut self, config: &RelativeConfig, x: i32, y: i32) -> (i32, i32) {
+        let Some((last_x, last_y)) = self.last_position.replace((x, y)) else {
+            return (0, 0);
+        };
+
+        let (dx, dy) = ((x - last_x) as f32, (y - last_y) as f32);
+        let speed = dx.hypot(dy);
+        let gain = config.sensitivity * (1.0 + config.acceleration * speed);
+
+        // Keep the sub-pixel part so slow movements still add up.
+        let exact_x = dx * gain + self.remainder.0;
+        let exact_y = dy * gain + self.remainder.1;
+        let (pixels_x, pixels_y) = (exact_x.trunc(), exact_y.trunc());
+        self.remainder = (exact_x - pixels_x, exact_y - pixels_y);
+
+        (pixels_x as i32, pixels_y as i32)
+    }
+}
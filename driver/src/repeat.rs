@@ -0,0 +1,99 @@
+use serde::Deserialize;
+
+use evdev::Key;
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+// Tablet buttons whose key repeats while held, like a keyboard key: first after
+// `delay_ms`, then `rate` times a second. Only the last key of a combination
+// repeats, so modifiers stay held.
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct AutoRepeatConfig {
+    pub buttons: Vec<u8>,
+    pub delay_ms: u64,
+    pub rate: f32,
+}
+
+impl Default for AutoRepeatConfig {
+    fn default() -> Self {
+        AutoRepeatConfig {
+            // PageUp and PageDown.
+            buttons: vec![4, 5],
+            delay_ms: 400,
+            rate: 20.0,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct KeyRepeat {
+    config: AutoRepeatConfig,
+    // Key each held button repeats, and when it next does.
+    held: HashMap<u8, (Key, Instant)>,
+}
+
+impl KeyRepeat {
+    pub fn new(config: &AutoRepeatConfig) -> Self {
+        KeyRepeat {
+            config: config.clone(),
+            held: HashMap::new(),
+        }
+    }
+
+    pub fn handles(&self, id: u8) -> bool {
+        self.config.buttons.contains(&id) && self.config.rate > 0.0
+    }
+
+    pub fn press(&mut self, id: u8, key: Key, now: Instant) {
+        let delay = Duration::from_millis(self.config.delay_ms);
+        self.held.insert(id, (key, now + delay));
+    }
+
+    pub fn release(&mut self, id: u8) {
+        self.held.remove(&id);
+    }
+
+    // Keys due to repeat by `now`. A repeat that is more than one interval late is
+    // sent once rather than caught up on.
+    pub fn poll(&mut self, now: Instant) -> Vec<Key> {
+        let interval = Duration::from_nanos((1e9 / self.config.rate as f64) as u64);
+        let mut keys = Vec::new();
+        for (key, next) in self.held.values_mut() {
+            if now >= *next {
+                keys.push(*key);
+                *next = (*next + interval).max(now);
+            }
+        }
+        keys
+    }
+
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.held.values().map(|(_, next)| *next).min()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn held_keys_repeat_after_the_delay() {
+        let mut repeat = KeyRepeat::new(&AutoRepeatConfig::default());
+        let start = Instant::now();
+        let ms = |millis| start + Duration::from_millis(millis);
+        assert!(repeat.handles(4) && !repeat.handles(0));
+
+        repeat.press(4, Key::KEY_PAGEUP, start);
+        assert_eq!(repeat.next_deadline(), Some(ms(400)));
+        assert_eq!(repeat.poll(ms(399)), vec![]);
+        assert_eq!(repeat.poll(ms(400)), vec![Key::KEY_PAGEUP]);
+        assert_eq!(repeat.next_deadline(), Some(ms(450)));
+        assert_eq!(repeat.poll(ms(450)), vec![Key::KEY_PAGEUP]);
+
+        repeat.release(4);
+        assert_eq!(repeat.poll(ms(1000)), vec![]);
+        assert_eq!(repeat.next_deadline(), None);
+    }
+}
@@ -0,0 +1,388 @@
+use serde::Deserialize;
+use serde_json::Value;
+use tracing::warn;
+
+use crate::focus_events::{self, X11Change, X11Events};
+
+use std::env;
+use std::process::Command;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rect {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl Rect {
+    // X11 geometry syntax: WIDTHxHEIGHT+X+Y
+    pub fn parse_geometry(geometry: &str) -> Option<Rect> {
+        let (width, rest) = geometry.split_once('x')?;
+        let (height, offset) = rest.split_at(rest.find(['+', '-'])?);
+        let (x, y) = offset.split_at(offset[1..].find(['+', '-'])? + 1);
+
+        Some(Rect {
+            x: x.trim_start_matches('+').parse().ok()?,
+            y: y.trim_start_matches('+').parse().ok()?,
+            width: width.parse().ok()?,
+            height: height.parse().ok()?,
+        })
+    }
+
+    pub fn contains(&self, x: i32, y: i32) -> bool {
+        (self.x..self.x + self.width).contains(&x) && (self.y..self.y + self.height).contains(&y)
+    }
+
+    fn union(&self, other: &Rect) -> Rect {
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        Rect {
+            x,
+            y,
+            width: (self.x + self.width).max(other.x + other.width) - x,
+            height: (self.y + self.height).max(other.y + other.height) - y,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Monitor {
+    pub name: String,
+    pub rect: Rect,
+}
+
+// Connected monitors, from the compositor when running under sway/Hyprland, else XRandR.
+pub fn query_monitors() -> Vec<Monitor> {
+    if env::var_os("SWAYSOCK").is_some() {
+        query_sway_monitors()
+    } else if env::var_os("HYPRLAND_INSTANCE_SIGNATURE").is_some() {
+        query_hyprland_monitors()
+    } else {
+        query_xrandr_monitors()
+    }
+    .unwrap_or_default()
+}
+
+// Which monitor the mapping follows when `follow_monitor` is set.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FollowMonitor {
+    // The monitor under the mouse cursor.
+    Cursor,
+    // The monitor holding the focused window.
+    Window,
+}
+
+// Reports the name of the monitor with the cursor or focused window in the
+// background whenever it changes, woken by the desktop's events.
+pub fn spawn_monitor_watcher(follow: FollowMonitor) -> Receiver<String> {
+    let (sender, receiver) = mpsc::channel();
+
+    thread::spawn(move || {
+        let mut last_name = String::new();
+        let report = |name: Option<String>| match name {
+            Some(name) if name != last_name => {
+                let is_open = sender.send(name.clone()).is_ok();
+                last_name = name;
+                is_open
+            }
+            _ => true,
+        };
+        if let Err(err) = watch_monitors(follow, report) {
+            warn!("Stopped following the monitor: {}", err);
+        }
+    });
+
+    receiver
+}
+
+// Calls `report` with the monitor to follow, then again whenever it may have changed,
+// until it returns false.
+fn watch_monitors(
+    follow: FollowMonitor,
+    mut report: impl FnMut(Option<String>) -> bool,
+) -> Result<(), String> {
+    // sway has no cursor query; its focused output follows the cursor between windows.
+    if env::var_os("SWAYSOCK").is_some() {
+        let focused = || focused_name(&json_output("swaymsg", &["-t", "get_outputs", "-r"])?);
+        if !report(focused()) {
+            return Ok(());
+        }
+        let result = focus_events::sway_events(r#"["window", "workspace"]"#, |event| {
+            match event["change"].as_str() {
+                Some("focus" | "move") => report(focused()),
+                _ => true,
+            }
+        });
+        return result.map_err(|err| format!("swaymsg: {}", err));
+    }
+    // Hyprland moves the monitor focus with the cursor as well as with windows.
+    if env::var_os("HYPRLAND_INSTANCE_SIGNATURE").is_some() {
+        let focused = json_output("hyprctl", &["monitors", "-j"]);
+        if !report(focused.and_then(|monitors| focused_name(&monitors))) {
+            return Ok(());
+        }
+        // focusedmon>>MONITOR,WORKSPACE
+        let result = focus_events::hyprland_events(|event, data| match event {
+            "focusedmon" => report(data.split_once(',').map(|(name, _)| name.to_string())),
+            _ => true,
+        });
+        return result.map_err(|err| format!("Hyprland event socket: {}", err));
+    }
+
+    let mut events = X11Events::connect()?;
+    match follow {
+        FollowMonitor::Cursor => events.follow_pointer()?,
+        FollowMonitor::Window => events.follow_window_moves().map_err(|err| err.to_string())?,
+    }
+    let position = |events: &X11Events| match follow {
+        FollowMonitor::Cursor => events.pointer(),
+        FollowMonitor::Window => events
+            .active_window()
+            .and_then(|window| events.window_center(window)),
+    };
+    let name = |monitors: &[Monitor], (x, y)| Some(monitor_at(monitors, x, y)?.name.clone());
+    let mut monitors = query_monitors();
+    if !report(position(&events).and_then(|position| name(&monitors, position))) {
+        return Ok(());
+    }
+    loop {
+        if events.wait().map_err(|err| err.to_string())? == X11Change::Screen {
+            monitors = query_monitors();
+        }
+        if !report(position(&events).and_then(|position| name(&monitors, position))) {
+            return Ok(());
+        }
+    }
+}
+
+fn json_output(program: &str, args: &[&str]) -> Option<Value> {
+    serde_json::from_str(&command_output(program, args)?).ok()
+}
+
+pub fn monitor_at(monitors: &[Monitor], x: i32, y: i32) -> Option<&Monitor> {
+    monitors.iter().find(|monitor| monitor.rect.contains(x, y))
+}
+
+// Name of the entry marked focused in a sway or Hyprland output list.
+fn focused_name(outputs: &Value) -> Option<String> {
+    outputs
+        .as_array()?
+        .iter()
+        .find(|output| output["focused"].as_bool() == Some(true))?["name"]
+        .as_str()
+        .map(str::to_string)
+}
+
+pub fn desktop_bounds(monitors: &[Monitor]) -> Option<Rect> {
+    monitors
+        .iter()
+        .map(|monitor| monitor.rect)
+        .reduce(|bounds, rect| bounds.union(&rect))
+}
+
+pub fn command_output(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    String::from_utf8(output.stdout).ok()
+}
+
+fn query_xrandr_monitors() -> Option<Vec<Monitor>> {
+    let output = command_output("xrandr", &["--query"])?;
+
+    Some(
+        output
+            .lines()
+            .filter_map(|line| {
+                let mut tokens = line.split_whitespace();
+                let name = tokens.next()?;
+                if tokens.next()? != "connected" {
+                    return None;
+                }
+                let rect = tokens.find_map(Rect::parse_geometry)?;
+                Some(Monitor {
+                    name: name.to_string(),
+                    rect,
+                })
+            })
+            .collect(),
+    )
+}
+
+fn query_sway_monitors() -> Option<Vec<Monitor>> {
+    #[derive(Deserialize)]
+    struct SwayRect {
+        x: i32,
+        y: i32,
+        width: i32,
+        height: i32,
+    }
+
+    #[derive(Deserialize)]
+    struct SwayOutput {
+        name: String,
+        active: bool,
+        rect: SwayRect,
+    }
+
+    let output = command_output("swaymsg", &["-t", "get_outputs", "-r"])?;
+    let outputs: Vec<SwayOutput> = serde_json::from_str(&output).ok()?;
+
+    Some(
+        outputs
+            .into_iter()
+            .filter(|output| output.active)
+            .map(|output| Monitor {
+                name: output.name,
+                rect: Rect {
+                    x: output.rect.x,
+                    y: output.rect.y,
+                    width: output.rect.width,
+                    height: output.rect.height,
+                },
+            })
+            .collect(),
+    )
+}
+
+fn query_hyprland_monitors() -> Option<Vec<Monitor>> {
+    #[derive(Deserialize)]
+    struct HyprlandMonitor {
+        name: String,
+        x: i32,
+        y: i32,
+        width: i32,
+        height: i32,
+    }
+
+    let output = command_output("hyprctl", &["monitors", "-j"])?;
+    let monitors: Vec<HyprlandMonitor> = serde_json::from_str(&output).ok()?;
+
+    Some(
+        monitors
+            .into_iter()
+            .map(|monitor| Monitor {
+                name: monitor.name,
+                rect: Rect {
+                    x: monitor.x,
+                    y: monitor.y,
+                    width: monitor.width,
+                    height: monitor.height,
+                },
+            })
+            .collect(),
+    )
+}
+
+// The virtual pen always spans the whole desktop, so pinning it to one monitor
+// means squeezing the axis range into that monitor's share of the desktop.
+#[derive(Clone, Copy, Debug)]
+pub struct OutputMapping {
+    target: Rect,
+    desktop: Rect,
+}
+
+impl OutputMapping {
+    // `output` is a monitor name ("HDMI-1") or a desktop rectangle ("1920x1080+1920+0").
+    pub fn resolve(output: &str) -> Result<Self, String> {
+        let monitors = query_monitors();
+        let desktop =
+            desktop_bounds(&monitors).ok_or("Could not query the monitor layout.".to_string())?;
+
+        let target = match Rect::parse_geometry(output) {
+            Some(rect) => rect,
+            None => {
+                monitors
+                    .iter()
+                    .find(|monitor| monitor.name == output)
+                    .ok_or_else(|| {
+                        let names: Vec<&str> = monitors.iter().map(|m| m.name.as_str()).collect();
+                        format!(
+                            "Unknown output `{}` (available: {}).",
+                            output,
+                            names.join(", ")
+                        )
+                    })?
+                    .rect
+            }
+        };
+
+        Ok(OutputMapping { target, desktop })
+    }
+
+    // Mapping to the monitor after the one `current` targets, left to right and
+    // wrapping around, or to the first monitor if `current` isn't one.
+    pub fn next_monitor(current: Option<&OutputMapping>) -> Option<(String, Self)> {
+        let mut monitors = query_monitors();
+        let desktop = desktop_bounds(&monitors)?;
+        monitors.sort_by_key(|monitor| (monitor.rect.x, monitor.rect.y));
+        let index = current
+            .and_then(|mapping| monitors.iter().position(|m| m.rect == mapping.target))
+            .map_or(0, |index| (index + 1) % monitors.len());
+        let monitor = monitors.swap_remove(index);
+        Some((
+            monitor.name,
+            OutputMapping {
+                target: monitor.rect,
+                desktop,
+            },
+        ))
+    }
+
+    pub fn map(&self, x: i32, y: i32, axis_max: i32) -> (i32, i32) {
+        let map_axis = |value: i32,
+                        target_start: i32,
+                        target_len: i32,
+                        desktop_start: i32,
+                        desktop_len: i32| {
+            let desktop_pos = (target_start - desktop_start) as i64
+                + value as i64 * target_len as i64 / axis_max as i64;
+            (desktop_pos * axis_max as i64 / desktop_len.max(1) as i64).clamp(0, axis_max as i64)
+                as i32
+        };
+
+        (
+            map_axis(
+                x,
+                self.target.x,
+                self.target.width,
+                self.desktop.x,
+                self.desktop.width,
+            ),
+            map_axis(
+                y,
+                self.target.y,
+                self.target.height,
+                self.desktop.y,
+                self.desktop.height,
+            ),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn points_are_matched_to_the_monitor_holding_them() {
+        let monitors = [
+            Monitor {
+                name: "HDMI-1".to_string(),
+                rect: Rect::parse_geometry("1920x1080+0+0").unwrap(),
+            },
+            Monitor {
+                name: "DP-2".to_string(),
+                rect: Rect::parse_geometry("2560x1440+1920+0").unwrap(),
+            },
+        ];
+        assert_eq!(monitor_at(&monitors, 1919, 500).unwrap().name, "HDMI-1");
+        assert_eq!(monitor_at(&monitors, 1920, 500).unwrap().name, "DP-2");
+        assert!(monitor_at(&monitors, 100, 1200).is_none());
+    }
+}
@@ -0,0 +1,243 @@
+use evdev::Key;
+use rhai::{AST, CallFnOptions, Dynamic, Engine, EvalAltResult, FuncArgs, Map, Scope};
+use tracing::{error, info};
+
+use std::cell::RefCell;
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+
+use crate::binding::Binding;
+use crate::keys;
+
+// Enough for any reasonable hook, while a runaway loop gives up within milliseconds
+// instead of freezing the pen.
+const MAX_OPERATIONS: u64 = 100_000;
+
+// Something a script asked for, run by the dispatcher once the hook returns.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Action {
+    // Tapped like a binding, e.g. "ctrl+shift+z", "undo" or "next_profile".
+    Run(Binding),
+    Press(Vec<Key>),
+    Release(Vec<Key>),
+}
+
+// What the hooks see of the pen and buttons, as the `pen` map.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PenState {
+    // 0.0 to 1.0 of the pressure sent, and of the tablet's width and height.
+    pub pressure: f64,
+    pub x: f64,
+    pub y: f64,
+    pub is_in_range: bool,
+    pub is_touching: bool,
+    pub tablet_buttons: Vec<u8>,
+    pub pen_buttons: Vec<u8>,
+    pub is_mouse_mode: bool,
+}
+
+impl PenState {
+    fn to_map(&self) -> Map {
+        let ids = |ids: &[u8]| ids.iter().map(|&id| Dynamic::from(id as i64)).collect::<Vec<_>>();
+        let mode = if self.is_mouse_mode { "mouse" } else { "tablet" };
+        let mut map = Map::new();
+        map.insert("pressure".into(), self.pressure.into());
+        map.insert("x".into(), self.x.into());
+        map.insert("y".into(), self.y.into());
+        map.insert("in_range".into(), self.is_in_range.into());
+        map.insert("touching".into(), self.is_touching.into());
+        map.insert("buttons".into(), ids(&self.tablet_buttons).into());
+        map.insert("pen_buttons".into(), ids(&self.pen_buttons).into());
+        map.insert("mode".into(), mode.into());
+        map
+    }
+}
+
+// A compiled script and the hooks it defines:
+//
+//   fn on_button(button, pressed, pen) - a tablet button went down or up; returning
+//     true on the press keeps the button's own binding from running until released.
+//   fn on_report(pen) - every report from the tablet.
+//
+// Hooks call send("ctrl+z"), press("shift"), release("shift") and exec("cmd"), and
+// keep state between calls in `this`, a map.
+pub struct ScriptHooks {
+    engine: Engine,
+    ast: AST,
+    state: Dynamic,
+    actions: Rc<RefCell<Vec<Action>>>,
+    pub pen: PenState,
+    has_on_button: bool,
+    has_on_report: bool,
+    // Buttons whose press the script took, so their hold and release are skipped too.
+    consumed: u16,
+}
+
+impl ScriptHooks {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let source = fs::read_to_string(path)
+            .map_err(|err| format!("Error reading {}: {}", path.display(), err))?;
+        let hooks = Self::compile(&source)
+            .map_err(|err| format!("Error in {}: {}", path.display(), err))?;
+        info!("Loaded script from {}.", path.display());
+        Ok(hooks)
+    }
+
+    pub fn compile(source: &str) -> Result<Self, String> {
+        let actions = Rc::new(RefCell::new(Vec::new()));
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+        engine.on_print(|text| info!("Script: {}", text));
+        engine.on_debug(|text, _, _| info!("Script: {}", text));
+
+        let queue = actions.clone();
+        engine.register_fn("send", move |binding: &str| -> Result<(), Box<EvalAltResult>> {
+            queue.borrow_mut().push(Action::Run(binding.parse::<Binding>()?));
+            Ok(())
+        });
+        let queue = actions.clone();
+        engine.register_fn("press", move |combo: &str| -> Result<(), Box<EvalAltResult>> {
+            queue.borrow_mut().push(Action::Press(keys::parse_combo(combo)?));
+            Ok(())
+        });
+        let queue = actions.clone();
+        engine.register_fn("release", move |combo: &str| -> Result<(), Box<EvalAltResult>> {
+            queue.borrow_mut().push(Action::Release(keys::parse_combo(combo)?));
+            Ok(())
+        });
+        let queue = actions.clone();
+        engine.register_fn("exec", move |exec: &str| {
+            let exec = exec.to_string();
+            queue.borrow_mut().push(Action::Run(Binding::Exec { exec }));
+        });
+
+        let ast = engine.compile(source).map_err(|err| err.to_string())?;
+        let has_hook = |name: &str| ast.iter_functions().any(|hook| hook.name == name);
+        let (has_on_button, has_on_report) = (has_hook("on_button"), has_hook("on_report"));
+        Ok(ScriptHooks {
+            engine,
+            ast,
+            state: Map::new().into(),
+            actions,
+            pen: PenState::default(),
+            has_on_button,
+            has_on_report,
+            consumed: 0,
+        })
+    }
+
+    // Whether the script took the button, in which case its binding is skipped.
+    pub fn on_button(&mut self, button: u8, is_pressed: bool) -> bool {
+        let mask = 1 << button;
+        let was_consumed = self.consumed & mask != 0;
+        let args = (button as i64, is_pressed, self.pen.to_map());
+        let is_taken = self.has_on_button
+            && self
+                .call("on_button", args)
+                .is_some_and(|taken| taken.as_bool().unwrap_or(false));
+        if is_pressed && is_taken {
+            self.consumed |= mask;
+        } else {
+            self.consumed &= !mask;
+        }
+        is_taken || was_consumed
+    }
+
+    pub fn is_consumed(&self, button: u8) -> bool {
+        self.consumed & (1 << button) != 0
+    }
+
+    pub fn on_report(&mut self) {
+        if self.has_on_report {
+            self.call("on_report", (self.pen.to_map(),));
+        }
+    }
+
+    pub fn take_actions(&mut self) -> Vec<Action> {
+        std::mem::take(&mut self.actions.borrow_mut())
+    }
+
+    // A hook that fails is turned off until the script is reloaded, rather than
+    // logging the same error on every report.
+    fn call(&mut self, name: &str, args: impl FuncArgs) -> Option<Dynamic> {
+        let options = CallFnOptions::new().eval_ast(false).bind_this_ptr(&mut self.state);
+        let mut scope = Scope::new();
+        let result = self.engine.call_fn_with_options(options, &mut scope, &self.ast, name, args);
+        match result {
+            Ok(value) => Some(value),
+            Err(err) => {
+                error!("Error in script hook {}, turning it off: {}", name, err);
+                match name {
+                    "on_button" => self.has_on_button = false,
+                    _ => self.has_on_report = false,
+                }
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hooks_see_the_pen_and_queue_actions() {
+        let mut hooks = ScriptHooks::compile(
+            r#"
+            fn on_button(button, pressed, pen) {
+                if button == 3 && pressed && pen.pressure > 0.9 {
+                    send("ctrl+shift+z");
+                    return true;
+                }
+                false
+            }
+
+            fn on_report(pen) {
+                if pen.touching && this.down != true {
+                    press("shift");
+                }
+                this.down = pen.touching;
+            }
+            "#,
+        )
+        .unwrap();
+
+        hooks.pen.pressure = 0.5;
+        assert!(!hooks.on_button(3, true));
+        assert!(!hooks.on_button(3, false));
+        assert_eq!(hooks.take_actions(), vec![]);
+
+        hooks.pen.pressure = 0.95;
+        assert!(hooks.on_button(3, true));
+        assert!(hooks.is_consumed(3));
+        // The release goes with the press it belongs to.
+        assert!(hooks.on_button(3, false));
+        let redo = vec![Key::KEY_LEFTCTRL, Key::KEY_LEFTSHIFT, Key::KEY_Z];
+        assert_eq!(hooks.take_actions(), vec![Action::Run(Binding::Keys(redo))]);
+
+        hooks.pen.is_touching = true;
+        hooks.on_report();
+        hooks.on_report();
+        assert_eq!(hooks.take_actions(), vec![Action::Press(vec![Key::KEY_LEFTSHIFT])]);
+    }
+
+    #[test]
+    fn broken_scripts_are_reported_and_failing_hooks_turned_off() {
+        assert!(ScriptHooks::compile("fn on_button(button { }").is_err());
+
+        let mut hooks = ScriptHooks::compile(
+            r#"
+            fn on_button(button, pressed, pen) { send("ctrl+zz"); true }
+            fn on_report(pen) { loop {} }
+            "#,
+        )
+        .unwrap();
+        assert!(!hooks.on_button(0, true));
+        assert!(!hooks.has_on_button);
+        hooks.on_report();
+        assert!(!hooks.has_on_report);
+        assert_eq!(hooks.take_actions(), vec![]);
+    }
+}
@@ -0,0 +1,101 @@
+use serde::Deserialize;
+
+// Pen button that turns pen movement into wheel events while held, as if dragging
+// the page around.
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct ScrollConfig {
+    pub button: Option<u8>,
+    // Tablet units of pen movement per wheel notch.
+    pub distance: f32,
+    // Also send REL_WHEEL_HI_RES/REL_HWHEEL_HI_RES so apps can scroll smoothly
+    // instead of a notch at a time.
+    pub hi_res: bool,
+}
+
+impl Default for ScrollConfig {
+    fn default() -> Self {
+        ScrollConfig {
+            button: None,
+            distance: 80.0,
+            hi_res: true,
+        }
+    }
+}
+
+// A notch is 120 hi-res units, as for real mice.
+pub const HI_RES_PER_NOTCH: i32 = 120;
+
+// Vertical and horizontal wheel movement for one report.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ScrollStep {
+    pub wheel: i32,
+    pub hwheel: i32,
+    pub wheel_hi_res: i32,
+    pub hwheel_hi_res: i32,
+}
+
+#[derive(Default)]
+pub struct ScrollDrag {
+    last_position: Option<(i32, i32)>,
+    // Hi-res units not sent yet, and hi-res units sent since the last full notch.
+    remainder: (f32, f32),
+    partial_notch: (i32, i32),
+}
+
+impl ScrollDrag {
+    pub fn reset(&mut self) {
+        self.last_position = None;
+        self.remainder = (0.0, 0.0);
+        self.partial_notch = (0, 0);
+    }
+
+    // Wheel movement for the pen travel since the last position, so faster strokes
+    // scroll further.
+    pub fn delta(&mut self, config: &ScrollConfig, x: i32, y: i32) -> ScrollStep {
+        let Some((last_x, last_y)) = self.last_position.replace((x, y)) else {
+            return ScrollStep::default();
+        };
+
+        // Moving the pen down pulls the content down, which is a wheel-up; likewise
+        // moving right is a wheel-left.
+        let units_per_tablet_unit = HI_RES_PER_NOTCH as f32 / config.distance.max(1.0);
+        let exact_wheel = (y - last_y) as f32 * units_per_tablet_unit + self.remainder.0;
+        let exact_hwheel = (last_x - x) as f32 * units_per_tablet_unit + self.remainder.1;
+        let (wheel_hi_res, hwheel_hi_res) = (exact_wheel.trunc(), exact_hwheel.trunc());
+        self.remainder = (exact_wheel - wheel_hi_res, exact_hwheel - hwheel_hi_res);
+
+        let (wheel_hi_res, hwheel_hi_res) = (wheel_hi_res as i32, hwheel_hi_res as i32);
+        let wheel_total = self.partial_notch.0 + wheel_hi_res;
+        let hwheel_total = self.partial_notch.1 + hwheel_hi_res;
+        self.partial_notch = (
+            wheel_total % HI_RES_PER_NOTCH,
+            hwheel_total % HI_RES_PER_NOTCH,
+        );
+
+        ScrollStep {
+            wheel: wheel_total / HI_RES_PER_NOTCH,
+            hwheel: hwheel_total / HI_RES_PER_NOTCH,
+            wheel_hi_res,
+            hwheel_hi_res,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn notches_are_sent_once_enough_hi_res_movement_adds_up() {
+        let config = ScrollConfig::default();
+        let mut drag = ScrollDrag::default();
+        drag.delta(&config, 0, 0);
+
+        let first = drag.delta(&config, 0, 60);
+        let second = drag.delta(&config, 0, 100);
+
+        assert_eq!((first.wheel, first.wheel_hi_res), (0, 90));
+        assert_eq!((second.wheel, second.wheel_hi_res), (1, 60));
+    }
+}
@@ -0,0 +1,72 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::udev;
+
+pub const SERVICE_NAME: &str = "v1060p.service";
+
+const SYSTEM_UNIT_DIR: &str = "/etc/systemd/system";
+
+// A systemd unit running the driver at `executable`. It is started by udev when a
+// tablet is plugged in, and restarted if it crashes.
+pub fn unit(executable: &Path, is_user: bool) -> String {
+    let target = if is_user { "default.target" } else { "multi-user.target" };
+    format!(
+        "# Written by `v1060p install-service`.\n\
+         [Unit]\n\
+         Description=VINSA 1060 Plus tablet driver\n\
+         \n\
+         [Service]\n\
+         ExecStart={}\n\
+         Restart=on-failure\n\
+         RestartSec=2\n\
+         \n\
+         [Install]\n\
+         WantedBy={}\n",
+        executable.display(),
+        target
+    )
+}
+
+// /etc/systemd/system, or ~/.config/systemd/user (or under $XDG_CONFIG_HOME).
+pub fn unit_dir(is_user: bool) -> Option<PathBuf> {
+    if !is_user {
+        return Some(PathBuf::from(SYSTEM_UNIT_DIR));
+    }
+    let config_dir = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(config_dir.join("systemd").join("user"))
+}
+
+// Writes the unit and tells systemd about it. The system service also gets the udev
+// rule that starts it; the user service relies on the one from `install-udev`.
+pub fn install(unit_dir: &Path, unit: &str, is_user: bool) -> io::Result<PathBuf> {
+    fs::create_dir_all(unit_dir)?;
+    let path = unit_dir.join(SERVICE_NAME);
+    fs::write(&path, unit)?;
+    match is_user {
+        true => udev::run("systemctl", &["--user", "daemon-reload"])?,
+        false => {
+            udev::run("systemctl", &["daemon-reload"])?;
+            udev::install(Path::new(udev::SERVICE_RULES_PATH), &udev::service_rules())?;
+        }
+    }
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unit_restarts_the_driver_on_failure() {
+        let unit = unit(Path::new("/usr/local/bin/v1060p"), true);
+        assert!(unit.contains("ExecStart=/usr/local/bin/v1060p\n"));
+        assert!(unit.contains("Restart=on-failure\n"));
+        assert!(unit.contains("WantedBy=default.target\n"));
+    }
+}
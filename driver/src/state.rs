@@ -0,0 +1,60 @@
+use crate::config::Mode;
+
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use std::env;
+use std::fs;
+use std::io::Error;
+use std::path::{Path, PathBuf};
+
+// Settings changed while the driver runs (with the mode button, the area buttons or
+// the control socket) that are restored on the next start.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+pub struct RuntimeState {
+    pub mode: Mode,
+    pub mouse_area_scale: f32,
+}
+
+impl RuntimeState {
+    pub fn default_path() -> Option<PathBuf> {
+        let state_dir = match env::var_os("XDG_STATE_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => PathBuf::from(env::var_os("HOME")?).join(".local/state"),
+        };
+        Some(state_dir.join("vinsa-driver").join("state.toml"))
+    }
+
+    // A missing or unreadable file just means there is nothing to restore.
+    pub fn load(path: &Path) -> Option<Self> {
+        let contents = fs::read_to_string(path).ok()?;
+        toml::from_str(&contents)
+            .inspect_err(|err| warn!("Ignoring {}: {}", path.display(), err))
+            .ok()
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let contents = toml::to_string(self).map_err(Error::other)?;
+        fs::write(path, contents)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn state_survives_a_round_trip() {
+        let path = env::temp_dir().join(format!("vinsa-state-{}.toml", std::process::id()));
+        let state = RuntimeState {
+            mode: Mode::Tablet,
+            mouse_area_scale: 0.5,
+        };
+        state.save(&path).unwrap();
+        assert_eq!(RuntimeState::load(&path), Some(state));
+        fs::remove_file(&path).unwrap();
+    }
+}
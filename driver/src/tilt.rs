@@ -0,0 +1,79 @@
+use serde::Deserialize;
+
+// ABS_TILT_X/Y are in degrees, -64..=63 as on Wacom pens.
+pub const TILT_MIN: i32 = -64;
+pub const TILT_MAX: i32 = 63;
+
+// The VINSA pen has no tilt sensor. For brushes that need tilt it can be emulated
+// from the stroke: the pen leans back against the direction it moves, more so the
+// faster it goes.
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct TiltConfig {
+    pub emulate: bool,
+    // Tilt at full speed, in degrees.
+    pub max_angle: i32,
+    // Tablet units per report at which the full angle is reached.
+    pub full_speed: f32,
+    // Weight of each new report, 0..=1; lower turns the pen more slowly.
+    pub smoothing: f32,
+}
+
+impl Default for TiltConfig {
+    fn default() -> Self {
+        TiltConfig {
+            emulate: false,
+            max_angle: 45,
+            full_speed: 40.0,
+            smoothing: 0.2,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct TiltEmulation {
+    last_position: Option<(i32, i32)>,
+    tilt: (f32, f32),
+}
+
+impl TiltEmulation {
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    // Tilt for the pen at `(x, y)`, from its movement since the last report.
+    pub fn update(&mut self, config: &TiltConfig, x: i32, y: i32) -> (i32, i32) {
+        let Some((last_x, last_y)) = self.last_position.replace((x, y)) else {
+            return (0, 0);
+        };
+
+        let max_angle = config.max_angle.clamp(0, TILT_MAX) as f32;
+        let lean = |delta: i32| {
+            let speed = delta as f32 / config.full_speed.max(1.0);
+            -speed.clamp(-1.0, 1.0) * max_angle
+        };
+        let weight = config.smoothing.clamp(0.0, 1.0);
+        self.tilt.0 += (lean(x - last_x) - self.tilt.0) * weight;
+        self.tilt.1 += (lean(y - last_y) - self.tilt.1) * weight;
+        (self.tilt.0.round() as i32, self.tilt.1.round() as i32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pen_leans_against_the_stroke() {
+        let config = TiltConfig {
+            emulate: true,
+            smoothing: 1.0,
+            ..TiltConfig::default()
+        };
+        let mut emulation = TiltEmulation::default();
+        assert_eq!(emulation.update(&config, 1000, 1000), (0, 0));
+        // Fast to the right, slowly down.
+        assert_eq!(emulation.update(&config, 1100, 1020), (-45, -23));
+        assert_eq!(emulation.update(&config, 1100, 1020), (0, 0));
+    }
+}
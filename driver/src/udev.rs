@@ -0,0 +1,98 @@
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process::Command;
+
+use crate::device::KNOWN_DEVICES;
+use crate::service::SERVICE_NAME;
+
+pub const RULES_PATH: &str = "/etc/udev/rules.d/99-vinsa-tablet.rules";
+pub const SERVICE_RULES_PATH: &str = "/etc/udev/rules.d/99-vinsa-tablet-service.rules";
+
+// Rules giving `group` access to every known tablet (its USB node, which the driver
+// claims, its hidraw nodes and the kernel's input nodes for grab_kernel_devices) and
+// to /dev/uinput, so the driver can run without root. Plugging in a tablet also
+// starts the user service from `install-service --user`, if there is one.
+pub fn rules(group: &str) -> String {
+    let access = format!("MODE=\"0660\", GROUP=\"{}\"", group);
+    let mut rules = String::from("# Written by `v1060p install-udev`.\n");
+    for device in KNOWN_DEVICES {
+        let ids = format!(
+            "ATTRS{{idVendor}}==\"{:04x}\", ATTRS{{idProduct}}==\"{:04x}\"",
+            device.vid, device.pid
+        );
+        let _ = writeln!(rules, "\n# {}", device.name);
+        for subsystem in ["usb", "hidraw", "input"] {
+            let _ = writeln!(rules, "SUBSYSTEM==\"{}\", {}, {}", subsystem, ids, access);
+        }
+        let _ = writeln!(rules, "{}, {}", usb_device(device.vid, device.pid), wants(true));
+    }
+    let _ = writeln!(
+        rules,
+        "\nKERNEL==\"uinput\", SUBSYSTEM==\"misc\", OPTIONS+=\"static_node=uinput\", {}",
+        access
+    );
+    rules
+}
+
+// Rules starting the system service when a known tablet is plugged in.
+pub fn service_rules() -> String {
+    let mut rules = String::from("# Written by `v1060p install-service`.\n");
+    for device in KNOWN_DEVICES {
+        let _ = writeln!(rules, "{}, {}", usb_device(device.vid, device.pid), wants(false));
+    }
+    rules
+}
+
+fn usb_device(vid: u16, pid: u16) -> String {
+    format!(
+        "SUBSYSTEM==\"usb\", ENV{{DEVTYPE}}==\"usb_device\", \
+         ATTR{{idVendor}}==\"{:04x}\", ATTR{{idProduct}}==\"{:04x}\"",
+        vid, pid
+    )
+}
+
+// Tags the device for systemd, which then starts the service along with it.
+fn wants(is_user: bool) -> String {
+    let key = if is_user { "SYSTEMD_USER_WANTS" } else { "SYSTEMD_WANTS" };
+    format!("TAG+=\"systemd\", ENV{{{}}}+=\"{}\"", key, SERVICE_NAME)
+}
+
+// Writes the rules and has udev apply them to devices already plugged in.
+pub fn install(path: &Path, rules: &str) -> io::Result<()> {
+    fs::write(path, rules)?;
+    run("udevadm", &["control", "--reload-rules"])?;
+    run("udevadm", &["trigger"])
+}
+
+pub(crate) fn run(program: &str, args: &[&str]) -> io::Result<()> {
+    let status = Command::new(program).args(args).status()?;
+    match status.success() {
+        true => Ok(()),
+        false => Err(io::Error::other(format!(
+            "`{} {}` failed ({})",
+            program,
+            args.join(" "),
+            status
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rules_cover_the_tablet_and_uinput() {
+        let rules = rules("plugdev");
+        assert!(rules.contains(
+            "SUBSYSTEM==\"usb\", ATTRS{idVendor}==\"08f2\", ATTRS{idProduct}==\"6811\", \
+             MODE=\"0660\", GROUP=\"plugdev\""
+        ));
+        assert!(rules.contains("SUBSYSTEM==\"hidraw\", ATTRS{idVendor}==\"08f2\""));
+        assert!(rules.contains("KERNEL==\"uinput\""));
+        assert!(rules.contains("ENV{SYSTEMD_USER_WANTS}+=\"v1060p.service\""));
+        assert!(service_rules().contains("ENV{SYSTEMD_WANTS}+=\"v1060p.service\""));
+    }
+}
@@ -0,0 +1,285 @@
+use evdev::{AbsoluteAxisType, EventType, InputEvent, Key};
+
+use std::fs::File;
+use std::io::{self, Write};
+
+use crate::config::InputProperty;
+use crate::pressure::MAX_PRESSURE;
+use crate::tilt::{TILT_MAX, TILT_MIN};
+use crate::virtual_output::VirtualOutput;
+use crate::{PID, VID};
+
+// enum uhid_event_type
+const UHID_DESTROY: u32 = 1;
+const UHID_CREATE2: u32 = 11;
+const UHID_INPUT2: u32 = 12;
+
+const BUS_USB: u16 = 0x03;
+const REPORT_ID: u8 = 1;
+
+// The pen as a HID digitizer through /dev/uhid. The kernel's hid-input maps it like
+// the report descriptor of a native tablet, tool types included, instead of taking
+// the axes and keys uinput is told about. Keys that don't fit in its reports, like
+// the mouse mode clicks or keys bound to the pen buttons, go to `buttons` instead.
+pub struct UhidPen {
+    device: Box<dyn Write>,
+    buttons: Box<dyn VirtualOutput>,
+    state: PenState,
+}
+
+impl UhidPen {
+    pub fn create(
+        name: &str,
+        output_max: i32,
+        property: InputProperty,
+        buttons: Box<dyn VirtualOutput>,
+    ) -> io::Result<Self> {
+        let file = File::options().read(true).write(true).open("/dev/uhid")?;
+        Self::with_device(Box::new(file), name, output_max, property, buttons)
+    }
+
+    // Creates the pen on an already open uhid `device`, e.g. a buffer in tests.
+    pub fn with_device(
+        mut device: Box<dyn Write>,
+        name: &str,
+        output_max: i32,
+        property: InputProperty,
+        buttons: Box<dyn VirtualOutput>,
+    ) -> io::Result<Self> {
+        device.write_all(&create_event(name, &report_descriptor(output_max, property)))?;
+        Ok(UhidPen {
+            device,
+            buttons,
+            state: PenState::default(),
+        })
+    }
+}
+
+impl VirtualOutput for UhidPen {
+    // The report goes first, so a click lands where the pen has just moved.
+    fn emit(&mut self, events: &[InputEvent]) -> Result<(), io::Error> {
+        let other_keys: Vec<InputEvent> = events
+            .iter()
+            .filter(|event| !self.state.apply(event) && event.event_type() == EventType::KEY)
+            .copied()
+            .collect();
+        let report = self.state.report();
+        let mut event = Vec::with_capacity(6 + report.len());
+        event.extend_from_slice(&UHID_INPUT2.to_ne_bytes());
+        event.extend_from_slice(&(report.len() as u16).to_ne_bytes());
+        event.extend_from_slice(&report);
+        self.device.write_all(&event)?;
+        match other_keys.is_empty() {
+            true => Ok(()),
+            false => self.buttons.emit(&other_keys),
+        }
+    }
+
+    fn destroy(&mut self) -> Result<(), io::Error> {
+        let result = self.device.write_all(&UHID_DESTROY.to_ne_bytes());
+        result.and(self.buttons.destroy())
+    }
+}
+
+// What the last frames left the pen at, as sent in every report.
+#[derive(Default)]
+struct PenState {
+    x: i32,
+    y: i32,
+    pressure: i32,
+    tilt: (i32, i32),
+    is_touching: bool,
+    is_pen: bool,
+    is_rubber: bool,
+    stylus: bool,
+    stylus2: bool,
+}
+
+impl PenState {
+    // Whether the event is one the report carries.
+    fn apply(&mut self, event: &InputEvent) -> bool {
+        let is_down = event.value() != 0;
+        match event.event_type() {
+            EventType::ABSOLUTE => match AbsoluteAxisType(event.code()) {
+                AbsoluteAxisType::ABS_X => self.x = event.value(),
+                AbsoluteAxisType::ABS_Y => self.y = event.value(),
+                AbsoluteAxisType::ABS_PRESSURE => self.pressure = event.value(),
+                AbsoluteAxisType::ABS_TILT_X => self.tilt.0 = event.value(),
+                AbsoluteAxisType::ABS_TILT_Y => self.tilt.1 = event.value(),
+                _ => return false,
+            },
+            EventType::KEY => match Key::new(event.code()) {
+                Key::BTN_TOUCH => self.is_touching = is_down,
+                Key::BTN_TOOL_PEN => self.is_pen = is_down,
+                Key::BTN_TOOL_RUBBER => self.is_rubber = is_down,
+                Key::BTN_STYLUS => self.stylus = is_down,
+                Key::BTN_STYLUS2 => self.stylus2 = is_down,
+                _ => return false,
+            },
+            _ => return false,
+        }
+        true
+    }
+
+    // The input report laid out by `report_descriptor`.
+    fn report(&self) -> Vec<u8> {
+        let is_in_range = self.is_pen || self.is_rubber;
+        // An inverted pen touches with its eraser rather than its tip.
+        let flags = [
+            self.is_touching && !self.is_rubber,
+            self.stylus,
+            self.is_rubber,
+            self.is_touching && self.is_rubber,
+            self.stylus2,
+            is_in_range,
+        ]
+        .iter()
+        .enumerate()
+        .fold(0u8, |flags, (bit, &is_set)| flags | (is_set as u8) << bit);
+
+        let mut report = vec![REPORT_ID, flags];
+        report.extend_from_slice(&self.x.max(0).to_le_bytes());
+        report.extend_from_slice(&self.y.max(0).to_le_bytes());
+        report.extend_from_slice(&(self.pressure.clamp(0, MAX_PRESSURE) as u16).to_le_bytes());
+        report.push(self.tilt.0.clamp(TILT_MIN, TILT_MAX) as i8 as u8);
+        report.push(self.tilt.1.clamp(TILT_MIN, TILT_MAX) as i8 as u8);
+        report
+    }
+}
+
+// A stylus in a Pen collection, which hid-input marks INPUT_PROP_DIRECT, or in a
+// Digitizer one, marked INPUT_PROP_POINTER. X and Y span the 10x6 inch active area.
+fn report_descriptor(output_max: i32, property: InputProperty) -> Vec<u8> {
+    let application = match property {
+        InputProperty::Pointer => 0x01,
+        InputProperty::Direct => 0x02,
+    };
+    let [max0, max1, max2, max3] = output_max.to_le_bytes();
+    let [pressure0, pressure1, ..] = MAX_PRESSURE.to_le_bytes();
+    let mut descriptor = vec![
+        0x05, 0x0d, // Usage Page (Digitizer)
+        0x09, application, // Usage (Digitizer or Pen)
+        0xa1, 0x01, // Collection (Application)
+        0x85, REPORT_ID, // Report ID
+        0x09, 0x20, // Usage (Stylus)
+        0xa1, 0x00, // Collection (Physical)
+        0x09, 0x42, // Usage (Tip Switch)
+        0x09, 0x44, // Usage (Barrel Switch)
+        0x09, 0x3c, // Usage (Invert)
+        0x09, 0x45, // Usage (Eraser)
+        0x09, 0x5a, // Usage (Secondary Barrel Switch)
+        0x09, 0x32, // Usage (In Range)
+        0x15, 0x00, // Logical Minimum (0)
+        0x25, 0x01, // Logical Maximum (1)
+        0x75, 0x01, // Report Size (1)
+        0x95, 0x06, // Report Count (6)
+        0x81, 0x02, // Input (Data, Variable, Absolute)
+        0x95, 0x02, // Report Count (2)
+        0x81, 0x03, // Input (Constant), padding
+        0x05, 0x01, // Usage Page (Generic Desktop)
+        0x27, max0, max1, max2, max3, // Logical Maximum (output_max)
+        0x55, 0x0e, // Unit Exponent (-2)
+        0x65, 0x13, // Unit (Inch)
+        0x75, 0x20, // Report Size (32)
+        0x95, 0x01, // Report Count (1)
+    ];
+    for (usage, hundredths) in [(0x30u8, 1000u16), (0x31, 600)] {
+        let [size0, size1] = hundredths.to_le_bytes();
+        descriptor.extend_from_slice(&[
+            0x09, usage, // Usage (X or Y)
+            0x46, size0, size1, // Physical Maximum
+            0x81, 0x02, // Input (Data, Variable, Absolute)
+        ]);
+    }
+    descriptor.extend_from_slice(&[
+        0x05, 0x0d, // Usage Page (Digitizer)
+        0x55, 0x00, // Unit Exponent (0)
+        0x65, 0x00, // Unit (None)
+        0x45, 0x00, // Physical Maximum (0)
+        0x09, 0x30, // Usage (Tip Pressure)
+        0x26, pressure0, pressure1, // Logical Maximum (MAX_PRESSURE)
+        0x75, 0x10, // Report Size (16)
+        0x81, 0x02, // Input (Data, Variable, Absolute)
+        0x09, 0x3d, // Usage (X Tilt)
+        0x09, 0x3e, // Usage (Y Tilt)
+        0x15, TILT_MIN as u8, // Logical Minimum
+        0x25, TILT_MAX as u8, // Logical Maximum
+        0x75, 0x08, // Report Size (8)
+        0x95, 0x02, // Report Count (2)
+        0x81, 0x02, // Input (Data, Variable, Absolute)
+        0xc0, // End Collection
+        0xc0, // End Collection
+    ]);
+    descriptor
+}
+
+// struct uhid_event of type UHID_CREATE2, up to the end of the report descriptor.
+fn create_event(name: &str, descriptor: &[u8]) -> Vec<u8> {
+    let mut event = UHID_CREATE2.to_ne_bytes().to_vec();
+    let mut name_field = [0u8; 128];
+    let name = &name.as_bytes()[..name.len().min(127)];
+    name_field[..name.len()].copy_from_slice(name);
+    event.extend_from_slice(&name_field);
+    event.extend_from_slice(&[0u8; 64 + 64]); // phys, uniq
+    event.extend_from_slice(&(descriptor.len() as u16).to_ne_bytes());
+    event.extend_from_slice(&BUS_USB.to_ne_bytes());
+    for value in [VID as u32, PID as u32, 2, 0] {
+        // vendor, product, version, country
+        event.extend_from_slice(&value.to_ne_bytes());
+    }
+    event.extend_from_slice(descriptor);
+    event
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(key: Key, value: i32) -> InputEvent {
+        InputEvent::new(EventType::KEY, key.code(), value)
+    }
+
+    fn abs(axis: AbsoluteAxisType, value: i32) -> InputEvent {
+        InputEvent::new(EventType::ABSOLUTE, axis.0, value)
+    }
+
+    #[test]
+    fn frames_become_digitizer_reports() {
+        let mut state = PenState::default();
+        for event in [
+            key(Key::BTN_TOOL_PEN, 1),
+            key(Key::BTN_TOUCH, 1),
+            key(Key::BTN_STYLUS2, 1),
+            abs(AbsoluteAxisType::ABS_X, 0x1234),
+            abs(AbsoluteAxisType::ABS_Y, 0x0800),
+            abs(AbsoluteAxisType::ABS_PRESSURE, 4000),
+            abs(AbsoluteAxisType::ABS_TILT_X, -20),
+            abs(AbsoluteAxisType::ABS_TILT_Y, 90),
+        ] {
+            assert!(state.apply(&event));
+        }
+        assert!(!state.apply(&key(Key::BTN_LEFT, 1)));
+        assert_eq!(
+            state.report(),
+            [1, 0b110001, 0x34, 0x12, 0, 0, 0, 0x08, 0, 0, 0xa0, 0x0f, 0xec, 63]
+        );
+
+        // The eraser end touches with the Eraser bit, inverted.
+        for event in [key(Key::BTN_TOOL_PEN, 0), key(Key::BTN_TOOL_RUBBER, 1)] {
+            state.apply(&event);
+        }
+        assert_eq!(state.report()[1], 0b111100);
+    }
+
+    #[test]
+    fn descriptor_reports_match_the_encoded_length() {
+        let descriptor = report_descriptor(4096, InputProperty::Direct);
+        assert_eq!(descriptor[3], 0x02);
+        // 8 flag bits, 32-bit X and Y, 16-bit pressure and two 8-bit tilts.
+        assert_eq!(PenState::default().report().len(), 1 + (8 + 32 * 2 + 16 + 8 * 2) / 8);
+
+        let event = create_event("VINSA 1060+ Pen", &descriptor);
+        assert_eq!(event.len(), 4 + 128 + 64 + 64 + 2 + 2 + 4 * 4 + descriptor.len());
+        assert_eq!(&event[4..19], b"VINSA 1060+ Pen");
+    }
+}
@@ -0,0 +1,271 @@
+use rusb::constants::{
+    LIBUSB_ERROR_BUSY, LIBUSB_ERROR_INTERRUPTED, LIBUSB_ERROR_NO_DEVICE,
+    LIBUSB_TRANSFER_CANCELLED, LIBUSB_TRANSFER_COMPLETED, LIBUSB_TRANSFER_NO_DEVICE,
+    LIBUSB_TRANSFER_OVERFLOW, LIBUSB_TRANSFER_STALL, LIBUSB_TRANSFER_TIMED_OUT,
+};
+use rusb::ffi::{self, libusb_transfer};
+use rusb::{
+    ConfigDescriptor, Device, DeviceHandle, Error as RusbError, GlobalContext,
+    InterfaceDescriptor, TransferType, UsbContext,
+};
+
+use std::io;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::vendor_init::InitTransport;
+
+// The tablet claimed through libusb. Its HID interfaces are detached from the kernel,
+// which gets past hidraw permissions and HID quirks, but also takes away the kernel's
+// own input devices for it while the driver runs.
+pub struct UsbTablet {
+    device_handle: DeviceHandle<GlobalContext>,
+    endpoint_address: u8,
+    interface_number: u8,
+}
+
+impl UsbTablet {
+    pub fn open(device: &Device<GlobalContext>) -> Result<Self, RusbError> {
+        let mut tablet = UsbTablet {
+            device_handle: device.open()?,
+            endpoint_address: 0,
+            interface_number: 0,
+        };
+        tablet.claim(device)?;
+        Ok(tablet)
+    }
+
+    fn claim(&mut self, device: &Device<GlobalContext>) -> Result<(), RusbError> {
+        self.device_handle.set_auto_detach_kernel_driver(true)?;
+
+        let configurations = get_configurations(device)?;
+        let interface_descriptors = get_hid_interface_descriptors(&configurations);
+
+        for interface_descriptor in interface_descriptors {
+            self.device_handle
+                .claim_interface(interface_descriptor.interface_number())?;
+            for endpoint_descriptor in interface_descriptor.endpoint_descriptors() {
+                if endpoint_descriptor.transfer_type() == TransferType::Interrupt
+                    && endpoint_descriptor.max_packet_size() == 64
+                {
+                    self.endpoint_address = endpoint_descriptor.address();
+                    self.interface_number = interface_descriptor.interface_number();
+                }
+            }
+        }
+        self.reset()
+    }
+
+    pub fn reset(&mut self) -> Result<(), RusbError> {
+        self.device_handle.reset()
+    }
+
+    // Blocks until a report arrives or `timeout` passes.
+    pub fn read_with_timeout(
+        &self,
+        buffer: &mut [u8],
+        timeout: Duration,
+    ) -> Result<usize, RusbError> {
+        self.device_handle
+            .read_interrupt(self.endpoint_address, buffer, timeout)
+    }
+
+    // Like `read_with_timeout`, without a timeout when `timeout` is None, and failing
+    // with `Interrupted` once `reads` are cancelled. The read is a transfer of its own
+    // for that, so it can be cancelled from another thread.
+    pub fn read_unless_cancelled(
+        &self,
+        buffer: &mut [u8],
+        timeout: Option<Duration>,
+        reads: &PendingReads,
+    ) -> Result<usize, RusbError> {
+        // Set by `read_done`, in whichever thread handles the transfer's completion.
+        let completed = AtomicI32::new(0);
+        // SAFETY: a transfer without isochronous packets.
+        let transfer = unsafe { ffi::libusb_alloc_transfer(0) };
+        if transfer.is_null() {
+            return Err(RusbError::NoMem);
+        }
+        // 0 waits for good.
+        let millis = timeout.map_or(0, |timeout| timeout.as_millis().clamp(1, u32::MAX as u128));
+        // SAFETY: `buffer` and `completed` outlive the transfer, which is done by the
+        // time this returns.
+        unsafe {
+            ffi::libusb_fill_interrupt_transfer(
+                transfer,
+                self.device_handle.as_raw(),
+                self.endpoint_address,
+                buffer.as_mut_ptr(),
+                buffer.len().min(i32::MAX as usize) as i32,
+                read_done,
+                completed.as_ptr().cast(),
+                millis as u32,
+            )
+        };
+        if let Err(err) = reads.submit(transfer) {
+            // SAFETY: the transfer was never submitted.
+            unsafe { ffi::libusb_free_transfer(transfer) };
+            return Err(err);
+        }
+
+        let context = self.device_handle.context().as_raw();
+        while completed.load(Ordering::SeqCst) == 0 {
+            // SAFETY: `completed` is an int libusb reads under its event lock.
+            let result =
+                unsafe { ffi::libusb_handle_events_completed(context, completed.as_ptr()) };
+            // As libusb's own synchronous transfers do, give up on the transfer and
+            // wait for it to finish cancelling.
+            if result < 0 && result != LIBUSB_ERROR_INTERRUPTED {
+                // SAFETY: the transfer is submitted until `completed` is set.
+                unsafe { ffi::libusb_cancel_transfer(transfer) };
+            }
+        }
+        reads.remove(transfer);
+        // SAFETY: the transfer completed and nothing else refers to it anymore.
+        let (status, length) = unsafe { ((*transfer).status, (*transfer).actual_length) };
+        unsafe { ffi::libusb_free_transfer(transfer) };
+        match status {
+            LIBUSB_TRANSFER_COMPLETED => Ok(length.max(0) as usize),
+            LIBUSB_TRANSFER_TIMED_OUT => Err(RusbError::Timeout),
+            LIBUSB_TRANSFER_CANCELLED => Err(RusbError::Interrupted),
+            LIBUSB_TRANSFER_STALL => Err(RusbError::Pipe),
+            LIBUSB_TRANSFER_NO_DEVICE => Err(RusbError::NoDevice),
+            LIBUSB_TRANSFER_OVERFLOW => Err(RusbError::Overflow),
+            _ => Err(RusbError::Io),
+        }
+    }
+
+    // HID report descriptor of the interface the reports come from.
+    pub fn report_descriptor(&self) -> Result<Vec<u8>, RusbError> {
+        let mut descriptor = [0u8; 4096];
+        let length = self.device_handle.read_control(
+            0x81,
+            0x06,
+            0x22 << 8,
+            self.interface_number as u16,
+            &mut descriptor,
+            Duration::from_secs(1),
+        )?;
+        Ok(descriptor[..length].to_vec())
+    }
+
+    pub fn serial_number(&self, device: &Device<GlobalContext>) -> Option<String> {
+        let descriptor = device.device_descriptor().ok()?;
+        self.device_handle
+            .read_serial_number_string_ascii(&descriptor)
+            .ok()
+    }
+}
+
+impl InitTransport for UsbTablet {
+    // SET_REPORT to the vendor interface, with the report type (feature) and ID in wValue.
+    fn feature_report(&mut self, report: &[u8]) -> io::Result<()> {
+        let report_id = report.first().copied().unwrap_or(0) as u16;
+        self.device_handle
+            .write_control(
+                0x21,
+                0x09,
+                0x0300 | report_id,
+                2,
+                report,
+                Duration::from_millis(250),
+            )
+            .map_err(io::Error::other)?;
+        Ok(())
+    }
+
+    fn string_descriptor(&mut self, index: u8) -> io::Result<Vec<u8>> {
+        let mut descriptor = [0u8; 256];
+        let length = self
+            .device_handle
+            .read_control(
+                0x80,
+                0x06,
+                0x03 << 8 | index as u16,
+                0x0409,
+                &mut descriptor,
+                Duration::from_millis(250),
+            )
+            .map_err(io::Error::other)?;
+        Ok(descriptor[..length].to_vec())
+    }
+}
+
+extern "system" fn read_done(transfer: *mut libusb_transfer) {
+    // SAFETY: `user_data` is the `completed` flag of the read waiting for the transfer.
+    unsafe { (*(*transfer).user_data.cast::<AtomicI32>()).store(1, Ordering::SeqCst) };
+}
+
+// Reads in flight, which `cancel_all` ends for good: reads submitted afterwards fail
+// right away.
+#[derive(Clone, Default)]
+pub struct PendingReads(Arc<Mutex<Transfers>>);
+
+#[derive(Default)]
+struct Transfers {
+    submitted: Vec<TransferPtr>,
+    is_cancelled: bool,
+}
+
+// Only ever dereferenced by libusb, while the transfer is submitted.
+#[derive(PartialEq, Eq)]
+struct TransferPtr(*mut libusb_transfer);
+
+// SAFETY: libusb transfers may be cancelled from any thread.
+unsafe impl Send for TransferPtr {}
+
+impl PendingReads {
+    pub fn cancel_all(&self) {
+        let mut transfers = self.0.lock().unwrap();
+        transfers.is_cancelled = true;
+        for transfer in &transfers.submitted {
+            // SAFETY: transfers are removed before they are freed, under the same lock.
+            unsafe { ffi::libusb_cancel_transfer(transfer.0) };
+        }
+    }
+
+    fn submit(&self, transfer: *mut libusb_transfer) -> Result<(), RusbError> {
+        let mut transfers = self.0.lock().unwrap();
+        if transfers.is_cancelled {
+            return Err(RusbError::Interrupted);
+        }
+        // SAFETY: the transfer is filled in, and its buffer outlives it.
+        match unsafe { ffi::libusb_submit_transfer(transfer) } {
+            0 => {
+                transfers.submitted.push(TransferPtr(transfer));
+                Ok(())
+            }
+            LIBUSB_ERROR_NO_DEVICE => Err(RusbError::NoDevice),
+            LIBUSB_ERROR_BUSY => Err(RusbError::Busy),
+            _ => Err(RusbError::Io),
+        }
+    }
+
+    fn remove(&self, transfer: *mut libusb_transfer) {
+        let mut transfers = self.0.lock().unwrap();
+        transfers.submitted.retain(|submitted| *submitted != TransferPtr(transfer));
+    }
+}
+
+fn get_hid_interface_descriptors(
+    config_descriptors: &[ConfigDescriptor],
+) -> Vec<InterfaceDescriptor<'_>> {
+    config_descriptors
+        .iter()
+        .flat_map(|config_descriptor| config_descriptor.interfaces())
+        .flat_map(|interface| interface.descriptors())
+        .filter(|interface_descriptor| {
+            interface_descriptor.class_code() == rusb::constants::LIBUSB_CLASS_HID
+        })
+        .collect()
+}
+
+fn get_configurations(
+    device: &Device<GlobalContext>,
+) -> Result<Vec<ConfigDescriptor>, RusbError> {
+    let device_descriptor = device.device_descriptor()?;
+    Ok((0..device_descriptor.num_configurations())
+        .filter_map(|n| device.config_descriptor(n).ok())
+        .collect())
+}
@@ -0,0 +1,187 @@
+use std::io;
+
+use tracing::{debug, info};
+
+use crate::config::{AXIS_MAX, AxesConfig};
+
+// One step of the handshake the vendor's Windows driver performs before the tablet
+// reports its full active area and pressure resolution.
+#[derive(Clone, Copy, Debug)]
+pub enum InitStep {
+    // Reading the parameter string descriptor (0xc8, or 0x64 on older firmware)
+    // switches UC-Logic based tablets to their vendor report and returns the
+    // digitizer's ranges.
+    ReadParameters,
+    // A feature report, report ID first.
+    FeatureReport(&'static [u8]),
+}
+
+// The "enable digitizer mode" handshake of the VINSA 1060 Plus.
+pub const VINSA_1060_PLUS: &[InitStep] = &[
+    InitStep::ReadParameters,
+    InitStep::FeatureReport(&[0x08, 0x03, 0x00, 0xff, 0xf0, 0x00, 0xff, 0xf0]),
+];
+
+// Ranges of the digitizer, as read from the parameter string descriptor.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DigitizerParams {
+    pub max_x: u32,
+    pub max_y: u32,
+    pub max_pressure: u16,
+    // Lines per inch.
+    pub resolution: u16,
+}
+
+impl DigitizerParams {
+    const V2_INDEX: u8 = 0xc8;
+    const V1_INDEX: u8 = 0x64;
+
+    // The digitizer's X and Y maximums in place of those the config leaves at the
+    // default.
+    pub fn raw_axes(&self, axes: AxesConfig) -> AxesConfig {
+        let max = |configured: i32, digitizer: u32| match configured {
+            AXIS_MAX => digitizer.min(u16::MAX as u32) as i32,
+            configured => configured,
+        };
+        AxesConfig {
+            x_max: max(axes.x_max, self.max_x),
+            y_max: max(axes.y_max, self.max_y),
+            ..axes
+        }
+    }
+
+    // `descriptor` is the raw string descriptor, length and type bytes included. The
+    // v2 layout has 24-bit maximums, the v1 layout 16-bit ones.
+    fn parse(index: u8, descriptor: &[u8]) -> Option<Self> {
+        let u16_at = |i: usize| {
+            Some(u16::from_le_bytes([
+                *descriptor.get(i)?,
+                *descriptor.get(i + 1)?,
+            ]))
+        };
+        let u24_at = |i: usize| Some(u16_at(i)? as u32 | (*descriptor.get(i + 2)? as u32) << 16);
+        let params = match index {
+            Self::V2_INDEX => DigitizerParams {
+                max_x: u24_at(2)?,
+                max_y: u24_at(5)?,
+                max_pressure: u16_at(8)?,
+                resolution: u16_at(10)?,
+            },
+            _ => DigitizerParams {
+                max_x: u16_at(2)? as u32,
+                max_y: u16_at(4)? as u32,
+                max_pressure: u16_at(8)?,
+                resolution: u16_at(10)?,
+            },
+        };
+        // Tablets without the descriptor answer with an unrelated string.
+        let is_valid = params.max_x > 0 && params.max_y > 0 && params.resolution > 0;
+        is_valid.then_some(params)
+    }
+}
+
+// Where the handshake is sent: the claimed USB device or the tablet's hidraw node.
+pub trait InitTransport {
+    fn feature_report(&mut self, report: &[u8]) -> io::Result<()>;
+    fn string_descriptor(&mut self, index: u8) -> io::Result<Vec<u8>>;
+}
+
+// Sends `steps` in order. Returns the digitizer parameters if the tablet gave them;
+// a tablet that doesn't is still usable, but a failed feature report is an error.
+pub fn run(
+    transport: &mut impl InitTransport,
+    steps: &[InitStep],
+) -> io::Result<Option<DigitizerParams>> {
+    let mut params = None;
+    for step in steps {
+        match step {
+            InitStep::ReadParameters => {
+                params = [DigitizerParams::V2_INDEX, DigitizerParams::V1_INDEX]
+                    .into_iter()
+                    .find_map(|index| {
+                        let descriptor = transport
+                            .string_descriptor(index)
+                            .inspect_err(|err| debug!("Cannot read string {:#04x}: {}", index, err))
+                            .ok()?;
+                        DigitizerParams::parse(index, &descriptor)
+                    });
+                match &params {
+                    Some(params) => info!(
+                        "Digitizer: {}x{}, {} pressure levels, {} lpi.",
+                        params.max_x,
+                        params.max_y,
+                        params.max_pressure as u32 + 1,
+                        params.resolution
+                    ),
+                    None => debug!("The tablet has no parameter descriptor."),
+                }
+            }
+            InitStep::FeatureReport(report) => transport.feature_report(report)?,
+        }
+    }
+    Ok(params)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct FakeTablet {
+        strings: Vec<(u8, Vec<u8>)>,
+        feature_reports: Vec<Vec<u8>>,
+    }
+
+    impl InitTransport for FakeTablet {
+        fn feature_report(&mut self, report: &[u8]) -> io::Result<()> {
+            self.feature_reports.push(report.to_vec());
+            Ok(())
+        }
+
+        fn string_descriptor(&mut self, index: u8) -> io::Result<Vec<u8>> {
+            self.strings
+                .iter()
+                .find(|(i, _)| *i == index)
+                .map(|(_, descriptor)| descriptor.clone())
+                .ok_or(io::Error::from(io::ErrorKind::NotFound))
+        }
+    }
+
+    #[test]
+    fn handshake_reads_the_parameters_and_sends_the_reports() {
+        // 50800 x 31750, pressure 0..=8191, 5080 lpi, in the v2 layout.
+        let v2 = vec![
+            0x14, 0x03, 0x70, 0xc6, 0x00, 0x06, 0x7c, 0x00, 0xff, 0x1f, 0xd8, 0x13, 0x00, 0x00,
+        ];
+        let mut tablet = FakeTablet {
+            strings: vec![(0xc8, v2)],
+            feature_reports: Vec::new(),
+        };
+        let params = run(&mut tablet, VINSA_1060_PLUS).unwrap();
+        assert_eq!(
+            params,
+            Some(DigitizerParams {
+                max_x: 50800,
+                max_y: 31750,
+                max_pressure: 8191,
+                resolution: 5080,
+            })
+        );
+        assert_eq!(
+            tablet.feature_reports,
+            vec![vec![0x08, 0x03, 0x00, 0xff, 0xf0, 0x00, 0xff, 0xf0]]
+        );
+        let axes = params.unwrap().raw_axes(AxesConfig {
+            y_max: 30000,
+            ..AxesConfig::default()
+        });
+        assert_eq!((axes.x_max, axes.y_max), (50800, 30000));
+
+        // Without the descriptor the handshake still goes through.
+        let mut tablet = FakeTablet {
+            strings: Vec::new(),
+            feature_reports: Vec::new(),
+        };
+        assert_eq!(run(&mut tablet, VINSA_1060_PLUS).unwrap(), None);
+        assert_eq!(tablet.feature_reports.len(), 1);
+    }
+}
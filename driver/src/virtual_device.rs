@@ -1,397 +1,3232 @@
 // VINSA 1060 Plus Linux Driver (V2), (by feveal@hotmail.com)
-use std::io::Error;
 use std::collections::HashMap;
+use std::io;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::binding::{self, Binding, Chord};
+use crate::calibration::{CalibrationConfig, PressureLearner};
+use crate::config::{
+    AXIS_MAX, AxesConfig, Config, DebounceConfig, EraserConfig, EraserMode, HoldClickConfig,
+    InputProperty, LayerConfig, Mode, MouseAreaAnchor, MouseAreaConfig, MultimediaConfig,
+    PenBackend, PressureConfig, PrecisionConfig, Profile, ProfileSwitch, Rotation, SettleConfig,
+    StabilizerConfig, TabletAreaConfig, VirtualDevicesConfig,
+};
+use crate::double_tap::{DoubleTap, DoubleTapConfig};
+use crate::error::Result;
+use crate::filter::FilterPipeline;
+use crate::flick::{FlickConfig, FlickTracker, Touch};
+use crate::gesture::{Gesture, GestureTracker};
+use crate::keys;
+use crate::palm::{PalmRejection, PalmRejectionConfig};
+use crate::pressure::{MAX_DISTANCE, PressureCurve};
+use crate::protocol::{MalformedReport, Protocol, ReportParser, ShortReport};
+use crate::radial_menu::{RadialMenu, RadialMenuConfig};
+use crate::relative::{RelativeConfig, RelativeMotion};
+use crate::repeat::KeyRepeat;
+#[cfg(feature = "scripting")]
+use crate::script::{Action, PenState, ScriptHooks};
+use crate::scroll::{ScrollConfig, ScrollDrag};
+use crate::tilt::{TILT_MAX, TILT_MIN, TiltConfig, TiltEmulation};
+use crate::uhid::UhidPen;
+use crate::vendor_init::DigitizerParams;
+use crate::screen::OutputMapping;
+use crate::virtual_output::VirtualOutput;
+use crate::zone::{ZoneConfig, ZoneTracker};
+use crate::{PID, VID};
 
 use evdev::{
     uinput::{VirtualDevice, VirtualDeviceBuilder},
-    AbsInfo, AbsoluteAxisType, AttributeSet, EventType, InputEvent, Key, Synchronization,
-    UinputAbsSetup,
+    AbsInfo, AbsoluteAxisType, AttributeSet, BusType, EventType, InputEvent, InputId, Key,
+    MiscType, PropType, RelativeAxisType, UinputAbsSetup,
 };
+use tracing::{debug, error, info, warn};
+
+// Longest report the tablet sends; reads need a buffer this large.
+pub const MAX_REPORT_LENGTH: usize = 64;
 
-#[derive(Default)]
 pub struct RawDataReader {
+    // The last report, as long as the tablet sent it.
     pub data: Vec<u8>,
+    // When the report was read from the tablet, sent along as MSC_TIMESTAMP.
+    pub read_at: Option<Instant>,
+    parser: Arc<dyn ReportParser>,
+    short_report: ShortReport,
+    // Length of the last whole report, to tell the rest of one cut short.
+    report_length: usize,
+    // Bytes missing from a report cut short, which the next read may bring.
+    missing: Option<usize>,
+    // While the tablet sends short reports, which are read with their own layout.
+    is_short: bool,
+    // Short reports carry the tablet buttons apart from the pen: the last of those,
+    // while `data` keeps the last pen report.
+    buttons_report: Vec<u8>,
 }
 
-impl RawDataReader {
-    const X_AXIS_HIGH: usize = 1;
-    const X_AXIS_LOW: usize = 2;
-    const Y_AXIS_HIGH: usize = 3;
-    const Y_AXIS_LOW: usize = 4;
-    const PRESSURE_HIGH: usize = 5;
-    const PRESSURE_LOW: usize = 6;
-    const PEN_BUTTONS: usize = 9;
-    const TABLET_BUTTONS_HIGH: usize = 12;
-    const TABLET_BUTTONS_LOW: usize = 11;
+impl Default for RawDataReader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
+impl RawDataReader {
     pub fn new() -> Self {
+        Self::with_protocol(Protocol::default())
+    }
+
+    pub fn with_protocol(protocol: Protocol) -> Self {
+        Self::with_parser(protocol.parser())
+    }
+
+    pub fn with_parser(parser: Arc<dyn ReportParser>) -> Self {
         RawDataReader {
-            data: vec![0u8; 64],
+            data: vec![0u8; MAX_REPORT_LENGTH],
+            read_at: None,
+            parser,
+            short_report: ShortReport::default(),
+            report_length: 0,
+            missing: None,
+            is_short: false,
+            buttons_report: Vec::new(),
+        }
+    }
+
+    // Reads short reports on the pressure scale of the digitizer, if it gave one.
+    pub fn with_digitizer_params(mut self, params: Option<DigitizerParams>) -> Self {
+        if let Some(params) = params {
+            self.short_report = ShortReport::new(params.max_pressure);
         }
+        self
+    }
+
+    // A pen report at the given raw values, with no tablet button held.
+    #[cfg(test)]
+    pub fn from_fields(x: u16, y: u16, pressure: u16, pen_buttons: u8) -> Self {
+        let mut reader = RawDataReader::new();
+        let [x_high, x_low] = x.to_be_bytes();
+        let [y_high, y_low] = y.to_be_bytes();
+        let [pressure_high, pressure_low] = pressure.to_be_bytes();
+        reader.data[1..=6]
+            .copy_from_slice(&[x_high, x_low, y_high, y_low, pressure_high, pressure_low]);
+        reader.data[9] = pen_buttons;
+        // Tablet buttons are active-low.
+        reader.data[11..=12].copy_from_slice(&[0xff, 0xff]);
+        reader
+    }
+
+    // Takes in a report as read, or leaves the last one in place if it is malformed.
+    // After a report cut short, the next read is dropped too if it is exactly what
+    // was missing, so the stream gets back in step instead of reading its tail as a
+    // report of its own.
+    pub fn load(&mut self, report: &[u8]) -> std::result::Result<(), MalformedReport> {
+        if self.missing.take() == Some(report.len()) {
+            return Err(MalformedReport::Remainder(report.len()));
+        }
+        // A short report and one cut short look alike, but a tablet sending longer
+        // reports doesn't switch to short ones.
+        let is_short = match self.parser.check(report) {
+            Ok(()) => false,
+            Err(_)
+                if matches!(self.report_length, 0 | 8 | 10)
+                    && self.short_report.check(report).is_ok() =>
+            {
+                true
+            }
+            Err(err) => {
+                if let MalformedReport::TooShort { length, .. }
+                | MalformedReport::WrongLength(length) = err
+                    && length > 0
+                    && length < self.report_length
+                {
+                    self.missing = Some(self.report_length - length);
+                }
+                return Err(err);
+            }
+        };
+        self.is_short = is_short;
+        self.report_length = report.len();
+        match is_short && ShortReport::is_buttons_report(report) {
+            true => self.buttons_report = report.to_vec(),
+            false => self.data = report.to_vec(),
+        }
+        Ok(())
+    }
+
+    fn parser(&self) -> &dyn ReportParser {
+        match self.is_short {
+            true => &self.short_report,
+            false => &*self.parser,
+        }
+    }
+
+    pub(crate) fn x_axis(&self) -> i32 {
+        self.parser().x_axis(&self.data) as i32
     }
 
-    fn u16_from_2_u8(&self, high: u8, low: u8) -> u16 {
-        (high as u16) << 8 | low as u16
+    pub(crate) fn y_axis(&self) -> i32 {
+        self.parser().y_axis(&self.data) as i32
     }
 
-    fn x_axis(&self) -> i32 {
-        let raw = self.u16_from_2_u8(self.data[Self::X_AXIS_HIGH], self.data[Self::X_AXIS_LOW]);
-        raw as i32
+    // Above the active area Y wraps around to 65535 and down; read it as a negative offset.
+    pub(crate) fn signed_y_axis(&self) -> i32 {
+        self.parser().y_axis(&self.data) as i16 as i32
     }
 
-    fn y_axis(&self) -> i32 {
-        let raw = self.u16_from_2_u8(self.data[Self::Y_AXIS_HIGH], self.data[Self::Y_AXIS_LOW]);
-        raw as i32
+    pub(crate) fn pressure(&self) -> i32 {
+        self.parser().pressure(&self.data) as i32
     }
 
-    fn pressure(&self) -> i32 {
-        self.u16_from_2_u8(
-            self.data[Self::PRESSURE_HIGH],
-            self.data[Self::PRESSURE_LOW],
-        ) as i32
+    pub(crate) fn tablet_buttons_as_binary_flags(&self) -> u16 {
+        match self.is_short {
+            true => self.short_report.tablet_buttons(&self.buttons_report),
+            false => self.parser.tablet_buttons(&self.data),
+        }
+    }
+
+    pub(crate) fn pen_buttons(&self) -> u8 {
+        self.parser().pen_buttons(&self.data)
     }
 
-    fn tablet_buttons_as_binary_flags(&self) -> u16 {
-        self.u16_from_2_u8(
-            self.data[Self::TABLET_BUTTONS_HIGH],
-            self.data[Self::TABLET_BUTTONS_LOW],
-        ) | (0xcc << 8)
+    pub(crate) fn tilt(&self) -> Option<(i32, i32)> {
+        self.parser().tilt(&self.data)
     }
 
-    fn pen_buttons(&self) -> u8 {
-        self.data[Self::PEN_BUTTONS]
+    // Hex dump of the report, 16 bytes per line, followed by the decoded fields.
+    pub fn annotated_hex(&self) -> String {
+        let mut dump = String::new();
+        for (line, bytes) in self.data.chunks(16).enumerate() {
+            let hex: Vec<String> = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+            dump.push_str(&format!("{:02x}: {}\n", line * 16, hex.join(" ")));
+        }
+
+        let flags = self.tablet_buttons_as_binary_flags();
+        let pressed: Vec<u8> = (0..14).filter(|i| flags & (1 << i) == 0).collect();
+        dump.push_str(&format!(
+            "x={} y={} pressure={} pen=0x{:02x} tablet_buttons={:?}",
+            self.x_axis(),
+            self.signed_y_axis(),
+            self.pressure(),
+            self.pen_buttons(),
+            pressed
+        ));
+        dump
     }
 }
 
 pub struct DeviceDispatcher {
     tablet_last_raw_pressed_buttons: u16,
+    // The buttons as last read, which debouncing may not have let through yet.
+    tablet_raw_buttons: u16,
+    debounce: DebounceConfig,
+    button_changed_at: HashMap<u8, Instant>,
     pen_last_raw_pressed_button: u8,
-    tablet_button_id_to_key_code_map: HashMap<u8, Vec<Key>>,
-    pen_button_id_to_key_code_map: HashMap<u8, Vec<Key>>,
-    virtual_pen: VirtualDevice,
-    virtual_keyboard: VirtualDevice,
+    tablet_button_id_to_key_code_map: HashMap<u8, Binding>,
+    last_command_launch: HashMap<u16, Instant>,
+    chords: Vec<Chord>,
+    // Buttons held as part of a chord; they send nothing of their own until released.
+    chord_buttons: u16,
+    gestures: GestureTracker,
+    key_repeat: KeyRepeat,
+    layer: LayerConfig,
+    is_layer_active: bool,
+    // Buttons pressed while the layer was active; they keep their layer binding until
+    // released even if the layer button is let go first.
+    layer_buttons: u16,
+    pen_button_id_to_key_code_map: HashMap<u8, Binding>,
+    virtual_pen: Box<dyn VirtualOutput>,
+    // Pen events of the report being dispatched, sent as one frame once it is done.
+    pen_frame: Vec<InputEvent>,
+    // Axis values last sent to the virtual pen, so unchanged ones aren't sent again.
+    sent_pen_axes: HashMap<u16, i32>,
+    // MSC_TIMESTAMP counts microseconds from the first frame; the frames of a report
+    // are stamped with when it was read.
+    timestamp_epoch: Option<Instant>,
+    frame_read_at: Option<Instant>,
+    virtual_keyboard: Box<dyn VirtualOutput>,
+    // Keys down on the virtual keyboard, in the order they went down.
+    pressed_keys: Vec<Key>,
     was_touching: bool,
+    // Pressure last sent to the virtual pen, 0 once it is out of range.
+    last_pressure: i32,
     is_mouse_mode: bool,
-    last_x: i32,
-    last_y: i32,
+    mouse_filter: FilterPipeline,
+    tablet_filter: FilterPipeline,
     last_valid_x: i32,
     mouse_area_scale: f32,
-}
-
-impl Default for DeviceDispatcher {
-    fn default() -> Self {
-        Self::new()
-    }
+    mouse_area_scale_max: f32,
+    mouse_area: MouseAreaConfig,
+    tablet_area: TabletAreaConfig,
+    rotation: Rotation,
+    invert_x: bool,
+    invert_y: bool,
+    rotation_button: Option<u8>,
+    // Raw position of the last pen-down, and the one the LastTouch anchor was set to.
+    last_touch_point: (i32, i32),
+    mouse_area_anchor_point: (i32, i32),
+    pressure: PressureConfig,
+    calibration: CalibrationConfig,
+    pressure_learner: PressureLearner,
+    axes: AxesConfig,
+    output_mapping: Option<OutputMapping>,
+    registered_tablet_keys: Vec<Key>,
+    registered_pen_keys: Vec<Key>,
+    eraser: EraserConfig,
+    is_eraser_active: bool,
+    hold_click: HoldClickConfig,
+    // When the hold-click button went down on the touching pen, until it fires.
+    hold_click_started: Option<Instant>,
+    is_hold_click_button_down: bool,
+    // The touch stays lifted after a hold-click until the pen leaves the surface.
+    is_hold_click_fired: bool,
+    hold_click_count: u32,
+    profile_switch: Option<ProfileSwitch>,
+    precision: PrecisionConfig,
+    is_precision_active: bool,
+    // Mapped position the precision area is centered on, taken from the first report
+    // after it is turned on.
+    precision_anchor: Option<(i32, i32)>,
+    stabilizer: StabilizerConfig,
+    is_stabilizer_active: bool,
+    // Where the lazy brush is while the pen touches.
+    brush: Option<(f32, f32)>,
+    settle: SettleConfig,
+    // Reports skipped since the pen came into range, and touching reports held back
+    // since it touched down.
+    settling_reports: u32,
+    settling_touches: u32,
+    multimedia: MultimediaConfig,
+    pressed_multimedia_keys: Option<Vec<Key>>,
+    is_in_proximity: bool,
+    sticky_buttons: Vec<u8>,
+    // Keys latched by sticky buttons, and whether the pen has touched down since.
+    sticky_keys: HashMap<u8, Vec<Key>>,
+    has_sticky_touch: bool,
+    virtual_mouse: Option<Box<dyn VirtualOutput>>,
+    // Where along the strip the pen last was while sliding on the dial, and movement
+    // not yet enough for a step.
+    virtual_dial: Option<Box<dyn VirtualOutput>>,
+    dial_position: Option<i32>,
+    dial_remainder: f32,
+    relative: RelativeConfig,
+    relative_motion: RelativeMotion,
+    is_mouse_button_down: bool,
+    scroll: ScrollConfig,
+    scroll_drag: ScrollDrag,
+    is_scrolling: bool,
+    tilt: TiltConfig,
+    tilt_emulation: TiltEmulation,
+    zones: Vec<ZoneConfig>,
+    zone_tracker: ZoneTracker,
+    radial_menu_config: RadialMenuConfig,
+    radial_menu: RadialMenu,
+    flicks: FlickConfig,
+    flick_tracker: FlickTracker,
+    double_tap_config: DoubleTapConfig,
+    double_tap: DoubleTap,
+    palm_rejection_config: PalmRejectionConfig,
+    palm_rejection: PalmRejection,
+    #[cfg(feature = "scripting")]
+    script: Option<ScriptHooks>,
 }
 
 impl DeviceDispatcher {
     const PRESSED: i32 = 1;
     const RELEASED: i32 = 0;
     const HOLD: i32 = 2;
+    // Presses of a command button closer together than this are ignored.
+    const COMMAND_REPEAT_INTERVAL: Duration = Duration::from_millis(500);
 
-    pub fn new() -> Self {
-        let default_tablet_button_id_to_key_code_map: HashMap<u8, Vec<Key>> = [
-            (0, vec![Key::KEY_TAB]),        // TAB
-            (1, vec![Key::KEY_SPACE]),      // SPACE
-            (2, vec![Key::KEY_LEFTALT]),    // ALT
-            (3, vec![Key::KEY_LEFTCTRL]),   // CTRL
-            (4, vec![Key::KEY_PAGEUP]),     // MOUSE UP
-            (5, vec![Key::KEY_PAGEDOWN]),   // MOUSE DOWN
-            (6, vec![Key::KEY_LEFTBRACE]),  // MOUSE AREA -
-            (7, vec![Key::KEY_LEFTCTRL, Key::KEY_KPMINUS]), // CTRL- ZOOM
-            (8, vec![Key::KEY_LEFTCTRL, Key::KEY_KPPLUS]),  // CTRL+ ZOOM
-            (9, vec![Key::KEY_ESC]),        // ESC CANCEL
-            (12, vec![Key::KEY_B]),         // TOGGLE MOUSE/TABLET
-            (13, vec![Key::KEY_RIGHTBRACE]), // MOUSE AREA +
-        ]
-        .iter()
-        .cloned()
-        .collect();
-
-        let default_pen_button_id_to_key_code_map: HashMap<u8, Vec<Key>> =
-            [(4, vec![Key::BTN_STYLUS]), (6, vec![Key::BTN_STYLUS2])]
-                .iter()
-                .cloned()
-                .collect();
+    pub fn new(config: &Config) -> Result<Self> {
+        let names = &config.virtual_devices;
+        let output_max = config.axes.output_max;
+        let virtual_pen: Box<dyn VirtualOutput> = match names.pen_backend {
+            PenBackend::Uinput => Box::new(Self::virtual_pen_builder(
+                &config.all_pen_keys(),
+                names,
+                output_max,
+            )?),
+            PenBackend::Uhid => {
+                let buttons = Self::virtual_pen_buttons_builder(&config.all_pen_keys(), names)?;
+                let property = names.input_property;
+                Box::new(UhidPen::create(&names.pen_name, output_max, property, Box::new(buttons))?)
+            }
+        };
+        let virtual_keyboard =
+            Self::virtual_keyboard_builder(&config.all_tablet_keys(), &names.pad_name)?;
+        let virtual_mouse = match config.needs_virtual_mouse() {
+            true => Some(Box::new(Self::virtual_mouse_builder(&names.mouse_name)?)
+                as Box<dyn VirtualOutput>),
+            false => None,
+        };
+        let virtual_dial = match config.multimedia.dial {
+            true => Some(Box::new(Self::virtual_dial_builder(&names.dial_name)?)
+                as Box<dyn VirtualOutput>),
+            false => None,
+        };
 
+        Ok(Self::with_outputs(
+            config,
+            virtual_pen,
+            Box::new(virtual_keyboard),
+            virtual_mouse,
+            virtual_dial,
+        ))
+    }
+
+    // Builds a dispatcher around arbitrary outputs, e.g. `RecordingOutput`s in tests.
+    pub fn with_outputs(
+        config: &Config,
+        virtual_pen: Box<dyn VirtualOutput>,
+        virtual_keyboard: Box<dyn VirtualOutput>,
+        virtual_mouse: Option<Box<dyn VirtualOutput>>,
+        virtual_dial: Option<Box<dyn VirtualOutput>>,
+    ) -> Self {
+        #[cfg(not(feature = "scripting"))]
+        if config.script.path.is_some() {
+            warn!("The driver was built without the `scripting` feature; ignoring [script].");
+        }
         DeviceDispatcher {
             tablet_last_raw_pressed_buttons: 0xFFFF,
+            tablet_raw_buttons: 0xFFFF,
+            debounce: config.debounce.clone(),
+            button_changed_at: HashMap::new(),
             pen_last_raw_pressed_button: 0,
-            tablet_button_id_to_key_code_map: default_tablet_button_id_to_key_code_map.clone(),
-            pen_button_id_to_key_code_map: default_pen_button_id_to_key_code_map.clone(),
-            virtual_pen: Self::virtual_pen_builder(
-                &default_pen_button_id_to_key_code_map
-                    .values()
-                    .flatten()
-                    .cloned()
-                    .collect::<Vec<Key>>(),
-            )
-            .expect("Error building virtual pen"),
-            virtual_keyboard: Self::virtual_keyboard_builder(
-                &default_tablet_button_id_to_key_code_map
-                    .values()
-                    .flatten()
-                    .cloned()
-                    .collect::<Vec<Key>>(),
-            )
-            .expect("Error building virtual keyboard"),
+            tablet_button_id_to_key_code_map: config.tablet_buttons.clone(),
+            last_command_launch: HashMap::new(),
+            chords: config.chords.clone(),
+            chord_buttons: 0,
+            gestures: GestureTracker::new(&config.gestures),
+            key_repeat: KeyRepeat::new(&config.auto_repeat),
+            layer: config.layer.clone(),
+            is_layer_active: false,
+            layer_buttons: 0,
+            pen_button_id_to_key_code_map: config.pen_buttons.clone(),
+            virtual_pen,
+            pen_frame: Vec::new(),
+            sent_pen_axes: HashMap::new(),
+            timestamp_epoch: None,
+            frame_read_at: None,
+            virtual_keyboard,
+            pressed_keys: Vec::new(),
             was_touching: false,
-            is_mouse_mode: true,
-            last_x: 2048,
-            last_y: 2048,
-            mouse_area_scale: 0.3,
+            last_pressure: 0,
+            is_mouse_mode: config.mode == Mode::Mouse,
+            mouse_filter: FilterPipeline::new(&config.smoothing.mouse),
+            tablet_filter: FilterPipeline::new(&config.smoothing.tablet),
+            mouse_area_scale: config
+                .mouse_area
+                .scale
+                .clamp(0.1, config.mouse_area_scale_max.clamp(0.1, 1.0)),
+            mouse_area_scale_max: config.mouse_area_scale_max.clamp(0.1, 1.0),
+            mouse_area: config.mouse_area.clone(),
+            tablet_area: config.tablet_area,
+            rotation: config.rotation,
+            invert_x: config.invert_x,
+            invert_y: config.invert_y,
+            rotation_button: config.rotation_button,
+            last_touch_point: (2048, 2048),
+            mouse_area_anchor_point: (2048, 2048),
             last_valid_x: 2048,
+            pressure: config.pressure.clone(),
+            calibration: config.calibration.or_raw_range(config.axes.x_max, config.axes.y_max),
+            pressure_learner: PressureLearner::default(),
+            axes: config.axes,
+            output_mapping: config.output.as_deref().and_then(|output| {
+                OutputMapping::resolve(output)
+                    .inspect_err(|err| warn!("{} Mapping to the whole desktop.", err))
+                    .ok()
+            }),
+            registered_tablet_keys: config.all_tablet_keys(),
+            registered_pen_keys: config.all_pen_keys(),
+            eraser: config.eraser.clone(),
+            is_eraser_active: false,
+            multimedia: config.multimedia.clone(),
+            pressed_multimedia_keys: None,
+            is_in_proximity: false,
+            sticky_buttons: config.sticky_buttons.clone(),
+            sticky_keys: HashMap::new(),
+            has_sticky_touch: false,
+            virtual_mouse,
+            virtual_dial,
+            dial_position: None,
+            dial_remainder: 0.0,
+            relative: config.relative.clone(),
+            relative_motion: RelativeMotion::default(),
+            is_mouse_button_down: false,
+            scroll: config.scroll.clone(),
+            scroll_drag: ScrollDrag::default(),
+            is_scrolling: false,
+            hold_click: config.hold_right_click.clone(),
+            hold_click_started: None,
+            is_hold_click_button_down: false,
+            is_hold_click_fired: false,
+            hold_click_count: 0,
+            profile_switch: None,
+            precision: config.precision,
+            is_precision_active: false,
+            stabilizer: config.stabilizer,
+            is_stabilizer_active: config.stabilizer.enabled,
+            brush: None,
+            settle: config.settle,
+            settling_reports: 0,
+            settling_touches: 0,
+            precision_anchor: None,
+            tilt: config.tilt.clone(),
+            tilt_emulation: TiltEmulation::default(),
+            zones: config.zones.clone(),
+            zone_tracker: ZoneTracker::default(),
+            radial_menu_config: config.radial_menu.clone(),
+            radial_menu: RadialMenu::default(),
+            flicks: config.flicks.clone(),
+            flick_tracker: FlickTracker::default(),
+            double_tap_config: config.double_tap.clone(),
+            double_tap: DoubleTap::default(),
+            palm_rejection_config: config.palm_rejection,
+            palm_rejection: PalmRejection::default(),
+            #[cfg(feature = "scripting")]
+            script: Self::load_script(config),
         }
     }
 
-    fn smooth_coordinates(&mut self, x: i32, y: i32) -> (i32, i32) {
-        let (smoothed_x, smoothed_y) = if self.is_mouse_mode {
-            ((self.last_x * 1 + x) / 2, (self.last_y * 1 + y) / 2)
-        } else {
-            ((self.last_x * 3 + x) / 4, (self.last_y * 3 + y) / 4)
-        };
+    #[cfg(feature = "scripting")]
+    fn load_script(config: &Config) -> Option<ScriptHooks> {
+        let path = config.script.path.as_deref()?;
+        ScriptHooks::load(path).inspect_err(|err| error!("{}", err)).ok()
+    }
 
-        self.last_x = smoothed_x;
-        self.last_y = smoothed_y;
+    pub fn is_mouse_mode(&self) -> bool {
+        self.is_mouse_mode
+    }
 
-        (smoothed_x, smoothed_y)
+    pub fn mouse_area_scale(&self) -> f32 {
+        self.mouse_area_scale
     }
 
-    pub fn syn(&mut self) -> Result<(), Error> {
-        self.virtual_keyboard.emit(&[InputEvent::new(
-            EventType::SYNCHRONIZATION,
-            Synchronization::SYN_REPORT.0,
-            0,
-        )])?;
-        self.virtual_pen.emit(&[InputEvent::new(
-            EventType::SYNCHRONIZATION,
-            Synchronization::SYN_REPORT.0,
-            0,
-        )])?;
-        Ok(())
+    // Where the mouse area lies on the tablet, as [left, top, right, bottom] fractions
+    // of the output it is mapped to, for showing it on screen.
+    pub fn mouse_area_on_screen(&self) -> [f32; 4] {
+        let area = AXIS_MAX as f32 * self.mouse_area_scale;
+        let (left, top) = self.mouse_area_origin(area);
+        let corner = |x: f32, y: f32| {
+            let (x, y) = (x.round() as i32, y.round() as i32);
+            let (x, y) = match &self.output_mapping {
+                Some(mapping) => mapping.map(x, y, AXIS_MAX),
+                None => (x, y),
+            };
+            (x as f32 / AXIS_MAX as f32, y as f32 / AXIS_MAX as f32)
+        };
+        let ((left, top), (right, bottom)) = (corner(left, top), corner(left + area, top + area));
+        [left, top, right, bottom]
     }
 
-    pub fn dispatch(&mut self, raw_data: &RawDataReader) {
-        self.emit_pen_events(raw_data);
-        self.emit_tablet_events(raw_data);
+    pub fn pressure(&self) -> i32 {
+        self.last_pressure
     }
 
-    fn emit_tablet_events(&mut self, raw_data: &RawDataReader) {
-        let raw_button_as_binary_flags = raw_data.tablet_buttons_as_binary_flags();
-        self.binary_flags_to_tablet_key_events(raw_button_as_binary_flags);
-        self.tablet_last_raw_pressed_buttons = raw_button_as_binary_flags;
+    pub fn is_pen_down(&self) -> bool {
+        self.was_touching
     }
 
-    fn virtual_keyboard_builder(tablet_emitted_keys: &[Key]) -> Result<VirtualDevice, Error> {
-        let mut key_set = AttributeSet::<Key>::new();
-        for key in tablet_emitted_keys {
-            key_set.insert(*key);
+    // Depression below rest the pen touches at, or clicks at in mouse mode.
+    pub fn touch_threshold(&self) -> i32 {
+        let response = self.pressure.for_mode(self.is_mouse_mode);
+        match self.is_mouse_mode {
+            true => response.click_threshold.unwrap_or(response.touch_threshold),
+            false => response.touch_threshold,
         }
+    }
 
-        VirtualDeviceBuilder::new()?
-            .name("virtual_tablet")
-            .with_keys(&key_set)?
-            .build()
+    // Right-clicks sent by holding the pen button, so each can be announced.
+    pub fn hold_click_count(&self) -> u32 {
+        self.hold_click_count
     }
 
-    fn binary_flags_to_tablet_key_events(&mut self, raw_button_as_flags: u16) {
-        (0..14)
-            .filter(|i| ![10, 11].contains(i))
-            .for_each(|i| self.emit_tablet_key_event(i, raw_button_as_flags));
+    pub fn set_mouse_mode(&mut self, is_mouse_mode: bool) -> Result<()> {
+        // Lift the touch or click of the old mode, which use different keys.
+        if is_mouse_mode != self.is_mouse_mode {
+            self.set_touching(false)?;
+            self.flush_pen_frame()?;
+        }
+        self.is_mouse_mode = is_mouse_mode;
+        self.relative_motion.reset();
+        self.set_mouse_button_down(false)
     }
 
-    pub fn emit_tablet_key_event(&mut self, i: u8, raw_button_as_flags: u16) {
-        let id_as_binary_mask = 1 << i;
-        let is_pressed = (raw_button_as_flags & id_as_binary_mask) == 0;
-        let was_pressed = (self.tablet_last_raw_pressed_buttons & id_as_binary_mask) == 0;
+    pub fn set_mouse_area_scale(&mut self, scale: f32) {
+        self.mouse_area_scale = scale.clamp(0.1, self.mouse_area_scale_max);
+    }
 
-        if let Some(state) = match (was_pressed, is_pressed) {
-            (false, true) => Some(Self::PRESSED),
-            (true, false) => Some(Self::RELEASED),
-            (true, true) => Some(Self::HOLD),
-            _ => None,
-        } {
-            // Button [ - Reduce mouse area
-            if i == 6 && state == Self::PRESSED {
-                self.mouse_area_scale = (self.mouse_area_scale * 0.8).max(0.1);
-                eprintln!("Mouse area reduced: {:.0}%", self.mouse_area_scale * 100.0);
-                return;
-            }
+    pub fn set_mouse_area_anchor(&mut self, anchor: MouseAreaAnchor) {
+        self.mouse_area.anchor = anchor;
+        self.mouse_area_anchor_point = self.last_touch_point;
+    }
 
-            // Button ] - Enlarge mouse area
-            if i == 13 && state == Self::PRESSED {
-                self.mouse_area_scale = (self.mouse_area_scale * 1.2).min(0.4);
-                eprintln!("Mouse area increased: {:.0}%", self.mouse_area_scale * 100.0);
-                return;
+    // Top-left corner of the mouse area, kept inside the tablet.
+    fn mouse_area_origin(&self, area: f32) -> (f32, f32) {
+        let max = AXIS_MAX as f32 - area;
+        match self.mouse_area.anchor {
+            MouseAreaAnchor::Center => (max / 2.0, max / 2.0),
+            MouseAreaAnchor::TopLeft => (0.0, 0.0),
+            MouseAreaAnchor::LastTouch => {
+                let (x, y) = self.mouse_area_anchor_point;
+                (
+                    (x as f32 - area / 2.0).clamp(0.0, max),
+                    (y as f32 - area / 2.0).clamp(0.0, max),
+                )
             }
+        }
+    }
 
-            // Toggle with B button
-            if i == 12 && state == Self::PRESSED {
-                self.is_mouse_mode = !self.is_mouse_mode;
-                eprintln!("Mode: {}", if self.is_mouse_mode { "MOUSE" } else { "TABLET" });
-                return;
-            }
+    // Swaps in the profile's overrides, or the top-level settings when `profile` is None.
+    pub fn apply_profile(&mut self, config: &Config, profile: Option<&Profile>) {
+        self.tablet_button_id_to_key_code_map = profile
+            .and_then(|profile| profile.tablet_buttons.clone())
+            .unwrap_or_else(|| config.tablet_buttons.clone());
+        self.pen_button_id_to_key_code_map = profile
+            .and_then(|profile| profile.pen_buttons.clone())
+            .unwrap_or_else(|| config.pen_buttons.clone());
+        self.pressure = profile
+            .and_then(|profile| profile.pressure.as_ref())
+            .unwrap_or(&config.pressure)
+            .clone();
+        self.tablet_area = profile
+            .and_then(|profile| profile.tablet_area)
+            .unwrap_or(config.tablet_area);
+    }
 
-            if let Some(keys) = self.tablet_button_id_to_key_code_map.get(&i) {
-                for &key in keys {
-                    self.virtual_keyboard
-                        .emit(&[InputEvent::new(EventType::KEY, key.code(), state)])
-                        .expect("Error emitting virtual keyboard key.");
-                }
+    // A profile switch asked for by a binding since the last call, which the caller
+    // applies to every tablet.
+    pub fn take_profile_switch(&mut self) -> Option<ProfileSwitch> {
+        self.profile_switch.take()
+    }
 
-                self.virtual_keyboard
-                    .emit(&[InputEvent::new(
-                        EventType::SYNCHRONIZATION,
-                        Synchronization::SYN_REPORT.0,
-                        0,
-                    )])
-                    .expect("Error emitting SYN.");
-            }
+    // Applies a reloaded config in place. The virtual devices are kept, so keys that
+    // weren't registered when they were created can't be emitted until a reconnect.
+    pub fn reload(&mut self, config: &Config, profile: Option<&Profile>) -> Result<()> {
+        let has_new_keys = config
+            .all_tablet_keys()
+            .iter()
+            .any(|key| !self.registered_tablet_keys.contains(key))
+            || config
+                .all_pen_keys()
+                .iter()
+                .any(|key| !self.registered_pen_keys.contains(key));
+        if has_new_keys {
+            warn!("New keys in the config will only work after reconnecting the tablet.");
+        }
+
+        self.output_mapping = config.output.as_deref().and_then(|output| {
+            OutputMapping::resolve(output)
+                .inspect_err(|err| warn!("{} Mapping to the whole desktop.", err))
+                .ok()
+        });
+        self.mouse_filter = FilterPipeline::new(&config.smoothing.mouse);
+        self.tablet_filter = FilterPipeline::new(&config.smoothing.tablet);
+        self.relative = config.relative.clone();
+        self.scroll = config.scroll.clone();
+        self.tilt = config.tilt.clone();
+        self.zones = config.zones.clone();
+        self.radial_menu_config = config.radial_menu.clone();
+        self.radial_menu.close();
+        self.flicks = config.flicks.clone();
+        self.double_tap_config = config.double_tap.clone();
+        self.palm_rejection_config = config.palm_rejection;
+        if config.needs_virtual_mouse() && self.virtual_mouse.is_none() {
+            self.virtual_mouse = Self::virtual_mouse_builder(&config.virtual_devices.mouse_name)
+                .inspect_err(|err| error!("Error building virtual mouse: {}", err))
+                .ok()
+                .map(|mouse| Box::new(mouse) as Box<dyn VirtualOutput>);
+        }
+        if config.multimedia.dial && self.virtual_dial.is_none() {
+            self.virtual_dial = Self::virtual_dial_builder(&config.virtual_devices.dial_name)
+                .inspect_err(|err| error!("Error building virtual dial: {}", err))
+                .ok()
+                .map(|dial| Box::new(dial) as Box<dyn VirtualOutput>);
+        }
+        self.eraser = config.eraser.clone();
+        self.hold_click = config.hold_right_click.clone();
+        self.precision = config.precision;
+        self.settle = config.settle;
+        if self.stabilizer.enabled != config.stabilizer.enabled {
+            self.set_stabilizer_active(config.stabilizer.enabled);
+        }
+        self.stabilizer = config.stabilizer;
+        self.release_multimedia_keys()?;
+        self.multimedia = config.multimedia.clone();
+        self.release_sticky_keys()?;
+        self.sticky_buttons = config.sticky_buttons.clone();
+        self.mouse_area_scale_max = config.mouse_area_scale_max.clamp(0.1, 1.0);
+        self.set_mouse_area_scale(self.mouse_area_scale);
+        self.mouse_area = config.mouse_area.clone();
+        self.tablet_area = config.tablet_area;
+        self.calibration = config.calibration.or_raw_range(config.axes.x_max, config.axes.y_max);
+        if config.axes.output_max != self.axes.output_max {
+            warn!("A new axes.output_max only takes effect after reconnecting the tablet.");
         }
+        self.axes.x_max = config.axes.x_max;
+        self.axes.y_max = config.axes.y_max;
+        self.rotation = config.rotation;
+        self.invert_x = config.invert_x;
+        self.invert_y = config.invert_y;
+        self.rotation_button = config.rotation_button;
+        self.gestures = GestureTracker::new(&config.gestures);
+        self.key_repeat = KeyRepeat::new(&config.auto_repeat);
+        self.debounce = config.debounce.clone();
+        self.chords = config.chords.clone();
+        self.layer = config.layer.clone();
+        #[cfg(feature = "scripting")]
+        {
+            self.script = Self::load_script(config);
+        }
+        self.apply_profile(config, profile);
+        if self.eraser.button.is_none() {
+            self.set_eraser_active(false)?;
+        }
+        self.flush_pen_frame()
     }
 
-    fn virtual_pen_builder(pen_emitted_keys: &[Key]) -> Result<VirtualDevice, Error> {
-        let abs_x_setup =
-            UinputAbsSetup::new(AbsoluteAxisType::ABS_X, AbsInfo::new(0, 0, 4096, 0, 0, 1));
-        let abs_y_setup =
-            UinputAbsSetup::new(AbsoluteAxisType::ABS_Y, AbsInfo::new(0, 0, 4096, 0, 0, 1));
-        let abs_pressure_setup = UinputAbsSetup::new(
-            AbsoluteAxisType::ABS_PRESSURE,
-            AbsInfo::new(0, 0, 8191, 0, 0, 1), // Cambiado a 8191
-        );
+    // Swaps the reported tool between pen and eraser so apps switch tools on their own.
+    fn set_eraser_active(&mut self, is_active: bool) -> Result<()> {
+        if is_active == self.is_eraser_active {
+            return Ok(());
+        }
+        self.is_eraser_active = is_active;
+        info!("Tool: {}", if is_active { "ERASER" } else { "PEN" });
+        // Out of range the new tool is announced on the next proximity-in.
+        if !self.is_in_proximity {
+            return Ok(());
+        }
 
-        let mut key_set = AttributeSet::<Key>::new();
-        for key in pen_emitted_keys {
-            key_set.insert(*key);
+        let (released_tool, pressed_tool) = if is_active {
+            (Key::BTN_TOOL_PEN, Key::BTN_TOOL_RUBBER)
+        } else {
+            (Key::BTN_TOOL_RUBBER, Key::BTN_TOOL_PEN)
+        };
+        self.pen_frame.extend([
+            InputEvent::new(EventType::KEY, released_tool.code(), Self::RELEASED),
+            InputEvent::new(EventType::KEY, pressed_tool.code(), Self::PRESSED),
+        ]);
+        Ok(())
+    }
+
+    // Runtime changes from `v1060p set`, kept until the config is reloaded.
+    pub fn set_tablet_area_rect(&mut self, rect: Option<[i32; 4]>) {
+        self.tablet_area.rect = rect;
+    }
+
+    pub fn set_rotation(&mut self, rotation: Rotation) {
+        self.rotation = rotation;
+    }
+
+    pub fn set_pressure_curve(&mut self, curve: PressureCurve) {
+        self.pressure.curve = curve;
+    }
+
+    // Fails if the binding needs keys the virtual pad wasn't created with.
+    pub fn set_tablet_button(
+        &mut self,
+        id: u8,
+        binding: Binding,
+    ) -> std::result::Result<(), String> {
+        if let Some(key) = binding
+            .keys()
+            .into_iter()
+            .find(|key| !self.registered_tablet_keys.contains(key))
+        {
+            return Err(format!(
+                "{:?} is not registered on the virtual pad; add it to the config and reconnect",
+                key
+            ));
         }
+        self.tablet_button_id_to_key_code_map.insert(id, binding);
+        Ok(())
+    }
 
-        for key in &[Key::BTN_TOOL_PEN, Key::BTN_LEFT, Key::BTN_RIGHT] {
-            key_set.insert(*key);
+    // Maps the pen to a monitor or desktop rectangle, as the `output` setting does.
+    pub fn set_output(&mut self, output: &str) {
+        match OutputMapping::resolve(output) {
+            Ok(mapping) => self.output_mapping = Some(mapping),
+            Err(err) => warn!("{} Keeping the current mapping.", err),
         }
+    }
 
-        VirtualDeviceBuilder::new()?
-            .name("virtual_tablet")
-            .with_absolute_axis(&abs_x_setup)?
-            .with_absolute_axis(&abs_y_setup)?
-            .with_absolute_axis(&abs_pressure_setup)?
-            .with_keys(&key_set)?
-            .build()
+    fn set_precision_active(&mut self, is_active: bool) {
+        if is_active != self.is_precision_active {
+            self.is_precision_active = is_active;
+            self.precision_anchor = None;
+            info!("Precision: {}", if is_active { "ON" } else { "OFF" });
+        }
     }
 
-    fn emit_pen_events(&mut self, raw_data: &RawDataReader) {
-        let y_raw = raw_data.y_axis();
-        let is_multimedia_area = y_raw >= 61000;
+    pub fn is_stabilizer_active(&self) -> bool {
+        self.is_stabilizer_active
+    }
 
-        if !is_multimedia_area {
-            self.last_valid_x = raw_data.x_axis();
+    pub fn set_stabilizer_active(&mut self, is_active: bool) {
+        if is_active != self.is_stabilizer_active {
+            self.is_stabilizer_active = is_active;
+            self.brush = None;
+            info!("Stabilizer: {}", if is_active { "ON" } else { "OFF" });
+        }
+    }
+
+    // Pulls the brush along behind the pen once it is more than the radius away. Out
+    // of a stroke the brush is left where the pen is, so the next one starts there.
+    fn apply_stabilizer(&mut self, x: i32, y: i32, is_touching: bool) -> (i32, i32) {
+        if !self.is_stabilizer_active || !is_touching {
+            self.brush = None;
+            return (x, y);
         }
+        let (brush_x, brush_y) = self.brush.get_or_insert((x as f32, y as f32));
+        let (dx, dy) = (x as f32 - *brush_x, y as f32 - *brush_y);
+        let distance = dx.hypot(dy);
+        let radius = self.stabilizer.radius.max(0.0);
+        if distance > radius {
+            let pull = (distance - radius) / distance;
+            *brush_x += dx * pull;
+            *brush_y += dy * pull;
+        }
+        (brush_x.round() as i32, brush_y.round() as i32)
+    }
 
-        let raw_pen_buttons = raw_data.pen_buttons();
-        self.raw_pen_buttons_to_pen_key_events(raw_pen_buttons);
-        self.pen_last_raw_pressed_button = raw_pen_buttons;
+    // Scales movement around the anchor down by the precision zoom.
+    fn apply_precision(&mut self, x: i32, y: i32) -> (i32, i32) {
+        if !self.is_precision_active {
+            return (x, y);
+        }
+        let (anchor_x, anchor_y) = *self.precision_anchor.get_or_insert((x, y));
+        let zoom = self.precision.zoom.max(1.0);
+        let scale = |axis: i32, anchor: i32| {
+            (anchor + ((axis - anchor) as f32 / zoom).round() as i32).clamp(0, AXIS_MAX)
+        };
+        (scale(x, anchor_x), scale(y, anchor_y))
+    }
 
-        // Pressure normalization by mode
-        let normalized_pressure = if self.is_mouse_mode {
-            Self::normalize_pressure_mode(raw_data.pressure(), 800, 2)
+    fn current_tool(&self) -> Key {
+        if self.is_eraser_active {
+            Key::BTN_TOOL_RUBBER
         } else {
-            Self::normalize_pressure_mode(raw_data.pressure(), 510, 3)
+            Key::BTN_TOOL_PEN
+        }
+    }
+
+    // Asserts the tool key while the pen hovers or touches, in the frame with its first
+    // position, and releases it when the pen leaves, after a frame lifting BTN_TOUCH.
+    fn set_proximity(&mut self, is_in_proximity: bool) -> Result<()> {
+        if is_in_proximity == self.is_in_proximity {
+            return Ok(());
+        }
+        self.is_in_proximity = is_in_proximity;
+
+        if !is_in_proximity {
+            self.mouse_filter.reset();
+            self.tablet_filter.reset();
+            self.relative_motion.reset();
+            self.tilt_emulation.reset();
+            self.is_hold_click_fired = false;
+            self.last_pressure = 0;
+            // Like scrolling, since a held pen button's release isn't seen out of range.
+            self.set_precision_active(false);
+            self.set_mouse_button_down(false)?;
+            self.release_multimedia_keys()?;
+            self.set_touching(false)?;
+            self.emit_distance(MAX_DISTANCE);
+            self.flush_pen_frame()?;
+            // The pen byte reads 0 out of range, so a held button's release is never seen.
+            self.is_scrolling = false;
+        }
+
+        let state = if is_in_proximity { Self::PRESSED } else { Self::RELEASED };
+        self.pen_frame
+            .push(InputEvent::new(EventType::KEY, self.current_tool().code(), state));
+        Ok(())
+    }
+
+    // Called when the tablet stops sending reports, which it does once the pen is out of range.
+    pub fn leave_proximity(&mut self) -> Result<()> {
+        let result = self.set_proximity(false);
+        result.and(self.flush_pen_frame())
+    }
+
+    // Lets go of everything held, as if the pen left and every button was released,
+    // then removes the virtual devices. Used when the tablet goes away or the driver
+    // exits, so no key or click stays stuck.
+    pub fn shutdown(&mut self) -> Result<()> {
+        // Releasing a button mid-gesture must not fire it.
+        for i in 0..16 {
+            self.gestures.cancel(i);
+            self.key_repeat.release(i);
+        }
+        let pen_buttons_result = match self.pen_last_raw_pressed_button {
+            0 => Ok(()),
+            _ => self.raw_pen_buttons_to_pen_key_events(2),
         };
+        self.pen_last_raw_pressed_button = 0;
+        let pen_result = pen_buttons_result.and(self.leave_proximity());
+        let tablet_result = self.binary_flags_to_tablet_key_events(0xFFFF);
+        self.tablet_last_raw_pressed_buttons = 0xFFFF;
+        self.tablet_raw_buttons = 0xFFFF;
+        let tablet_result = tablet_result.and(self.release_sticky_keys());
+        // Whatever is still down, e.g. from a script.
+        let tablet_result = tablet_result.and(self.release_all_keys());
+        let release_result = pen_result.and(tablet_result);
 
-        let (smoothed_x, smoothed_y) = if is_multimedia_area {
-            (self.last_valid_x, 0) // Multimedia area: last X, top Y
+        let mut destroy_result = self.virtual_pen.destroy();
+        destroy_result = destroy_result.and(self.virtual_keyboard.destroy());
+        if let Some(virtual_mouse) = &mut self.virtual_mouse {
+            destroy_result = destroy_result.and(virtual_mouse.destroy());
+        }
+        if let Some(virtual_dial) = &mut self.virtual_dial {
+            destroy_result = destroy_result.and(virtual_dial.destroy());
+        }
+        release_result.and(destroy_result.map_err(Into::into))
+    }
+
+    fn is_multimedia_area(&self, raw_data: &RawDataReader) -> bool {
+        self.multimedia.enabled && raw_data.y_axis() >= self.multimedia.boundary
+    }
+
+    // Raw pen coordinates with the calibration and mirroring applied.
+    fn pen_position(&self, raw_data: &RawDataReader) -> (i32, i32) {
+        let (x, y) = self
+            .calibration
+            .map_position(raw_data.x_axis(), raw_data.signed_y_axis());
+        (
+            if self.invert_x { AXIS_MAX - x } else { x },
+            if self.invert_y { AXIS_MAX - y } else { y },
+        )
+    }
+
+    fn smooth_coordinates(&mut self, x: i32, y: i32) -> (i32, i32) {
+        if self.is_mouse_mode {
+            self.mouse_filter.apply(x, y)
         } else {
-            self.smooth_coordinates(raw_data.x_axis(), raw_data.y_axis())
-        };
+            self.tablet_filter.apply(x, y)
+        }
+    }
 
-        self.raw_pen_abs_to_pen_abs_events(
-            smoothed_x,
-            smoothed_y,
-            normalized_pressure,
-            is_multimedia_area
-        );
+    // Sends the queued pen events in a single write, so X, Y and pressure change together.
+    fn flush_pen_frame(&mut self) -> Result<()> {
+        if self.pen_frame.is_empty() {
+            return Ok(());
+        }
+        // A pen hovering still sends the same position over and over; a frame with
+        // nothing new isn't sent at all.
+        let mut frame = std::mem::take(&mut self.pen_frame);
+        frame.retain(|event| {
+            event.event_type() != EventType::ABSOLUTE
+                || self.sent_pen_axes.insert(event.code(), event.value()) != Some(event.value())
+        });
+        if frame.is_empty() {
+            return Ok(());
+        }
+        frame.push(self.timestamp_event());
+        if let Err(err) = self.virtual_pen.emit(&frame) {
+            self.sent_pen_axes.clear();
+            return Err(err.into());
+        }
+        Ok(())
+    }
 
-        self.pen_emit_touch(raw_data);
+    // The kernel's MSC_TIMESTAMP wraps around as a u32 of microseconds.
+    fn timestamp_event(&mut self) -> InputEvent {
+        let read_at = self.frame_read_at.unwrap_or_else(Instant::now);
+        let epoch = *self.timestamp_epoch.get_or_insert(read_at);
+        let micros = read_at.saturating_duration_since(epoch).as_micros() as u32;
+        InputEvent::new(EventType::MISC, MiscType::MSC_TIMESTAMP.0, micros as i32)
     }
 
-    fn normalize_pressure_mode(raw_pressure: i32, threshold: i32, scaling: i32) -> i32 {
-        match 2000 - raw_pressure {
-            x if x <= threshold => 0,
-            x => x * scaling,
+    pub fn dispatch(&mut self, raw_data: &RawDataReader) -> Result<()> {
+        self.frame_read_at = raw_data.read_at;
+        // Run both halves even if one fails, so button state keeps tracking the hardware.
+        let pen_result = self.emit_pen_events(raw_data);
+        let pen_result = pen_result.and(self.flush_pen_frame());
+        // Sticky keys are let go once the tap they were for is over.
+        let pen_result = match self.has_sticky_touch && !self.was_touching {
+            true => pen_result.and(self.release_sticky_keys()),
+            false => pen_result,
+        };
+        #[cfg(feature = "scripting")]
+        self.update_script_pen(raw_data);
+        let tablet_result = self.tick().and(self.emit_tablet_events(raw_data));
+        #[cfg(feature = "scripting")]
+        let tablet_result = tablet_result.and(self.run_script_report());
+        let result = pen_result.and(tablet_result);
+        self.frame_read_at = None;
+        // A key whose release failed to go out, or never came, would stay down.
+        if result.is_err() {
+            let _ = self.release_all_keys();
         }
+        result
     }
 
-    fn raw_pen_abs_to_pen_abs_events(&mut self, x_axis: i32, y_axis: i32, pressure: i32, is_multimedia_area: bool) {
-        let (x, y) = if is_multimedia_area {
-            (self.last_valid_x, 0) // Use last valid X and top position
-        } else if self.is_mouse_mode {
-            let center_x = 1024;
-            let center_y = 2048;
-            let range = (4096.0 * self.mouse_area_scale) as i32;
-            let scale_factor = 4096 / range.max(1);
+    #[cfg(feature = "scripting")]
+    fn update_script_pen(&mut self, raw_data: &RawDataReader) {
+        let Some(script) = &mut self.script else {
+            return;
+        };
+        let flags = raw_data.tablet_buttons_as_binary_flags();
+        let pen_button = raw_data.pen_buttons();
+        let fraction = |value: i32, max: i32| (value as f64 / max.max(1) as f64).clamp(0.0, 1.0);
+        script.pen = PenState {
+            pressure: fraction(self.last_pressure, crate::pressure::MAX_PRESSURE),
+            x: fraction(raw_data.x_axis(), self.axes.x_max),
+            y: fraction(raw_data.y_axis(), self.axes.y_max),
+            is_in_range: pen_button != 0,
+            is_touching: self.was_touching,
+            tablet_buttons: (0..14).filter(|i| flags & (1 << i) == 0).collect(),
+            pen_buttons: [4, 6].into_iter().filter(|&id| pen_button == id).collect(),
+            is_mouse_mode: self.is_mouse_mode,
+        };
+    }
 
-            let scaled_x = ((x_axis - center_x) * scale_factor) + 2048;
-            let scaled_y = ((y_axis - center_y) * scale_factor) + 2048;
+    #[cfg(feature = "scripting")]
+    fn run_script_report(&mut self) -> Result<()> {
+        if let Some(script) = &mut self.script {
+            script.on_report();
+        }
+        self.run_script_actions(0)
+    }
 
-            (scaled_x.clamp(0, 4096), scaled_y.clamp(0, 4096))
-        } else {
-            (x_axis, y_axis.clamp(0, 4095))
+    // Whether the script took the button, in which case it does nothing else.
+    #[cfg(feature = "scripting")]
+    fn run_script_button(&mut self, i: u8, state: i32) -> Result<bool> {
+        let Some(script) = &mut self.script else {
+            return Ok(false);
         };
+        let is_taken = match state {
+            Self::HOLD => script.is_consumed(i),
+            _ => script.on_button(i, state == Self::PRESSED),
+        };
+        self.run_script_actions(1 << i)?;
+        Ok(is_taken)
+    }
 
-        self.virtual_pen.emit(&[InputEvent::new(
-            EventType::ABSOLUTE,
-            AbsoluteAxisType::ABS_X.0,
-            x,
-        )]).expect("Error emitting ABS_X.");
+    // `buttons` is the mask of the button the hook ran for, as for bindings.
+    #[cfg(feature = "scripting")]
+    fn run_script_actions(&mut self, buttons: u16) -> Result<()> {
+        let actions = self.script.as_mut().map(ScriptHooks::take_actions).unwrap_or_default();
+        let mut result = Ok(());
+        for action in actions {
+            result = result.and(match action {
+                Action::Run(binding) => self.run_binding_once(buttons, &binding),
+                Action::Press(keys) => self.emit_keyboard_keys(&keys, Self::PRESSED),
+                Action::Release(keys) => self.emit_keyboard_keys(&keys, Self::RELEASED),
+            });
+        }
+        result
+    }
 
-        self.virtual_pen.emit(&[InputEvent::new(
-            EventType::ABSOLUTE,
-            AbsoluteAxisType::ABS_Y.0,
-            y,
-        )]).expect("Error emitting ABS_Y.");
+    // Fires long and short presses that were waiting on a timeout, and repeats held
+    // keys. Called for every report and whenever a read times out, since the tablet
+    // goes quiet while idle.
+    pub fn tick(&mut self) -> Result<()> {
+        let now = Instant::now();
+        let mut result = Ok(());
+        for (i, gesture) in self.gestures.poll(now) {
+            result = result.and(self.run_gesture(i, gesture));
+        }
+        // A fresh press and release, since applications ignore value-2 repeats.
+        for key in self.key_repeat.poll(now) {
+            result = result.and(self.emit_keyboard_keys(&[key], Self::RELEASED));
+            result = result.and(self.emit_keyboard_keys(&[key], Self::PRESSED));
+        }
+        // A change held back by debouncing still counts if the tablet then goes quiet.
+        let flags = self.debounced_buttons(now);
+        if flags != self.tablet_last_raw_pressed_buttons {
+            result = result.and(self.binary_flags_to_tablet_key_events(flags));
+            self.tablet_last_raw_pressed_buttons = flags;
+        }
+        result
+    }
 
-        self.virtual_pen.emit(&[InputEvent::new(
-            EventType::ABSOLUTE,
-            AbsoluteAxisType::ABS_PRESSURE.0,
-            pressure,
-        )]).expect("Error emitting Pressure.");
+    // The last read buttons, keeping the previous state of any button that changed
+    // within its debounce window.
+    fn debounced_buttons(&mut self, now: Instant) -> u16 {
+        let mut flags = self.tablet_last_raw_pressed_buttons;
+        for i in 0..16 {
+            let mask = 1 << i;
+            if (self.tablet_raw_buttons ^ flags) & mask == 0 {
+                continue;
+            }
+            let window = self.debounce.window(i);
+            if self.button_changed_at.get(&i).is_some_and(|at| now < *at + window) {
+                continue;
+            }
+            flags ^= mask;
+            self.button_changed_at.insert(i, now);
+        }
+        flags
     }
 
-    fn pen_emit_touch(&mut self, raw_data: &RawDataReader) {
-        let normalized_pressure = if self.is_mouse_mode {
-            Self::normalize_pressure_mode(raw_data.pressure(), 800, 2)
-        } else {
-            Self::normalize_pressure_mode(raw_data.pressure(), 510, 3)
-        };
+    // When `tick` next needs to run, while a button gesture is undecided, a key is
+    // repeating or a button change is being debounced.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        let changed = self.tablet_raw_buttons ^ self.tablet_last_raw_pressed_buttons;
+        let debounce_deadline = (0..16)
+            .filter(|i| changed & (1 << i) != 0)
+            .filter_map(|i| Some(*self.button_changed_at.get(&i)? + self.debounce.window(i)))
+            .min();
+        [self.gestures.next_deadline(), self.key_repeat.next_deadline(), debounce_deadline]
+            .into_iter()
+            .flatten()
+            .min()
+    }
 
-        let is_touching = normalized_pressure > 0;
-        if let Some(state) = match (self.was_touching, is_touching) {
-            (false, true) => Some(Self::PRESSED),
-            (true, false) => Some(Self::RELEASED),
-            _ => None,
-        } {
-            self.virtual_pen.emit(&[InputEvent::new(
-                EventType::KEY,
-                Key::BTN_TOUCH.code(),
-                state,
-            )]).expect("Error emitting Touch");
+    fn emit_tablet_events(&mut self, raw_data: &RawDataReader) -> Result<()> {
+        self.tablet_raw_buttons = raw_data.tablet_buttons_as_binary_flags();
+        let raw_button_as_binary_flags = self.debounced_buttons(Instant::now());
+        let result = self.binary_flags_to_tablet_key_events(raw_button_as_binary_flags);
+        self.tablet_last_raw_pressed_buttons = raw_button_as_binary_flags;
+        result
+    }
+
+    fn virtual_keyboard_builder(keys: &[Key], name: &str) -> io::Result<VirtualDevice> {
+        let mut key_set = AttributeSet::<Key>::new();
+        for key in keys {
+            key_set.insert(*key);
         }
-        self.was_touching = is_touching;
+
+        VirtualDeviceBuilder::new()?
+            .name(name)
+            .input_id(input_id())
+            .with_keys(&key_set)?
+            .build()
     }
 
-    fn raw_pen_buttons_to_pen_key_events(&mut self, pen_button: u8) {
-        if let Some((state, id)) = match (self.pen_last_raw_pressed_button, pen_button) {
-            (2, x) if x == 6 || x == 4 => Some((Self::PRESSED, x)),
-            (x, 2) if x == 6 || x == 4 => Some((Self::RELEASED, x)),
-            (x, y) if x != 2 && x == y => Some((Self::HOLD, x)),
+    fn binary_flags_to_tablet_key_events(&mut self, raw_button_as_flags: u16) -> Result<()> {
+        // Keep going after a failed button so the others still get their events.
+        let mut result = self.emit_chord_events(raw_button_as_flags);
+        for i in (0..14).filter(|i| ![10, 11].contains(i)) {
+            if self.chord_buttons & (1 << i) == 0 {
+                result = result.and(self.emit_tablet_key_event(i, raw_button_as_flags));
+            }
+        }
+        // Chord buttons stay muted until let go, so releasing a chord one button at a
+        // time doesn't fire the buttons still held.
+        self.chord_buttons &= !raw_button_as_flags;
+        result
+    }
+
+    fn emit_chord_events(&mut self, raw_button_as_flags: u16) -> Result<()> {
+        let mut result = Ok(());
+        for chord in self.chords.clone() {
+            let mask = chord.mask();
+            let is_active = raw_button_as_flags & mask == 0;
+            let was_active = self.tablet_last_raw_pressed_buttons & mask == 0;
+            if is_active && !was_active && self.chord_buttons & mask == 0 {
+                // A button pressed a report early may already be holding its own keys.
+                for &i in &chord.buttons {
+                    self.gestures.cancel(i);
+                    self.key_repeat.release(i);
+                    let was_pressed = self.tablet_last_raw_pressed_buttons & (1 << i) == 0;
+                    if let (true, Some(Binding::Keys(keys))) =
+                        (was_pressed, self.tablet_button_id_to_key_code_map.get(&i))
+                    {
+                        let keys = keys.clone();
+                        result = result.and(self.emit_keyboard_keys(&keys, Self::RELEASED));
+                    }
+                }
+                self.chord_buttons |= mask;
+                result = result.and(match &chord.binding {
+                    Binding::Keys(keys) => self.emit_keyboard_keys(keys, Self::PRESSED),
+                    binding => self.run_binding_once(mask, binding),
+                });
+            } else if was_active
+                && !is_active
+                && let Binding::Keys(keys) = &chord.binding
+            {
+                result = result.and(self.emit_keyboard_keys(keys, Self::RELEASED));
+            }
+        }
+        result
+    }
+
+    pub fn emit_tablet_key_event(&mut self, i: u8, raw_button_as_flags: u16) -> Result<()> {
+        let id_as_binary_mask = 1 << i;
+        let is_pressed = (raw_button_as_flags & id_as_binary_mask) == 0;
+        let was_pressed = (self.tablet_last_raw_pressed_buttons & id_as_binary_mask) == 0;
+
+        if let Some(state) = match (was_pressed, is_pressed) {
+            (false, true) => Some(Self::PRESSED),
+            (true, false) => Some(Self::RELEASED),
+            (true, true) => Some(Self::HOLD),
             _ => None,
         } {
-            if let Some(keys) = self.pen_button_id_to_key_code_map.get(&id) {
-                for key in keys {
-                    self.virtual_pen
-                        .emit(&[InputEvent::new(EventType::KEY, key.code(), state)])
-                        .expect("Error emitting pen keys.")
+            #[cfg(feature = "scripting")]
+            if self.run_script_button(i, state)? {
+                return Ok(());
+            }
+
+            if self.radial_menu_config.button == Some(i) {
+                match state {
+                    Self::PRESSED => self.radial_menu.open(),
+                    Self::RELEASED => return self.run_radial_menu_item(),
+                    _ => {}
+                }
+                return Ok(());
+            }
+
+            if self.mouse_area.cycle_button == Some(i) {
+                if state == Self::PRESSED {
+                    self.set_mouse_area_anchor(self.mouse_area.anchor.next());
+                    info!("Mouse area anchor: {:?}", self.mouse_area.anchor);
+                }
+                return Ok(());
+            }
+
+            if self.rotation_button == Some(i) {
+                if state == Self::PRESSED {
+                    self.rotation = self.rotation.flipped();
+                    info!("Rotation: {}°", self.rotation.degrees());
+                }
+                return Ok(());
+            }
+
+            if self.layer.button == Some(i) {
+                match state {
+                    Self::PRESSED => self.is_layer_active = true,
+                    Self::RELEASED => self.is_layer_active = false,
+                    _ => {}
+                }
+                return Ok(());
+            }
+
+            if state == Self::PRESSED {
+                let is_layered = self.is_layer_active && self.layer.tablet_buttons.contains_key(&i);
+                self.layer_buttons = match is_layered {
+                    true => self.layer_buttons | id_as_binary_mask,
+                    false => self.layer_buttons & !id_as_binary_mask,
+                };
+            }
+            // Layer bindings take over gestures too.
+            let is_layered = self.layer_buttons & id_as_binary_mask != 0;
+
+            if self.gestures.handles(i) && !is_layered {
+                let now = Instant::now();
+                let gesture = match state {
+                    Self::PRESSED => self.gestures.press(i, now),
+                    Self::RELEASED => self.gestures.release(i, now),
+                    _ => None,
+                };
+                return match gesture {
+                    Some(gesture) => self.run_gesture(i, gesture),
+                    None => Ok(()),
+                };
+            }
+
+            let bindings = match is_layered {
+                true => &self.layer.tablet_buttons,
+                false => &self.tablet_button_id_to_key_code_map,
+            };
+            match bindings.get(&i) {
+                Some(Binding::Precision) => match state {
+                    Self::PRESSED => self.set_precision_active(true),
+                    Self::RELEASED => self.set_precision_active(false),
+                    _ => {}
+                },
+                Some(Binding::Keys(keys)) if self.sticky_buttons.contains(&i) => {
+                    let keys = keys.clone();
+                    if state == Self::PRESSED {
+                        self.toggle_sticky_keys(i, keys)?;
+                    }
+                }
+                Some(Binding::Keys(keys)) => {
+                    let keys = keys.clone();
+                    if self.key_repeat.handles(i) {
+                        match (state, keys.last()) {
+                            (Self::PRESSED, Some(&key)) => {
+                                self.key_repeat.press(i, key, Instant::now())
+                            }
+                            (Self::RELEASED, _) => self.key_repeat.release(i),
+                            // `tick` sends the repeats instead.
+                            _ => return Ok(()),
+                        }
+                    }
+                    self.emit_keyboard_keys(&keys, state)?;
+                }
+                // Macros and commands run once per press; holding or releasing does nothing.
+                Some(binding) if state == Self::PRESSED => {
+                    let binding = binding.clone();
+                    self.run_binding_once(1 << i, &binding)?;
                 }
+                _ => {}
             }
         }
+        Ok(())
+    }
+
+    // A press latches the button's keys, and another one lets go of them early.
+    fn toggle_sticky_keys(&mut self, i: u8, keys: Vec<Key>) -> Result<()> {
+        match self.sticky_keys.remove(&i) {
+            Some(keys) => {
+                info!("Sticky button {} released.", i);
+                self.emit_keyboard_keys(&keys, Self::RELEASED)
+            }
+            None => {
+                info!("Sticky button {} held until the next tap.", i);
+                self.emit_keyboard_keys(&keys, Self::PRESSED)?;
+                self.sticky_keys.insert(i, keys);
+                Ok(())
+            }
+        }
+    }
+
+    fn release_sticky_keys(&mut self) -> Result<()> {
+        self.has_sticky_touch = false;
+        let mut result = Ok(());
+        for (_, keys) in std::mem::take(&mut self.sticky_keys) {
+            result = result.and(self.emit_keyboard_keys(&keys, Self::RELEASED));
+        }
+        result
+    }
+
+    fn run_radial_menu_item(&mut self) -> Result<()> {
+        let item = self.radial_menu.close().and_then(|i| self.radial_menu_config.items.get(i));
+        let Some(item) = item.cloned() else {
+            return Ok(());
+        };
+        info!("Radial menu: {}", item.label);
+        let button = self.radial_menu_config.button.unwrap_or_default();
+        self.run_binding_once(1 << button, &item.action)
+    }
+
+    // The radial menu, for showing it on screen.
+    pub fn radial_menu(&self) -> &RadialMenu {
+        &self.radial_menu
+    }
+
+    fn run_gesture(&mut self, i: u8, gesture: Gesture) -> Result<()> {
+        let binding = match gesture {
+            Gesture::Short => self.tablet_button_id_to_key_code_map.get(&i),
+            _ => self.gestures.binding(i, gesture),
+        };
+        match binding.cloned() {
+            Some(binding) => self.run_binding_once(1 << i, &binding),
+            None => Ok(()),
+        }
+    }
+
+    // Runs a binding as a single action: key combinations are tapped rather than held.
+    // `buttons` is the mask of the button or chord that triggered it.
+    fn run_binding_once(&mut self, buttons: u16, binding: &Binding) -> Result<()> {
+        match binding {
+            Binding::Keys(keys) => {
+                self.emit_keyboard_keys(keys, Self::PRESSED)?;
+                self.emit_keyboard_keys(keys, Self::RELEASED)?;
+            }
+            Binding::Macro { steps } => {
+                binding::play_macro(steps, |keys, state| self.emit_keyboard_keys(keys, state))?;
+            }
+            Binding::Eraser => self.set_eraser_active(!self.is_eraser_active)?,
+            Binding::Precision => self.set_precision_active(!self.is_precision_active),
+            Binding::Stabilizer => self.set_stabilizer_active(!self.is_stabilizer_active),
+            Binding::ShrinkArea => {
+                self.mouse_area_scale = (self.mouse_area_scale * 0.8).max(0.1);
+                info!("Mouse area reduced: {:.0}%", self.mouse_area_scale * 100.0);
+            }
+            Binding::GrowArea => {
+                let scale = self.mouse_area_scale * 1.2;
+                self.mouse_area_scale = scale.min(self.mouse_area_scale_max);
+                info!("Mouse area increased: {:.0}%", self.mouse_area_scale * 100.0);
+            }
+            Binding::ToggleMode => {
+                self.set_mouse_mode(!self.is_mouse_mode)?;
+                info!("Mode: {}", if self.is_mouse_mode { "MOUSE" } else { "TABLET" });
+            }
+            Binding::Profile(switch) => self.profile_switch = Some(switch.clone()),
+            Binding::NextMonitor => {
+                match OutputMapping::next_monitor(self.output_mapping.as_ref()) {
+                    Some((name, mapping)) => {
+                        self.output_mapping = Some(mapping);
+                        info!("Output: {}", name);
+                    }
+                    None => warn!("Could not query the monitor layout."),
+                }
+            }
+            Binding::Exec { exec } => {
+                let now = Instant::now();
+                let is_repeat = self.last_command_launch.get(&buttons).is_some_and(|last| {
+                    now.duration_since(*last) < Self::COMMAND_REPEAT_INTERVAL
+                });
+                if !is_repeat {
+                    self.last_command_launch.insert(buttons, now);
+                    if let Err(err) = binding::spawn_command(exec) {
+                        error!("Error running `{}`: {}", exec, err);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn emit_keyboard_keys(&mut self, keys: &[Key], state: i32) -> Result<()> {
+        let events: Vec<InputEvent> = keys
+            .iter()
+            .map(|key| InputEvent::new(EventType::KEY, key.code(), state))
+            .collect();
+        self.virtual_keyboard.emit(&events)?;
+        match state {
+            Self::PRESSED => {
+                for key in keys {
+                    if !self.pressed_keys.contains(key) {
+                        self.pressed_keys.push(*key);
+                    }
+                }
+            }
+            Self::RELEASED => self.pressed_keys.retain(|key| !keys.contains(key)),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    // Lets go of every key still down on the virtual keyboard, last pressed first, so
+    // none stays stuck after an error or once the tablet is gone.
+    pub fn release_all_keys(&mut self) -> Result<()> {
+        let keys: Vec<Key> = self.pressed_keys.iter().rev().copied().collect();
+        if keys.is_empty() {
+            return Ok(());
+        }
+        debug!("Releasing {:?}.", keys);
+        self.sticky_keys.clear();
+        self.emit_keyboard_keys(&keys, Self::RELEASED)
+    }
+
+    fn virtual_pen_builder(
+        pen_emitted_keys: &[Key],
+        config: &VirtualDevicesConfig,
+        output_max: i32,
+    ) -> io::Result<VirtualDevice> {
+        // Units per mm over the 10x6 inch active area, which libinput uses for the
+        // tablet's size and pointer acceleration.
+        let resolution = |size_mm: i32| (output_max / size_mm).max(1);
+        let abs_x_setup = UinputAbsSetup::new(
+            AbsoluteAxisType::ABS_X,
+            AbsInfo::new(0, 0, output_max, 0, 0, resolution(254)),
+        );
+        let abs_y_setup = UinputAbsSetup::new(
+            AbsoluteAxisType::ABS_Y,
+            AbsInfo::new(0, 0, output_max, 0, 0, resolution(152)),
+        );
+        let abs_pressure_setup = UinputAbsSetup::new(
+            AbsoluteAxisType::ABS_PRESSURE,
+            AbsInfo::new(0, 0, 8191, 0, 0, 1), // Cambiado a 8191
+        );
+        let abs_distance_setup = UinputAbsSetup::new(
+            AbsoluteAxisType::ABS_DISTANCE,
+            AbsInfo::new(MAX_DISTANCE, 0, MAX_DISTANCE, 0, 0, 0),
+        );
+
+        // In degrees; the resolution is in units per radian.
+        let tilt_info = AbsInfo::new(0, TILT_MIN, TILT_MAX, 0, 0, 57);
+        let abs_tilt_x_setup = UinputAbsSetup::new(AbsoluteAxisType::ABS_TILT_X, tilt_info);
+        let abs_tilt_y_setup = UinputAbsSetup::new(AbsoluteAxisType::ABS_TILT_Y, tilt_info);
+
+        let mut key_set = AttributeSet::<Key>::new();
+        for key in pen_emitted_keys {
+            key_set.insert(*key);
+        }
+
+        for key in &[
+            Key::BTN_TOOL_PEN,
+            Key::BTN_TOOL_RUBBER,
+            Key::BTN_TOUCH,
+            Key::BTN_LEFT,
+            Key::BTN_RIGHT,
+        ] {
+            key_set.insert(*key);
+        }
+
+        let mut misc_set = AttributeSet::<MiscType>::new();
+        misc_set.insert(MiscType::MSC_TIMESTAMP);
+
+        let mut properties = AttributeSet::<PropType>::new();
+        properties.insert(match config.input_property {
+            InputProperty::Pointer => PropType::POINTER,
+            InputProperty::Direct => PropType::DIRECT,
+        });
+
+        VirtualDeviceBuilder::new()?
+            .name(&config.pen_name)
+            .input_id(input_id())
+            .with_properties(&properties)?
+            .with_msc(&misc_set)?
+            .with_absolute_axis(&abs_x_setup)?
+            .with_absolute_axis(&abs_y_setup)?
+            .with_absolute_axis(&abs_pressure_setup)?
+            .with_absolute_axis(&abs_distance_setup)?
+            .with_absolute_axis(&abs_tilt_x_setup)?
+            .with_absolute_axis(&abs_tilt_y_setup)?
+            .with_keys(&key_set)?
+            .build()
+    }
+
+    fn emit_pen_events(&mut self, raw_data: &RawDataReader) -> Result<()> {
+        let is_multimedia_area = self.is_multimedia_area(raw_data);
+
+        if !is_multimedia_area {
+            self.last_valid_x = self.pen_position(raw_data).0;
+        }
+
+        let raw_pen_buttons = raw_data.pen_buttons();
+        let buttons_result = self.raw_pen_buttons_to_pen_key_events(raw_pen_buttons);
+        self.pen_last_raw_pressed_button = raw_pen_buttons;
+        buttons_result?;
+
+        // The pen status byte is 0 when the pen is out of range; its coordinates are stale.
+        let is_in_range = raw_pen_buttons != 0;
+        if is_in_range && !self.is_in_proximity && self.settling_reports < self.settle_count() {
+            self.settling_reports += 1;
+            return Ok(());
+        }
+        self.settling_reports = 0;
+        self.set_proximity(is_in_range)?;
+        if !self.is_in_proximity {
+            return Ok(());
+        }
+        if self.pressure.learn {
+            let is_touching = self.is_touching(raw_data);
+            self.pressure_learner.observe(raw_data.pressure(), is_touching);
+        }
+
+        // The pen picks a slice of an open radial menu instead of moving.
+        if self.radial_menu.is_open() {
+            let (x, y) = self.pen_position(raw_data);
+            let (x, y) = self.rotation.apply(x, y, AXIS_MAX);
+            self.radial_menu.track(&self.radial_menu_config, x, y);
+            self.set_touching(false)?;
+            return self.set_mouse_button_down(false);
+        }
+
+        // A touch landing on a zone drives it instead of the pen until lifted.
+        let (x, y) = (raw_data.x_axis(), raw_data.y_axis());
+        let is_touching = self.is_touching(raw_data);
+        if let Some((zone, steps)) = self.zone_tracker.update(&self.zones, x, y, is_touching) {
+            self.set_touching(false)?;
+            self.set_mouse_button_down(false)?;
+            return self.emit_zone_steps(zone, steps);
+        }
+
+        if is_multimedia_area && self.multimedia.dial && self.virtual_dial.is_some() {
+            self.set_touching(false)?;
+            self.set_mouse_button_down(false)?;
+            return self.emit_dial_events(raw_data);
+        }
+        self.dial_position = None;
+
+        if self.is_scrolling && self.virtual_mouse.is_some() {
+            return self.emit_scroll_events(raw_data);
+        }
+
+        if self.is_mouse_mode && self.relative.enabled && self.virtual_mouse.is_some() {
+            return self.emit_relative_events(raw_data);
+        }
+
+        let is_touching = self.is_touching(raw_data);
+        let is_settling = self.is_touch_settling(is_touching);
+        let is_touching = is_touching && !is_settling;
+
+        // Pressure normalization by mode
+        let normalized_pressure = self.normalized_pressure(raw_data, is_touching);
+        let curved_pressure = self.pressure.curve.apply(normalized_pressure);
+        let curved_pressure = if is_settling { 0 } else { curved_pressure };
+        // Taps on a mapped icon strip trigger its keys instead of touching the screen.
+        let is_on_icon_strip = is_multimedia_area && !self.multimedia.icons.is_empty();
+        let curved_pressure = if is_on_icon_strip { 0 } else { curved_pressure };
+
+        let (smoothed_x, smoothed_y) = if is_multimedia_area {
+            (self.last_valid_x, 0) // Multimedia area: last X, top Y
+        } else {
+            let (x, y) = self.pen_position(raw_data);
+            let (x, y) = self.smooth_coordinates(x, y);
+            self.apply_stabilizer(x, y, is_touching)
+        };
+        let (oriented_x, oriented_y) = self.rotation.apply(smoothed_x, smoothed_y, AXIS_MAX);
+        let is_palm = !is_multimedia_area
+            && self.palm_rejection.rejects(
+                &self.palm_rejection_config,
+                (oriented_x, oriented_y),
+                curved_pressure,
+                is_touching,
+            );
+        let is_touching = is_touching && !is_palm;
+        // A touch landing on a bound edge is held back until it is known not to be a flick.
+        let touch = match is_multimedia_area || is_palm {
+            true => Touch::Draw,
+            false => {
+                let (x, y, now) = (oriented_x, oriented_y, Instant::now());
+                self.flick_tracker.update(&self.flicks, x, y, is_touching, now)
+            }
+        };
+        if let Touch::Flick(edge) = touch
+            && let Some(binding) = self.flicks.binding(edge).cloned()
+        {
+            debug!("Flick from the {:?} edge.", edge);
+            self.run_binding_once(0, &binding)?;
+        }
+        let is_touching = is_touching && touch == Touch::Draw;
+        let curved_pressure = match touch == Touch::Draw && !is_palm {
+            true => curved_pressure,
+            false => 0,
+        };
+        let (smoothed_x, smoothed_y) = match self.is_mouse_mode && !is_multimedia_area {
+            true => {
+                let position = (smoothed_x, smoothed_y);
+                let now = Instant::now();
+                self.double_tap.update(&self.double_tap_config, position, is_touching, now)
+            }
+            false => (smoothed_x, smoothed_y),
+        };
+        let tilt = match is_multimedia_area {
+            true => (0, 0),
+            false => self.pen_tilt(raw_data, smoothed_x, smoothed_y),
+        };
+
+        self.raw_pen_abs_to_pen_abs_events(
+            smoothed_x,
+            smoothed_y,
+            curved_pressure,
+            tilt,
+            is_multimedia_area
+        );
+        let distance = match is_touching || is_on_icon_strip {
+            true => 0,
+            false => self.hover_distance(raw_data),
+        };
+        self.emit_distance(distance);
+
+        if is_on_icon_strip {
+            self.set_touching(false)?;
+            return self.emit_multimedia_events(raw_data, normalized_pressure > 0);
+        }
+        self.release_multimedia_keys()?;
+        if touch == Touch::Tap {
+            self.pen_emit_touch(raw_data, true)?;
+            self.flush_pen_frame()?;
+        }
+        self.pen_emit_touch(raw_data, is_touching)
+    }
+
+    fn emit_multimedia_events(&mut self, raw_data: &RawDataReader, is_touching: bool) -> Result<()> {
+        match (self.pressed_multimedia_keys.is_some(), is_touching) {
+            (false, true) => {
+                let (x, _) = self.calibration.map_position(raw_data.x_axis(), 0);
+                let icon_count = self.multimedia.icons.len() as i32;
+                let icon = (x.clamp(0, AXIS_MAX - 1) * icon_count / AXIS_MAX) as usize;
+                let keys = self.multimedia.icons[icon].clone();
+                self.emit_keyboard_keys(&keys, Self::PRESSED)?;
+                self.pressed_multimedia_keys = Some(keys);
+                Ok(())
+            }
+            (true, false) => self.release_multimedia_keys(),
+            _ => Ok(()),
+        }
+    }
+
+    // Sliding along the strip turns the dial by its steps, and moves the wheel to where
+    // the pen is.
+    fn emit_dial_events(&mut self, raw_data: &RawDataReader) -> Result<()> {
+        if !self.is_touching(raw_data) {
+            self.dial_position = None;
+            return Ok(());
+        }
+        let (x, _) = self.calibration.map_position(raw_data.x_axis(), 0);
+        let x = x.clamp(0, AXIS_MAX);
+        let mut events = vec![InputEvent::new(
+            EventType::ABSOLUTE,
+            AbsoluteAxisType::ABS_WHEEL.0,
+            x,
+        )];
+        match self.dial_position.replace(x) {
+            Some(last) => {
+                let exact = (x - last) as f32 / self.multimedia.dial_step.max(1.0)
+                    + self.dial_remainder;
+                let steps = exact.trunc();
+                self.dial_remainder = exact - steps;
+                if steps != 0.0 {
+                    events.push(InputEvent::new(
+                        EventType::RELATIVE,
+                        RelativeAxisType::REL_DIAL.0,
+                        steps as i32,
+                    ));
+                }
+            }
+            None => self.dial_remainder = 0.0,
+        }
+        match &mut self.virtual_dial {
+            Some(virtual_dial) => Ok(virtual_dial.emit(&events)?),
+            None => Ok(()),
+        }
+    }
+
+    fn release_multimedia_keys(&mut self) -> Result<()> {
+        match self.pressed_multimedia_keys.take() {
+            Some(keys) => self.emit_keyboard_keys(&keys, Self::RELEASED),
+            None => Ok(()),
+        }
+    }
+
+    fn emit_relative_events(&mut self, raw_data: &RawDataReader) -> Result<()> {
+        let (x, y) = self.pen_position(raw_data);
+        let (smoothed_x, smoothed_y) = self.smooth_coordinates(x, y);
+        let (dx, dy) = self.relative_motion.delta(&self.relative, smoothed_x, smoothed_y);
+
+        let raw_pressure = self.mapped_pressure(raw_data);
+        let is_touching = self.pressure.mouse.is_clicking(raw_pressure, self.is_mouse_button_down);
+        self.set_mouse_button_down(is_touching)?;
+
+        if (dx, dy) != (0, 0)
+            && let Some(virtual_mouse) = &mut self.virtual_mouse
+        {
+            virtual_mouse
+                .emit(&[
+                    InputEvent::new(EventType::RELATIVE, RelativeAxisType::REL_X.0, dx),
+                    InputEvent::new(EventType::RELATIVE, RelativeAxisType::REL_Y.0, dy),
+                ])?;
+        }
+        Ok(())
+    }
+
+    fn emit_scroll_events(&mut self, raw_data: &RawDataReader) -> Result<()> {
+        self.set_touching(false)?;
+        self.set_mouse_button_down(false)?;
+
+        let (x, y) = self.pen_position(raw_data);
+        let (smoothed_x, smoothed_y) = self.smooth_coordinates(x, y);
+        let step = self.scroll_drag.delta(&self.scroll, smoothed_x, smoothed_y);
+
+        let mut axes = vec![
+            (RelativeAxisType::REL_WHEEL, step.wheel),
+            (RelativeAxisType::REL_HWHEEL, step.hwheel),
+        ];
+        if self.scroll.hi_res {
+            axes.push((RelativeAxisType::REL_WHEEL_HI_RES, step.wheel_hi_res));
+            axes.push((RelativeAxisType::REL_HWHEEL_HI_RES, step.hwheel_hi_res));
+        }
+        let events: Vec<InputEvent> = axes
+            .into_iter()
+            .filter(|&(_, value)| value != 0)
+            .map(|(axis, value)| InputEvent::new(EventType::RELATIVE, axis.0, value))
+            .collect();
+        if !events.is_empty()
+            && let Some(virtual_mouse) = &mut self.virtual_mouse
+        {
+            virtual_mouse.emit(&events)?;
+        }
+        Ok(())
+    }
+
+    fn emit_zone_steps(&mut self, zone: usize, steps: i32) -> Result<()> {
+        let zone = &self.zones[zone];
+        if steps == 0 {
+            return Ok(());
+        }
+        match zone.binding(steps).cloned() {
+            Some(binding) => {
+                for _ in 0..steps.abs() {
+                    self.run_binding_once(0, &binding)?;
+                }
+            }
+            None if zone.scrolls() => {
+                let event = InputEvent::new(EventType::RELATIVE, zone.wheel_axis().0, steps);
+                if let Some(virtual_mouse) = &mut self.virtual_mouse {
+                    virtual_mouse.emit(&[event])?;
+                }
+            }
+            // Only one direction is bound.
+            None => {}
+        }
+        Ok(())
+    }
+
+    fn set_mouse_button_down(&mut self, is_down: bool) -> Result<()> {
+        if is_down == self.is_mouse_button_down {
+            return Ok(());
+        }
+        self.is_mouse_button_down = is_down;
+
+        if let Some(virtual_mouse) = &mut self.virtual_mouse {
+            let state = if is_down { Self::PRESSED } else { Self::RELEASED };
+            virtual_mouse
+                .emit(&[InputEvent::new(EventType::KEY, Key::BTN_LEFT.code(), state)])?;
+        }
+        Ok(())
+    }
+
+    fn virtual_mouse_builder(name: &str) -> io::Result<VirtualDevice> {
+        let mut axes = AttributeSet::<RelativeAxisType>::new();
+        axes.insert(RelativeAxisType::REL_X);
+        axes.insert(RelativeAxisType::REL_Y);
+        axes.insert(RelativeAxisType::REL_WHEEL);
+        axes.insert(RelativeAxisType::REL_HWHEEL);
+        axes.insert(RelativeAxisType::REL_WHEEL_HI_RES);
+        axes.insert(RelativeAxisType::REL_HWHEEL_HI_RES);
+
+        let mut key_set = AttributeSet::<Key>::new();
+        for key in &[Key::BTN_LEFT, Key::BTN_RIGHT, Key::BTN_MIDDLE] {
+            key_set.insert(*key);
+        }
+
+        VirtualDeviceBuilder::new()?
+            .name(name)
+            .input_id(input_id())
+            .with_relative_axes(&axes)?
+            .with_keys(&key_set)?
+            .build()
+    }
+
+    // Clicks and keys of a uhid pen that its HID reports can't carry. The unused
+    // relative axes make libinput take it as a mouse, whose clicks land on the cursor
+    // the pen moves.
+    fn virtual_pen_buttons_builder(
+        pen_emitted_keys: &[Key],
+        config: &VirtualDevicesConfig,
+    ) -> io::Result<VirtualDevice> {
+        let mut axes = AttributeSet::<RelativeAxisType>::new();
+        axes.insert(RelativeAxisType::REL_X);
+        axes.insert(RelativeAxisType::REL_Y);
+
+        let mut key_set = AttributeSet::<Key>::new();
+        for key in pen_emitted_keys.iter().chain(&[Key::BTN_LEFT, Key::BTN_RIGHT]) {
+            key_set.insert(*key);
+        }
+
+        VirtualDeviceBuilder::new()?
+            .name(&format!("{} Buttons", config.pen_name))
+            .input_id(input_id())
+            .with_relative_axes(&axes)?
+            .with_keys(&key_set)?
+            .build()
+    }
+
+    fn virtual_dial_builder(name: &str) -> io::Result<VirtualDevice> {
+        let mut axes = AttributeSet::<RelativeAxisType>::new();
+        axes.insert(RelativeAxisType::REL_DIAL);
+        let wheel_setup = UinputAbsSetup::new(
+            AbsoluteAxisType::ABS_WHEEL,
+            AbsInfo::new(0, 0, AXIS_MAX, 0, 0, 0),
+        );
+
+        VirtualDeviceBuilder::new()?
+            .name(name)
+            .input_id(input_id())
+            .with_relative_axes(&axes)?
+            .with_absolute_axis(&wheel_setup)?
+            .build()
+    }
+
+    // Raw pressure on the nominal scale, by the calibration or the learned range.
+    pub(crate) fn mapped_pressure(&self, raw_data: &RawDataReader) -> i32 {
+        match self.pressure.learn {
+            true => self.pressure_learner.apply(&self.calibration),
+            false => self.calibration,
+        }
+        .map_pressure(raw_data.pressure())
+    }
+
+    fn normalized_pressure(&self, raw_data: &RawDataReader, is_touching: bool) -> i32 {
+        self.pressure
+            .for_mode(self.is_mouse_mode)
+            .normalize(self.mapped_pressure(raw_data), is_touching)
+    }
+
+    fn settle_count(&self) -> u32 {
+        match self.is_mouse_mode {
+            true => self.settle.mouse,
+            false => self.settle.tablet,
+        }
+    }
+
+    // Whether a touching report is held back, counting it; the count starts over
+    // once the pen is down or lifted again.
+    fn is_touch_settling(&mut self, is_touching: bool) -> bool {
+        let is_touch_down = is_touching && !self.was_touching;
+        if is_touch_down && self.settling_touches < self.settle_count() {
+            self.settling_touches += 1;
+            return true;
+        }
+        self.settling_touches = 0;
+        false
+    }
+
+    // Whether the pen touches, or clicks in mouse mode, with the lower release
+    // threshold applied while it does.
+    fn is_touching(&self, raw_data: &RawDataReader) -> bool {
+        let raw_pressure = self.mapped_pressure(raw_data);
+        match self.is_mouse_mode {
+            true => self.pressure.mouse.is_clicking(raw_pressure, self.was_touching),
+            false => self.pressure.tablet.is_touching(raw_pressure, self.was_touching),
+        }
+    }
+
+    fn hover_distance(&self, raw_data: &RawDataReader) -> i32 {
+        self.pressure
+            .for_mode(self.is_mouse_mode)
+            .hover_distance(self.mapped_pressure(raw_data))
+    }
+
+    fn emit_distance(&mut self, distance: i32) {
+        self.pen_frame.push(InputEvent::new(
+            EventType::ABSOLUTE,
+            AbsoluteAxisType::ABS_DISTANCE.0,
+            distance,
+        ));
+    }
+
+    // Real tilt if the pen reports it, else emulated from the stroke if enabled.
+    fn pen_tilt(&mut self, raw_data: &RawDataReader, x: i32, y: i32) -> (i32, i32) {
+        let (tilt_x, tilt_y) = match raw_data.tilt() {
+            Some(tilt) => tilt,
+            None if self.tilt.emulate => self.tilt_emulation.update(&self.tilt, x, y),
+            None => (0, 0),
+        };
+        // Tilt is a direction, so it turns with the tablet but isn't offset.
+        let (tilt_x, tilt_y) = self.rotation.apply(tilt_x, tilt_y, 0);
+        (
+            tilt_x.clamp(TILT_MIN, TILT_MAX),
+            tilt_y.clamp(TILT_MIN, TILT_MAX),
+        )
+    }
+
+    fn raw_pen_abs_to_pen_abs_events(
+        &mut self,
+        x_axis: i32,
+        y_axis: i32,
+        pressure: i32,
+        (tilt_x, tilt_y): (i32, i32),
+        is_multimedia_area: bool,
+    ) {
+        self.last_pressure = pressure;
+        let (x_axis, y_axis) = (x_axis.clamp(0, AXIS_MAX), y_axis.clamp(0, AXIS_MAX));
+        let (x_axis, y_axis) = self.rotation.apply(x_axis, y_axis, AXIS_MAX);
+        // Every step keeps the position within 0..=AXIS_MAX, the same in both modes, so
+        // the pen stops at the edge instead of jumping back.
+        let (x, y) = if is_multimedia_area {
+            // Use last valid X and top position
+            self.rotation.apply(self.last_valid_x, 0, AXIS_MAX)
+        } else if self.is_mouse_mode {
+            // The mouse area is a square of `mouse_area_scale` of the tablet, placed by
+            // its anchor and stretched over the whole output.
+            let area = AXIS_MAX as f32 * self.mouse_area_scale;
+            let origin = self.mouse_area_origin(area);
+            scale_to_area(x_axis, y_axis, area, origin)
+        } else if let Some(rect) = self.tablet_area.rect {
+            scale_to_rect(x_axis, y_axis, rect)
+        } else {
+            // Without a rect the tablet area is centered.
+            let area = AXIS_MAX as f32 * self.tablet_area.scale.clamp(0.1, 1.0);
+            let margin = (AXIS_MAX as f32 - area) / 2.0;
+            scale_to_area(x_axis, y_axis, area, (margin, margin))
+        };
+        let (x, y) = match is_multimedia_area {
+            true => (x, y),
+            false => self.apply_precision(x, y),
+        };
+        let (x, y) = match &self.output_mapping {
+            Some(mapping) => mapping.map(x, y, AXIS_MAX),
+            None => (x, y),
+        };
+        let (x, y) = self.axes.to_output(x, y);
+
+        self.pen_frame.extend([
+            InputEvent::new(EventType::ABSOLUTE, AbsoluteAxisType::ABS_X.0, x),
+            InputEvent::new(EventType::ABSOLUTE, AbsoluteAxisType::ABS_Y.0, y),
+            InputEvent::new(EventType::ABSOLUTE, AbsoluteAxisType::ABS_PRESSURE.0, pressure),
+            InputEvent::new(EventType::ABSOLUTE, AbsoluteAxisType::ABS_TILT_X.0, tilt_x),
+            InputEvent::new(EventType::ABSOLUTE, AbsoluteAxisType::ABS_TILT_Y.0, tilt_y),
+        ]);
+    }
+
+    fn pen_emit_touch(&mut self, raw_data: &RawDataReader, is_touching: bool) -> Result<()> {
+        if self.is_hold_click_fired {
+            self.is_hold_click_fired = is_touching;
+            return self.set_touching(false);
+        }
+        if is_touching && !self.was_touching && !self.is_multimedia_area(raw_data) {
+            let (x, y) = self.pen_position(raw_data);
+            self.last_touch_point = (x.clamp(0, AXIS_MAX), y.clamp(0, AXIS_MAX));
+        }
+        self.set_touching(is_touching)
+    }
+
+    fn set_touching(&mut self, is_touching: bool) -> Result<()> {
+        let was_touching = std::mem::replace(&mut self.was_touching, is_touching);
+        self.has_sticky_touch |= is_touching && !self.sticky_keys.is_empty();
+        if let Some(state) = match (was_touching, is_touching) {
+            (false, true) => Some(Self::PRESSED),
+            (true, false) => Some(Self::RELEASED),
+            _ => None,
+        } {
+            // Mouse mode clicks like a mouse rather than touching like a pen.
+            let key = if self.is_mouse_mode { Key::BTN_LEFT } else { Key::BTN_TOUCH };
+            self.pen_frame.push(InputEvent::new(EventType::KEY, key.code(), state));
+        }
+        Ok(())
+    }
+
+    fn raw_pen_buttons_to_pen_key_events(&mut self, pen_button: u8) -> Result<()> {
+        let Some((state, id)) = (match (self.pen_last_raw_pressed_button, pen_button) {
+            (2, x) if x == 6 || x == 4 => Some((Self::PRESSED, x)),
+            (x, 2) if x == 6 || x == 4 => Some((Self::RELEASED, x)),
+            (x, y) if x != 2 && x == y => Some((Self::HOLD, x)),
+            _ => None,
+        }) else {
+            return Ok(());
+        };
+
+        if self.hold_click.button == Some(id) && self.update_hold_click(id, state)? {
+            return Ok(());
+        }
+        self.pen_button_binding(id, state)
+    }
+
+    // Sends what pen button `id` is bound to, for a press, hold or release.
+    fn pen_button_binding(&mut self, id: u8, state: i32) -> Result<()> {
+        let binding = self.pen_button_id_to_key_code_map.get(&id).cloned();
+        if self.eraser.button == Some(id) || binding == Some(Binding::Eraser) {
+            return match (self.eraser.mode, state) {
+                (EraserMode::Toggle, Self::PRESSED) => self.set_eraser_active(!self.is_eraser_active),
+                (EraserMode::Hold, Self::PRESSED) => self.set_eraser_active(true),
+                (EraserMode::Hold, Self::RELEASED) => self.set_eraser_active(false),
+                _ => Ok(()),
+            };
+        }
+
+        if self.scroll.button == Some(id) {
+            match state {
+                Self::PRESSED => {
+                    self.is_scrolling = true;
+                    self.scroll_drag.reset();
+                }
+                Self::RELEASED => self.is_scrolling = false,
+                _ => {}
+            }
+            return Ok(());
+        }
+
+        match binding {
+            // Stylus and mouse buttons go out with the pen, keyboard keys through the
+            // keyboard.
+            Some(Binding::Keys(keys)) => {
+                let (buttons, keys): (Vec<Key>, Vec<Key>) =
+                    keys.into_iter().partition(|key| keys::is_button(*key));
+                let events = buttons
+                    .iter()
+                    .map(|key| InputEvent::new(EventType::KEY, key.code(), state));
+                self.pen_frame.extend(events);
+                if !keys.is_empty() {
+                    self.emit_keyboard_keys(&keys, state)?;
+                }
+            }
+            Some(Binding::Precision) => match state {
+                Self::PRESSED => self.set_precision_active(true),
+                Self::RELEASED => self.set_precision_active(false),
+                _ => {}
+            },
+            Some(binding) if state == Self::PRESSED => {
+                self.run_binding_once(Self::pen_button_mask(id), &binding)?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    // Handles the hold-click button, returning whether it was pressed on the touching
+    // pen and so doesn't send its own binding. Its press is held back until the delay
+    // tells a hold from a click: released sooner, or with the pen lifted, the button
+    // sends its binding after all.
+    fn update_hold_click(&mut self, id: u8, state: i32) -> Result<bool> {
+        match state {
+            Self::PRESSED if self.was_touching => {
+                self.is_hold_click_button_down = true;
+                self.hold_click_started = Some(Instant::now());
+            }
+            Self::HOLD if self.is_hold_click_button_down => {
+                let delay = Duration::from_millis(self.hold_click.delay_ms);
+                match self.hold_click_started {
+                    // From here on it is an ordinary button, released like one.
+                    Some(_) if !self.was_touching => {
+                        self.is_hold_click_button_down = false;
+                        self.hold_click_started = None;
+                        self.pen_button_binding(id, Self::PRESSED)?;
+                    }
+                    Some(started) if started.elapsed() >= delay => self.fire_hold_click()?,
+                    _ => {}
+                }
+            }
+            Self::RELEASED if self.is_hold_click_button_down => {
+                self.is_hold_click_button_down = false;
+                if self.hold_click_started.take().is_some() {
+                    self.pen_button_binding(id, Self::PRESSED)?;
+                    self.flush_pen_frame()?;
+                    self.pen_button_binding(id, Self::RELEASED)?;
+                }
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    // Lifts the pen and clicks, with the press in a frame of its own so the click
+    // isn't lost.
+    fn fire_hold_click(&mut self) -> Result<()> {
+        self.hold_click_started = None;
+        self.is_hold_click_fired = true;
+        self.hold_click_count += 1;
+        info!("Hold-click: right click");
+        self.set_touching(false)?;
+        self.pen_frame
+            .push(InputEvent::new(EventType::KEY, Key::BTN_RIGHT.code(), Self::PRESSED));
+        self.flush_pen_frame()?;
+        self.pen_frame
+            .push(InputEvent::new(EventType::KEY, Key::BTN_RIGHT.code(), Self::RELEASED));
+        Ok(())
+    }
+
+    // Pen buttons 4 and 6 take the two bits above the tablet buttons, so commands on
+    // them get their own repeat interval.
+    fn pen_button_mask(id: u8) -> u16 {
+        if id == 4 { 1 << 14 } else { 1 << 15 }
+    }
+}
+
+// The tablet's own USB IDs, so udev rules and hwdb entries can match the virtual
+// devices; the version is the driver's major version.
+fn input_id() -> InputId {
+    InputId::new(BusType::BUS_USB, VID, PID, 2)
+}
+
+// Stretches the [left, top, right, bottom] rectangle over the full 0..=AXIS_MAX range.
+fn scale_to_rect(x: i32, y: i32, [left, top, right, bottom]: [i32; 4]) -> (i32, i32) {
+    let scale = |axis: i32, start: i32, end: i32| {
+        let scaled = (axis - start) as i64 * AXIS_MAX as i64 / (end - start).max(1) as i64;
+        scaled.clamp(0, AXIS_MAX as i64) as i32
+    };
+    (scale(x, left, right), scale(y, top, bottom))
+}
+
+// Stretches the square of side `area` at `origin` over the full 0..=AXIS_MAX range.
+fn scale_to_area(x: i32, y: i32, area: f32, (origin_x, origin_y): (f32, f32)) -> (i32, i32) {
+    let scale = |axis: i32, origin: f32| {
+        let scaled = (axis as f32 - origin) * AXIS_MAX as f32 / area;
+        (scaled.round() as i32).clamp(0, AXIS_MAX)
+    };
+    (scale(x, origin_x), scale(y, origin_y))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::binding::MacroStep;
+    use crate::virtual_output::RecordingOutput;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn dispatcher(config: &Config) -> (DeviceDispatcher, RecordingOutput, RecordingOutput) {
+        let (pen, keyboard) = (RecordingOutput::new(), RecordingOutput::new());
+        let dispatcher = DeviceDispatcher::with_outputs(
+            config,
+            Box::new(pen.clone()),
+            Box::new(keyboard.clone()),
+            None,
+            None,
+        );
+        (dispatcher, pen, keyboard)
+    }
+
+    fn report(x: u16, y: u16, pressure: u16, pen_buttons: u8, tablet_buttons: &[u8]) -> RawDataReader {
+        let mut reader = RawDataReader::from_fields(x, y, pressure, pen_buttons);
+        let flags = tablet_buttons.iter().fold(0xffffu16, |flags, id| flags & !(1 << id));
+        reader.data[11] = flags as u8;
+        reader.data[12] = (flags >> 8) as u8;
+        reader
+    }
+
+    fn key_values(events: &[InputEvent], key: Key) -> Vec<i32> {
+        events
+            .iter()
+            .filter(|event| event.event_type() == EventType::KEY && event.code() == key.code())
+            .map(|event| event.value())
+            .collect()
+    }
+
+    fn abs_values(events: &[InputEvent], axis: AbsoluteAxisType) -> Vec<i32> {
+        events
+            .iter()
+            .filter(|event| event.event_type() == EventType::ABSOLUTE && event.code() == axis.0)
+            .map(|event| event.value())
+            .collect()
+    }
+
+    #[test]
+    fn malformed_reports_leave_the_last_one_in_place() {
+        let mut reader = RawDataReader::new();
+        let whole = report(1000, 2000, 1500, 2, &[]).data;
+        reader.load(&whole).unwrap();
+        assert_eq!((reader.x_axis(), reader.y_axis()), (1000, 2000));
+
+        // A read cut after 8 bytes, then the 56 it was missing.
+        assert!(reader.load(&[0x00, 0xff, 0xff, 0xff, 0xff, 0, 0, 2]).is_err());
+        assert_eq!(reader.load(&[0x12; 56]), Err(MalformedReport::Remainder(56)));
+        assert_eq!((reader.x_axis(), reader.y_axis()), (1000, 2000));
+
+        // Back in step; a shorter report no longer leaves the old tail behind.
+        let mut next = report(1100, 2100, 1500, 2, &[]).data;
+        next[20] = 0x55;
+        reader.load(&next).unwrap();
+        reader.load(&report(1200, 2200, 1500, 2, &[]).data[..13]).unwrap();
+        assert_eq!((reader.x_axis(), reader.data.len()), (1200, 13));
+    }
+
+    #[test]
+    fn short_reports_are_read_with_their_own_layout() {
+        let (mut dispatcher, pen, keyboard) = dispatcher(&Config::default());
+        dispatcher.set_mouse_mode(false).unwrap();
+        let mut reader = RawDataReader::new();
+
+        // Hovering at (1000, 2000), then tablet button 2 pressed while it stays there.
+        reader.load(&[0x08, 0x80, 0xe8, 0x03, 0xd0, 0x07, 0x00, 0x00]).unwrap();
+        dispatcher.dispatch(&reader).unwrap();
+        reader.load(&[0x08, 0xe0, 0, 0, 0x04, 0x00, 0, 0, 0, 0]).unwrap();
+        dispatcher.dispatch(&reader).unwrap();
+
+        let events = pen.events();
+        assert_eq!(abs_values(&events, AbsoluteAxisType::ABS_X), vec![1000]);
+        assert_eq!(abs_values(&events, AbsoluteAxisType::ABS_Y), vec![2000]);
+        assert_eq!(reader.pen_buttons(), 2);
+        assert_eq!(key_values(&keyboard.events(), Key::KEY_LEFTALT), vec![1]);
+
+        // A stream of full reports doesn't take a cut one for a short report.
+        let mut reader = RawDataReader::new();
+        reader.load(&report(1000, 2000, 2000, 2, &[]).data).unwrap();
+        assert!(reader.load(&[0x08, 0x80, 0, 0, 0, 0, 0, 0]).is_err());
+    }
+
+    #[test]
+    fn annotated_hex_decodes_the_report() {
+        let dump = report(0x0102, 0xfff0, 1980, 2, &[3, 12]).annotated_hex();
+        let lines: Vec<&str> = dump.lines().collect();
+
+        assert_eq!(lines.len(), 5);
+        assert!(lines[0].starts_with("00: 00 01 02 ff f0 07 bc"), "{}", lines[0]);
+        assert!(lines[3].starts_with("30: "), "{}", lines[3]);
+        assert_eq!(lines[4], "x=258 y=-16 pressure=1980 pen=0x02 tablet_buttons=[3, 12]");
+    }
+
+    #[test]
+    fn tablet_button_press_hold_and_release() {
+        let (mut dispatcher, _, keyboard) = dispatcher(&Config::default());
+
+        dispatcher.dispatch(&report(0, 0, 2000, 0, &[0])).unwrap();
+        dispatcher.dispatch(&report(0, 0, 2000, 0, &[0])).unwrap();
+        dispatcher.dispatch(&report(0, 0, 2000, 0, &[])).unwrap();
+
+        assert_eq!(key_values(&keyboard.events(), Key::KEY_TAB), vec![1, 2, 0]);
+    }
+
+    // Keeps every write to a uhid device, one per event.
+    #[derive(Clone, Default)]
+    struct UhidWrites(Rc<RefCell<Vec<Vec<u8>>>>);
+
+    impl io::Write for UhidWrites {
+        fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().push(buffer.to_vec());
+            Ok(buffer.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn uhid_pen_clicks_through_its_buttons_device_in_mouse_mode() {
+        let config = Config::default();
+        let (writes, buttons) = (UhidWrites::default(), RecordingOutput::new());
+        let property = config.virtual_devices.input_property;
+        let pen = UhidPen::with_device(
+            Box::new(writes.clone()),
+            "Pen",
+            AXIS_MAX,
+            property,
+            Box::new(buttons.clone()),
+        )
+        .unwrap();
+        let keyboard = RecordingOutput::new();
+        let mut dispatcher =
+            DeviceDispatcher::with_outputs(&config, Box::new(pen), Box::new(keyboard), None, None);
+        assert!(dispatcher.is_mouse_mode());
+
+        dispatcher.dispatch(&report(1000, 1000, 1000, 2, &[])).unwrap();
+        dispatcher.dispatch(&report(1000, 1000, 2000, 2, &[])).unwrap();
+
+        assert_eq!(key_values(&buttons.events(), Key::BTN_LEFT), vec![1, 0]);
+        // After the create event, input reports: in range, but never touching.
+        let writes = writes.0.borrow();
+        assert!(writes.len() > 2);
+        for write in &writes[1..] {
+            let flags = write[7];
+            assert_eq!(flags & 0b100001, 0b100000, "{:02x?}", write);
+        }
+    }
+
+    #[test]
+    fn keys_a_macro_leaves_pressed_are_released_on_shutdown() {
+        let mut config = Config::default();
+        let steps = vec![MacroStep::Press(Key::KEY_LEFTSHIFT), MacroStep::Tap(vec![Key::KEY_A])];
+        config.tablet_buttons.insert(0, Binding::Macro { steps });
+        let (mut dispatcher, _, keyboard) = dispatcher(&config);
+
+        dispatcher.dispatch(&report(0, 0, 2000, 0, &[0])).unwrap();
+        dispatcher.dispatch(&report(0, 0, 2000, 0, &[])).unwrap();
+        assert_eq!(key_values(&keyboard.events(), Key::KEY_LEFTSHIFT), vec![1]);
+        dispatcher.shutdown().unwrap();
+
+        assert_eq!(key_values(&keyboard.events(), Key::KEY_LEFTSHIFT), vec![1, 0]);
+        assert_eq!(key_values(&keyboard.events(), Key::KEY_A), vec![1, 0]);
+    }
+
+    #[test]
+    fn held_page_button_repeats_its_key() {
+        let mut config = Config::default();
+        config.auto_repeat.delay_ms = 0;
+        let (mut dispatcher, _, keyboard) = dispatcher(&config);
+
+        dispatcher.dispatch(&report(0, 0, 2000, 0, &[4])).unwrap();
+        assert!(dispatcher.next_deadline().is_some());
+        dispatcher.tick().unwrap();
+        dispatcher.dispatch(&report(0, 0, 2000, 0, &[4])).unwrap();
+        dispatcher.dispatch(&report(0, 0, 2000, 0, &[])).unwrap();
+
+        assert_eq!(key_values(&keyboard.events(), Key::KEY_PAGEUP), vec![1, 0, 1, 0]);
+        assert_eq!(dispatcher.next_deadline(), None);
+    }
+
+    #[test]
+    fn bouncing_button_is_pressed_once() {
+        let mut config = Config::default();
+        config.debounce.buttons.insert(0, 60_000);
+        let (mut dispatcher, _, keyboard) = dispatcher(&config);
+
+        dispatcher.dispatch(&report(0, 0, 2000, 0, &[0])).unwrap();
+        dispatcher.dispatch(&report(0, 0, 2000, 0, &[])).unwrap();
+        assert!(dispatcher.next_deadline().is_some());
+        dispatcher.dispatch(&report(0, 0, 2000, 0, &[0])).unwrap();
+
+        assert_eq!(key_values(&keyboard.events(), Key::KEY_TAB), vec![1, 2, 2]);
+    }
+
+    #[test]
+    fn tablet_button_emits_every_key_of_a_combination() {
+        let (mut dispatcher, _, keyboard) = dispatcher(&Config::default());
+
+        dispatcher.dispatch(&report(0, 0, 2000, 0, &[7])).unwrap();
+
+        let events = keyboard.events();
+        assert_eq!(key_values(&events, Key::KEY_LEFTCTRL), vec![1]);
+        assert_eq!(key_values(&events, Key::KEY_KPMINUS), vec![1]);
+    }
+
+    #[test]
+    fn macro_button_plays_its_steps_once_per_press() {
+        let mut config = Config::default();
+        let steps = vec![MacroStep::Tap(vec![Key::KEY_LEFTCTRL, Key::KEY_E]), MacroStep::Delay(1)];
+        config.tablet_buttons.insert(0, Binding::Macro { steps });
+        let (mut dispatcher, _, keyboard) = dispatcher(&config);
+
+        dispatcher.dispatch(&report(0, 0, 2000, 0, &[0])).unwrap();
+        dispatcher.dispatch(&report(0, 0, 2000, 0, &[0])).unwrap();
+        dispatcher.dispatch(&report(0, 0, 2000, 0, &[])).unwrap();
+
+        assert_eq!(key_values(&keyboard.events(), Key::KEY_E), vec![1, 0]);
+    }
+
+    #[test]
+    fn double_press_taps_its_own_binding_instead_of_the_normal_one() {
+        let mut config = Config::default();
+        config.gestures.double_press_ms = 10_000;
+        config.gestures.double_press.insert(0, Binding::Keys(vec![Key::KEY_ESC]));
+        let (mut dispatcher, _, keyboard) = dispatcher(&config);
+
+        dispatcher.dispatch(&report(0, 0, 2000, 0, &[0])).unwrap();
+        dispatcher.dispatch(&report(0, 0, 2000, 0, &[])).unwrap();
+        dispatcher.dispatch(&report(0, 0, 2000, 0, &[0])).unwrap();
+        dispatcher.dispatch(&report(0, 0, 2000, 0, &[])).unwrap();
+
+        let events = keyboard.events();
+        assert_eq!(key_values(&events, Key::KEY_ESC), vec![1, 0]);
+        assert!(key_values(&events, Key::KEY_TAB).is_empty());
+    }
+
+    #[test]
+    fn chord_replaces_the_keys_of_its_buttons() {
+        let mut config = Config::default();
+        config.chords.push(Chord {
+            buttons: vec![3, 4],
+            binding: Binding::Keys(vec![Key::KEY_ESC]),
+        });
+        let (mut dispatcher, _, keyboard) = dispatcher(&config);
+
+        dispatcher.dispatch(&report(0, 0, 2000, 0, &[3])).unwrap();
+        dispatcher.dispatch(&report(0, 0, 2000, 0, &[3, 4])).unwrap();
+        dispatcher.dispatch(&report(0, 0, 2000, 0, &[4])).unwrap();
+        dispatcher.dispatch(&report(0, 0, 2000, 0, &[])).unwrap();
+
+        let events = keyboard.events();
+        assert_eq!(key_values(&events, Key::KEY_LEFTCTRL), vec![1, 0]);
+        assert_eq!(key_values(&events, Key::KEY_ESC), vec![1, 0]);
+        assert!(key_values(&events, Key::KEY_PAGEUP).is_empty());
+    }
+
+    #[test]
+    fn layer_button_switches_to_the_secondary_bindings() {
+        let mut config = Config::default();
+        config.layer.button = Some(9);
+        config.layer.tablet_buttons.insert(0, Binding::Keys(vec![Key::KEY_Z]));
+        let (mut dispatcher, _, keyboard) = dispatcher(&config);
+
+        dispatcher.dispatch(&report(0, 0, 2000, 0, &[9])).unwrap();
+        dispatcher.dispatch(&report(0, 0, 2000, 0, &[9, 0])).unwrap();
+        // Letting go of the layer button first still releases the layer key.
+        dispatcher.dispatch(&report(0, 0, 2000, 0, &[0])).unwrap();
+        dispatcher.dispatch(&report(0, 0, 2000, 0, &[])).unwrap();
+        dispatcher.dispatch(&report(0, 0, 2000, 0, &[0])).unwrap();
+
+        let events = keyboard.events();
+        assert_eq!(key_values(&events, Key::KEY_Z), vec![1, 2, 0]);
+        assert_eq!(key_values(&events, Key::KEY_TAB), vec![1]);
+        assert!(key_values(&events, Key::KEY_ESC).is_empty());
+    }
+
+    #[test]
+    fn mode_button_toggles_mode_without_emitting_its_key() {
+        let mut config = Config::default();
+        config.tablet_buttons.clear();
+        config.tablet_buttons.insert(0, Binding::ToggleMode);
+        let (mut dispatcher, _, keyboard) = dispatcher(&config);
+        assert!(dispatcher.is_mouse_mode());
+
+        dispatcher.dispatch(&report(0, 0, 2000, 0, &[0])).unwrap();
+        dispatcher.dispatch(&report(0, 0, 2000, 0, &[])).unwrap();
+        assert!(!dispatcher.is_mouse_mode());
+        assert!(keyboard.events().is_empty());
+
+        // Other buttons, 12 included, are left to their bindings.
+        dispatcher.dispatch(&report(0, 0, 2000, 0, &[12])).unwrap();
+        assert!(!dispatcher.is_mouse_mode());
+    }
+
+    #[test]
+    fn pressure_below_threshold_is_zero() {
+        let pressure = PressureConfig::default();
+        assert_eq!(pressure.tablet.normalize(2000, false), 0);
+        assert_eq!(pressure.tablet.normalize(2000 - 510, false), 0);
+        assert_eq!(pressure.tablet.normalize(2000 - 600, false), 1800);
+        assert_eq!(pressure.mouse.normalize(2000 - 900, false), 1800);
+    }
+
+    #[test]
+    fn touch_threshold_is_configurable() {
+        let mut config = Config::default();
+        config.pressure.tablet.touch_threshold = 900;
+        let (mut dispatcher, pen, _) = dispatcher(&config);
+        dispatcher.set_mouse_mode(false).unwrap();
+
+        dispatcher.dispatch(&report(1000, 1000, 2000 - 600, 2, &[])).unwrap();
+        dispatcher.dispatch(&report(1000, 1000, 2000 - 1000, 2, &[])).unwrap();
+
+        assert_eq!(key_values(&pen.events(), Key::BTN_TOUCH), vec![1]);
+    }
+
+    #[test]
+    fn descriptor_parsed_reports_touch_with_pressure() {
+        let layout = crate::descriptor::parse(crate::descriptor::tests::PEN_DESCRIPTOR);
+        let parser = crate::protocol::select_parser(None, Protocol::Vinsa, layout);
+        let mut reader = RawDataReader::with_parser(parser);
+        let (mut dispatcher, pen, _) = dispatcher(&Config::default());
+        dispatcher.set_mouse_mode(false).unwrap();
+
+        // In range at the middle of the 0..=0x7fff axes, with Tip Pressure out of 8191.
+        for pressure in [0u16, 0, 4096, 6000, 4096, 0] {
+            let [low, high] = pressure.to_le_bytes();
+            reader.load(&[0x0a, 0x08, 0xff, 0x3f, 0xff, 0x3f, low, high, 0]).unwrap();
+            assert_eq!((reader.x_axis(), reader.y_axis()), (2047, 2047));
+            dispatcher.dispatch(&reader).unwrap();
+        }
+
+        assert_eq!(key_values(&pen.events(), Key::BTN_TOUCH), vec![1, 0]);
+        let pressures = abs_values(&pen.events(), AbsoluteAxisType::ABS_PRESSURE);
+        assert!(pressures.iter().any(|&pressure| pressure > 0), "{:?}", pressures);
+        assert_eq!(pressures.last(), Some(&0));
+    }
+
+    #[test]
+    fn light_pressure_at_the_threshold_does_not_flicker() {
+        let (mut dispatcher, pen, _) = dispatcher(&Config::default());
+        dispatcher.set_mouse_mode(false).unwrap();
+
+        for pressure in [520, 500, 520, 470, 440] {
+            dispatcher.dispatch(&report(1000, 1000, 2000 - pressure, 2, &[])).unwrap();
+        }
+
+        assert_eq!(key_values(&pen.events(), Key::BTN_TOUCH), vec![1, 0]);
+        // No gaps of zero pressure while the pen stays down.
+        let pressures = abs_values(&pen.events(), AbsoluteAxisType::ABS_PRESSURE);
+        let (last, stroke) = pressures.split_last().unwrap();
+        assert!(stroke.iter().all(|&pressure| pressure > 0), "{:?}", pressures);
+        assert_eq!(*last, 0);
+    }
+
+    #[test]
+    fn first_reports_in_range_and_touching_are_skipped() {
+        let mut config = Config {
+            mode: Mode::Tablet,
+            ..Config::default()
+        };
+        config.settle.tablet = 2;
+        config.smoothing.tablet.clear();
+        let (mut dispatcher, pen, _) = dispatcher(&config);
+
+        // Two reports still at where the pen left range.
+        dispatcher.dispatch(&report(4000, 4000, 2000, 2, &[])).unwrap();
+        dispatcher.dispatch(&report(4000, 4000, 2000, 2, &[])).unwrap();
+        dispatcher.dispatch(&report(1000, 1000, 2000, 2, &[])).unwrap();
+        for _ in 0..3 {
+            dispatcher.dispatch(&report(1000, 1000, 1000, 2, &[])).unwrap();
+        }
+
+        let events = pen.events();
+        assert_eq!(abs_values(&events, AbsoluteAxisType::ABS_X), vec![1000]);
+        assert_eq!(key_values(&events, Key::BTN_TOOL_PEN), vec![1]);
+        assert_eq!(key_values(&events, Key::BTN_TOUCH), vec![1]);
+        let pressures = abs_values(&events, AbsoluteAxisType::ABS_PRESSURE);
+        assert_eq!(pressures.iter().filter(|pressure| **pressure > 0).count(), 1);
+    }
+
+    #[test]
+    fn stabilizer_trails_the_pen_while_it_touches() {
+        let mut config = Config {
+            mode: Mode::Tablet,
+            ..Config::default()
+        };
+        config.stabilizer.radius = 100.0;
+        config.smoothing.tablet.clear();
+        config.tablet_buttons.insert(0, Binding::Stabilizer);
+        let (mut dispatcher, pen, _) = dispatcher(&config);
+
+        dispatcher.dispatch(&report(1000, 1000, 2000, 2, &[])).unwrap();
+        dispatcher.dispatch(&report(1000, 1000, 2000, 2, &[0])).unwrap();
+        assert!(dispatcher.is_stabilizer_active());
+        for x in [1000, 1050, 1300] {
+            dispatcher.dispatch(&report(x, 1000, 1000, 2, &[])).unwrap();
+        }
+        dispatcher.dispatch(&report(1300, 1000, 2000, 2, &[])).unwrap();
+
+        let x = abs_values(&pen.events(), AbsoluteAxisType::ABS_X);
+        assert_eq!(x, vec![1000, 1200, 1300]);
+    }
+
+    #[test]
+    fn pen_buttons_can_click_or_send_keys() {
+        let mut config = Config::default();
+        config.pen_buttons.insert(4, Binding::Keys(vec![Key::BTN_RIGHT]));
+        config.pen_buttons.insert(6, Binding::Keys(vec![Key::KEY_LEFTCTRL, Key::KEY_Z]));
+        let (mut dispatcher, pen, keyboard) = dispatcher(&config);
+
+        dispatcher.dispatch(&report(1000, 1000, 2000, 2, &[])).unwrap();
+        dispatcher.dispatch(&report(1000, 1000, 2000, 4, &[])).unwrap();
+        dispatcher.dispatch(&report(1000, 1000, 2000, 2, &[])).unwrap();
+        dispatcher.dispatch(&report(1000, 1000, 2000, 6, &[])).unwrap();
+        dispatcher.dispatch(&report(1000, 1000, 2000, 2, &[])).unwrap();
+
+        assert_eq!(key_values(&pen.events(), Key::BTN_RIGHT), vec![1, 0]);
+        assert!(key_values(&pen.events(), Key::KEY_Z).is_empty());
+        assert_eq!(key_values(&keyboard.events(), Key::KEY_Z), vec![1, 0]);
+    }
+
+    #[test]
+    fn eraser_binding_switches_the_tool() {
+        let mut config = Config::default();
+        config.pen_buttons.insert(6, Binding::Eraser);
+        let (mut dispatcher, pen, _) = dispatcher(&config);
+
+        dispatcher.dispatch(&report(1000, 1000, 2000, 2, &[])).unwrap();
+        dispatcher.dispatch(&report(1000, 1000, 2000, 6, &[])).unwrap();
+
+        assert_eq!(key_values(&pen.events(), Key::BTN_TOOL_RUBBER), vec![1]);
+        assert!(key_values(&pen.events(), Key::BTN_STYLUS2).is_empty());
+    }
+
+    #[test]
+    fn holding_the_pen_button_while_touching_right_clicks() {
+        let mut config = Config::default();
+        config.hold_right_click.button = Some(4);
+        config.hold_right_click.delay_ms = 0;
+        let (mut dispatcher, pen, _) = dispatcher(&config);
+        dispatcher.set_mouse_mode(false).unwrap();
+
+        dispatcher.dispatch(&report(1000, 1000, 2000, 2, &[])).unwrap();
+        dispatcher.dispatch(&report(1000, 1000, 1000, 2, &[])).unwrap();
+        dispatcher.dispatch(&report(1000, 1000, 1000, 4, &[])).unwrap();
+        dispatcher.dispatch(&report(1000, 1000, 1000, 4, &[])).unwrap();
+        dispatcher.dispatch(&report(1000, 1000, 1000, 2, &[])).unwrap();
+
+        let events = pen.events();
+        assert_eq!(key_values(&events, Key::BTN_RIGHT), vec![1, 0]);
+        assert_eq!(key_values(&events, Key::BTN_TOUCH), vec![1, 0]);
+        assert!(key_values(&events, Key::BTN_STYLUS).is_empty());
+        assert_eq!(dispatcher.hold_click_count(), 1);
+    }
+
+    #[test]
+    fn pen_button_released_before_the_hold_click_keeps_its_binding() {
+        let mut config = Config::default();
+        config.hold_right_click.button = Some(4);
+        config.hold_right_click.delay_ms = 60_000;
+        let (mut dispatcher, pen, _) = dispatcher(&config);
+        dispatcher.set_mouse_mode(false).unwrap();
+
+        // A quick click while touching.
+        dispatcher.dispatch(&report(1000, 1000, 2000, 2, &[])).unwrap();
+        dispatcher.dispatch(&report(1000, 1000, 1000, 2, &[])).unwrap();
+        dispatcher.dispatch(&report(1000, 1000, 1000, 4, &[])).unwrap();
+        dispatcher.dispatch(&report(1000, 1000, 1000, 2, &[])).unwrap();
+        // Pressed while touching and held on after the pen lifts.
+        dispatcher.dispatch(&report(1000, 1000, 1000, 4, &[])).unwrap();
+        dispatcher.dispatch(&report(1000, 1000, 2000, 4, &[])).unwrap();
+        dispatcher.dispatch(&report(1000, 1000, 2000, 4, &[])).unwrap();
+        dispatcher.dispatch(&report(1000, 1000, 2000, 2, &[])).unwrap();
+
+        let events = pen.events();
+        assert_eq!(key_values(&events, Key::BTN_STYLUS), vec![1, 0, 1, 0]);
+        assert!(key_values(&events, Key::BTN_RIGHT).is_empty());
+        assert_eq!(dispatcher.hold_click_count(), 0);
+    }
+
+    #[test]
+    fn precision_button_slows_the_pen_around_where_it_was() {
+        let mut config = Config::default();
+        config.smoothing.tablet.clear();
+        config.tablet_buttons.insert(0, Binding::Precision);
+        let (mut dispatcher, pen, _) = dispatcher(&config);
+        dispatcher.set_mouse_mode(false).unwrap();
+
+        // Buttons are read after the pen, so each change shows from the next report.
+        dispatcher.dispatch(&report(1000, 1000, 2000, 2, &[0])).unwrap();
+        dispatcher.dispatch(&report(1000, 1000, 2000, 2, &[0])).unwrap();
+        dispatcher.dispatch(&report(2000, 1000, 2000, 2, &[])).unwrap();
+        dispatcher.dispatch(&report(2000, 1000, 2000, 2, &[])).unwrap();
+
+        let x_values = abs_values(&pen.events(), AbsoluteAxisType::ABS_X);
+        assert_eq!(x_values, vec![1000, 1250, 2000]);
+    }
+
+    #[test]
+    fn tablet_area_rect_is_stretched_over_the_output() {
+        let mut config = Config::default();
+        config.smoothing.tablet.clear();
+        config.tablet_area.rect = Some([1024, 0, 3072, 2048]);
+        let (mut dispatcher, pen, _) = dispatcher(&config);
+        dispatcher.set_mouse_mode(false).unwrap();
+
+        dispatcher.dispatch(&report(2048, 1024, 2000, 2, &[])).unwrap();
+        dispatcher.set_tablet_area_rect(None);
+        dispatcher.dispatch(&report(1024, 1024, 2000, 2, &[])).unwrap();
+
+        let events = pen.events();
+        assert_eq!(abs_values(&events, AbsoluteAxisType::ABS_X), vec![2048, 1024]);
+        assert_eq!(abs_values(&events, AbsoluteAxisType::ABS_Y), vec![2048, 1024]);
+    }
+
+    #[test]
+    fn mouse_area_is_centered_on_the_tablet() {
+        let mut config = Config::default();
+        config.smoothing.mouse.clear();
+        let (mut dispatcher, pen, _) = dispatcher(&config);
+        dispatcher.set_mouse_area_scale(0.5);
+        assert_eq!(dispatcher.mouse_area_on_screen(), [0.25, 0.25, 0.75, 0.75]);
+
+        dispatcher.dispatch(&report(2048, 2048, 2000, 2, &[])).unwrap();
+        dispatcher.dispatch(&report(3072, 3072, 2000, 2, &[])).unwrap();
+
+        let events = pen.events();
+        assert_eq!(abs_values(&events, AbsoluteAxisType::ABS_X).first(), Some(&2048));
+        assert_eq!(abs_values(&events, AbsoluteAxisType::ABS_Y).first(), Some(&2048));
+        assert_eq!(abs_values(&events, AbsoluteAxisType::ABS_X).last(), Some(&4096));
+    }
+
+    #[test]
+    fn tablet_mode_uses_its_own_area_and_starts_from_config() {
+        let mut config = Config {
+            mode: Mode::Tablet,
+            ..Config::default()
+        };
+        config.tablet_area.scale = 0.5;
+        config.smoothing.tablet.clear();
+        let (mut dispatcher, pen, _) = dispatcher(&config);
+        assert!(!dispatcher.is_mouse_mode());
+
+        dispatcher.dispatch(&report(1024, 1024, 2000, 2, &[])).unwrap();
+        dispatcher.dispatch(&report(3072, 3072, 2000, 2, &[])).unwrap();
+
+        let events = pen.events();
+        assert_eq!(abs_values(&events, AbsoluteAxisType::ABS_X), vec![0, 4096]);
+        assert_eq!(abs_values(&events, AbsoluteAxisType::ABS_Y), vec![0, 4096]);
+    }
+
+    #[test]
+    fn profile_switch_binding_is_handed_over_and_profiles_set_the_area() {
+        let mut config = Config {
+            mode: Mode::Tablet,
+            ..Config::default()
+        };
+        config.smoothing.tablet.clear();
+        config.tablet_buttons.insert(0, Binding::Profile(ProfileSwitch::Next));
+        let profile = Profile {
+            tablet_area: Some(TabletAreaConfig {
+                scale: 0.5,
+                rect: None,
+            }),
+            ..Profile::default()
+        };
+        let (mut dispatcher, pen, _) = dispatcher(&config);
+
+        dispatcher.dispatch(&report(1024, 1024, 2000, 2, &[0])).unwrap();
+        assert_eq!(dispatcher.take_profile_switch(), Some(ProfileSwitch::Next));
+        assert_eq!(dispatcher.take_profile_switch(), None);
+
+        dispatcher.apply_profile(&config, Some(&profile));
+        dispatcher.dispatch(&report(1024, 1024, 2000, 2, &[])).unwrap();
+        dispatcher.apply_profile(&config, None);
+        dispatcher.dispatch(&report(1024, 1024, 2000, 2, &[])).unwrap();
+        assert_eq!(abs_values(&pen.events(), AbsoluteAxisType::ABS_X), vec![1024, 0, 1024]);
+    }
+
+    #[test]
+    fn raw_range_is_mapped_onto_the_output_range() {
+        let mut config = Config {
+            mode: Mode::Tablet,
+            ..Config::default()
+        };
+        config.axes.x_max = 8192;
+        config.axes.output_max = 1000;
+        config.smoothing.tablet.clear();
+        let (mut dispatcher, pen, _) = dispatcher(&config);
+
+        dispatcher.dispatch(&report(8192, 2048, 2000, 2, &[])).unwrap();
+        // Past the raw maximum the pen stays on the edge.
+        dispatcher.dispatch(&report(9000, 5000, 2000, 2, &[])).unwrap();
+
+        let events = pen.events();
+        assert_eq!(abs_values(&events, AbsoluteAxisType::ABS_X), vec![1000]);
+        assert_eq!(abs_values(&events, AbsoluteAxisType::ABS_Y), vec![500, 1000]);
+    }
+
+    #[test]
+    fn cycle_button_moves_the_mouse_area_without_emitting_its_key() {
+        let mut config = Config::default();
+        config.smoothing.mouse.clear();
+        config.mouse_area.cycle_button = Some(0);
+        let (mut dispatcher, pen, keyboard) = dispatcher(&config);
+        dispatcher.set_mouse_area_scale(0.5);
+
+        dispatcher.dispatch(&report(0, 0, 2000, 0, &[0])).unwrap();
+        dispatcher.dispatch(&report(1024, 1024, 2000, 2, &[])).unwrap();
+
+        assert_eq!(abs_values(&pen.events(), AbsoluteAxisType::ABS_X), vec![2048]);
+        assert!(key_values(&keyboard.events(), Key::KEY_TAB).is_empty());
+    }
+
+    #[test]
+    fn rotation_button_flips_the_tablet() {
+        let mut config = Config::default();
+        config.smoothing.tablet.clear();
+        config.rotation_button = Some(0);
+        let (mut dispatcher, pen, _) = dispatcher(&config);
+        dispatcher.set_mouse_mode(false).unwrap();
+
+        dispatcher.dispatch(&report(1000, 500, 2000, 0, &[0])).unwrap();
+        dispatcher.dispatch(&report(1000, 500, 2000, 2, &[])).unwrap();
+
+        let events = pen.events();
+        assert_eq!(abs_values(&events, AbsoluteAxisType::ABS_X), vec![3096]);
+        assert_eq!(abs_values(&events, AbsoluteAxisType::ABS_Y), vec![3596]);
+    }
+
+    #[test]
+    fn inverted_axes_are_mirrored() {
+        let mut config = Config::default();
+        config.smoothing.tablet.clear();
+        config.invert_x = true;
+        let (mut dispatcher, pen, _) = dispatcher(&config);
+        dispatcher.set_mouse_mode(false).unwrap();
+
+        dispatcher.dispatch(&report(1000, 500, 2000, 2, &[])).unwrap();
+
+        let events = pen.events();
+        assert_eq!(abs_values(&events, AbsoluteAxisType::ABS_X), vec![3096]);
+        assert_eq!(abs_values(&events, AbsoluteAxisType::ABS_Y), vec![500]);
+    }
+
+    #[test]
+    fn mouse_area_scale_is_capped_by_config() {
+        let config = Config {
+            mouse_area_scale_max: 0.4,
+            ..Config::default()
+        };
+        let (mut dispatcher, _, _) = dispatcher(&config);
+
+        dispatcher.set_mouse_area_scale(0.8);
+
+        assert_eq!(dispatcher.mouse_area_scale(), 0.4);
+    }
+
+    #[test]
+    fn touch_follows_pressure_threshold() {
+        let (mut dispatcher, pen, _) = dispatcher(&Config::default());
+        dispatcher.set_mouse_mode(false).unwrap();
+
+        dispatcher.dispatch(&report(1000, 1000, 2000, 2, &[])).unwrap();
+        dispatcher.dispatch(&report(1000, 1000, 1000, 2, &[])).unwrap();
+        dispatcher.dispatch(&report(1000, 1000, 2000, 2, &[])).unwrap();
+
+        assert_eq!(key_values(&pen.events(), Key::BTN_TOUCH), vec![1, 0]);
+    }
+
+    #[test]
+    fn mouse_mode_clicks_at_its_own_threshold() {
+        let (mut dispatcher, pen, _) = dispatcher(&Config::default());
+
+        dispatcher.dispatch(&report(1000, 1000, 2000 - 700, 2, &[])).unwrap();
+        dispatcher.dispatch(&report(1000, 1000, 2000, 2, &[])).unwrap();
+
+        let events = pen.events();
+        assert_eq!(key_values(&events, Key::BTN_LEFT), vec![1, 0]);
+        assert!(key_values(&events, Key::BTN_TOUCH).is_empty());
+        // Clicking needs less force than pressure starts being reported at.
+        assert_eq!(abs_values(&events, AbsoluteAxisType::ABS_PRESSURE), vec![0]);
+    }
+
+    #[test]
+    fn multimedia_area_keeps_last_x_and_pins_y_to_top() {
+        let (mut dispatcher, pen, _) = dispatcher(&Config::default());
+        dispatcher.set_mouse_mode(false).unwrap();
+
+        dispatcher.dispatch(&report(1000, 2000, 2000, 2, &[])).unwrap();
+        pen.clear();
+        dispatcher.dispatch(&report(3000, 62000, 2000, 2, &[])).unwrap();
+
+        // X stays where it was, so only Y is sent.
+        let events = pen.events();
+        assert!(abs_values(&events, AbsoluteAxisType::ABS_X).is_empty());
+        assert_eq!(abs_values(&events, AbsoluteAxisType::ABS_Y), vec![0]);
+    }
+
+    #[test]
+    fn tapping_the_icon_strip_sends_its_keys() {
+        let mut config = Config::default();
+        config.multimedia.icons = vec![vec![Key::KEY_MUTE], vec![Key::KEY_PLAYPAUSE]];
+        let (mut dispatcher, pen, keyboard) = dispatcher(&config);
+        dispatcher.set_mouse_mode(false).unwrap();
+
+        dispatcher.dispatch(&report(3000, 62000, 2000, 2, &[])).unwrap();
+        dispatcher.dispatch(&report(3000, 62000, 1000, 2, &[])).unwrap();
+        dispatcher.dispatch(&report(3000, 62000, 2000, 2, &[])).unwrap();
+
+        let events = keyboard.events();
+        assert_eq!(key_values(&events, Key::KEY_PLAYPAUSE), vec![1, 0]);
+        assert!(key_values(&events, Key::KEY_MUTE).is_empty());
+        assert!(key_values(&pen.events(), Key::BTN_TOUCH).is_empty());
+    }
+
+    #[test]
+    fn sliding_along_the_strip_turns_the_dial() {
+        let mut config = Config::default();
+        config.multimedia.dial = true;
+        let (pen, keyboard, dial) =
+            (RecordingOutput::new(), RecordingOutput::new(), RecordingOutput::new());
+        let mut dispatcher = DeviceDispatcher::with_outputs(
+            &config,
+            Box::new(pen.clone()),
+            Box::new(keyboard.clone()),
+            None,
+            Some(Box::new(dial.clone())),
+        );
+        dispatcher.set_mouse_mode(false).unwrap();
+
+        for x in [1000, 1050, 1250, 1100] {
+            dispatcher.dispatch(&report(x, 62000, 1000, 2, &[])).unwrap();
+        }
+        dispatcher.dispatch(&report(1100, 62000, 2000, 2, &[])).unwrap();
+
+        let events = dial.events();
+        assert_eq!(abs_values(&events, AbsoluteAxisType::ABS_WHEEL), vec![1000, 1050, 1250, 1100]);
+        let steps: Vec<i32> = events
+            .iter()
+            .filter(|event| event.event_type() == EventType::RELATIVE)
+            .map(|event| event.value())
+            .collect();
+        assert_eq!(steps, vec![2, -1]);
+        assert!(keyboard.events().is_empty());
+        assert!(key_values(&pen.events(), Key::BTN_TOUCH).is_empty());
+    }
+
+    #[test]
+    fn flick_from_an_edge_runs_its_binding_instead_of_drawing() {
+        let mut config = Config::default();
+        config.smoothing.tablet.clear();
+        config.flicks.from_left = Some("undo".parse().unwrap());
+        let (mut dispatcher, pen, keyboard) = dispatcher(&config);
+        dispatcher.set_mouse_mode(false).unwrap();
+
+        for x in [50, 400, 800, 1200] {
+            dispatcher.dispatch(&report(x, 2000, 1000, 2, &[])).unwrap();
+        }
+        dispatcher.dispatch(&report(1200, 2000, 2000, 2, &[])).unwrap();
+        assert_eq!(key_values(&keyboard.events(), Key::KEY_Z), vec![1, 0]);
+        assert!(key_values(&pen.events(), Key::BTN_TOUCH).is_empty());
+
+        // A tap on the edge still gets through.
+        dispatcher.dispatch(&report(50, 2000, 1000, 2, &[])).unwrap();
+        dispatcher.dispatch(&report(50, 2000, 2000, 2, &[])).unwrap();
+        assert_eq!(key_values(&pen.events(), Key::BTN_TOUCH), vec![1, 0]);
+        assert_eq!(key_values(&keyboard.events(), Key::KEY_Z), vec![1, 0]);
+    }
+
+    #[test]
+    fn double_tap_clicks_twice_on_the_same_spot() {
+        let mut config = Config::default();
+        config.smoothing.mouse.clear();
+        config.double_tap.enabled = true;
+        let (mut dispatcher, pen, _) = dispatcher(&config);
+
+        dispatcher.dispatch(&report(2000, 2000, 1000, 2, &[])).unwrap();
+        dispatcher.dispatch(&report(2000, 2000, 2000, 2, &[])).unwrap();
+        dispatcher.dispatch(&report(2040, 2030, 2000, 2, &[])).unwrap();
+        dispatcher.dispatch(&report(2030, 2020, 1000, 2, &[])).unwrap();
+        dispatcher.dispatch(&report(2030, 2020, 2000, 2, &[])).unwrap();
+
+        let events = pen.events();
+        assert_eq!(key_values(&events, Key::BTN_LEFT), vec![1, 0, 1, 0]);
+        // The hover in between follows the pen; both clicks land on the same spot.
+        let xs = abs_values(&events, AbsoluteAxisType::ABS_X);
+        let (tap, hover) = (xs[0], xs[1]);
+        assert_eq!(xs, vec![tap, hover, tap]);
+        assert_ne!(hover, tap);
+    }
+
+    #[test]
+    fn light_touches_on_the_edge_are_taken_for_a_palm() {
+        let mut config = Config::default();
+        config.smoothing.tablet.clear();
+        config.palm_rejection.enabled = true;
+        let (mut dispatcher, pen, _) = dispatcher(&config);
+        dispatcher.set_mouse_mode(false).unwrap();
+
+        dispatcher.dispatch(&report(4000, 2000, 1400, 2, &[])).unwrap();
+        dispatcher.dispatch(&report(4000, 2000, 2000, 2, &[])).unwrap();
+        assert!(key_values(&pen.events(), Key::BTN_TOUCH).is_empty());
+        assert_eq!(abs_values(&pen.events(), AbsoluteAxisType::ABS_PRESSURE), vec![0]);
+
+        dispatcher.dispatch(&report(4000, 2000, 1000, 2, &[])).unwrap();
+        assert_eq!(key_values(&pen.events(), Key::BTN_TOUCH), vec![1]);
+    }
+
+    #[test]
+    fn sticky_button_holds_its_keys_until_the_next_tap() {
+        let config = Config {
+            sticky_buttons: vec![3],
+            ..Config::default()
+        };
+        let (mut dispatcher, pen, keyboard) = dispatcher(&config);
+        dispatcher.set_mouse_mode(false).unwrap();
+
+        dispatcher.dispatch(&report(2000, 2000, 2000, 2, &[3])).unwrap();
+        dispatcher.dispatch(&report(2000, 2000, 2000, 2, &[])).unwrap();
+        dispatcher.dispatch(&report(2000, 2000, 1000, 2, &[])).unwrap();
+        assert_eq!(key_values(&keyboard.events(), Key::KEY_LEFTCTRL), vec![1]);
+        dispatcher.dispatch(&report(2000, 2000, 2000, 2, &[])).unwrap();
+        assert_eq!(key_values(&keyboard.events(), Key::KEY_LEFTCTRL), vec![1, 0]);
+        assert_eq!(key_values(&pen.events(), Key::BTN_TOUCH), vec![1, 0]);
+
+        // Pressing it again lets go without a tap.
+        keyboard.clear();
+        dispatcher.dispatch(&report(2000, 2000, 2000, 2, &[3])).unwrap();
+        dispatcher.dispatch(&report(2000, 2000, 2000, 2, &[])).unwrap();
+        dispatcher.dispatch(&report(2000, 2000, 2000, 2, &[3])).unwrap();
+        assert_eq!(key_values(&keyboard.events(), Key::KEY_LEFTCTRL), vec![1, 0]);
+    }
+
+    #[test]
+    fn pen_hovering_still_sends_nothing_new() {
+        let mut config = Config::default();
+        config.smoothing.tablet.clear();
+        let (mut dispatcher, pen, _) = dispatcher(&config);
+        dispatcher.set_mouse_mode(false).unwrap();
+
+        dispatcher.dispatch(&report(1000, 1000, 2000, 2, &[])).unwrap();
+        pen.clear();
+        dispatcher.dispatch(&report(1000, 1000, 2000, 2, &[])).unwrap();
+        assert!(pen.events().is_empty());
+
+        dispatcher.dispatch(&report(1000, 1200, 2000, 2, &[])).unwrap();
+        // ABS_Y, MSC_TIMESTAMP and SYN_REPORT.
+        let events = pen.events();
+        assert_eq!(events.len(), 3);
+        assert_eq!(abs_values(&events, AbsoluteAxisType::ABS_Y), vec![1200]);
+    }
+
+    #[test]
+    fn frames_are_stamped_with_when_their_report_was_read() {
+        let mut config = Config::default();
+        config.smoothing.tablet.clear();
+        let (mut dispatcher, pen, _) = dispatcher(&config);
+        dispatcher.set_mouse_mode(false).unwrap();
+
+        let start = Instant::now();
+        for (i, x) in [1000, 1100, 1200].into_iter().enumerate() {
+            let mut report = report(x, 1000, 2000, 2, &[]);
+            report.read_at = Some(start + Duration::from_millis(8 * i as u64));
+            dispatcher.dispatch(&report).unwrap();
+        }
+
+        let timestamps: Vec<i32> = pen
+            .events()
+            .iter()
+            .filter(|event| event.event_type() == EventType::MISC)
+            .map(|event| event.value())
+            .collect();
+        assert_eq!(timestamps, vec![0, 8000, 16000]);
+    }
+
+    #[test]
+    fn disabled_icon_strip_is_drawing_surface() {
+        let mut config = Config::default();
+        config.multimedia.enabled = false;
+        let (mut dispatcher, pen, keyboard) = dispatcher(&config);
+        dispatcher.set_mouse_mode(false).unwrap();
+
+        dispatcher.dispatch(&report(3000, 62000, 1000, 2, &[])).unwrap();
+
+        assert!(keyboard.events().is_empty());
+        assert_eq!(key_values(&pen.events(), Key::BTN_TOUCH), vec![1]);
+        assert_eq!(abs_values(&pen.events(), AbsoluteAxisType::ABS_X), vec![3000]);
+    }
+
+    #[test]
+    fn scroll_button_turns_pen_movement_into_wheel_events() {
+        let mut config = Config::default();
+        config.smoothing.tablet.clear();
+        config.scroll.button = Some(6);
+        let (pen, mouse) = (RecordingOutput::new(), RecordingOutput::new());
+        let mut dispatcher = DeviceDispatcher::with_outputs(
+            &config,
+            Box::new(pen.clone()),
+            Box::new(RecordingOutput::new()),
+            Some(Box::new(mouse.clone())),
+            None,
+        );
+        dispatcher.set_mouse_mode(false).unwrap();
+
+        dispatcher.dispatch(&report(1000, 1000, 2000, 2, &[])).unwrap();
+        dispatcher.dispatch(&report(1000, 1000, 2000, 6, &[])).unwrap();
+        pen.clear();
+        dispatcher.dispatch(&report(1000, 1000, 2000, 6, &[])).unwrap();
+        dispatcher.dispatch(&report(1000, 1250, 2000, 6, &[])).unwrap();
+
+        let rel_values = |axis: RelativeAxisType| -> Vec<i32> {
+            mouse
+                .events()
+                .iter()
+                .filter(|event| event.code() == axis.0)
+                .map(|event| event.value())
+                .collect()
+        };
+        assert_eq!(rel_values(RelativeAxisType::REL_WHEEL), vec![3]);
+        assert_eq!(rel_values(RelativeAxisType::REL_WHEEL_HI_RES), vec![375]);
+        assert!(abs_values(&pen.events(), AbsoluteAxisType::ABS_Y).is_empty());
+        assert!(key_values(&pen.events(), Key::BTN_STYLUS2).is_empty());
+    }
+
+    #[test]
+    fn pen_leaving_range_releases_the_tool() {
+        let (mut dispatcher, pen, _) = dispatcher(&Config::default());
+
+        dispatcher.dispatch(&report(1000, 1000, 2000, 2, &[])).unwrap();
+        dispatcher.dispatch(&report(1000, 1000, 2000, 0, &[])).unwrap();
+
+        assert_eq!(key_values(&pen.events(), Key::BTN_TOOL_PEN), vec![1, 0]);
+    }
+
+    #[test]
+    fn pen_report_is_a_single_frame() {
+        let (mut dispatcher, pen, _) = dispatcher(&Config::default());
+        dispatcher.set_mouse_mode(false).unwrap();
+
+        dispatcher.dispatch(&report(1000, 1000, 1000, 2, &[])).unwrap();
+
+        let events = pen.events();
+        let syn_count = events
+            .iter()
+            .filter(|event| event.event_type() == EventType::SYNCHRONIZATION)
+            .count();
+        assert_eq!(syn_count, 1);
+        assert_eq!(events.last().unwrap().event_type(), EventType::SYNCHRONIZATION);
+        assert_eq!(key_values(&events, Key::BTN_TOUCH), vec![1]);
+        assert_eq!(abs_values(&events, AbsoluteAxisType::ABS_X), vec![1000]);
+    }
+
+    #[test]
+    fn shutdown_releases_everything_held() {
+        let (mut dispatcher, pen, keyboard) = dispatcher(&Config::default());
+        dispatcher.set_mouse_mode(false).unwrap();
+
+        dispatcher.dispatch(&report(1000, 1000, 1000, 6, &[0])).unwrap();
+        dispatcher.dispatch(&report(1000, 1000, 1000, 6, &[0])).unwrap();
+        dispatcher.shutdown().unwrap();
+
+        let events = pen.events();
+        assert_eq!(key_values(&events, Key::BTN_TOUCH), vec![1, 0]);
+        assert_eq!(key_values(&events, Key::BTN_TOOL_PEN), vec![1, 0]);
+        assert_eq!(key_values(&keyboard.events(), Key::KEY_TAB), vec![1, 2, 0]);
+    }
+
+    #[cfg(feature = "scripting")]
+    #[test]
+    fn script_takes_a_button_pressed_hard_instead_of_its_binding() {
+        let path = std::env::temp_dir().join(format!("vinsa-script-{}.rhai", std::process::id()));
+        std::fs::write(
+            &path,
+            "fn on_button(button, pressed, pen) {\n\
+                 if button == 3 && pressed && pen.pressure > 0.5 { send(\"ctrl+shift+z\"); true }\n\
+             }\n",
+        )
+        .unwrap();
+        let mut config = Config {
+            mode: Mode::Tablet,
+            ..Config::default()
+        };
+        config.script.path = Some(path.clone());
+        let (mut dispatcher, _, keyboard) = dispatcher(&config);
+        std::fs::remove_file(&path).unwrap();
+
+        // Pressed lightly, button 3 holds Ctrl as bound.
+        dispatcher.dispatch(&report(1000, 1000, 1500, 2, &[3])).unwrap();
+        dispatcher.dispatch(&report(1000, 1000, 1500, 2, &[])).unwrap();
+        assert_eq!(key_values(&keyboard.events(), Key::KEY_LEFTCTRL), vec![1, 0]);
+        assert!(key_values(&keyboard.events(), Key::KEY_Z).is_empty());
+        keyboard.clear();
+
+        // Pressed hard, the script taps Ctrl+Shift+Z instead, and the hold and release
+        // are skipped too.
+        dispatcher.dispatch(&report(1000, 1000, 0, 2, &[])).unwrap();
+        dispatcher.dispatch(&report(1000, 1000, 0, 2, &[3])).unwrap();
+        dispatcher.dispatch(&report(1000, 1000, 0, 2, &[3])).unwrap();
+        dispatcher.dispatch(&report(1000, 1000, 0, 2, &[])).unwrap();
+        let events = keyboard.events();
+        assert_eq!(key_values(&events, Key::KEY_LEFTCTRL), vec![1, 0]);
+        assert_eq!(key_values(&events, Key::KEY_LEFTSHIFT), vec![1, 0]);
+        assert_eq!(key_values(&events, Key::KEY_Z), vec![1, 0]);
+    }
+
+    #[test]
+    fn touch_on_a_zone_drags_its_slider_instead_of_drawing() {
+        let mut config = Config {
+            mode: Mode::Tablet,
+            ..Config::default()
+        };
+        config.zones = vec![ZoneConfig {
+            rect: [0, 0, 400, 4096],
+            kind: crate::zone::ZoneKind::Slider,
+            step: Some(100.0),
+            increase: Some("volumeup".parse().unwrap()),
+            decrease: Some("volumedown".parse().unwrap()),
+        }];
+        let (mut dispatcher, pen, keyboard) = dispatcher(&config);
+
+        for y in [2000, 1900, 1750, 1700] {
+            dispatcher.dispatch(&report(200, y, 1000, 2, &[])).unwrap();
+        }
+        dispatcher.dispatch(&report(200, 1700, 2000, 2, &[])).unwrap();
+        // Outside the zone the pen draws as usual.
+        dispatcher.dispatch(&report(2000, 1700, 1000, 2, &[])).unwrap();
+
+        assert_eq!(key_values(&keyboard.events(), Key::KEY_VOLUMEUP), vec![1, 0, 1, 0, 1, 0]);
+        assert_eq!(key_values(&pen.events(), Key::BTN_TOUCH), vec![1]);
+    }
+
+    #[test]
+    fn radial_menu_runs_the_slice_the_pen_moved_towards() {
+        let mut config = Config::default();
+        config.smoothing.mouse.clear();
+        config.radial_menu.button = Some(9);
+        config.radial_menu.items = ["undo", "redo", "save", "copy"]
+            .map(|action| crate::radial_menu::RadialMenuItem {
+                label: action.to_string(),
+                action: action.parse().unwrap(),
+            })
+            .to_vec();
+        let (mut dispatcher, pen, keyboard) = dispatcher(&config);
+
+        dispatcher.dispatch(&report(2048, 2048, 2000, 2, &[])).unwrap();
+        dispatcher.dispatch(&report(2048, 2048, 2000, 2, &[9])).unwrap();
+        pen.clear();
+        for x in [2048, 2200, 2600] {
+            dispatcher.dispatch(&report(x, 2048, 2000, 2, &[9])).unwrap();
+        }
+        assert_eq!(dispatcher.radial_menu().selected(), Some(1));
+        dispatcher.dispatch(&report(2600, 2048, 2000, 2, &[])).unwrap();
+
+        let events = keyboard.events();
+        assert_eq!(key_values(&events, Key::KEY_LEFTSHIFT), vec![1, 0]);
+        assert_eq!(key_values(&events, Key::KEY_Z), vec![1, 0]);
+        assert!(key_values(&events, Key::KEY_ESC).is_empty());
+        // The pen stays put while the menu is open.
+        assert!(abs_values(&pen.events(), AbsoluteAxisType::ABS_X).is_empty());
+        assert!(!dispatcher.radial_menu().is_open());
+    }
+
+    struct FailingOutput;
+
+    impl VirtualOutput for FailingOutput {
+        fn emit(&mut self, _events: &[InputEvent]) -> io::Result<()> {
+            Err(io::Error::other("device gone"))
+        }
+    }
+
+    #[test]
+    fn failing_keyboard_is_reported_without_stopping_the_pen() {
+        let pen = RecordingOutput::new();
+        let mut dispatcher = DeviceDispatcher::with_outputs(
+            &Config::default(),
+            Box::new(pen.clone()),
+            Box::new(FailingOutput),
+            None,
+            None,
+        );
+        dispatcher.set_mouse_mode(false).unwrap();
+
+        assert!(dispatcher.dispatch(&report(1000, 1000, 2000, 2, &[0])).is_err());
+        assert_eq!(abs_values(&pen.events(), AbsoluteAxisType::ABS_X), vec![1000]);
+    }
+
+    #[test]
+    fn held_keys_are_released_when_dispatching_fails() {
+        let keyboard = RecordingOutput::new();
+        let mut dispatcher = DeviceDispatcher::with_outputs(
+            &Config::default(),
+            Box::new(FailingOutput),
+            Box::new(keyboard.clone()),
+            None,
+            None,
+        );
+
+        assert!(dispatcher.dispatch(&report(1000, 1000, 2000, 2, &[3])).is_err());
+        assert_eq!(key_values(&keyboard.events(), Key::KEY_LEFTCTRL), vec![1, 0]);
+        // The button's own release later is harmless.
+        assert!(dispatcher.dispatch(&report(1000, 1000, 2000, 2, &[])).is_err());
+        assert_eq!(key_values(&keyboard.events(), Key::KEY_LEFTCTRL), vec![1, 0, 0]);
+        dispatcher.release_all_keys().unwrap();
+        assert_eq!(key_values(&keyboard.events(), Key::KEY_LEFTCTRL), vec![1, 0, 0]);
     }
 }
@@ -0,0 +1,90 @@
+use evdev::uinput::VirtualDevice;
+use evdev::{EventType, InputEvent, Synchronization};
+
+use std::cell::RefCell;
+use std::io::{Error, ErrorKind};
+use std::os::fd::AsRawFd;
+use std::rc::Rc;
+use std::thread;
+use std::time::Duration;
+
+const EMIT_ATTEMPTS: u32 = 3;
+
+// Where the dispatcher sends its events: a uinput device, or a recorder in tests.
+// Each `emit` is one frame: the events are followed by a single SYN_REPORT.
+pub trait VirtualOutput {
+    fn emit(&mut self, events: &[InputEvent]) -> Result<(), Error>;
+
+    // Removes the device from the system ahead of being dropped.
+    fn destroy(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+// uinput writes can fail transiently (EINTR, EAGAIN, or EIO under load); retry a few
+// times with a short backoff instead of dropping the event.
+impl VirtualOutput for VirtualDevice {
+    fn emit(&mut self, events: &[InputEvent]) -> Result<(), Error> {
+        let mut attempt = 1;
+        loop {
+            match VirtualDevice::emit(self, events) {
+                Err(err) if attempt < EMIT_ATTEMPTS && is_transient(&err) => {
+                    thread::sleep(Duration::from_millis(1 << attempt));
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    // Closing the node would also remove the device, but only once every copy of the
+    // descriptor is gone; UI_DEV_DESTROY removes it right away.
+    fn destroy(&mut self) -> Result<(), Error> {
+        // UI_DEV_DESTROY: _IO('U', 2)
+        let request = ((b'U' as libc::c_ulong) << 8) | 2;
+        // SAFETY: UI_DEV_DESTROY takes no argument.
+        match unsafe { libc::ioctl(self.as_raw_fd(), request) } {
+            -1 => Err(Error::last_os_error()),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn is_transient(err: &Error) -> bool {
+    matches!(err.kind(), ErrorKind::Interrupted | ErrorKind::WouldBlock)
+        || err.raw_os_error() == Some(libc::EIO)
+}
+
+// Keeps every emitted event, SYN_REPORTs included. Clones share the same log, so one clone can be handed
+// to the dispatcher while another is used to inspect what it emitted.
+#[derive(Clone, Default)]
+pub struct RecordingOutput {
+    events: Rc<RefCell<Vec<InputEvent>>>,
+}
+
+impl RecordingOutput {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn events(&self) -> Vec<InputEvent> {
+        self.events.borrow().clone()
+    }
+
+    pub fn clear(&self) {
+        self.events.borrow_mut().clear();
+    }
+}
+
+impl VirtualOutput for RecordingOutput {
+    fn emit(&mut self, events: &[InputEvent]) -> Result<(), Error> {
+        let mut recorded = self.events.borrow_mut();
+        recorded.extend_from_slice(events);
+        recorded.push(InputEvent::new(
+            EventType::SYNCHRONIZATION,
+            Synchronization::SYN_REPORT.0,
+            0,
+        ));
+        Ok(())
+    }
+}
@@ -0,0 +1,181 @@
+use evdev::RelativeAxisType;
+use serde::Deserialize;
+
+use crate::binding::Binding;
+
+// A rectangle of the tablet that works as the touch strip or ring it lacks: a touch
+// that lands in it drags a slider or turns a dial instead of moving the pen, until
+// lifted. Each `step` of movement sends the `increase` or `decrease` binding, or a
+// wheel notch when they are left out.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ZoneConfig {
+    // [left, top, right, bottom] in raw tablet units.
+    pub rect: [i32; 4],
+    #[serde(default, rename = "type")]
+    pub kind: ZoneKind,
+    // Tablet units per step along a slider, or degrees around a dial.
+    pub step: Option<f32>,
+    pub increase: Option<Binding>,
+    pub decrease: Option<Binding>,
+}
+
+// A slider runs along the longer side of its rectangle and increases upwards or to
+// the right; a dial turns around the rectangle's center and increases clockwise.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ZoneKind {
+    #[default]
+    Slider,
+    Dial,
+}
+
+impl ZoneConfig {
+    fn contains(&self, x: i32, y: i32) -> bool {
+        let [left, top, right, bottom] = self.rect;
+        (left..=right).contains(&x) && (top..=bottom).contains(&y)
+    }
+
+    fn is_horizontal(&self) -> bool {
+        let [left, top, right, bottom] = self.rect;
+        right - left > bottom - top
+    }
+
+    fn step(&self) -> f32 {
+        let default = match self.kind {
+            ZoneKind::Slider => 200.0,
+            ZoneKind::Dial => 15.0,
+        };
+        self.step.unwrap_or(default).max(0.1)
+    }
+
+    // Where along the slider or around the dial (in degrees) the pen is.
+    fn position(&self, x: i32, y: i32) -> f32 {
+        match self.kind {
+            ZoneKind::Slider if self.is_horizontal() => x as f32,
+            ZoneKind::Slider => -y as f32,
+            ZoneKind::Dial => {
+                let [left, top, right, bottom] = self.rect;
+                let center = ((left + right) as f32 / 2.0, (top + bottom) as f32 / 2.0);
+                (y as f32 - center.1).atan2(x as f32 - center.0).to_degrees()
+            }
+        }
+    }
+
+    // Whether steps go out as wheel notches, which need the virtual mouse.
+    pub fn scrolls(&self) -> bool {
+        self.increase.is_none() && self.decrease.is_none()
+    }
+
+    pub fn wheel_axis(&self) -> RelativeAxisType {
+        match self.kind == ZoneKind::Slider && self.is_horizontal() {
+            true => RelativeAxisType::REL_HWHEEL,
+            false => RelativeAxisType::REL_WHEEL,
+        }
+    }
+
+    // The binding for steps in the direction of `steps`, if the zone has one.
+    pub fn binding(&self, steps: i32) -> Option<&Binding> {
+        match steps > 0 {
+            true => self.increase.as_ref(),
+            false => self.decrease.as_ref(),
+        }
+    }
+}
+
+// Follows a touch on a zone.
+#[derive(Default)]
+pub struct ZoneTracker {
+    // The zone being touched, and where the pen last was on it.
+    active: Option<(usize, f32)>,
+    // Movement not yet enough for a step.
+    remainder: f32,
+    was_touching: bool,
+}
+
+impl ZoneTracker {
+    // The zone the touch is on and the steps it moved by since the last report, or
+    // None when the pen is used as usual.
+    pub fn update(
+        &mut self,
+        zones: &[ZoneConfig],
+        x: i32,
+        y: i32,
+        is_touching: bool,
+    ) -> Option<(usize, i32)> {
+        let is_touch_down = is_touching && !self.was_touching;
+        self.was_touching = is_touching;
+        if !is_touching {
+            self.active = None;
+            return None;
+        }
+        if is_touch_down {
+            let index = zones.iter().position(|zone| zone.contains(x, y))?;
+            self.active = Some((index, zones[index].position(x, y)));
+            self.remainder = 0.0;
+            return Some((index, 0));
+        }
+
+        let (index, last) = self.active?;
+        let zone = zones.get(index)?;
+        let position = zone.position(x, y);
+        let mut moved = position - last;
+        // The shorter way around, as a dial's angle wraps at ±180°.
+        if zone.kind == ZoneKind::Dial {
+            moved = (moved + 540.0).rem_euclid(360.0) - 180.0;
+        }
+        let exact = moved / zone.step() + self.remainder;
+        let steps = exact.trunc();
+        self.remainder = exact - steps;
+        self.active = Some((index, position));
+        Some((index, steps as i32))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slider_and_dial_turn_movement_into_steps() {
+        #[derive(Deserialize)]
+        struct Zones {
+            zones: Vec<ZoneConfig>,
+        }
+        let Zones { zones } = toml::from_str(
+            r#"
+            [[zones]]
+            rect = [0, 0, 400, 4000]
+            step = 100.0
+            increase = "volumeup"
+            decrease = "volumedown"
+
+            [[zones]]
+            rect = [3000, 3000, 4000, 4000]
+            type = "dial"
+            step = 90.0
+            "#,
+        )
+        .unwrap();
+        let mut tracker = ZoneTracker::default();
+
+        // A touch landing outside the zones, then sliding in, stays a pen stroke.
+        assert_eq!(tracker.update(&zones, 1000, 2000, true), None);
+        assert_eq!(tracker.update(&zones, 200, 2000, true), None);
+        assert_eq!(tracker.update(&zones, 200, 2000, false), None);
+
+        // Upwards on the slider increases, with partial steps carried over.
+        assert_eq!(tracker.update(&zones, 200, 2000, true), Some((0, 0)));
+        assert_eq!(tracker.update(&zones, 200, 1950, true), Some((0, 0)));
+        assert_eq!(tracker.update(&zones, 200, 1790, true), Some((0, 2)));
+        assert_eq!(tracker.update(&zones, 200, 2150, true), Some((0, -3)));
+        assert_eq!(zones[0].binding(-3), zones[0].decrease.as_ref());
+        assert!(!zones[0].scrolls());
+        tracker.update(&zones, 200, 2150, false);
+
+        // A quarter turn clockwise across the wrap-around from the left to the top.
+        assert_eq!(tracker.update(&zones, 3000, 3501, true), Some((1, 0)));
+        assert_eq!(tracker.update(&zones, 3500, 3000, true), Some((1, 1)));
+        assert!(zones[1].scrolls());
+        assert_eq!(zones[1].wheel_axis(), RelativeAxisType::REL_WHEEL);
+    }
+}